@@ -0,0 +1,22 @@
+#![no_main]
+
+use git_starter_rust::pack_file::{apply_patch, DeltaInstructionType};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // First byte selects how input is split between base payload and patch.
+    let Some((split, data)) = data.split_first() else {
+        return;
+    };
+    let (payload, patch) = data.split_at((*split as usize).min(data.len()));
+
+    let mut base = format!("blob {}\0", payload.len()).into_bytes();
+    base.extend_from_slice(payload);
+    let _ = apply_patch(&base, patch);
+
+    // Also decode instructions one by one.
+    let mut instructions = patch;
+    while let Ok((rem, _instr)) = DeltaInstructionType::from_bytes(instructions) {
+        instructions = rem;
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use git_starter_rust::header::GitObjectHeader;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(header) = GitObjectHeader::read(&mut &data[..]) {
+        let mut output = Vec::new();
+        header
+            .write(&mut output)
+            .expect("Cannot write parsed header");
+        assert_eq!(
+            GitObjectHeader::read(&mut output.as_slice()).unwrap(),
+            header
+        );
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use git_starter_rust::object::GitObject;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Any object we manage to parse must be written back and read again unchanged.
+    if let Ok(object) = GitObject::read(&mut &data[..]) {
+        let (_hash_code, bytes) = object.to_bytes_vec().expect("Cannot write parsed object");
+        let read_back = GitObject::read(&mut bytes.as_slice()).expect("Cannot read written object");
+        assert_eq!(read_back, object);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use git_starter_rust::packet_line::PacketLine;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    // Read packet lines until input is exhausted or invalid.
+    let mut reader = data;
+    while PacketLine::read(&mut reader).is_ok() && !reader.is_empty() {}
+});
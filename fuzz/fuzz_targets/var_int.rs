@@ -0,0 +1,11 @@
+#![no_main]
+
+use git_starter_rust::pack_file::{read_object_pack_header, read_var_int};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok((rem, _value)) = read_var_int(data) {
+        assert!(rem.len() < data.len());
+    }
+    let _ = read_object_pack_header(&mut &data[..]);
+});
@@ -0,0 +1,23 @@
+#!/bin/sh
+#
+# Fill fuzz corpus with known valid inputs from test data.
+set -e
+
+cd "$(dirname "$0")"
+DATA=../tests/data
+
+mkdir -p corpus/object corpus/header corpus/packet_line corpus/var_int corpus/apply_patch
+
+cp $DATA/simple-commit.bin $DATA/simple-tree.bin corpus/object/
+cp $DATA/simple-commit.bin $DATA/simple-tree.bin corpus/header/
+printf 'blob 5\0hello' >corpus/object/blob.bin
+
+printf '0008NAK\n0000' >corpus/packet_line/nak.bin
+printf '000fwant hello\n00000009done\n' >corpus/packet_line/want.bin
+
+# Pack entries headers start after the 12 bytes pack header.
+tail -c +13 $DATA/sqlite-rust.pack | head -c 64 >corpus/var_int/pack-entry.bin
+printf '\321\220\040' >corpus/var_int/multi-bytes.bin
+
+# Split byte, "hello" base payload, then patch copying "hell" and inserting "!!".
+printf '\005hello\005\006\220\004\002!!' >corpus/apply_patch/copy-insert.bin
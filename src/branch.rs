@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use crate::{
+    config::GitConfig,
+    log::ancestors,
+    refs::{
+        delete_ref_at, head_branch_at, is_valid_ref_name, list_refs_at, read_ref_at, rename_ref_at,
+        resolve_ref_at, write_ref_at, RefValue,
+    },
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// Local branch with its tracking information.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Branch {
+    pub name: String,
+    pub target: HashCode,
+    /// HEAD points to this branch.
+    pub is_head: bool,
+    pub upstream: Option<Upstream>,
+}
+
+/// Branch tracked by a local branch, from `branch.<name>.remote` and `branch.<name>.merge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// Remote name, `.` for a local branch.
+    pub remote: String,
+    /// Branch ref on the remote side, like `refs/heads/main`.
+    pub merge: String,
+}
+
+impl Upstream {
+    /// Local ref holding upstream value, like `refs/remotes/origin/main`.
+    pub fn tracking_ref(&self) -> String {
+        let branch = self
+            .merge
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&self.merge);
+        if self.remote == "." {
+            format!("refs/heads/{branch}")
+        } else {
+            format!("refs/remotes/{}/{branch}", self.remote)
+        }
+    }
+
+    /// Name shown to users, like `origin/main`.
+    pub fn short_name(&self) -> String {
+        let tracking_ref = self.tracking_ref();
+        let name = tracking_ref
+            .strip_prefix("refs/remotes/")
+            .or_else(|| tracking_ref.strip_prefix("refs/heads/"))
+            .unwrap_or(&tracking_ref);
+        name.to_string()
+    }
+}
+
+/// Check name can be used for a branch, like `feature/login`.
+pub fn is_valid_branch_name(name: &str) -> bool {
+    !name.starts_with('-') && name != "HEAD" && is_valid_ref_name(&format!("refs/heads/{name}"))
+}
+
+/// List local branches sorted by name.
+pub fn list_branches<P: AsRef<Path>>(root: P) -> Result<Vec<Branch>, GitError> {
+    let root = root.as_ref();
+    let config = GitConfig::read_at(root)?;
+    let head = head_branch_at(root)?;
+
+    Ok(list_refs_at(root)?
+        .into_iter()
+        .filter_map(|(name, target)| {
+            let name = name.strip_prefix("refs/heads/")?.to_string();
+            Some(Branch {
+                is_head: head.as_ref() == Some(&name),
+                upstream: read_upstream(&config, &name),
+                name,
+                target,
+            })
+        })
+        .collect())
+}
+
+/// Read branch upstream from config.
+pub fn read_upstream(config: &GitConfig, name: &str) -> Option<Upstream> {
+    Some(Upstream {
+        remote: config.get(&format!("branch.{name}.remote"))?.to_string(),
+        merge: config.get(&format!("branch.{name}.merge"))?.to_string(),
+    })
+}
+
+/// Create branch `name` at `start`. Existing branches are only reset when `force` is set,
+/// and never when checked out.
+pub fn create_branch<P: AsRef<Path>>(
+    root: P,
+    name: &str,
+    start: HashCode,
+    force: bool,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    if !is_valid_branch_name(name) {
+        return Err(GitError::InvalidContent(format!(
+            "'{name}' is not a valid branch name"
+        )));
+    }
+
+    let ref_name = format!("refs/heads/{name}");
+    if read_ref_at(root, &ref_name)?.is_some() {
+        if !force {
+            return Err(GitError::InvalidContent(format!(
+                "A branch named '{name}' already exists"
+            )));
+        }
+        if head_branch_at(root)?.as_deref() == Some(name) {
+            return Err(GitError::InvalidContent(format!(
+                "Cannot force update the current branch '{name}'"
+            )));
+        }
+    }
+
+    write_ref_at(root, &ref_name, &RefValue::Direct(start))
+}
+
+/// Delete a branch and its config. Unless `force` is set, branch must be merged in its
+/// upstream, or in HEAD when it has none. Returns commit branch was pointing to.
+pub fn delete_branch<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    name: &str,
+    force: bool,
+) -> Result<HashCode, GitError> {
+    let root = root.as_ref();
+    let ref_name = format!("refs/heads/{name}");
+    let target = resolve_ref_at(root, &ref_name)?
+        .ok_or_else(|| GitError::InvalidContent(format!("Branch '{name}' not found")))?;
+
+    if head_branch_at(root)?.as_deref() == Some(name) {
+        return Err(GitError::InvalidContent(format!(
+            "Cannot delete branch '{name}' checked out"
+        )));
+    }
+
+    if !force {
+        let config = GitConfig::read_at(root)?;
+        let base = match read_upstream(&config, name) {
+            Some(upstream) => resolve_ref_at(root, &upstream.tracking_ref())?,
+            None => None,
+        };
+        let base = match base {
+            Some(base) => Some(base),
+            None => resolve_ref_at(root, "HEAD")?,
+        };
+        let is_merged = match base {
+            Some(base) => is_ancestor(store, target, base)?,
+            None => false,
+        };
+        if !is_merged {
+            return Err(GitError::InvalidContent(format!(
+                "The branch '{name}' is not fully merged"
+            )));
+        }
+    }
+
+    delete_ref_at(root, &ref_name)?;
+    let mut config = GitConfig::read_at(root)?;
+    config.remove_section("branch", Some(name));
+    config.write_at(root)?;
+    Ok(target)
+}
+
+/// Rename a branch, moving its config and HEAD along with it.
+pub fn rename_branch<P: AsRef<Path>>(
+    root: P,
+    old: &str,
+    new: &str,
+    force: bool,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    if !is_valid_branch_name(new) {
+        return Err(GitError::InvalidContent(format!(
+            "'{new}' is not a valid branch name"
+        )));
+    }
+
+    let old_ref = format!("refs/heads/{old}");
+    let new_ref = format!("refs/heads/{new}");
+    let is_head = head_branch_at(root)?.as_deref() == Some(old);
+
+    // Current branch may still be unborn.
+    if read_ref_at(root, &old_ref)?.is_none() && !is_head {
+        return Err(GitError::InvalidContent(format!(
+            "Branch '{old}' not found"
+        )));
+    }
+    if old != new && read_ref_at(root, &new_ref)?.is_some() {
+        if !force {
+            return Err(GitError::InvalidContent(format!(
+                "A branch named '{new}' already exists"
+            )));
+        }
+        delete_ref_at(root, &new_ref)?;
+    }
+
+    if read_ref_at(root, &old_ref)?.is_some() {
+        rename_ref_at(root, &old_ref, &new_ref)?;
+    }
+    if is_head {
+        write_ref_at(root, "HEAD", &RefValue::Symbolic(new_ref))?;
+    }
+
+    if old == new {
+        return Ok(());
+    }
+    let mut config = GitConfig::read_at(root)?;
+    config.remove_section("branch", Some(new));
+    config.rename_section("branch", Some(old), Some(new));
+    config.write_at(root)
+}
+
+/// Make `name` track `upstream`, either a remote tracking branch like `origin/main`
+/// or a local branch.
+pub fn set_upstream<P: AsRef<Path>>(
+    root: P,
+    name: &str,
+    upstream: &str,
+) -> Result<Upstream, GitError> {
+    let root = root.as_ref();
+    if read_ref_at(root, &format!("refs/heads/{name}"))?.is_none() {
+        return Err(GitError::InvalidContent(format!(
+            "Branch '{name}' not found"
+        )));
+    }
+
+    let upstream = if read_ref_at(root, &format!("refs/remotes/{upstream}"))?.is_some() {
+        let (remote, branch) = upstream
+            .split_once('/')
+            .ok_or_else(|| GitError::InvalidContent(format!("Invalid upstream '{upstream}'")))?;
+        Upstream {
+            remote: remote.to_string(),
+            merge: format!("refs/heads/{branch}"),
+        }
+    } else if read_ref_at(root, &format!("refs/heads/{upstream}"))?.is_some() {
+        Upstream {
+            remote: ".".to_string(),
+            merge: format!("refs/heads/{upstream}"),
+        }
+    } else {
+        return Err(GitError::InvalidContent(format!(
+            "The requested upstream branch '{upstream}' does not exist"
+        )));
+    };
+
+    let mut config = GitConfig::read_at(root)?;
+    config.set(&format!("branch.{name}.remote"), &upstream.remote);
+    config.set(&format!("branch.{name}.merge"), &upstream.merge);
+    config.write_at(root)?;
+    Ok(upstream)
+}
+
+/// Check if `commit` is reachable from `tip`.
+pub fn is_ancestor(store: &ObjectStore, commit: HashCode, tip: HashCode) -> Result<bool, GitError> {
+    Ok(commit == tip || ancestors(store, &[tip], false)?.contains(&commit))
+}
+
+/// Count commits `(ahead, behind)` of `local` compared to `upstream`.
+pub fn ahead_behind(
+    store: &ObjectStore,
+    local: HashCode,
+    upstream: HashCode,
+) -> Result<(usize, usize), GitError> {
+    let local_commits = ancestors(store, &[local], false)?;
+    let upstream_commits = ancestors(store, &[upstream], false)?;
+    Ok((
+        local_commits.difference(&upstream_commits).count(),
+        upstream_commits.difference(&local_commits).count(),
+    ))
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs::{self, Permissions},
+    io,
+    os::unix::{ffi::OsStrExt, fs::symlink, fs::PermissionsExt},
+    path::Path,
+};
+
+use crate::{
+    branch::is_valid_branch_name,
+    diff::{flatten_tree, hash_worktree_file, FileMap},
+    index::{worktree_mode, Index, IndexEntry},
+    object::{validate_entry_name, GitObject},
+    refs::{read_ref_at, resolve_ref_at, write_ref_at, RefValue},
+    revision::peel_to_tree,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+const MODE_SYMLINK: u32 = 0o120000;
+const MODE_GITLINK: u32 = 0o160000;
+
+/// Where HEAD goes after a checkout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckoutTarget {
+    /// Existing branch, like `main`.
+    Branch(String),
+    /// Commit checked out without any branch.
+    Detached(HashCode),
+    /// Branch created at `start` before switching to it.
+    NewBranch { name: String, start: HashCode },
+}
+
+impl CheckoutTarget {
+    /// Commit checked out in repository `root`.
+    pub fn commit<P: AsRef<Path>>(&self, root: P) -> Result<HashCode, GitError> {
+        match self {
+            Self::Branch(name) => resolve_ref_at(root, &format!("refs/heads/{name}"))?
+                .ok_or_else(|| GitError::UnknownRevision(name.clone())),
+            Self::Detached(commit) => Ok(*commit),
+            Self::NewBranch { start, .. } => Ok(*start),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CheckoutOptions {
+    /// Throw away local changes instead of refusing to switch.
+    pub force: bool,
+}
+
+/// New state of a path: `None` to delete it.
+type Updates = BTreeMap<String, Option<(u32, HashCode)>>;
+
+/// What is found in the worktree at a given path.
+enum LocalFile {
+    Missing,
+    Directory,
+    File(u32, HashCode),
+}
+
+/// Switch HEAD to a branch or a commit, updating index and worktree on the way.
+pub fn checkout<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    target: &CheckoutTarget,
+    options: &CheckoutOptions,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+
+    if let CheckoutTarget::NewBranch { name, .. } = target {
+        if !is_valid_branch_name(name) {
+            return Err(GitError::InvalidContent(format!(
+                "'{name}' is not a valid branch name"
+            )));
+        }
+        if read_ref_at(root, &format!("refs/heads/{name}"))?.is_some() {
+            return Err(GitError::InvalidContent(format!(
+                "A branch named '{name}' already exists"
+            )));
+        }
+    }
+    let commit = target.commit(root)?;
+
+    let old_tree = resolve_ref_at(root, "HEAD")?
+        .map(|x| peel_to_tree(store, x))
+        .transpose()?;
+    let new_tree = peel_to_tree(store, commit)?;
+    checkout_tree(root, store, old_tree, new_tree, options)?;
+
+    let head = match target {
+        CheckoutTarget::Branch(name) => RefValue::Symbolic(format!("refs/heads/{name}")),
+        CheckoutTarget::Detached(commit) => RefValue::Direct(*commit),
+        CheckoutTarget::NewBranch { name, start } => {
+            let ref_name = format!("refs/heads/{name}");
+            write_ref_at(root, &ref_name, &RefValue::Direct(*start))?;
+            RefValue::Symbolic(ref_name)
+        }
+    };
+    write_ref_at(root, "HEAD", &head)
+}
+
+/// Move index and worktree from `old` tree (the one of current HEAD) to `new` one.
+///
+/// Like `git read-tree -m -u`: paths identical in both trees keep their local changes,
+/// other ones are only updated when they have none. Nothing is touched when some
+/// local change would be lost, unless `force` is set.
+pub fn checkout_tree<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    old: Option<HashCode>,
+    new: HashCode,
+    options: &CheckoutOptions,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let mut index = Index::read_at(root)?;
+    if index.has_conflicts() && !options.force {
+        return Err(GitError::invalid_content(
+            "You need to resolve your current index first",
+        ));
+    }
+
+    let old_files = match old {
+        Some(tree) => flatten_tree(store, tree)?,
+        None => FileMap::new(),
+    };
+    let new_files = flatten_tree(store, new)?;
+    for path in new_files.keys() {
+        path.split('/').try_for_each(validate_entry_name)?;
+    }
+
+    let updates = if options.force {
+        forced_updates(root, &index, &new_files)?
+    } else {
+        two_way_updates(root, &index, &old_files, &new_files)?
+    };
+
+    apply_updates(root, store, &mut index, &updates)?;
+    index.write_at(root)
+}
+
+/// Reset `paths` in index and worktree to their content in `tree`, dropping their
+/// conflict stages, like `git checkout <tree> -- <paths>`. Paths missing from `tree`
+/// are removed.
+pub fn checkout_paths<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    tree: HashCode,
+    paths: &[String],
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let mut index = Index::read_at(root)?;
+    let files = flatten_tree(store, tree)?;
+
+    let updates: Updates = paths
+        .iter()
+        .map(|path| (path.clone(), files.get(path).copied()))
+        .collect();
+    apply_updates(root, store, &mut index, &updates)?;
+    index.write_at(root)
+}
+
+/// Changes from `old` to `new` trees, failing when a local change is in the way.
+fn two_way_updates(
+    root: &Path,
+    index: &Index,
+    old_files: &FileMap,
+    new_files: &FileMap,
+) -> Result<Updates, GitError> {
+    let paths: BTreeSet<_> = old_files.keys().chain(new_files.keys()).collect();
+    let mut updates = Updates::new();
+    let mut conflicts = Vec::new();
+
+    for path in paths {
+        let old = old_files.get(path).copied();
+        let new = new_files.get(path).copied();
+        let entry = index.find(path, 0);
+        let staged = entry.map(|x| (x.mode, x.hash_code));
+
+        // Unchanged between trees or already staged: keep local state.
+        if old == new || staged == new {
+            continue;
+        }
+
+        let is_clean = match (staged, old) {
+            (Some(staged), Some(old)) if staged == old => match local_file(root, path, entry)? {
+                LocalFile::Missing => true,
+                LocalFile::Directory => staged.0 == MODE_GITLINK,
+                LocalFile::File(mode, hash_code) => (mode, hash_code) == staged,
+            },
+            // New file: do not overwrite untracked files, unless they are identical.
+            (None, None) => {
+                let is_free = match local_file(root, path, None)? {
+                    LocalFile::Missing => true,
+                    // Only tracked files are inside, they are handled on their own.
+                    LocalFile::Directory => {
+                        let prefix = format!("{path}/");
+                        old_files.keys().any(|x| x.starts_with(&prefix))
+                    }
+                    LocalFile::File(mode, hash_code) => Some((mode, hash_code)) == new,
+                };
+                is_free && !has_untracked_parent(root, path, old_files)
+            }
+            _ => false,
+        };
+
+        if is_clean {
+            updates.insert(path.clone(), new);
+        } else {
+            conflicts.push(path.clone());
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(updates)
+    } else {
+        Err(GitError::WouldOverwrite(conflicts))
+    }
+}
+
+/// Changes resetting every tracked path to `new` tree content.
+fn forced_updates(root: &Path, index: &Index, new_files: &FileMap) -> Result<Updates, GitError> {
+    let paths: BTreeSet<_> = index
+        .entries
+        .iter()
+        .map(|x| &x.path)
+        .chain(new_files.keys())
+        .collect();
+    let mut updates = Updates::new();
+
+    for path in paths {
+        let new = new_files.get(path).copied();
+        let entry = index.find(path, 0);
+
+        let is_up_to_date = entry.map(|x| (x.mode, x.hash_code)) == new
+            && index.entries.iter().filter(|x| x.path == *path).count() == 1
+            && match (local_file(root, path, entry)?, new) {
+                (LocalFile::File(mode, hash_code), Some(new)) => (mode, hash_code) == new,
+                (LocalFile::Directory, Some((mode, _))) => mode == MODE_GITLINK,
+                _ => false,
+            };
+        if !is_up_to_date {
+            updates.insert(path.clone(), new);
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Check if a parent directory of `path` is in fact an untracked file.
+fn has_untracked_parent(root: &Path, path: &str, old_files: &FileMap) -> bool {
+    path.match_indices('/').any(|(idx, _)| {
+        let parent = &path[..idx];
+        !old_files.contains_key(parent)
+            && fs::symlink_metadata(root.join(parent)).is_ok_and(|x| !x.is_dir())
+    })
+}
+
+fn local_file(root: &Path, path: &str, entry: Option<&IndexEntry>) -> Result<LocalFile, GitError> {
+    let full_path = root.join(path);
+    let metadata = match fs::symlink_metadata(&full_path) {
+        Ok(metadata) => metadata,
+        Err(err) if is_missing(&err) => return Ok(LocalFile::Missing),
+        Err(err) => return Err(err.into()),
+    };
+
+    if metadata.is_dir() {
+        return Ok(LocalFile::Directory);
+    }
+    match entry {
+        Some(entry) if entry.is_stat_clean(&metadata) => {
+            Ok(LocalFile::File(entry.mode, entry.hash_code))
+        }
+        _ => Ok(LocalFile::File(
+            worktree_mode(&metadata),
+            hash_worktree_file(&full_path)?,
+        )),
+    }
+}
+
+/// Path does not exist, or one of its parents is a file.
+fn is_missing(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+    )
+}
+
+fn apply_updates(
+    root: &Path,
+    store: &ObjectStore,
+    index: &mut Index,
+    updates: &Updates,
+) -> Result<(), GitError> {
+    // Removals first, so files can replace directories and the opposite.
+    for path in updates.iter().filter(|(_, x)| x.is_none()).map(|(x, _)| x) {
+        remove_worktree_file(root, path)?;
+        index.remove(path);
+    }
+
+    for (path, (mode, hash_code)) in updates.iter().filter_map(|(path, x)| x.map(|x| (path, x))) {
+        write_worktree_file(root, store, path, mode, hash_code)?;
+
+        let entry = if mode == MODE_GITLINK {
+            IndexEntry::new(path, mode, hash_code)
+        } else {
+            let metadata = fs::symlink_metadata(root.join(path))?;
+            IndexEntry::from_metadata(path, &metadata, hash_code)
+        };
+        index.remove(path);
+        index.add(entry);
+    }
+
+    Ok(())
+}
+
+/// Remove a file and the directories left empty by its removal.
+fn remove_worktree_file(root: &Path, path: &str) -> Result<(), GitError> {
+    let full_path = root.join(path);
+    match fs::symlink_metadata(&full_path) {
+        // Submodules are left alone when not empty.
+        Ok(metadata) if metadata.is_dir() => {
+            let _ = fs::remove_dir(&full_path);
+        }
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(err) if is_missing(&err) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let mut parent = full_path.parent();
+    while let Some(dir) = parent.filter(|x| *x != root) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+    Ok(())
+}
+
+fn write_worktree_file(
+    root: &Path,
+    store: &ObjectStore,
+    path: &str,
+    mode: u32,
+    hash_code: HashCode,
+) -> Result<(), GitError> {
+    let full_path = root.join(path);
+
+    // Make room for parent directories, removing files in the way.
+    for (idx, _) in path.match_indices('/') {
+        let parent = root.join(&path[..idx]);
+        if fs::symlink_metadata(&parent).is_ok_and(|x| !x.is_dir()) {
+            fs::remove_file(&parent)?;
+        }
+    }
+    if let Some(parent) = full_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    match fs::symlink_metadata(&full_path) {
+        Ok(metadata) if metadata.is_dir() => {
+            if mode == MODE_GITLINK {
+                return Ok(());
+            }
+            fs::remove_dir(&full_path)?;
+        }
+        Ok(_) => fs::remove_file(&full_path)?,
+        Err(err) if is_missing(&err) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    if mode == MODE_GITLINK {
+        // Submodule content is not available here, only its directory is created.
+        fs::create_dir(&full_path)?;
+        return Ok(());
+    }
+
+    let GitObject::Blob(content) = store.read(hash_code)? else {
+        return Err(GitError::InvalidContent(format!(
+            "{} is not a blob",
+            hex::encode(hash_code)
+        )));
+    };
+    if mode == MODE_SYMLINK {
+        symlink(OsStr::from_bytes(&content), &full_path)?;
+    } else {
+        fs::write(&full_path, &content)?;
+        let permissions = if mode & 0o111 != 0 { 0o755 } else { 0o644 };
+        fs::set_permissions(&full_path, Permissions::from_mode(permissions))?;
+    }
+    Ok(())
+}
@@ -1,220 +1,678 @@
 use std::{
-    future::Future,
+    io,
     path::{Path, PathBuf},
-    pin::Pin,
 };
 
-use bytes::{Buf, Bytes};
+use bytes::Bytes;
 use tokio::{fs, try_join};
 
 use crate::{
-    fs_utils::read_compressed_at,
+    checkout::{checkout_tree, CheckoutOptions},
+    config::GitConfig,
+    fetch::{fetch_advertised, supports},
+    fs_utils::git_dir,
     hash_code_text_to_array,
-    object::{GitObject, GitTreeItem},
-    pack_file::unpack_into,
     packet_line::PacketLine,
-    GitError,
+    promisor::{fetch_missing_tree, write_promisor_config, ObjectFilter},
+    refs::{append_reflog_at, is_valid_ref_name, resolve_ref_at, write_ref_at, RefValue},
+    remote::{RefSpec, Remote},
+    revision::peel_to_tree,
+    shallow::{read_shallow, ShallowOptions},
+    sideband::print_progress,
+    signature::Signature,
+    store::ObjectStore,
+    transport::{copy_objects, list_local_refs, Connection, Transport},
+    GitError, HashCode,
 };
 
-pub async fn clone<P>(url: &str, dst: P) -> Result<(), GitError>
+/// How `clone` sets up the new repository.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CloneOptions {
+    /// Branch, or tag, to check out instead of the remote `HEAD` (`-b`).
+    pub branch: Option<String>,
+    /// Only fetch history of the checked out branch (`--single-branch`).
+    pub single_branch: bool,
+    /// Leave the worktree empty (`--no-checkout`).
+    pub no_checkout: bool,
+    /// Make a repository without worktree whose branches are the remote ones (`--bare`).
+    pub bare: bool,
+    /// Make a bare repository mirroring every remote ref (`--mirror`).
+    pub mirror: bool,
+    /// Use the pack protocol even for a repository on this machine (`--no-local`).
+    pub no_local: bool,
+    /// Copy objects of a local repository instead of hard linking them (`--no-hardlinks`).
+    pub no_hardlinks: bool,
+    pub shallow: ShallowOptions,
+    /// Objects left on the server, fetched on demand from the promisor remote.
+    pub filter: Option<ObjectFilter>,
+}
+
+impl CloneOptions {
+    fn is_bare(&self) -> bool {
+        self.bare || self.mirror
+    }
+}
+
+/// Where `HEAD` of a new clone points.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CloneHead {
+    /// Local branch created from the remote one.
+    Branch { name: String, id: HashCode },
+    /// Commit checked out without branch, like a tag.
+    Detached(HashCode),
+    /// Branch of an empty repository, created by the first commit.
+    Unborn(String),
+}
+
+/// Clone `url` into `dst` as `origin` remote: remote branches are fetched as
+/// `refs/remotes/origin/*` along with tags, then the remote default branch, or the one
+/// asked for, is checked out.
+///
+/// Objects of a repository given by path are copied, or hard linked, instead of going
+/// through the pack protocol like with a `file://` URL.
+pub async fn clone<P>(url: &str, dst: P, options: &CloneOptions) -> Result<(), GitError>
 where
     P: AsRef<Path>,
 {
     let dst = dst.as_ref();
+    // Like git, never clone over existing content.
+    let is_new = match std::fs::read_dir(dst).map(|mut x| x.next().is_none()) {
+        Ok(true) => false,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => true,
+        _ => {
+            return Err(GitError::Io(format!(
+                "destination path '{}' already exists and is not an empty directory",
+                dst.display()
+            )))
+        }
+    };
+
+    let result = clone_into(url, dst, options).await;
+    // Leave no half made repository behind, but only remove what this clone created.
+    if result.is_err() && is_new {
+        let _ = fs::remove_dir_all(dst).await;
+    }
+    result
+}
 
-    // Remove previous directory.
-    let _ = fs::remove_dir_all(dst).await;
+async fn clone_into(url: &str, dst: &Path, options: &CloneOptions) -> Result<(), GitError> {
+    let url = url.trim_end_matches('/');
+    // Paths are made absolute so the remote is still found from the new repository.
+    let url = match Transport::parse(url)? {
+        Transport::Local {
+            path,
+            is_file_url: false,
+        } => match std::fs::canonicalize(&path) {
+            Ok(path) => path.to_string_lossy().into_owned(),
+            Err(_) => {
+                return Err(GitError::Transport(format!(
+                    "repository '{url}' does not exist"
+                )))
+            }
+        },
+        _ => url.to_string(),
+    };
+    let url = url.as_str();
+    let local_source = match options.no_local {
+        true => None,
+        false => local_clone_source(url)?,
+    };
 
     // Prepare output dir.
     println!(">> Configuring new repository ...");
-    fs::create_dir_all(dst).await?;
-    fs::create_dir(dst.join(".git")).await?;
-
+    let git_dir = if options.is_bare() {
+        dst.to_path_buf()
+    } else {
+        dst.join(".git")
+    };
     try_join!(
-        fs::create_dir(dst.join(".git/objects")),
-        fs::create_dir_all(dst.join(".git/refs/heads")),
-        fs::write(dst.join(".git/config"), include_str!("./data/config.ini")),
-        fs::write(dst.join(".git/description"), "empty repository"),
-        fs::write(dst.join(".git/HEAD"), "ref: refs/heads/master\n"),
+        fs::create_dir_all(git_dir.join("objects")),
+        fs::create_dir_all(git_dir.join("refs/heads")),
+        fs::create_dir_all(git_dir.join("refs/tags")),
+    )?;
+    try_join!(
+        fs::write(git_dir.join("description"), "empty repository"),
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/master\n"),
     )?;
 
     // List refs from remote repository.
-    println!(">> Finding HEAD on remote server ...");
-    let refs = InfoRef::list_for_repo(url).await?;
-
-    // Find HEAD.
-    let head = refs
-        .into_iter()
-        .find(|x| x.name == "HEAD")
-        .ok_or(GitError::NoHead)?;
+    println!(">> Finding refs on remote server ...");
+    let prefixes: Vec<_> = if options.mirror {
+        Vec::new()
+    } else {
+        ["HEAD", "refs/heads/", "refs/tags/"]
+            .map(|x| x.to_string())
+            .to_vec()
+    };
+    let (mut connection, advertisement) = match &local_source {
+        Some(source) => {
+            let advertisement = Advertisement {
+                version: ProtocolVersion::V0,
+                capabilities: Vec::new(),
+                refs: list_local_refs(source, &prefixes)?,
+            };
+            (None, advertisement)
+        }
+        None => {
+            let (connection, advertisement) = Connection::upload_pack(url, &prefixes).await?;
+            (Some(connection), advertisement)
+        }
+    };
+    // A mirror takes every name as is: refuse the ones which could escape `refs/`.
+    if options.mirror {
+        let invalid = advertisement
+            .refs
+            .iter()
+            .find(|x| x.name != "HEAD" && !x.name.ends_with("^{}") && !is_valid_ref_name(&x.name));
+        if let Some(invalid) = invalid {
+            return Err(GitError::InvalidContent(format!(
+                "Invalid ref name '{}' on remote",
+                invalid.name
+            )));
+        }
+    }
+    let head = clone_head(&advertisement.refs, options.branch.as_deref())?;
+
+    let mut shallow = options.shallow.clone();
+    let mut filter = options.filter;
+    if local_source.is_some() {
+        if shallow != ShallowOptions::default() {
+            eprintln!("warning: --depth is ignored in local clones; use file:// instead.");
+            shallow = ShallowOptions::default();
+        }
+        if filter.take().is_some() {
+            eprintln!("warning: --filter is ignored in local clones; use file:// instead.");
+        }
+    }
+    // Like git, go on with a full clone if the server cannot filter.
+    if filter.is_some() && !supports(&advertisement, "filter") {
+        eprintln!("warning: filtering not recognized by server, ignoring");
+        filter = None;
+    }
 
-    // Configure HEAD
     println!(">> Configuring git repo ...");
-    fs::write(dst.join(".git/refs/heads/master"), &head.object_id).await?;
+    let mut config = GitConfig::default();
+    config.set("core.repositoryformatversion", "0");
+    config.set("core.filemode", "true");
+    config.set("core.bare", &options.is_bare().to_string());
+    if !options.is_bare() {
+        config.set("core.logallrefupdates", "true");
+    }
+    config.set("remote.origin.url", url);
+    let configured_fetch = clone_refspecs(options, &head, false);
+    for refspec in &configured_fetch {
+        config.add("remote.origin.fetch", refspec);
+    }
+    if options.mirror {
+        config.set("remote.origin.mirror", "true");
+    }
+    if let (CloneHead::Branch { name, .. }, false) = (&head, options.is_bare()) {
+        config.set(&format!("branch.{name}.remote"), "origin");
+        config.set(
+            &format!("branch.{name}.merge"),
+            &format!("refs/heads/{name}"),
+        );
+    }
+    config.write_at(dst)?;
+    if let Some(filter) = filter {
+        write_promisor_config(dst, "origin", filter)?;
+    }
 
-    // Download it locally.
-    println!(">> Downloading data ...");
-    let mut reader = head.download(url).await?;
+    if advertisement.refs.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+    } else {
+        println!(">> Downloading data ...");
+        let remote = Remote {
+            name: "origin".to_string(),
+            url: url.to_string(),
+            fetch: clone_refspecs(options, &head, true)
+                .iter()
+                .map(|x| RefSpec::parse(x))
+                .collect::<Result<_, _>>()?,
+            partial_clone_filter: filter,
+        };
+        if let Some(source) = &local_source {
+            copy_objects(source, dst, !options.no_hardlinks)?;
+        }
+        fetch_advertised(
+            dst,
+            &remote,
+            connection.as_mut(),
+            &advertisement,
+            &shallow,
+            &mut print_progress,
+        )
+        .await?;
+    }
+    if let Some(connection) = connection {
+        connection.close().await?;
+    }
 
-    // Unpack downloaded file
-    println!(">> Unpacking data ...");
-    unpack_into(&mut reader, dst)?;
+    // Configure HEAD
+    let reflog_message = format!("clone: from {url}");
+    let committer = Signature::from_env("COMMITTER", &config).ok();
+    let checkout = match &head {
+        CloneHead::Branch { name, id } => {
+            let ref_name = format!("refs/heads/{name}");
+            if !options.is_bare() {
+                write_ref_at(dst, &ref_name, &RefValue::Direct(*id))?;
+                if let Some(committer) = &committer {
+                    append_reflog_at(dst, &ref_name, None, *id, committer, &reflog_message)?;
+                    append_reflog_at(dst, "HEAD", None, *id, committer, &reflog_message)?;
+                }
+                // Remember the remote default branch.
+                let remote_ref = format!("refs/remotes/origin/{name}");
+                if resolve_ref_at(dst, &remote_ref)?.is_some() {
+                    write_ref_at(
+                        dst,
+                        "refs/remotes/origin/HEAD",
+                        &RefValue::Symbolic(remote_ref),
+                    )?;
+                }
+            }
+            write_ref_at(dst, "HEAD", &RefValue::Symbolic(ref_name))?;
+            Some(*id)
+        }
+        CloneHead::Detached(id) => {
+            write_ref_at(dst, "HEAD", &RefValue::Direct(*id))?;
+            Some(*id)
+        }
+        CloneHead::Unborn(name) => {
+            write_ref_at(
+                dst,
+                "HEAD",
+                &RefValue::Symbolic(format!("refs/heads/{name}")),
+            )?;
+            None
+        }
+    };
 
     // Extract data from git database
-    println!(">> Extracting data");
-    extract_files_from_commit(&head.object_id, dst).await?;
+    if let Some(commit) = checkout.filter(|_| !options.no_checkout && !options.is_bare()) {
+        println!(">> Extracting data");
+        let store = ObjectStore::open(dst)?;
+        let tree = peel_to_tree(&store, commit)?;
+        fetch_missing_tree(dst, tree, &mut print_progress).await?;
+        let store = ObjectStore::open(dst)?;
+        checkout_tree(dst, &store, None, tree, &CheckoutOptions::default())?;
+    }
 
     Ok(())
 }
 
-#[derive(Debug)]
-pub struct InfoRef {
-    pub name: String,
-    pub object_id: String,
+/// Repository at `url` whose objects a clone can copy: a path to a complete repository.
+///
+/// Like git, a shallow repository goes through the pack protocol, which knows which
+/// commits lack parents.
+fn local_clone_source(url: &str) -> Result<Option<PathBuf>, GitError> {
+    let Transport::Local {
+        path,
+        is_file_url: false,
+    } = Transport::parse(url)?
+    else {
+        return Ok(None);
+    };
+    if !git_dir(&path).join("objects").is_dir() {
+        return Err(GitError::Transport(format!(
+            "'{url}' does not appear to be a git repository"
+        )));
+    }
+    if !read_shallow(&path)?.is_empty() {
+        eprintln!("warning: source repository is shallow, ignoring --local");
+        return Ok(None);
+    }
+    Ok(Some(path))
+}
+
+/// Pick what `HEAD` of a clone points to among advertised `refs`: `branch` if given,
+/// else the branch the remote `HEAD` points to.
+fn clone_head(refs: &[InfoRef], branch: Option<&str>) -> Result<CloneHead, GitError> {
+    let find = |name: &str| -> Result<Option<HashCode>, GitError> {
+        refs.iter()
+            .find(|x| x.name == name)
+            .map(|x| hash_code_text_to_array(&x.object_id))
+            .transpose()
+    };
+
+    if let Some(name) = branch {
+        check_branch_name(name)?;
+        if let Some(id) = find(&format!("refs/heads/{name}"))? {
+            return Ok(CloneHead::Branch {
+                name: name.to_string(),
+                id,
+            });
+        }
+        let tag = format!("refs/tags/{name}");
+        if let Some(id) = find(&format!("{tag}^{{}}"))?.or(find(&tag)?) {
+            return Ok(CloneHead::Detached(id));
+        }
+        return Err(GitError::InvalidContent(format!(
+            "Remote branch {name} not found in upstream origin"
+        )));
+    }
+
+    let Some(head) = refs.iter().find(|x| x.name == "HEAD") else {
+        return Ok(CloneHead::Unborn("master".to_string()));
+    };
+    let target = match head.symref_target.as_deref() {
+        Some(target) => match target.strip_prefix("refs/heads/") {
+            Some(name) if is_valid_ref_name(target) => Some(name),
+            _ => {
+                return Err(GitError::InvalidContent(format!(
+                    "Invalid remote HEAD target '{target}'"
+                )))
+            }
+        },
+        None => None,
+    };
+    // Without symref information, guess from branches at the same commit, like git.
+    let guessed = || {
+        let mut candidates = refs
+            .iter()
+            .filter(|x| x.object_id == head.object_id && x.name.starts_with("refs/heads/"));
+        let first = candidates.next()?;
+        let name = if first.name == "refs/heads/master" {
+            &first.name
+        } else {
+            candidates
+                .find(|x| x.name == "refs/heads/master")
+                .map_or(&first.name, |x| &x.name)
+        };
+        name.strip_prefix("refs/heads/")
+    };
+
+    let id = hash_code_text_to_array(&head.object_id)?;
+    let Some(name) = target.or_else(guessed) else {
+        return Ok(CloneHead::Detached(id));
+    };
+    check_branch_name(name)?;
+    match find(&format!("refs/heads/{name}"))? {
+        Some(id) => Ok(CloneHead::Branch {
+            name: name.to_string(),
+            id,
+        }),
+        None => Ok(CloneHead::Unborn(name.to_string())),
+    }
+}
+
+/// Refuse branch `name` if it makes an invalid ref, before it gets written to `HEAD`,
+/// refs and config.
+fn check_branch_name(name: &str) -> Result<(), GitError> {
+    if !is_valid_ref_name(&format!("refs/heads/{name}")) {
+        return Err(GitError::InvalidContent(format!(
+            "Invalid branch name '{name}'"
+        )));
+    }
+    Ok(())
+}
+
+/// Refspecs of the `origin` remote of a clone, with the ones only used by the first fetch
+/// when `fetching`.
+fn clone_refspecs(options: &CloneOptions, head: &CloneHead, fetching: bool) -> Vec<String> {
+    if options.mirror {
+        return vec!["+refs/*:refs/*".to_string()];
+    }
+    let dst_prefix = if options.bare {
+        "refs/heads/"
+    } else {
+        "refs/remotes/origin/"
+    };
+
+    let mut output = Vec::new();
+    if options.single_branch {
+        match (&head, &options.branch) {
+            (CloneHead::Branch { name, .. } | CloneHead::Unborn(name), _) => {
+                output.push(format!("+refs/heads/{name}:{dst_prefix}{name}"))
+            }
+            // A tag is fetched once, later fetches have nothing to follow.
+            (CloneHead::Detached(_), Some(tag)) if fetching => {
+                output.push(format!("+refs/tags/{tag}:refs/tags/{tag}"))
+            }
+            (CloneHead::Detached(_), _) => {}
+        }
+    } else if fetching || !options.bare {
+        output.push(format!("+refs/heads/*:{dst_prefix}*"));
+    }
+    // Tags are all fetched at first, then followed along with history.
+    if fetching && !options.single_branch {
+        output.push("+refs/tags/*:refs/tags/*".to_string());
+    }
+    output
+}
+
+/// Version of the git wire protocol spoken with a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    /// Original protocol, also used for version 1: refs are advertised upfront.
+    V0,
+    /// Refs are listed on demand with the `ls-refs` command.
+    V2,
+}
+
+/// Server answer to `info/refs` discovery.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Advertisement {
+    pub version: ProtocolVersion,
+    /// Capabilities of the first v0 ref, or v2 capability lines like `fetch=shallow`.
     pub capabilities: Vec<String>,
+    /// Advertised refs, only listed on demand by `list` with v2.
+    pub refs: Vec<InfoRef>,
 }
 
-impl InfoRef {
-    pub async fn list_for_repo(url: &str) -> Result<Vec<Self>, GitError> {
-        let client = reqwest::Client::new();
-        let content = client
-            .get(format!("{url}/info/refs?service=git-upload-pack"))
-            .send()
-            .await?
-            .error_for_status()?
-            .text()
-            .await?;
+impl Advertisement {
+    /// Read the advertisement of `service` at `url`, asking for protocol v2 when
+    /// `version` is `V2`.
+    ///
+    /// Servers not supporting v2 ignore the request and answer with v0.
+    pub async fn discover(
+        url: &str,
+        service: &str,
+        version: ProtocolVersion,
+    ) -> Result<Self, GitError> {
+        let (connection, advertisement) = Connection::open(url, service, version).await?;
+        connection.close().await?;
+        Ok(advertisement)
+    }
 
-        let mut output = Vec::new();
+    /// Parse a v0 ref advertisement or a v2 capability advertisement, with or without
+    /// the `# service=` header of smart HTTP.
+    pub fn parse<R: io::Read>(reader: &mut R) -> Result<Self, GitError> {
+        let mut line = read_text_line(reader)?;
+        if line.as_deref().is_some_and(|x| x.starts_with("# service=")) {
+            if read_text_line(reader)?.is_some() {
+                return Err(GitError::Http(
+                    "Missing flush after service header".to_string(),
+                ));
+            }
+            line = read_text_line(reader)?;
+        }
 
-        for (idx, mut line) in content
-            .lines()
-            .skip(1) // Skip header
-            .take_while(|x| *x != "0000") // Continue until we reach end tag
-            .enumerate()
-        {
-            // Skip start sequence
-            if idx == 0 {
-                line = &line[4..];
+        match line.as_deref() {
+            Some(line) if line.starts_with("ERR ") => {
+                return Err(GitError::Http(format!("remote error: {}", &line[4..])));
             }
+            Some("version 2") => {
+                let mut capabilities = Vec::new();
+                while let Some(line) = read_text_line(reader)? {
+                    capabilities.push(line);
+                }
+                return Ok(Self {
+                    version: ProtocolVersion::V2,
+                    capabilities,
+                    refs: Vec::new(),
+                });
+            }
+            Some("version 1") => line = read_text_line(reader)?,
+            _ => {}
+        }
 
+        let mut refs: Vec<InfoRef> = Vec::new();
+        while let Some(text) = line {
             // parse line like:
-            // 004895dcfa3633004da0049d3d0fa03f80589cbcaf31 refs/heads/main\0multi_ack\n
-            // 003c2cb58b79488a98d2721cea644875a8dd0026b115 refs/tags/v1.0\n
-            let (obj_id, rem) = line
-                .split_once(' ')
-                .ok_or(GitError::InvalidObjectPayload("Missing object ID"))?;
-
-            let (obj_name, capabilities) = match rem.split_once('\0') {
-                Some((obj_name, rem)) => (
-                    obj_name,
+            // 95dcfa3633004da0049d3d0fa03f80589cbcaf31 refs/heads/main\0multi_ack
+            // 2cb58b79488a98d2721cea644875a8dd0026b115 refs/tags/v1.0
+            let (text, capabilities) = match text.split_once('\0') {
+                Some((text, rem)) => (
+                    text,
                     rem.split_whitespace().map(|x| x.to_string()).collect(),
                 ),
-                None => (rem, vec![]),
+                None => (text.as_str(), vec![]),
             };
+            let (object_id, name) = text
+                .split_once(' ')
+                .ok_or(GitError::InvalidObjectPayload("Missing object ID"))?;
+            hash_code_text_to_array(object_id)?;
 
-            output.push(Self {
-                name: obj_name.to_string(),
-                object_id: obj_id[4..].to_string(),
+            refs.push(InfoRef {
+                name: name.to_string(),
+                object_id: object_id.to_string(),
                 capabilities,
+                symref_target: None,
             });
+            line = read_text_line(reader)?;
         }
 
-        Ok(output)
+        let capabilities = refs
+            .first()
+            .map(|x| x.capabilities.clone())
+            .unwrap_or_default();
+        // v0 tells symbolic refs with capabilities like `symref=HEAD:refs/heads/main`.
+        for symref in capabilities
+            .iter()
+            .filter_map(|x| x.strip_prefix("symref="))
+        {
+            if let Some((name, target)) = symref.split_once(':') {
+                if let Some(info_ref) = refs.iter_mut().find(|x| x.name == name) {
+                    info_ref.symref_target = Some(target.to_string());
+                }
+            }
+        }
+        // An empty repository only advertises capabilities, on a made up ref.
+        refs.retain(|x| x.name != "capabilities^{}");
+
+        Ok(Self {
+            version: ProtocolVersion::V0,
+            capabilities,
+            refs,
+        })
     }
 
-    pub async fn download(&self, url: &str) -> Result<bytes::buf::Reader<Bytes>, GitError> {
-        let client = reqwest::Client::new();
+    pub fn has_capability(&self, name: &str) -> bool {
+        self.capabilities
+            .iter()
+            .any(|x| x == name || x.split_once('=').is_some_and(|(key, _)| key == name))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct InfoRef {
+    pub name: String,
+    pub object_id: String,
+    pub capabilities: Vec<String>,
+    /// Ref it points to when symbolic, like `refs/heads/main` for `HEAD`.
+    pub symref_target: Option<String>,
+}
+
+impl InfoRef {
+    /// List refs of an upload-pack service, with protocol v2 when the server supports it.
+    pub async fn list_for_repo(url: &str) -> Result<Vec<Self>, GitError> {
+        let (connection, advertisement) = Connection::upload_pack(url, &[]).await?;
+        connection.close().await?;
+        Ok(advertisement.refs)
+    }
 
-        // Create git request
-        let mut request_body = Vec::with_capacity(128);
-        PacketLine::want(&self.object_id).write(&mut request_body)?;
-        PacketLine::End.write(&mut request_body)?;
-        PacketLine::done().write(&mut request_body)?;
+    /// List refs advertised by `service`, `git-upload-pack` or `git-receive-pack`, with
+    /// protocol v0.
+    pub async fn list_for_service(url: &str, service: &str) -> Result<Vec<Self>, GitError> {
+        Ok(Advertisement::discover(url, service, ProtocolVersion::V0)
+            .await?
+            .refs)
+    }
 
-        // Query server
-        let content = client
-            .post(format!("{url}/git-upload-pack"))
-            .header("Content-Type", "application/x-git-upload-pack-request")
-            .body(request_body)
-            .send()
+    /// List refs starting with one of `prefixes`, or all refs when empty, with the v2
+    /// `ls-refs` command.
+    ///
+    /// Peeled tags are returned as extra `<name>^{}` refs, like v0 advertises them.
+    pub async fn ls_refs(
+        connection: &mut Connection,
+        prefixes: &[String],
+    ) -> Result<Vec<Self>, GitError> {
+        let body = Self::ls_refs_request(prefixes)?;
+        let head = connection
+            .request(body)
             .await?
-            .error_for_status()?
-            .bytes()
+            .read_head(|x| *x == PacketLine::End)
             .await?;
+        Self::parse_ls_refs(&mut head.as_slice())
+    }
 
-        let mut reader = content.reader();
-
-        // Check first packet line is a NAK
-        let command = PacketLine::read(&mut reader)?;
-        if command != PacketLine::command(b"NAK\n") {
-            return Err(GitError::Http("Bad response first packet line".to_string()));
+    pub fn ls_refs_request(prefixes: &[String]) -> Result<Vec<u8>, GitError> {
+        let mut output = Vec::new();
+        PacketLine::command(b"command=ls-refs").write(&mut output)?;
+        PacketLine::Delimiter.write(&mut output)?;
+        PacketLine::command(b"peel").write(&mut output)?;
+        PacketLine::command(b"symrefs").write(&mut output)?;
+        for prefix in prefixes {
+            PacketLine::Command(Bytes::from(format!("ref-prefix {prefix}"))).write(&mut output)?;
         }
-
-        Ok(reader)
+        PacketLine::End.write(&mut output)?;
+        Ok(output)
     }
-}
-
-async fn extract_files_from_commit<P>(commit_id: &str, dst: P) -> Result<(), GitError>
-where
-    P: AsRef<Path>,
-{
-    // Find object from git DB.
-    let mut commit_data = read_compressed_at(hash_code_text_to_array(commit_id), &dst)?;
-    let Ok(GitObject::Commit { tree, .. }) = GitObject::read(&mut commit_data) else {
-        return Err(GitError::invalid_content(
-            "Invalid object type, expected 'commit'",
-        ));
-    };
 
-    // Find object from git DB.
-    let mut tree_data = read_compressed_at(tree, &dst)?;
-    let Ok(GitObject::Tree(items)) = GitObject::read(&mut tree_data) else {
-        return Err(GitError::invalid_content(
-            "Invalid object type, expected 'tree'",
-        ));
-    };
-
-    // Extract tree
-    let root = dst.as_ref().to_path_buf();
-    extract_files_from_tree(items, root.clone(), root).await?;
-
-    Ok(())
-}
+    /// Parse `ls-refs` output lines like
+    /// `<id> <name> [symref-target:<target>] [peeled:<id>]` up to the flush.
+    pub fn parse_ls_refs<R: io::Read>(reader: &mut R) -> Result<Vec<Self>, GitError> {
+        let mut output = Vec::new();
 
-fn extract_files_from_tree(
-    items: Vec<GitTreeItem>,
-    dst: PathBuf,
-    repo_root: PathBuf,
-) -> Pin<Box<dyn Future<Output = Result<(), GitError>> + Send>> {
-    Box::pin(async move {
-        // For each tree item.
-        for item in items {
-            let sub_dst = dst.join(&item.name);
-
-            // Read and parse git object content.
-            let mut item_data = read_compressed_at(item.hash_code, &repo_root)?;
-            let obj_item = GitObject::read(&mut item_data)?;
-
-            // Extract it to the file system.
-            match obj_item {
-                GitObject::Blob(obj_content) => {
-                    fs::write(&sub_dst, obj_content).await?;
-                    fs::set_permissions(&sub_dst, item.permissions()).await?;
-                }
-                GitObject::Tree(sub_items) => {
-                    fs::create_dir(&sub_dst).await?;
-                    extract_files_from_tree(sub_items, sub_dst, repo_root.clone()).await?;
-                }
-                // If we are here there is probably a bug 😭.
-                GitObject::Commit { .. } => {
-                    return Err(GitError::invalid_content(
-                        "Cannot extract a commit as a file system object",
-                    ))
+        while let Some(line) = read_text_line(reader)? {
+            if let Some(message) = line.strip_prefix("ERR ") {
+                return Err(GitError::Http(format!("remote error: {message}")));
+            }
+            let mut parts = line.split(' ');
+            let (Some(object_id), Some(name)) = (parts.next(), parts.next()) else {
+                return Err(GitError::InvalidObjectPayload("Missing object ID"));
+            };
+            hash_code_text_to_array(object_id)?;
+
+            let mut symref_target = None;
+            let mut peeled = None;
+            for attribute in parts {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    symref_target = Some(target.to_string());
+                } else if let Some(id) = attribute.strip_prefix("peeled:") {
+                    hash_code_text_to_array(id)?;
+                    peeled = Some(id.to_string());
                 }
             }
+
+            output.push(Self {
+                name: name.to_string(),
+                object_id: object_id.to_string(),
+                capabilities: Vec::new(),
+                symref_target,
+            });
+            if let Some(peeled) = peeled {
+                output.push(Self {
+                    name: format!("{name}^{{}}"),
+                    object_id: peeled,
+                    capabilities: Vec::new(),
+                    symref_target: None,
+                });
+            }
         }
 
-        Ok(())
-    })
+        Ok(output)
+    }
+}
+
+/// Read a packet line as text without its trailing newline, `None` on a flush or delimiter.
+fn read_text_line<R: io::Read>(reader: &mut R) -> Result<Option<String>, GitError> {
+    match PacketLine::read(reader)? {
+        PacketLine::Command(data) => Ok(Some(
+            std::str::from_utf8(&data)?
+                .trim_end_matches('\n')
+                .to_string(),
+        )),
+        PacketLine::End | PacketLine::Delimiter => Ok(None),
+    }
 }
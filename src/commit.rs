@@ -0,0 +1,262 @@
+use std::{fs, io, path::Path};
+
+use crate::{
+    config::GitConfig,
+    diff::{read_worktree_file, untracked_files},
+    fs_utils::git_dir,
+    hash_code_text_to_array,
+    header::GitObjectHeaderType,
+    ignore::IgnoreRules,
+    index::{worktree_mode, Index, IndexEntry},
+    object::GitObject,
+    refs::{resolve_ref_at, update_head_at},
+    signature::Signature,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// How a new commit relates to HEAD.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitOptions {
+    /// Replace HEAD commit instead of adding a child to it.
+    pub amend: bool,
+    /// Record a commit even if its tree is the same as its parent's.
+    pub allow_empty: bool,
+}
+
+/// Stage files matching `pathspecs` from the worktree, like `git add`.
+///
+/// A pathspec is a file or a directory relative to `root`, `.` standing for the whole
+/// worktree. Tracked files missing from the worktree are removed from the index.
+pub fn add_paths<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    pathspecs: &[String],
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let mut index = Index::read_at(root)?;
+    let untracked = untracked_files(root, &index)?;
+    let ignore_rules = IgnoreRules::load_at(root)?;
+
+    let mut paths = Vec::new();
+    for pathspec in pathspecs {
+        let prefix = match pathspec.trim_end_matches('/') {
+            "." | "" => "",
+            x => x.strip_prefix("./").unwrap_or(x),
+        };
+        let matches = |path: &str| {
+            prefix.is_empty()
+                || path == prefix
+                || path
+                    .strip_prefix(prefix)
+                    .is_some_and(|x| x.starts_with('/'))
+        };
+
+        let len = paths.len();
+        paths.extend(
+            index
+                .entries
+                .iter()
+                .map(|x| x.path.as_str())
+                .chain(untracked.iter().map(|x| x.as_str()))
+                .filter(|x| matches(x))
+                .map(|x| x.to_string()),
+        );
+        if paths.len() == len {
+            let is_file = fs::symlink_metadata(root.join(prefix)).is_ok_and(|x| !x.is_dir());
+            return Err(if is_file && ignore_rules.is_ignored(prefix, false) {
+                GitError::InvalidContent(format!(
+                    "The following paths are ignored by one of your .gitignore files: {prefix}"
+                ))
+            } else {
+                GitError::InvalidContent(format!("pathspec '{pathspec}' did not match any files"))
+            });
+        }
+    }
+    paths.sort();
+    paths.dedup();
+
+    for path in paths {
+        let metadata = match fs::symlink_metadata(root.join(&path)) {
+            Ok(metadata) => metadata,
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::NotADirectory
+                ) =>
+            {
+                index.remove(&path);
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        // Nested repositories are left as they are.
+        if metadata.is_dir() {
+            continue;
+        }
+        if index
+            .find(&path, 0)
+            .is_some_and(|x| x.mode == worktree_mode(&metadata) && x.is_stat_clean(&metadata))
+        {
+            continue;
+        }
+
+        let content = read_worktree_file(&root.join(&path))?;
+        let hash_code = store.write_raw(GitObjectHeaderType::Blob, &content)?;
+        // Staging a file resolves its conflict.
+        index.remove(&path);
+        index.add(IndexEntry::from_metadata(&path, &metadata, hash_code));
+    }
+
+    index.write_at(root)
+}
+
+/// Record index content as a new commit on top of HEAD and move the current branch, or
+/// HEAD itself when detached, to it.
+///
+/// Returns the new commit ID.
+pub fn commit<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    message: &str,
+    options: &CommitOptions,
+) -> Result<HashCode, GitError> {
+    let root = root.as_ref();
+    let config = GitConfig::read_at(root)?;
+
+    let index = Index::read_at(root)?;
+    if index.has_conflicts() {
+        return Err(GitError::invalid_content(
+            "Committing is not possible because you have unmerged files",
+        ));
+    }
+
+    let message = cleanup_message(message);
+    if message.is_empty() {
+        return Err(GitError::invalid_content(
+            "Aborting commit due to empty commit message",
+        ));
+    }
+
+    let head = resolve_ref_at(root, "HEAD")?;
+
+    let merge_heads = read_merge_heads(root)?;
+    if options.amend && !merge_heads.is_empty() {
+        return Err(GitError::invalid_content(
+            "You are in the middle of a merge -- cannot amend",
+        ));
+    }
+
+    let (mut parents, author) = match (options.amend, head) {
+        (true, Some(head)) => match store.read(head)? {
+            GitObject::Commit {
+                parents, author, ..
+            } => (parents, author),
+            _ => {
+                return Err(GitError::InvalidContent(format!(
+                    "HEAD {} is not a commit",
+                    hex::encode(head)
+                )))
+            }
+        },
+        (true, None) => return Err(GitError::invalid_content("You have nothing to amend")),
+        (false, head) => (head.into_iter().collect(), None),
+    };
+    let author = match author {
+        Some(author) => author,
+        None => Signature::from_env("AUTHOR", &config)?.to_string(),
+    };
+    let committer = Signature::from_env("COMMITTER", &config)?;
+
+    parents.extend(&merge_heads);
+
+    let tree = index.write_tree(store)?;
+    if !options.allow_empty && !options.amend {
+        let is_empty = match parents.as_slice() {
+            [] => index.entries.is_empty(),
+            [parent] => matches!(
+                store.read(*parent)?,
+                GitObject::Commit { tree: parent_tree, .. } if parent_tree == tree
+            ),
+            // Merge commits are recorded even when they do not change the tree.
+            _ => false,
+        };
+        if is_empty {
+            return Err(GitError::invalid_content("Nothing to commit"));
+        }
+    }
+
+    let hash_code = store.write(&GitObject::Commit {
+        tree,
+        parents: parents.clone(),
+        author: Some(author),
+        committer: Some(committer.to_string()),
+        message: message.clone(),
+    })?;
+
+    let kind = if options.amend {
+        "commit (amend)"
+    } else if !merge_heads.is_empty() {
+        "commit (merge)"
+    } else if parents.is_empty() {
+        "commit (initial)"
+    } else {
+        "commit"
+    };
+    let subject = message.lines().next().unwrap_or_default();
+    update_head_at(
+        root,
+        head,
+        hash_code,
+        &committer,
+        &format!("{kind}: {subject}"),
+    )?;
+
+    if !merge_heads.is_empty() {
+        clear_merge_state(root)?;
+    }
+    Ok(hash_code)
+}
+
+/// Commits being merged, from `MERGE_HEAD` left by a merge stopped on conflicts.
+pub fn read_merge_heads<P: AsRef<Path>>(root: P) -> Result<Vec<HashCode>, GitError> {
+    match fs::read_to_string(git_dir(root.as_ref()).join("MERGE_HEAD")) {
+        Ok(content) => content.lines().map(hash_code_text_to_array).collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Forget about a merge in progress.
+pub fn clear_merge_state<P: AsRef<Path>>(root: P) -> Result<(), GitError> {
+    let git_dir = git_dir(root.as_ref());
+    for name in ["MERGE_HEAD", "MERGE_MSG", "MERGE_MODE"] {
+        match fs::remove_file(git_dir.join(name)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Clean up a commit message like git `whitespace` mode: trailing spaces are stripped,
+/// runs of blank lines are collapsed and leading or trailing ones removed.
+pub fn cleanup_message(message: &str) -> String {
+    let mut output = String::new();
+    let mut pending_blank = false;
+
+    for line in message.lines().map(|x| x.trim_end()) {
+        if line.is_empty() {
+            pending_blank = !output.is_empty();
+            continue;
+        }
+        if pending_blank {
+            output.push('\n');
+            pending_blank = false;
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+
+    output.trim_end().to_string()
+}
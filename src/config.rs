@@ -0,0 +1,255 @@
+use std::{fmt, fs, io, path::Path};
+
+use crate::{fs_utils::git_dir, GitError};
+
+/// In memory representation of a `.git/config` file.
+///
+/// Sections are kept in file order so the config can be written back without
+/// shuffling user content around.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GitConfig {
+    pub sections: Vec<GitConfigSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitConfigSection {
+    pub name: String,
+    pub subsection: Option<String>,
+    pub entries: Vec<(String, String)>,
+}
+
+impl GitConfig {
+    pub fn read_at<P: AsRef<Path>>(root: P) -> Result<Self, GitError> {
+        match fs::read_to_string(git_dir(root.as_ref()).join("config")) {
+            Ok(content) => Self::parse(&content),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn write_at<P: AsRef<Path>>(&self, root: P) -> Result<(), GitError> {
+        fs::write(git_dir(root.as_ref()).join("config"), self.to_string())?;
+        Ok(())
+    }
+
+    pub fn parse(content: &str) -> Result<Self, GitError> {
+        let mut config = Self::default();
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            // Skip empty lines and comments.
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            // Parse section header like: [remote "origin"]
+            if let Some(header) = line.strip_prefix('[') {
+                let header = header
+                    .split_once(']')
+                    .map(|(x, _)| x)
+                    .ok_or_else(|| GitError::invalid_content("Unterminated config section"))?;
+
+                let (name, subsection) = match header.split_once(char::is_whitespace) {
+                    Some((name, sub)) => (
+                        name,
+                        Some(sub.trim().trim_matches('"').replace("\\\"", "\"")),
+                    ),
+                    // Legacy syntax: [branch.main]
+                    None => match header.split_once('.') {
+                        Some((name, sub)) => (name, Some(sub.to_string())),
+                        None => (header, None),
+                    },
+                };
+
+                config.sections.push(GitConfigSection {
+                    name: name.to_lowercase(),
+                    subsection,
+                    entries: Vec::new(),
+                });
+                continue;
+            }
+
+            // Parse key / value pair.
+            let section = config
+                .sections
+                .last_mut()
+                .ok_or_else(|| GitError::invalid_content("Config entry outside of section"))?;
+
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value)),
+                // A key without value is an implicit boolean.
+                None => (line, "true".to_string()),
+            };
+
+            section.entries.push((key.to_lowercase(), value));
+        }
+
+        Ok(config)
+    }
+
+    /// Get last value for key like `core.bare` or `remote.origin.url`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).last().copied()
+    }
+
+    /// Get every value for a multi-valued key like `remote.origin.fetch`.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return Vec::new();
+        };
+
+        self.sections
+            .iter()
+            .filter(|x| x.matches(&name, subsection))
+            .flat_map(|x| x.entries.iter())
+            .filter(|(k, _)| *k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    pub fn get_bool(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|x| {
+            matches!(
+                x.to_lowercase().as_str(),
+                "true" | "yes" | "on" | "1" | "" | "all"
+            )
+        })
+    }
+
+    /// Replace every value of `key` by `value`, creating the section if needed.
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.unset(key);
+        self.add(key, value);
+    }
+
+    /// Append a new value to `key`, keeping existing ones.
+    pub fn add(&mut self, key: &str, value: &str) {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return;
+        };
+
+        let position = self
+            .sections
+            .iter()
+            .rposition(|x| x.matches(&name, subsection));
+
+        let section = match position {
+            Some(position) => &mut self.sections[position],
+            None => {
+                self.sections.push(GitConfigSection {
+                    name,
+                    subsection: subsection.map(|x| x.to_string()),
+                    entries: Vec::new(),
+                });
+                self.sections.last_mut().expect("Section just pushed")
+            }
+        };
+
+        section.entries.push((key, value.to_string()));
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        let Some((name, subsection, key)) = split_key(key) else {
+            return;
+        };
+
+        for section in self
+            .sections
+            .iter_mut()
+            .filter(|x| x.matches(&name, subsection))
+        {
+            section.entries.retain(|(k, _)| *k != key);
+        }
+    }
+
+    pub fn remove_section(&mut self, name: &str, subsection: Option<&str>) {
+        let name = name.to_lowercase();
+        self.sections.retain(|x| !x.matches(&name, subsection));
+    }
+
+    pub fn rename_section(
+        &mut self,
+        name: &str,
+        subsection: Option<&str>,
+        new_subsection: Option<&str>,
+    ) {
+        let name = name.to_lowercase();
+        for section in self
+            .sections
+            .iter_mut()
+            .filter(|x| x.matches(&name, subsection))
+        {
+            section.subsection = new_subsection.map(|x| x.to_string());
+        }
+    }
+}
+
+impl GitConfigSection {
+    fn matches(&self, name: &str, subsection: Option<&str>) -> bool {
+        self.name == name && self.subsection.as_deref() == subsection
+    }
+}
+
+impl fmt::Display for GitConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for section in &self.sections {
+            match &section.subsection {
+                Some(sub) => writeln!(f, "[{} \"{}\"]", section.name, sub.replace('"', "\\\""))?,
+                None => writeln!(f, "[{}]", section.name)?,
+            }
+
+            for (key, value) in &section.entries {
+                writeln!(f, "\t{key} = {}", format_value(value))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn split_key(key: &str) -> Option<(String, Option<&str>, String)> {
+    let (name, rem) = key.split_once('.')?;
+    let (subsection, key) = match rem.rsplit_once('.') {
+        Some((subsection, key)) => (Some(subsection), key),
+        None => (None, rem),
+    };
+    Some((name.to_lowercase(), subsection, key.to_lowercase()))
+}
+
+fn parse_value(raw: &str) -> String {
+    let mut output = String::with_capacity(raw.len());
+    let mut in_quote = false;
+    let mut chars = raw.trim().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quote = !in_quote,
+            '\\' => match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('t') => output.push('\t'),
+                Some(x) => output.push(x),
+                None => {}
+            },
+            // Inline comment.
+            '#' | ';' if !in_quote => break,
+            _ => output.push(c),
+        }
+    }
+
+    output.trim_end().to_string()
+}
+
+fn format_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+        format!("\"{escaped}\"")
+    } else {
+        escaped
+    }
+}
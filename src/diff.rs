@@ -0,0 +1,667 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt, fs, io,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+
+use crate::{
+    header::GitObjectHeaderType,
+    ignore::IgnoreRules,
+    index::{worktree_mode, Index},
+    object::{encode_raw_object, GitObject},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+const MODE_TREE: u32 = 0o40000;
+const MODE_TYPE_MASK: u32 = 0o170000;
+
+/// Similarity score, in percent, above which a file is considered renamed by default.
+pub const DEFAULT_RENAME_THRESHOLD: u8 = 50;
+
+/// Above this many `added x deleted` pairs, only exact renames are detected.
+const RENAME_LIMIT: usize = 1_000_000;
+
+/// Flat view of files: path to `(mode, hash)`.
+pub type FileMap = BTreeMap<String, (u32, HashCode)>;
+
+/// One side of a file pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffSide {
+    pub path: String,
+    pub mode: u32,
+    pub hash_code: HashCode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffStatus {
+    Added,
+    Deleted,
+    Modified,
+    /// File changed kind, like a regular file becoming a symlink.
+    TypeChanged,
+    /// Renamed with given similarity percentage.
+    Renamed(u8),
+    /// Copied with given similarity percentage.
+    Copied(u8),
+}
+
+impl DiffStatus {
+    /// Status as printed by `--name-status`, like `M` or `R086`.
+    pub fn as_string(&self) -> String {
+        match self {
+            Self::Added => "A".to_string(),
+            Self::Deleted => "D".to_string(),
+            Self::Modified => "M".to_string(),
+            Self::TypeChanged => "T".to_string(),
+            Self::Renamed(score) => format!("R{score:03}"),
+            Self::Copied(score) => format!("C{score:03}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffEntry {
+    pub status: DiffStatus,
+    pub old: Option<DiffSide>,
+    pub new: Option<DiffSide>,
+}
+
+impl DiffEntry {
+    /// Destination path, or source path for deleted files.
+    pub fn path(&self) -> &str {
+        match (&self.new, &self.old) {
+            (Some(side), _) | (None, Some(side)) => &side.path,
+            (None, None) => "",
+        }
+    }
+
+    /// Format entry like `git diff-tree --name-status` does.
+    pub fn name_status(&self) -> String {
+        match (&self.status, &self.old, &self.new) {
+            (DiffStatus::Renamed(_) | DiffStatus::Copied(_), Some(old), Some(new)) => {
+                format!("{}\t{}\t{}", self.status.as_string(), old.path, new.path)
+            }
+            _ => format!("{}\t{}", self.status.as_string(), self.path()),
+        }
+    }
+}
+
+/// Raw format used by `git diff-tree`: `:100644 100644 <old> <new> M\tpath`.
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = |side: &Option<DiffSide>| side.as_ref().map_or(0, |x| x.mode);
+        let hash_code = |side: &Option<DiffSide>| side.as_ref().map_or([0; 20], |x| x.hash_code);
+
+        write!(
+            f,
+            ":{:06o} {:06o} {} {} {}",
+            mode(&self.old),
+            mode(&self.new),
+            hex::encode(hash_code(&self.old)),
+            hex::encode(hash_code(&self.new)),
+            self.name_status()
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffOptions {
+    /// Descend into sub trees instead of reporting them as a single entry.
+    pub recursive: bool,
+    pub detect_renames: bool,
+    pub detect_copies: bool,
+    /// Also look for copy sources in unmodified files.
+    pub find_copies_harder: bool,
+    /// Minimum similarity, in percent, to pair two files.
+    pub rename_threshold: u8,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            recursive: true,
+            detect_renames: false,
+            detect_copies: false,
+            find_copies_harder: false,
+            rename_threshold: DEFAULT_RENAME_THRESHOLD,
+        }
+    }
+}
+
+/// Parse similarity given to `-M` / `-C` like git: `90%`, `5` (meaning 50%) or `05`.
+pub fn parse_similarity(input: &str) -> Result<u8, GitError> {
+    let invalid = || GitError::InvalidContent(format!("Invalid similarity: {input:?}"));
+
+    if let Some(percent) = input.strip_suffix('%') {
+        let value: u8 = percent.parse().map_err(|_err| invalid())?;
+        return if value <= 100 {
+            Ok(value)
+        } else {
+            Err(invalid())
+        };
+    }
+
+    // Digits are the fractional part of a number: `5` is 0.5 and `05` is 0.05.
+    if input.is_empty() || !input.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let digits = format!("{input:0<2}");
+    digits[..2].parse().map_err(|_err| invalid())
+}
+
+/// Compare two trees, any of them being possibly missing (like for a root commit).
+pub fn diff_trees(
+    store: &ObjectStore,
+    old: Option<HashCode>,
+    new: Option<HashCode>,
+    options: &DiffOptions,
+) -> Result<Vec<DiffEntry>, GitError> {
+    let mut output = Vec::new();
+    diff_tree_items(store, "", old, new, options, &mut output)?;
+
+    if options.detect_renames || options.detect_copies {
+        let copy_sources = if options.find_copies_harder {
+            match old {
+                Some(old) => file_map_sides(&flatten_tree(store, old)?),
+                None => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+        output = detect_renames(output, copy_sources, options, &|side| {
+            Ok(store.read_raw(side.hash_code)?.1)
+        })?;
+    }
+
+    output.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(output)
+}
+
+fn diff_tree_items(
+    store: &ObjectStore,
+    prefix: &str,
+    old: Option<HashCode>,
+    new: Option<HashCode>,
+    options: &DiffOptions,
+    output: &mut Vec<DiffEntry>,
+) -> Result<(), GitError> {
+    let mut items: BTreeMap<String, [Option<(u32, HashCode)>; 2]> = BTreeMap::new();
+    for (idx, tree) in [old, new].into_iter().enumerate() {
+        let Some(tree) = tree else {
+            continue;
+        };
+        let GitObject::Tree(tree_items) = store.read(tree)? else {
+            return Err(GitError::InvalidContent(format!(
+                "{} is not a tree",
+                hex::encode(tree)
+            )));
+        };
+        for item in tree_items {
+            items.entry(item.name).or_default()[idx] = Some((item.mode, item.hash_code));
+        }
+    }
+
+    for (name, [old_item, new_item]) in items {
+        if old_item == new_item {
+            continue;
+        }
+        let path = format!("{prefix}{name}");
+
+        // Split each side in a sub tree to walk and a file to compare.
+        let split = |item: Option<(u32, HashCode)>| match item {
+            Some((MODE_TREE, hash_code)) if options.recursive => (Some(hash_code), None),
+            item => (None, item),
+        };
+        let (old_tree, old_file) = split(old_item);
+        let (new_tree, new_file) = split(new_item);
+
+        if let Some(entry) = compare_files(&path, old_file, new_file) {
+            output.push(entry);
+        }
+        if old_tree.is_some() || new_tree.is_some() {
+            diff_tree_items(
+                store,
+                &format!("{path}/"),
+                old_tree,
+                new_tree,
+                options,
+                output,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn compare_files(
+    path: &str,
+    old: Option<(u32, HashCode)>,
+    new: Option<(u32, HashCode)>,
+) -> Option<DiffEntry> {
+    let side = |(mode, hash_code): (u32, HashCode)| DiffSide {
+        path: path.to_string(),
+        mode,
+        hash_code,
+    };
+
+    let status = match (old, new) {
+        (None, None) => return None,
+        (Some(old), Some(new)) if old == new => return None,
+        (None, Some(_)) => DiffStatus::Added,
+        (Some(_), None) => DiffStatus::Deleted,
+        (Some((old_mode, _)), Some((new_mode, _)))
+            if old_mode & MODE_TYPE_MASK != new_mode & MODE_TYPE_MASK =>
+        {
+            DiffStatus::TypeChanged
+        }
+        (Some(_), Some(_)) => DiffStatus::Modified,
+    };
+
+    Some(DiffEntry {
+        status,
+        old: old.map(side),
+        new: new.map(side),
+    })
+}
+
+/// List every file of a tree, recursively.
+pub fn flatten_tree(store: &ObjectStore, tree: HashCode) -> Result<FileMap, GitError> {
+    let mut output = FileMap::new();
+    let mut stack = vec![(String::new(), tree)];
+
+    while let Some((prefix, tree)) = stack.pop() {
+        let GitObject::Tree(items) = store.read(tree)? else {
+            return Err(GitError::InvalidContent(format!(
+                "{} is not a tree",
+                hex::encode(tree)
+            )));
+        };
+        for item in items {
+            let path = format!("{prefix}{}", item.name);
+            if item.mode == MODE_TREE {
+                stack.push((format!("{path}/"), item.hash_code));
+            } else {
+                output.insert(path, (item.mode, item.hash_code));
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+/// Files of the index, ignoring conflicted entries.
+pub fn index_file_map(index: &Index) -> FileMap {
+    index
+        .entries
+        .iter()
+        .filter(|x| x.stage == 0)
+        .map(|x| (x.path.clone(), (x.mode, x.hash_code)))
+        .collect()
+}
+
+/// Compare two flat file lists.
+pub fn diff_file_maps(old: &FileMap, new: &FileMap) -> Vec<DiffEntry> {
+    let mut paths: Vec<_> = old.keys().chain(new.keys()).collect();
+    paths.sort();
+    paths.dedup();
+
+    paths
+        .into_iter()
+        .filter_map(|path| compare_files(path, old.get(path).copied(), new.get(path).copied()))
+        .collect()
+}
+
+/// Compare a tree (usually `HEAD` one) with the index: changes staged for commit.
+pub fn diff_tree_to_index(
+    store: &ObjectStore,
+    tree: Option<HashCode>,
+    index: &Index,
+    options: &DiffOptions,
+) -> Result<Vec<DiffEntry>, GitError> {
+    let old = match tree {
+        Some(tree) => flatten_tree(store, tree)?,
+        None => FileMap::new(),
+    };
+    let mut output = diff_file_maps(&old, &index_file_map(index));
+
+    if options.detect_renames || options.detect_copies {
+        let copy_sources = if options.find_copies_harder {
+            file_map_sides(&old)
+        } else {
+            Vec::new()
+        };
+        output = detect_renames(output, copy_sources, options, &|side| {
+            Ok(store.read_raw(side.hash_code)?.1)
+        })?;
+        output.sort_by(|a, b| a.path().cmp(b.path()));
+    }
+
+    Ok(output)
+}
+
+/// Compare the index with files on disk: changes not staged yet.
+pub fn diff_index_to_worktree<P: AsRef<Path>>(
+    root: P,
+    index: &Index,
+) -> Result<Vec<DiffEntry>, GitError> {
+    Ok(diff_file_maps(
+        &index_file_map(index),
+        &worktree_file_map(root, index)?,
+    ))
+}
+
+/// Files on disk that are tracked by the index.
+///
+/// Files whose stat information matches the index are not read again.
+pub fn worktree_file_map<P: AsRef<Path>>(root: P, index: &Index) -> Result<FileMap, GitError> {
+    let root = root.as_ref();
+    let mut output = FileMap::new();
+
+    for entry in index.entries.iter().filter(|x| x.stage == 0) {
+        let path = root.join(&entry.path);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if metadata.is_dir() {
+            continue;
+        }
+        if entry.is_stat_clean(&metadata) {
+            output.insert(entry.path.clone(), (entry.mode, entry.hash_code));
+        } else {
+            let mode = worktree_mode(&metadata);
+            output.insert(entry.path.clone(), (mode, hash_worktree_file(&path)?));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Compute blob ID of a worktree file (or symlink target) without storing it.
+pub fn hash_worktree_file(path: &Path) -> Result<HashCode, GitError> {
+    Ok(encode_raw_object(GitObjectHeaderType::Blob, &read_worktree_file(path)?).0)
+}
+
+/// Read file content the way it is stored in a blob.
+pub fn read_worktree_file(path: &Path) -> Result<Vec<u8>, GitError> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        Ok(fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+    } else {
+        Ok(fs::read(path)?)
+    }
+}
+
+/// List files of the worktree that are neither in the index nor ignored.
+pub fn untracked_files<P: AsRef<Path>>(root: P, index: &Index) -> Result<Vec<String>, GitError> {
+    let root = root.as_ref();
+    let mut ignore_rules = IgnoreRules::load_at(root)?;
+    let mut output = Vec::new();
+    let mut stack = vec![String::new()];
+
+    while let Some(prefix) = stack.pop() {
+        if !prefix.is_empty() {
+            ignore_rules.add_file(root.join(&prefix).join(".gitignore"), &prefix)?;
+        }
+
+        let mut dir_entries: Vec<_> = fs::read_dir(root.join(&prefix))?
+            .filter_map(|x| x.ok())
+            .collect();
+        dir_entries.sort_by_key(|x| x.file_name());
+
+        for dir_entry in dir_entries {
+            let name = dir_entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                continue;
+            }
+
+            let path = format!("{prefix}{name}");
+            let is_dir = dir_entry.file_type()?.is_dir();
+            if ignore_rules.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if is_dir {
+                stack.push(format!("{path}/"));
+            } else if (0..=3).all(|stage| index.find(&path, stage).is_none()) {
+                output.push(path);
+            }
+        }
+    }
+
+    output.sort();
+    Ok(output)
+}
+
+fn file_map_sides(files: &FileMap) -> Vec<DiffSide> {
+    files
+        .iter()
+        .map(|(path, (mode, hash_code))| DiffSide {
+            path: path.clone(),
+            mode: *mode,
+            hash_code: *hash_code,
+        })
+        .collect()
+}
+
+/// Pair deleted and added files with similar content as renames, and optionally
+/// find added files copied from another one.
+///
+/// `load` reads content of a side, either from the object store or from the worktree.
+pub fn detect_renames(
+    entries: Vec<DiffEntry>,
+    mut copy_sources: Vec<DiffSide>,
+    options: &DiffOptions,
+    load: &dyn Fn(&DiffSide) -> Result<Vec<u8>, GitError>,
+) -> Result<Vec<DiffEntry>, GitError> {
+    let mut output = Vec::with_capacity(entries.len());
+    let mut added = Vec::new();
+    let mut deleted = Vec::new();
+
+    for entry in entries {
+        match (entry.status, &entry.old, &entry.new) {
+            (DiffStatus::Added, _, Some(new)) if is_file(new.mode) => added.push(new.clone()),
+            (DiffStatus::Deleted, Some(old), _) if is_file(old.mode) => deleted.push(old.clone()),
+            (DiffStatus::Modified, Some(old), _) => {
+                if options.detect_copies && !options.find_copies_harder {
+                    copy_sources.push(old.clone());
+                }
+                output.push(entry);
+            }
+            _ => output.push(entry),
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize, u8)> = Vec::new();
+    let mut added_used = vec![false; added.len()];
+    let mut deleted_used = vec![false; deleted.len()];
+
+    // Exact renames first, preferring a source with the same file name.
+    for (added_idx, new) in added.iter().enumerate() {
+        let candidates = deleted
+            .iter()
+            .enumerate()
+            .filter(|(idx, old)| !deleted_used[*idx] && old.hash_code == new.hash_code);
+        let best = candidates
+            .max_by_key(|(_, old)| file_name(&old.path) == file_name(&new.path))
+            .map(|(idx, _)| idx);
+
+        if let Some(deleted_idx) = best {
+            added_used[added_idx] = true;
+            deleted_used[deleted_idx] = true;
+            pairs.push((added_idx, deleted_idx, 100));
+        }
+    }
+
+    // Then inexact ones, best scores first.
+    let remaining_pairs =
+        added_used.iter().filter(|x| !**x).count() * deleted_used.iter().filter(|x| !**x).count();
+    if options.rename_threshold < 100 && remaining_pairs > 0 && remaining_pairs <= RENAME_LIMIT {
+        let mut added_signatures = HashMap::new();
+        for (idx, side) in added.iter().enumerate().filter(|(x, _)| !added_used[*x]) {
+            added_signatures.insert(idx, Signature::new(&load(side)?));
+        }
+
+        let mut candidates = Vec::new();
+        for (deleted_idx, old) in deleted
+            .iter()
+            .enumerate()
+            .filter(|(x, _)| !deleted_used[*x])
+        {
+            let old_signature = Signature::new(&load(old)?);
+            for (added_idx, new_signature) in &added_signatures {
+                let score = old_signature.similarity(new_signature);
+                if score >= options.rename_threshold {
+                    candidates.push((*added_idx, deleted_idx, score));
+                }
+            }
+        }
+
+        candidates.sort_by(|a, b| {
+            b.2.cmp(&a.2)
+                .then_with(|| added[a.0].path.cmp(&added[b.0].path))
+                .then_with(|| deleted[a.1].path.cmp(&deleted[b.1].path))
+        });
+        for (added_idx, deleted_idx, score) in candidates {
+            if !added_used[added_idx] && !deleted_used[deleted_idx] {
+                added_used[added_idx] = true;
+                deleted_used[deleted_idx] = true;
+                pairs.push((added_idx, deleted_idx, score));
+            }
+        }
+    }
+
+    for (added_idx, deleted_idx, score) in pairs {
+        output.push(DiffEntry {
+            status: DiffStatus::Renamed(score),
+            old: Some(deleted[deleted_idx].clone()),
+            new: Some(added[added_idx].clone()),
+        });
+    }
+
+    // Copies can come from any source, including already renamed ones.
+    if options.detect_copies {
+        copy_sources.extend(deleted.iter().cloned());
+        copy_sources.retain(|x| is_file(x.mode));
+
+        let mut source_signatures = Vec::with_capacity(copy_sources.len());
+        for source in &copy_sources {
+            source_signatures.push(Signature::new(&load(source)?));
+        }
+
+        for (added_idx, new) in added.iter().enumerate() {
+            if added_used[added_idx] {
+                continue;
+            }
+
+            let mut best: Option<(u8, usize)> = None;
+            if let Some(idx) = copy_sources
+                .iter()
+                .position(|x| x.hash_code == new.hash_code)
+            {
+                best = Some((100, idx));
+            } else if options.rename_threshold < 100 && !copy_sources.is_empty() {
+                let new_signature = Signature::new(&load(new)?);
+                for (idx, source_signature) in source_signatures.iter().enumerate() {
+                    let score = source_signature.similarity(&new_signature);
+                    if score >= options.rename_threshold && best.map_or(true, |x| score > x.0) {
+                        best = Some((score, idx));
+                    }
+                }
+            }
+
+            if let Some((score, idx)) = best {
+                added_used[added_idx] = true;
+                output.push(DiffEntry {
+                    status: DiffStatus::Copied(score),
+                    old: Some(copy_sources[idx].clone()),
+                    new: Some(new.clone()),
+                });
+            }
+        }
+    }
+
+    for (idx, side) in added.into_iter().enumerate() {
+        if !added_used[idx] {
+            output.push(DiffEntry {
+                status: DiffStatus::Added,
+                old: None,
+                new: Some(side),
+            });
+        }
+    }
+    for (idx, side) in deleted.into_iter().enumerate() {
+        if !deleted_used[idx] {
+            output.push(DiffEntry {
+                status: DiffStatus::Deleted,
+                old: Some(side),
+                new: None,
+            });
+        }
+    }
+
+    Ok(output)
+}
+
+fn is_file(mode: u32) -> bool {
+    matches!(mode & MODE_TYPE_MASK, 0o100000 | 0o120000)
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Content fingerprint used to estimate similarity of two files.
+///
+/// Content is cut in chunks ending at each new line (or every 64 bytes for long lines), and
+/// similarity is the amount of bytes found in common chunks, like git does.
+struct Signature {
+    len: usize,
+    chunks: HashMap<u64, usize>,
+}
+
+impl Signature {
+    fn new(content: &[u8]) -> Self {
+        let mut chunks = HashMap::new();
+        let mut start = 0;
+
+        for (idx, byte) in content.iter().enumerate() {
+            if *byte == b'\n' || idx + 1 - start == 64 {
+                *chunks.entry(fnv1a(&content[start..=idx])).or_default() += idx + 1 - start;
+                start = idx + 1;
+            }
+        }
+        if start < content.len() {
+            *chunks.entry(fnv1a(&content[start..])).or_default() += content.len() - start;
+        }
+
+        Self {
+            len: content.len(),
+            chunks,
+        }
+    }
+
+    /// Similarity in percent.
+    fn similarity(&self, other: &Self) -> u8 {
+        let max_len = self.len.max(other.len);
+        if max_len == 0 {
+            return 100;
+        }
+
+        let shared: usize = self
+            .chunks
+            .iter()
+            .filter_map(|(hash, len)| other.chunks.get(hash).map(|x| *x.min(len)))
+            .sum();
+        (shared * 100 / max_len) as u8
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
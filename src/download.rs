@@ -0,0 +1,185 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::{Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    fs_utils::{git_dir, temp_path_in},
+    packet_line::PacketLine,
+    sideband::{Demuxer, Progress},
+    GitError,
+};
+
+/// Bytes read at once from a stream.
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Server response pulled chunk by chunk, so a pack is never held whole in memory.
+pub struct ResponseReader {
+    body: Body,
+    buffer: BytesMut,
+}
+
+/// Where a response comes from.
+enum Body {
+    Http(reqwest::Response),
+    /// Output of a process or socket, carrying every response of a connection.
+    Stream(Box<dyn AsyncRead + Send + Unpin>),
+}
+
+impl ResponseReader {
+    pub fn new(response: reqwest::Response) -> Self {
+        Self {
+            body: Body::Http(response),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    pub fn from_stream<R: AsyncRead + Send + Unpin + 'static>(stream: R) -> Self {
+        Self {
+            body: Body::Stream(Box::new(stream)),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Append the next chunk of the body to the buffer, returning `false` at its end.
+    async fn read_chunk(&mut self) -> Result<bool, GitError> {
+        match &mut self.body {
+            Body::Http(response) => match response.chunk().await? {
+                Some(chunk) => {
+                    self.buffer.extend_from_slice(&chunk);
+                    Ok(true)
+                }
+                None => Ok(false),
+            },
+            Body::Stream(stream) => {
+                self.buffer.reserve(STREAM_CHUNK_SIZE);
+                Ok(stream.read_buf(&mut self.buffer).await? > 0)
+            }
+        }
+    }
+
+    /// Buffer at least `len` bytes, returning `false` if the body ends before.
+    async fn fill(&mut self, len: usize) -> Result<bool, GitError> {
+        while self.buffer.len() < len {
+            if !self.read_chunk().await? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read the next packet line along with its raw bytes.
+    async fn read_packet(&mut self) -> Result<(PacketLine, Bytes), GitError> {
+        let truncated = || GitError::Http("Truncated response".to_string());
+
+        if !self.fill(4).await? {
+            return Err(truncated());
+        }
+        let len = usize::from_str_radix(std::str::from_utf8(&self.buffer[..4])?, 16)?;
+        // Flush and delimiter packets are only made of their length.
+        let len = len.max(4);
+        if !self.fill(len).await? {
+            return Err(truncated());
+        }
+
+        let raw = self.buffer.split_to(len).freeze();
+        let line = PacketLine::read(&mut raw.as_ref())?;
+        Ok((line, raw))
+    }
+
+    /// Read packet lines up to the one `is_last` accepts and return their raw bytes, for
+    /// parsing the small part of a response preceding a pack.
+    pub async fn read_head(
+        &mut self,
+        mut is_last: impl FnMut(&PacketLine) -> bool,
+    ) -> Result<Vec<u8>, GitError> {
+        let mut output = Vec::new();
+        loop {
+            let (line, raw) = self.read_packet().await?;
+            output.extend_from_slice(&raw);
+            if is_last(&line) {
+                return Ok(output);
+            }
+        }
+    }
+
+    /// Write the pack making the rest of the response to `sink`, demultiplexing
+    /// side-band packets up to a flush when `side_band` is set.
+    pub async fn receive_pack<W: Write>(
+        &mut self,
+        side_band: bool,
+        sink: W,
+        progress: &mut dyn FnMut(Progress),
+    ) -> Result<W, GitError> {
+        if side_band {
+            let mut demuxer = Demuxer::new(sink, progress);
+            while let (PacketLine::Command(data), _) = self.read_packet().await? {
+                demuxer.packet(&data)?;
+            }
+            return demuxer.finish();
+        }
+
+        let mut sink = sink;
+        let mut received = 0;
+        loop {
+            sink.write_all(&self.buffer)?;
+            received += self.buffer.len() as u64;
+            self.buffer.clear();
+            progress(Progress::Received {
+                bytes: received,
+                done: false,
+            });
+            if !self.read_chunk().await? {
+                break;
+            }
+        }
+        progress(Progress::Received {
+            bytes: received,
+            done: true,
+        });
+        sink.flush()?;
+        Ok(sink)
+    }
+}
+
+/// Temporary pack file in `.git/objects/pack`, removed when dropped.
+pub struct TempPack {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempPack {
+    pub fn create<P: AsRef<Path>>(root: P) -> Result<Self, GitError> {
+        let dir = git_dir(root.as_ref()).join("objects/pack");
+        fs::create_dir_all(&dir)?;
+        let path = temp_path_in(&dir, "tmp_pack_");
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    /// Buffered writer appending to the pack.
+    pub fn writer(&self) -> BufWriter<&File> {
+        BufWriter::new(&self.file)
+    }
+
+    /// Buffered reader from the start of the pack.
+    pub fn reader(&self) -> io::Result<BufReader<&File>> {
+        let mut file = &self.file;
+        file.rewind()?;
+        Ok(BufReader::new(file))
+    }
+}
+
+impl Drop for TempPack {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -2,6 +2,8 @@ use std::{fmt, io, num::ParseIntError, str::Utf8Error, string::FromUtf8Error};
 
 use thiserror::Error;
 
+use crate::HashCode;
+
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum GitError {
     #[error("I/O: {0}")]
@@ -10,6 +12,14 @@ pub enum GitError {
     #[error("HTTP: {0}")]
     Http(String),
 
+    /// Failure to reach a remote repository other than over HTTP.
+    #[error("Transport: {0}")]
+    Transport(String),
+
+    /// Error reported by the server on side-band channel 3.
+    #[error("Remote: {0}")]
+    Remote(String),
+
     #[error("No HEAD ref found")]
     NoHead,
 
@@ -21,6 +31,34 @@ pub enum GitError {
 
     #[error("Invalid object payload: {0}")]
     InvalidObjectPayload(&'static str),
+
+    #[error("Invalid hash code: {0:?}")]
+    InvalidHashCode(String),
+
+    #[error("Object not found: {0}")]
+    MissingObject(String),
+
+    #[error("Invalid delta: {0}")]
+    InvalidDelta(&'static str),
+
+    #[error("Unsupported pack object type: {0}")]
+    UnsupportedObjectType(u8),
+
+    #[error("Unknown revision: {0}")]
+    UnknownRevision(String),
+
+    #[error("Local changes would be overwritten: {}", .0.join(", "))]
+    WouldOverwrite(Vec<String>),
+
+    #[error("Invalid tree entry name: {0:?}")]
+    InvalidPath(String),
+
+    #[error("Pack entry {index} at offset {offset}: {source}")]
+    PackEntry {
+        index: u32,
+        offset: u64,
+        source: Box<GitError>,
+    },
 }
 
 impl GitError {
@@ -31,6 +69,19 @@ impl GitError {
     pub fn invalid_content(msg: &str) -> Self {
         Self::InvalidContent(msg.to_string())
     }
+
+    pub fn missing_object(hash_code: HashCode) -> Self {
+        Self::MissingObject(hex::encode(hash_code))
+    }
+
+    /// Attach pack position to an error raised while decoding a pack entry.
+    pub fn in_pack_entry(self, index: u32, offset: u64) -> Self {
+        Self::PackEntry {
+            index,
+            offset,
+            source: Box::new(self),
+        }
+    }
 }
 
 impl From<io::Error> for GitError {
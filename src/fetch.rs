@@ -0,0 +1,941 @@
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fs, io,
+    path::Path,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    branch::{is_ancestor, read_upstream},
+    clone::{Advertisement, ProtocolVersion},
+    config::GitConfig,
+    download::TempPack,
+    fs_utils::git_dir,
+    hash_code_text_to_array,
+    log::CommitInfo,
+    pack_file::unpack_into,
+    packet_line::PacketLine,
+    promisor::ObjectFilter,
+    refs::{
+        append_reflog_at, head_branch_at, is_valid_ref_name, list_refs_at, resolve_ref_at,
+        write_ref_at, RefValue,
+    },
+    remote::{expand_short_name, short_ref_name, Remote},
+    revision::peel_to_commit,
+    shallow::{read_shallow, update_shallow, write_request_lines, ShallowOptions, ShallowUpdate},
+    sideband::Progress,
+    signature::Signature,
+    store::ObjectStore,
+    transport::Connection,
+    GitError, HashCode,
+};
+
+/// Number of `have` lines sent in the first negotiation round, doubled at each round.
+const INITIAL_FLUSH: usize = 16;
+const MAX_FLUSH: usize = 1024;
+/// Stop negotiation after this many `have` lines without a new common commit.
+const MAX_IN_VAIN: usize = 256;
+/// Capabilities we ask for when the server supports them.
+const WANTED_CAPABILITIES: [&str; 5] = [
+    "multi_ack_detailed",
+    "side-band-64k",
+    "thin-pack",
+    "ofs-delta",
+    "include-tag",
+];
+/// Arguments of protocol v2 `fetch` commands, which servers always support.
+const FETCH_ARGUMENTS: [&str; 3] = ["thin-pack", "ofs-delta", "include-tag"];
+
+/// Remote ref selected by a refspec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRef {
+    pub remote_ref: String,
+    pub id: HashCode,
+    /// Local ref to update, `None` when only recorded in `FETCH_HEAD`.
+    pub local_ref: Option<String>,
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefUpdateStatus {
+    New,
+    UpToDate,
+    FastForward,
+    Forced,
+    /// Not a fast-forward and not forced: local ref is left as it was.
+    Rejected,
+}
+
+/// What happened to a local ref after a fetch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub remote_ref: String,
+    pub local_ref: Option<String>,
+    pub old: Option<HashCode>,
+    pub new: HashCode,
+    pub status: RefUpdateStatus,
+}
+
+/// Server answer to `have` lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ack {
+    Common(HashCode),
+    Ready(HashCode),
+    Continue(HashCode),
+    /// Last answer after `done`: pack data follows.
+    Final(HashCode),
+    Nak,
+}
+
+/// Sections of a protocol v2 `fetch` response.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FetchResponse {
+    /// Commits of our `have` lines the server has.
+    pub common: Vec<HashCode>,
+    /// Server has enough to send a pack without more `have` lines.
+    pub ready: bool,
+    pub shallow_update: ShallowUpdate,
+    /// A `packfile` section follows, once negotiation is over.
+    pub has_pack: bool,
+}
+
+/// Fetch refs selected by `remote` refspecs, download objects we miss and update local
+/// refs and `FETCH_HEAD`.
+///
+/// Local commits are advertised with `have` lines so the server only sends what is missing,
+/// possibly as a thin pack whose deltas are resolved against local objects.
+///
+/// History can be limited by `shallow`, the boundary being kept in `.git/shallow`. The
+/// pack is written to a temporary file as it arrives, progress being passed to `progress`.
+pub async fn fetch<P: AsRef<Path>>(
+    root: P,
+    remote: &Remote,
+    shallow: &ShallowOptions,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<RefUpdate>, GitError> {
+    let (mut connection, advertisement) =
+        Connection::upload_pack(&remote.url, &ref_prefixes(remote)).await?;
+    let updates = fetch_advertised(
+        root,
+        remote,
+        Some(&mut connection),
+        &advertisement,
+        shallow,
+        progress,
+    )
+    .await?;
+    connection.close().await?;
+    Ok(updates)
+}
+
+/// Like `fetch`, with refs already listed in `advertisement` by `connection`.
+///
+/// Without connection, every wanted object must be present already, like after copying
+/// objects of a local repository.
+pub async fn fetch_advertised<P: AsRef<Path>>(
+    root: P,
+    remote: &Remote,
+    connection: Option<&mut Connection>,
+    advertisement: &Advertisement,
+    shallow: &ShallowOptions,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<RefUpdate>, GitError> {
+    let root = root.as_ref();
+    let remote_refs = advertisement
+        .refs
+        .iter()
+        .filter(|x| !x.name.ends_with("^{}"))
+        .map(|x| Ok((x.name.clone(), hash_code_text_to_array(&x.object_id)?)))
+        .collect::<Result<Vec<_>, GitError>>()?;
+
+    let mut store = ObjectStore::open(root)?;
+    let mut selected = select_refs(remote, &remote_refs)?;
+
+    // Moving the shallow boundary needs history of tips we may already have.
+    let mut wants = Vec::new();
+    for fetch_ref in &selected {
+        if (shallow.is_deepening() || !store.contains(fetch_ref.id))
+            && !wants.contains(&fetch_ref.id)
+        {
+            wants.push(fetch_ref.id);
+        }
+    }
+
+    if !wants.is_empty() {
+        let connection = connection.ok_or_else(|| {
+            GitError::Transport(format!("No connection to fetch from {}", remote.url))
+        })?;
+        let request = PackRequest {
+            wants: &wants,
+            tips: local_tips(root, &store)?,
+            shallow,
+            filter: remote.partial_clone_filter,
+        };
+        let (pack, shallow_update) =
+            fetch_pack(root, connection, &store, &request, advertisement, progress).await?;
+        unpack_into(pack.reader()?, root)?;
+        update_shallow(root, &shallow_update)?;
+        store = ObjectStore::open(root)?;
+
+        if let Some(missing) = wants.iter().find(|x| !store.contains(**x)) {
+            return Err(GitError::missing_object(*missing));
+        }
+    }
+
+    // Like git, follow tags sent along with fetched history thanks to `include-tag`,
+    // unless only `FETCH_HEAD` is updated.
+    if remote.fetch.iter().any(|x| x.dst.is_some()) {
+        for (name, id) in &remote_refs {
+            let is_new_tag = name.starts_with("refs/tags/")
+                && is_valid_ref_name(name)
+                && !selected.iter().any(|x| x.remote_ref == *name)
+                && resolve_ref_at(root, name)?.is_none();
+            if is_new_tag && store.contains(*id) {
+                selected.push(FetchRef {
+                    remote_ref: name.clone(),
+                    id: *id,
+                    local_ref: Some(name.clone()),
+                    force: false,
+                });
+            }
+        }
+    }
+
+    update_refs(root, &store, remote, &selected)
+}
+
+/// Fetch objects `ids`, whatever their type, from the promisor `remote` of a partial clone.
+///
+/// Like git, nothing is negotiated and only objects asked for or their trees are sent.
+pub async fn fetch_objects<P: AsRef<Path>>(
+    root: P,
+    remote: &Remote,
+    ids: &[HashCode],
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let (mut connection, advertisement) =
+        Connection::open(&remote.url, "git-upload-pack", ProtocolVersion::V2).await?;
+    let store = ObjectStore::open(root)?;
+    let request = PackRequest {
+        wants: ids,
+        tips: Vec::new(),
+        shallow: &ShallowOptions::default(),
+        filter: Some(ObjectFilter::BlobNone),
+    };
+    let (pack, _) = fetch_pack(
+        root,
+        &mut connection,
+        &store,
+        &request,
+        &advertisement,
+        progress,
+    )
+    .await?;
+    connection.close().await?;
+    unpack_into(pack.reader()?, root)?;
+
+    let store = ObjectStore::open(root)?;
+    match ids.iter().find(|x| !store.contains(**x)) {
+        Some(missing) => Err(GitError::missing_object(*missing)),
+        None => Ok(()),
+    }
+}
+
+/// Match advertised `(name, id)` refs against `remote` refspecs.
+pub fn select_refs(
+    remote: &Remote,
+    remote_refs: &[(String, HashCode)],
+) -> Result<Vec<FetchRef>, GitError> {
+    let mut output: Vec<FetchRef> = Vec::new();
+
+    for refspec in &remote.fetch {
+        let mut found = false;
+        for (name, id) in remote_refs {
+            if !refspec.matches(name) {
+                continue;
+            }
+            // Names come from the server: like git, ignore those which could escape
+            // `refs/` once written.
+            let local_ref = refspec.map(name);
+            if !is_valid_ref_name(name)
+                || local_ref.as_deref().is_some_and(|x| !is_valid_ref_name(x))
+            {
+                continue;
+            }
+            found = true;
+
+            let is_duplicate = output.iter().any(|x| {
+                x.remote_ref == *name && (x.local_ref == local_ref || local_ref.is_none())
+            });
+            if !is_duplicate {
+                output.push(FetchRef {
+                    remote_ref: name.clone(),
+                    id: *id,
+                    local_ref,
+                    force: refspec.force,
+                });
+            }
+            // An exact refspec selects the first ref it expands to.
+            if !refspec.is_glob() {
+                break;
+            }
+        }
+
+        if !found && !refspec.is_glob() {
+            return Err(GitError::InvalidContent(format!(
+                "couldn't find remote ref {}",
+                refspec.src
+            )));
+        }
+    }
+
+    Ok(output)
+}
+
+/// Prefixes of remote refs `remote` refspecs may select, so a v2 server lists only them.
+pub fn ref_prefixes(remote: &Remote) -> Vec<String> {
+    let mut output = Vec::new();
+    for refspec in &remote.fetch {
+        let prefixes = match refspec.src.split_once('*') {
+            Some((prefix, _)) => vec![prefix.to_string()],
+            None => expand_short_name(&refspec.src),
+        };
+        output.extend(prefixes);
+    }
+    // Tags are followed when local refs are updated.
+    if remote.fetch.iter().any(|x| x.dst.is_some()) {
+        output.push("refs/tags/".to_string());
+    }
+
+    let mut seen = HashSet::new();
+    output.retain(|x| seen.insert(x.clone()));
+    output
+}
+
+/// Commits local refs point to, starting points of `have` lines.
+fn local_tips(root: &Path, store: &ObjectStore) -> Result<Vec<HashCode>, GitError> {
+    let mut output = Vec::new();
+    let refs = list_refs_at(root)?.into_iter().map(|(_, id)| id);
+    for id in resolve_ref_at(root, "HEAD")?.into_iter().chain(refs) {
+        // Tags may point to trees or blobs, which are not negotiated.
+        if let Ok(commit) = peel_to_commit(store, id) {
+            if !output.contains(&commit) {
+                output.push(commit);
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// What `fetch_pack` asks the server for.
+struct PackRequest<'a> {
+    wants: &'a [HashCode],
+    /// Local commits offered in `have` lines, none to get every wanted object.
+    tips: Vec<HashCode>,
+    shallow: &'a ShallowOptions,
+    /// Objects to leave out, ignored by servers without the `filter` feature like git does.
+    filter: Option<ObjectFilter>,
+}
+
+/// Negotiate with the server which objects to send and download the pack in a temporary
+/// file of `root`, along with the change of the shallow boundary.
+///
+/// Over a stateless connection like smart HTTP, every request repeats `want` lines,
+/// `shallow` lines and commits already known to be common, then adds a new batch of
+/// `have` lines. Otherwise, protocol v0 only sends them once, then `have` batches, then
+/// `done`. With protocol v2, the pack comes as soon as the server is ready.
+async fn fetch_pack(
+    root: &Path,
+    connection: &mut Connection,
+    store: &ObjectStore,
+    request: &PackRequest<'_>,
+    advertisement: &Advertisement,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(TempPack, ShallowUpdate), GitError> {
+    let version = advertisement.version;
+    let wants = request.wants;
+    let mut capabilities: Vec<_> = WANTED_CAPABILITIES
+        .into_iter()
+        .filter(|x| advertisement.has_capability(x))
+        .collect();
+
+    // A shallow repository tells its boundary even when not moving it.
+    let shallow = request.shallow;
+    let shallow_lines = shallow.request_lines(&read_shallow(root)?, version);
+    if !shallow_lines.is_empty() {
+        check_shallow_support(advertisement, shallow)?;
+        if version == ProtocolVersion::V0 {
+            capabilities.push("shallow");
+            capabilities.extend(shallow.capabilities());
+        }
+    }
+
+    let mut request_lines = shallow_lines.clone();
+    if let Some(filter) = request.filter.filter(|_| supports(advertisement, "filter")) {
+        request_lines.push(format!("filter {filter}"));
+        if version == ProtocolVersion::V0 {
+            capabilities.push("filter");
+        }
+    }
+
+    let tips = &request.tips;
+    let stateless = connection.is_stateless();
+    let mut common = Vec::new();
+    // Whether wants were sent on a stateful connection, along with a first `have` batch.
+    let mut wants_sent = false;
+    // Sent by a v0 server in answer to wants.
+    let mut shallow_update = ShallowUpdate::default();
+
+    // Without multi_ack_detailed, we could not tell which commits are common: ask for
+    // everything.
+    if version == ProtocolVersion::V2 || capabilities.contains(&"multi_ack_detailed") {
+        let mut negotiator = Negotiator::new(store, tips)?;
+        let mut batch_size = INITIAL_FLUSH;
+        let mut in_vain = 0;
+
+        loop {
+            // A v0 server keeping state already knows common commits.
+            let mut haves = match stateless || version == ProtocolVersion::V2 {
+                true => common.clone(),
+                false => Vec::new(),
+            };
+            let known = haves.len();
+            while haves.len() - known < batch_size {
+                match negotiator.next_have()? {
+                    Some(id) => haves.push(id),
+                    None => break,
+                }
+            }
+            let sent = haves.len() - known;
+            if sent == 0 {
+                break;
+            }
+
+            let (acked, is_ready) = match version {
+                ProtocolVersion::V0 => {
+                    let body = if wants_sent {
+                        haves_request(&haves)?
+                    } else {
+                        upload_pack_request(wants, &capabilities, &request_lines, &haves, false)?
+                    };
+                    let reader = connection.request(body).await?;
+                    if !shallow_lines.is_empty() && !wants_sent {
+                        let head = reader.read_head(|x| *x == PacketLine::End).await?;
+                        shallow_update = ShallowUpdate::read(&mut head.as_slice())?;
+                    }
+                    wants_sent = !stateless;
+                    let head = reader.read_head(ends_acks).await?;
+                    let mut acked = Vec::new();
+                    let mut is_ready = false;
+                    for ack in read_acks(&mut head.as_slice())? {
+                        match ack {
+                            Ack::Common(id) | Ack::Continue(id) | Ack::Ready(id) => {
+                                acked.push(id);
+                                is_ready |= matches!(ack, Ack::Ready(_));
+                            }
+                            Ack::Final(_) | Ack::Nak => {}
+                        }
+                    }
+                    (acked, is_ready)
+                }
+                ProtocolVersion::V2 => {
+                    let body = fetch_request(wants, &request_lines, &haves, false)?;
+                    let (response, pack) =
+                        send_fetch_command(connection, root, body, progress).await?;
+                    if let Some(pack) = pack {
+                        return Ok((pack, response.shallow_update));
+                    }
+                    (response.common, response.ready)
+                }
+            };
+
+            in_vain += sent;
+            for id in acked {
+                if negotiator.ack(id)? {
+                    common.push(id);
+                    in_vain = 0;
+                }
+            }
+
+            if is_ready || sent < batch_size || (!common.is_empty() && in_vain >= MAX_IN_VAIN) {
+                break;
+            }
+            batch_size = (batch_size * 2).min(MAX_FLUSH);
+        }
+    }
+
+    match version {
+        ProtocolVersion::V0 => {
+            let body = if wants_sent {
+                let mut body = Vec::new();
+                PacketLine::done().write(&mut body)?;
+                body
+            } else {
+                upload_pack_request(wants, &capabilities, &request_lines, &common, true)?
+            };
+            let reader = connection.request(body).await?;
+            if !shallow_lines.is_empty() && !wants_sent {
+                let head = reader.read_head(|x| *x == PacketLine::End).await?;
+                shallow_update = ShallowUpdate::read(&mut head.as_slice())?;
+            }
+            let head = reader.read_head(ends_acks).await?;
+            read_acks(&mut head.as_slice())?;
+
+            let pack = TempPack::create(root)?;
+            let side_band = capabilities.contains(&"side-band-64k");
+            reader
+                .receive_pack(side_band, pack.writer(), progress)
+                .await?;
+            Ok((pack, shallow_update))
+        }
+        ProtocolVersion::V2 => {
+            let body = fetch_request(wants, &request_lines, &common, true)?;
+            let (response, pack) = send_fetch_command(connection, root, body, progress).await?;
+            let pack =
+                pack.ok_or_else(|| GitError::Http("Missing packfile section".to_string()))?;
+            Ok((pack, response.shallow_update))
+        }
+    }
+}
+
+/// Fail unless the server can move the shallow boundary as asked by `shallow`.
+fn check_shallow_support(
+    advertisement: &Advertisement,
+    shallow: &ShallowOptions,
+) -> Result<(), GitError> {
+    let unsupported = |what: &str| Err(GitError::Http(format!("Server does not support {what}")));
+
+    if !supports(advertisement, "shallow") {
+        return unsupported("shallow clients");
+    }
+    // With protocol v2, every deepen argument comes with the `shallow` feature.
+    if advertisement.version == ProtocolVersion::V0 {
+        if let Some(capability) = shallow
+            .capabilities()
+            .into_iter()
+            .find(|x| !advertisement.has_capability(x))
+        {
+            return unsupported(capability);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the server supports `feature`: a capability with protocol v0, a feature of
+/// the `fetch` command with protocol v2.
+pub(crate) fn supports(advertisement: &Advertisement, feature: &str) -> bool {
+    match advertisement.version {
+        ProtocolVersion::V0 => advertisement.has_capability(feature),
+        ProtocolVersion::V2 => advertisement.capabilities.iter().any(|x| {
+            x.strip_prefix("fetch=")
+                .is_some_and(|x| x.split(' ').any(|x| x == feature))
+        }),
+    }
+}
+
+/// Send a protocol v2 `fetch` command and download the pack if the server sends one.
+async fn send_fetch_command(
+    connection: &mut Connection,
+    root: &Path,
+    body: Vec<u8>,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(FetchResponse, Option<TempPack>), GitError> {
+    let reader = connection.request(body).await?;
+    let head = reader
+        .read_head(|x| *x == PacketLine::End || *x == PacketLine::command(b"packfile\n"))
+        .await?;
+    let response = read_fetch_response(&mut head.as_slice())?;
+    if !response.has_pack {
+        return Ok((response, None));
+    }
+
+    let pack = TempPack::create(root)?;
+    reader.receive_pack(true, pack.writer(), progress).await?;
+    Ok((response, Some(pack)))
+}
+
+/// Whether `line` ends acknowledgements of a `have` batch, or of `done` with pack data
+/// following it.
+fn ends_acks(line: &PacketLine) -> bool {
+    let PacketLine::Command(data) = line else {
+        return true;
+    };
+    let text = String::from_utf8_lossy(data);
+    let text = text.trim_end();
+    text == "NAK"
+        || text.starts_with("ERR ")
+        || text.strip_prefix("ACK ").is_some_and(|x| !x.contains(' '))
+}
+
+/// Build an upload-pack request: `want` lines, the first one carrying `capabilities`,
+/// then `request_lines` like `shallow`, `deepen` or `filter`, then `have` lines, then
+/// either `done` or a flush asking for acknowledgements.
+pub fn upload_pack_request(
+    wants: &[HashCode],
+    capabilities: &[&str],
+    request_lines: &[String],
+    haves: &[HashCode],
+    done: bool,
+) -> Result<Vec<u8>, GitError> {
+    let mut output = Vec::new();
+
+    for (idx, want) in wants.iter().enumerate() {
+        let mut line = format!("want {}", hex::encode(want));
+        if idx == 0 && !capabilities.is_empty() {
+            line.push(' ');
+            line.push_str(&capabilities.join(" "));
+        }
+        PacketLine::Command(Bytes::from(line)).write(&mut output)?;
+    }
+    write_request_lines(&mut output, request_lines)?;
+    PacketLine::End.write(&mut output)?;
+
+    for have in haves {
+        PacketLine::have(&hex::encode(have)).write(&mut output)?;
+    }
+    if done {
+        PacketLine::done().write(&mut output)?;
+    } else {
+        PacketLine::End.write(&mut output)?;
+    }
+
+    Ok(output)
+}
+
+/// Build the next batch of `have` lines of a stateful negotiation, ended by a flush.
+fn haves_request(haves: &[HashCode]) -> Result<Vec<u8>, GitError> {
+    let mut output = Vec::new();
+    for have in haves {
+        PacketLine::have(&hex::encode(have)).write(&mut output)?;
+    }
+    PacketLine::End.write(&mut output)?;
+    Ok(output)
+}
+
+/// Read server acknowledgements until a `NAK` or a final `ACK`.
+pub fn read_acks<R: io::Read>(reader: &mut R) -> Result<Vec<Ack>, GitError> {
+    let mut output = Vec::new();
+
+    loop {
+        let PacketLine::Command(data) = PacketLine::read(reader)? else {
+            return Err(GitError::Http(
+                "Unexpected flush in acknowledgements".to_string(),
+            ));
+        };
+        let line = std::str::from_utf8(&data)?.trim_end();
+
+        if let Some(message) = line.strip_prefix("ERR ") {
+            return Err(GitError::Http(format!("remote error: {message}")));
+        }
+        if line == "NAK" {
+            output.push(Ack::Nak);
+            return Ok(output);
+        }
+
+        let rem = line
+            .strip_prefix("ACK ")
+            .ok_or_else(|| GitError::Http(format!("Expected ACK/NAK, got '{line}'")))?;
+        let (id, status) = rem.split_once(' ').unwrap_or((rem, ""));
+        let id = hash_code_text_to_array(id)?;
+        let ack = match status {
+            "common" => Ack::Common(id),
+            "ready" => Ack::Ready(id),
+            "continue" => Ack::Continue(id),
+            "" => Ack::Final(id),
+            _ => return Err(GitError::Http(format!("Invalid ACK status '{status}'"))),
+        };
+        output.push(ack);
+        if let Ack::Final(_) = ack {
+            return Ok(output);
+        }
+    }
+}
+
+/// Build a protocol v2 `fetch` command: arguments, `want` lines, `request_lines` like
+/// `shallow`, `deepen` or `filter`, `have` lines, then `done` when negotiation is over.
+pub fn fetch_request(
+    wants: &[HashCode],
+    request_lines: &[String],
+    haves: &[HashCode],
+    done: bool,
+) -> Result<Vec<u8>, GitError> {
+    let mut output = Vec::new();
+
+    PacketLine::command(b"command=fetch").write(&mut output)?;
+    PacketLine::Delimiter.write(&mut output)?;
+    for argument in FETCH_ARGUMENTS {
+        PacketLine::Command(Bytes::from_static(argument.as_bytes())).write(&mut output)?;
+    }
+    for want in wants {
+        PacketLine::want(&hex::encode(want)).write(&mut output)?;
+    }
+    write_request_lines(&mut output, request_lines)?;
+    for have in haves {
+        PacketLine::have(&hex::encode(have)).write(&mut output)?;
+    }
+    if done {
+        PacketLine::done().write(&mut output)?;
+    }
+    PacketLine::End.write(&mut output)?;
+
+    Ok(output)
+}
+
+/// Read a protocol v2 `fetch` response, made of sections separated by delimiters, up to
+/// the `packfile` section whose side-band packets are left to read.
+pub fn read_fetch_response<R: io::Read>(reader: &mut R) -> Result<FetchResponse, GitError> {
+    let mut output = FetchResponse::default();
+
+    loop {
+        let header = match PacketLine::read(reader)? {
+            PacketLine::Command(data) => std::str::from_utf8(&data)?.trim_end().to_string(),
+            PacketLine::End | PacketLine::Delimiter => return Ok(output),
+        };
+        if let Some(message) = header.strip_prefix("ERR ") {
+            return Err(GitError::Http(format!("remote error: {message}")));
+        }
+
+        // The pack is always the last section.
+        if header == "packfile" {
+            output.has_pack = true;
+            return Ok(output);
+        }
+
+        let last_line = loop {
+            let data = match PacketLine::read(reader)? {
+                PacketLine::Command(data) => data,
+                line => break line,
+            };
+
+            let line = std::str::from_utf8(&data)?.trim_end();
+            match (header.as_str(), line.split_once(' ')) {
+                ("acknowledgments", _) if line == "NAK" => {}
+                ("acknowledgments", _) if line == "ready" => output.ready = true,
+                ("acknowledgments", Some(("ACK", id))) => {
+                    output.common.push(hash_code_text_to_array(id)?)
+                }
+                ("shallow-info", _) if output.shallow_update.parse_line(line)? => {}
+                // We never send `want-ref`, but the section is harmless.
+                ("wanted-refs", _) => {}
+                _ => {
+                    return Err(GitError::Http(format!(
+                        "Unexpected line '{line}' in section '{header}'"
+                    )))
+                }
+            }
+        };
+
+        if last_line == PacketLine::End {
+            return Ok(output);
+        }
+    }
+}
+
+/// Walk local history from most recent commits to pick `have` lines, skipping commits
+/// the server is known to have.
+pub struct Negotiator<'a> {
+    store: &'a ObjectStore,
+    /// Commits to send, by committer date.
+    queue: BinaryHeap<(i64, HashCode)>,
+    seen: HashSet<HashCode>,
+    common: HashSet<HashCode>,
+}
+
+impl<'a> Negotiator<'a> {
+    pub fn new(store: &'a ObjectStore, tips: &[HashCode]) -> Result<Self, GitError> {
+        let mut negotiator = Self {
+            store,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            common: HashSet::new(),
+        };
+        for tip in tips {
+            negotiator.push(*tip)?;
+        }
+        Ok(negotiator)
+    }
+
+    fn push(&mut self, id: HashCode) -> Result<(), GitError> {
+        if self.seen.insert(id) {
+            let commit = CommitInfo::read(self.store, id)?;
+            self.queue.push((commit.committer.time, id));
+        }
+        Ok(())
+    }
+
+    /// Next commit to advertise, `None` once history is exhausted.
+    pub fn next_have(&mut self) -> Result<Option<HashCode>, GitError> {
+        while let Some((_, id)) = self.queue.pop() {
+            if self.common.contains(&id) {
+                continue;
+            }
+            for parent in CommitInfo::read(self.store, id)?.parents {
+                self.push(parent)?;
+            }
+            return Ok(Some(id));
+        }
+        Ok(None)
+    }
+
+    /// Record that the server has `id`, hence all its ancestors too.
+    ///
+    /// Returns `false` if it was already known as common.
+    pub fn ack(&mut self, id: HashCode) -> Result<bool, GitError> {
+        if self.common.contains(&id) {
+            return Ok(false);
+        }
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if self.common.insert(id) {
+                stack.extend(CommitInfo::read(self.store, id)?.parents);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Move local refs to fetched commits and write `FETCH_HEAD`.
+///
+/// A ref which is not fast-forwarded is only updated if its refspec is forced. Existing
+/// tags are never moved without force.
+pub fn update_refs<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    remote: &Remote,
+    fetched: &[FetchRef],
+) -> Result<Vec<RefUpdate>, GitError> {
+    let root = root.as_ref();
+    let config = GitConfig::read_at(root)?;
+    // Reflogs are nice to have: a missing identity should not fail the fetch.
+    let committer = Signature::from_env("COMMITTER", &config).ok();
+
+    let mut output = Vec::with_capacity(fetched.len());
+    for fetch_ref in fetched {
+        let Some(local_ref) = &fetch_ref.local_ref else {
+            output.push(RefUpdate {
+                remote_ref: fetch_ref.remote_ref.clone(),
+                local_ref: None,
+                old: None,
+                new: fetch_ref.id,
+                status: RefUpdateStatus::New,
+            });
+            continue;
+        };
+        if !local_ref.starts_with("refs/") || !is_valid_ref_name(local_ref) {
+            return Err(GitError::InvalidContent(format!(
+                "Invalid ref name '{local_ref}'"
+            )));
+        }
+
+        let old = resolve_ref_at(root, local_ref)?;
+        let status = match old {
+            None => RefUpdateStatus::New,
+            Some(old) if old == fetch_ref.id => RefUpdateStatus::UpToDate,
+            Some(old) => {
+                let is_fast_forward = !local_ref.starts_with("refs/tags/")
+                    && match (
+                        peel_to_commit(store, old),
+                        peel_to_commit(store, fetch_ref.id),
+                    ) {
+                        (Ok(old), Ok(new)) => is_ancestor(store, old, new)?,
+                        _ => false,
+                    };
+                match (is_fast_forward, fetch_ref.force) {
+                    (true, _) => RefUpdateStatus::FastForward,
+                    (false, true) => RefUpdateStatus::Forced,
+                    (false, false) => RefUpdateStatus::Rejected,
+                }
+            }
+        };
+
+        let message = match status {
+            RefUpdateStatus::New => Some("storing head"),
+            RefUpdateStatus::FastForward => Some("fast-forward"),
+            RefUpdateStatus::Forced => Some("forced-update"),
+            RefUpdateStatus::UpToDate | RefUpdateStatus::Rejected => None,
+        };
+        if let Some(message) = message {
+            write_ref_at(root, local_ref, &RefValue::Direct(fetch_ref.id))?;
+            if let Some(committer) = &committer {
+                append_reflog_at(
+                    root,
+                    local_ref,
+                    old,
+                    fetch_ref.id,
+                    committer,
+                    &format!("fetch {}: {message}", remote.name),
+                )?;
+            }
+        }
+
+        output.push(RefUpdate {
+            remote_ref: fetch_ref.remote_ref.clone(),
+            local_ref: Some(local_ref.clone()),
+            old,
+            new: fetch_ref.id,
+            status,
+        });
+    }
+
+    write_fetch_head(root, &config, remote, fetched)?;
+    Ok(output)
+}
+
+/// Record fetched refs in `FETCH_HEAD`, the ones to merge into current branch first.
+///
+/// Refs fetched by an exact refspec are merged, as well as the upstream of current branch
+/// when fetched from its remote.
+fn write_fetch_head(
+    root: &Path,
+    config: &GitConfig,
+    remote: &Remote,
+    fetched: &[FetchRef],
+) -> Result<(), GitError> {
+    let upstream = head_branch_at(root)?.and_then(|x| read_upstream(config, &x));
+    let exact_sources: Vec<_> = remote.fetch.iter().filter(|x| !x.is_glob()).collect();
+
+    let is_for_merge = |fetch_ref: &FetchRef| {
+        exact_sources
+            .iter()
+            .any(|x| x.matches(&fetch_ref.remote_ref))
+            || upstream
+                .as_ref()
+                .is_some_and(|x| x.remote == remote.name && x.merge == fetch_ref.remote_ref)
+    };
+
+    let url = remote.display_url();
+
+    let mut lines: Vec<_> = fetched
+        .iter()
+        .map(|fetch_ref| {
+            let name = &fetch_ref.remote_ref;
+            let description = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                format!("branch '{branch}' of {url}")
+            } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+                format!("tag '{tag}' of {url}")
+            } else if name == "HEAD" {
+                url.to_string()
+            } else {
+                format!("'{}' of {url}", short_ref_name(name))
+            };
+            let for_merge = is_for_merge(fetch_ref);
+            (
+                !for_merge,
+                format!(
+                    "{}\t{}\t{description}\n",
+                    hex::encode(fetch_ref.id),
+                    if for_merge { "" } else { "not-for-merge" }
+                ),
+            )
+        })
+        .collect();
+    // Stable sort keeps refspec order within each group.
+    lines.sort_by_key(|(not_for_merge, _)| *not_for_merge);
+
+    let content: String = lines.into_iter().map(|(_, line)| line).collect();
+    fs::write(git_dir(root).join("FETCH_HEAD"), content)?;
+    Ok(())
+}
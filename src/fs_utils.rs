@@ -1,39 +1,120 @@
 use std::{
-    fs,
+    fs::{self, Permissions},
     io::{self, BufRead, BufReader, BufWriter, Write},
+    os::unix::fs::PermissionsExt,
     path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
 
-use crate::HashCode;
+use crate::{config::GitConfig, HashCode};
 
-pub fn write_compressed(hash_code: HashCode, content: &[u8]) -> io::Result<()> {
-    write_compressed_at(hash_code, content, ".")
+/// Directory holding repository data: `.git` in a worktree, `root` itself for a bare
+/// repository.
+pub fn git_dir<P: AsRef<Path>>(root: P) -> PathBuf {
+    let root = root.as_ref();
+    let dot_git = root.join(".git");
+    if !dot_git.exists() && root.join("HEAD").is_file() && root.join("objects").is_dir() {
+        return root.to_path_buf();
+    }
+    dot_git
 }
 
+/// Write a loose object, flushing it to disk if `fsync` is set (see
+/// [`fsync_objects_enabled`]).
+///
+/// Object is written to a temporary file next to its final location and then renamed
+/// so a crash never leaves a truncated object under a valid name.
 pub fn write_compressed_at<P: AsRef<Path>>(
     hash_code: HashCode,
     content: &[u8],
     dst: P,
+    fsync: bool,
 ) -> io::Result<()> {
-    let path = dst.as_ref().join(checksum_to_path(hash_code));
+    let path = git_dir(dst).join(checksum_to_path(hash_code));
+
+    // Objects are immutable: if it already exists there is nothing to do.
+    if path.exists() {
+        return Ok(());
+    }
+
     let parent_path = path.parent().expect("Missing object top tree node");
     fs::create_dir_all(parent_path)?;
 
-    let file = fs::File::create(path)?;
+    let tmp_path = temp_path_in(parent_path, "tmp_obj_");
+    let result = write_temp_object(&tmp_path, content, fsync).and_then(|_| {
+        fs::set_permissions(&tmp_path, Permissions::from_mode(0o444))?;
+        fs::rename(&tmp_path, &path)?;
+        if fsync {
+            fs::File::open(parent_path)?.sync_all()?;
+        }
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// Check if loose object writes should be flushed to disk based on `core.fsync`
+/// (or legacy `core.fsyncObjectFiles`) setting.
+pub fn fsync_objects_enabled(config: &GitConfig) -> bool {
+    if let Some(components) = config.get("core.fsync") {
+        return components.split(',').map(str::trim).any(|x| {
+            matches!(
+                x,
+                "loose-object" | "objects" | "committed" | "added" | "all"
+            )
+        });
+    }
+
+    config.get_bool("core.fsyncobjectfiles").unwrap_or(false)
+}
+
+fn write_temp_object(path: &Path, content: &[u8], fsync: bool) -> io::Result<()> {
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+
     let mut writer = BufWriter::new(ZlibEncoder::new(file, Compression::best()));
     writer.write_all(content)?;
 
+    let file = writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .finish()?;
+    if fsync {
+        file.sync_all()?;
+    }
+
     Ok(())
 }
 
+/// Build a path to a file that does not exist yet in `dir`.
+pub fn temp_path_in<P: AsRef<Path>>(dir: P, prefix: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    dir.as_ref()
+        .join(format!("{prefix}{}_{count}_{nanos:x}", process::id()))
+}
+
 pub fn read_compressed(hash_code: HashCode) -> io::Result<impl BufRead> {
     read_compressed_at(hash_code, ".")
 }
 
 pub fn read_compressed_at<P: AsRef<Path>>(hash_code: HashCode, src: P) -> io::Result<impl BufRead> {
-    let path = src.as_ref().join(checksum_to_path(hash_code));
+    let path = git_dir(src).join(checksum_to_path(hash_code));
     let file = fs::File::open(path)?;
     Ok(BufReader::new(ZlibDecoder::new(file)))
 }
@@ -41,7 +122,7 @@ pub fn read_compressed_at<P: AsRef<Path>>(hash_code: HashCode, src: P) -> io::Re
 fn checksum_to_path(hash_code: HashCode) -> PathBuf {
     let cs = hex::encode(hash_code);
     assert_eq!(cs.len(), 40, "Invalid checksum size");
-    PathBuf::from(".git/objects").join(&cs[..2]).join(&cs[2..])
+    PathBuf::from("objects").join(&cs[..2]).join(&cs[2..])
 }
 
 #[cfg(test)]
@@ -53,10 +134,10 @@ mod tests {
     #[test]
     fn test_checksum_to_path() {
         assert_eq!(
-            checksum_to_path(hash_code_text_to_array(
-                "e547aac8945402134e4c0b9bb85ad82361eed68a"
-            )),
-            PathBuf::from(".git/objects/e5/47aac8945402134e4c0b9bb85ad82361eed68a"),
+            checksum_to_path(
+                hash_code_text_to_array("e547aac8945402134e4c0b9bb85ad82361eed68a").unwrap()
+            ),
+            PathBuf::from("objects/e5/47aac8945402134e4c0b9bb85ad82361eed68a"),
         );
     }
 }
@@ -0,0 +1,489 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    config::GitConfig,
+    fs_utils::read_compressed_at,
+    header::{GitObjectHeader, GitObjectHeaderType},
+    object::{encode_raw_object, GitObject, GitTreeItem},
+    promisor::promisor_remote,
+    refs::{list_refs_at, resolve_ref_at},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// Problem found while checking a repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// Object content does not hash to its name.
+    HashMismatch {
+        id: HashCode,
+        actual: HashCode,
+    },
+    /// Object cannot be decoded or has an invalid structure.
+    BadObject {
+        id: HashCode,
+        r#type: Option<GitObjectHeaderType>,
+        message: String,
+    },
+    /// Object is referenced but not in the store.
+    Missing {
+        id: HashCode,
+        r#type: Option<GitObjectHeaderType>,
+    },
+    /// Object is in the store but nothing points to it.
+    Dangling {
+        id: HashCode,
+        r#type: GitObjectHeaderType,
+    },
+    BadRef {
+        name: String,
+        message: String,
+    },
+    BadPack {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl FsckIssue {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::HashMismatch { .. } => "hash-mismatch",
+            Self::BadObject { .. } => "bad-object",
+            Self::Missing { .. } => "missing",
+            Self::Dangling { .. } => "dangling",
+            Self::BadRef { .. } => "bad-ref",
+            Self::BadPack { .. } => "bad-pack",
+        }
+    }
+
+    /// Dangling objects are reported but do not make the repository invalid.
+    pub fn is_error(&self) -> bool {
+        !matches!(self, Self::Dangling { .. })
+    }
+
+    /// Encode issue as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![("kind", json_string(self.kind()))];
+
+        match self {
+            Self::HashMismatch { id, actual } => {
+                fields.push(("id", json_string(&hex::encode(id))));
+                fields.push(("actual", json_string(&hex::encode(actual))));
+            }
+            Self::BadObject {
+                id,
+                r#type,
+                message,
+            } => {
+                fields.push(("id", json_string(&hex::encode(id))));
+                fields.push(("type", json_type(*r#type)));
+                fields.push(("message", json_string(message)));
+            }
+            Self::Missing { id, r#type } => {
+                fields.push(("id", json_string(&hex::encode(id))));
+                fields.push(("type", json_type(*r#type)));
+            }
+            Self::Dangling { id, r#type } => {
+                fields.push(("id", json_string(&hex::encode(id))));
+                fields.push(("type", json_type(Some(*r#type))));
+            }
+            Self::BadRef { name, message } => {
+                fields.push(("name", json_string(name)));
+                fields.push(("message", json_string(message)));
+            }
+            Self::BadPack { path, message } => {
+                fields.push(("path", json_string(&path.to_string_lossy())));
+                fields.push(("message", json_string(message)));
+            }
+        }
+
+        let fields: Vec<_> = fields
+            .into_iter()
+            .map(|(key, value)| format!("\"{key}\":{value}"))
+            .collect();
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let type_name = |x: &Option<GitObjectHeaderType>| x.map_or("object", |x| x.as_str());
+
+        match self {
+            Self::HashMismatch { id, actual } => write!(
+                f,
+                "hash-mismatch {} (content hashes to {})",
+                hex::encode(id),
+                hex::encode(actual)
+            ),
+            Self::BadObject {
+                id,
+                r#type,
+                message,
+            } => write!(
+                f,
+                "bad-object {} {}: {message}",
+                type_name(r#type),
+                hex::encode(id)
+            ),
+            Self::Missing { id, r#type } => {
+                write!(f, "missing {} {}", type_name(r#type), hex::encode(id))
+            }
+            Self::Dangling { id, r#type } => {
+                write!(f, "dangling {} {}", r#type.as_str(), hex::encode(id))
+            }
+            Self::BadRef { name, message } => write!(f, "bad-ref {name}: {message}"),
+            Self::BadPack { path, message } => {
+                write!(f, "bad-pack {}: {message}", path.display())
+            }
+        }
+    }
+}
+
+/// Check integrity and connectivity of every object in repository at `root`.
+pub fn fsck_at<P: AsRef<Path>>(root: P) -> Result<Vec<FsckIssue>, GitError> {
+    let root = root.as_ref();
+    let store = ObjectStore::open(root)?;
+    let mut issues = Vec::new();
+
+    // Find every object and make sure it is stored under the right name.
+    let mut objects: HashMap<HashCode, GitObjectHeaderType> = HashMap::new();
+
+    for id in store.loose_objects()? {
+        match check_loose_object(root, id) {
+            Ok(r#type) => {
+                objects.insert(id, r#type);
+            }
+            Err(issue) => issues.push(issue),
+        }
+    }
+
+    for pack in store.packs() {
+        for message in pack.verify() {
+            issues.push(FsckIssue::BadPack {
+                path: pack.path.clone(),
+                message,
+            });
+        }
+
+        for entry in &pack.index.entries {
+            match pack.read_at(entry.offset, &|base| store.read_raw(base)) {
+                Ok((r#type, payload)) => {
+                    let (actual, _) = encode_raw_object(r#type, &payload);
+                    if actual == entry.hash_code {
+                        objects.insert(entry.hash_code, r#type);
+                    } else {
+                        issues.push(FsckIssue::HashMismatch {
+                            id: entry.hash_code,
+                            actual,
+                        });
+                    }
+                }
+                Err(err) => issues.push(FsckIssue::BadObject {
+                    id: entry.hash_code,
+                    r#type: None,
+                    message: err.to_string(),
+                }),
+            }
+        }
+    }
+
+    // Check object structure and collect links between objects.
+    let mut links: HashMap<HashCode, Vec<(HashCode, GitObjectHeaderType)>> = HashMap::new();
+    let mut ids: Vec<_> = objects.keys().copied().collect();
+    ids.sort();
+
+    for id in ids {
+        let r#type = objects[&id];
+        let object = match store.read(id) {
+            Ok(object) => object,
+            Err(err) => {
+                issues.push(FsckIssue::BadObject {
+                    id,
+                    r#type: Some(r#type),
+                    message: err.to_string(),
+                });
+                continue;
+            }
+        };
+
+        for message in check_object(&object) {
+            issues.push(FsckIssue::BadObject {
+                id,
+                r#type: Some(r#type),
+                message,
+            });
+        }
+
+        let mut object_links = object_links(&object);
+        // Parents of shallow commits are expected to be missing.
+        if store.is_shallow(id) {
+            object_links.retain(|(_, r#type)| *r#type != GitObjectHeaderType::Commit);
+        }
+        for (target, expected_type) in &object_links {
+            if let Some(actual_type) = objects.get(target) {
+                if actual_type != expected_type {
+                    issues.push(FsckIssue::BadObject {
+                        id,
+                        r#type: Some(r#type),
+                        message: format!(
+                            "{} is a {} but is referenced as a {}",
+                            hex::encode(target),
+                            actual_type.as_str(),
+                            expected_type.as_str()
+                        ),
+                    });
+                }
+            }
+        }
+        links.insert(id, object_links);
+    }
+
+    // Walk every object reachable from refs.
+    let mut starts = Vec::new();
+    match resolve_ref_at(root, "HEAD") {
+        Ok(Some(id)) => starts.push(("HEAD".to_string(), id)),
+        // Unborn branch: nothing to check.
+        Ok(None) => {}
+        Err(err) => issues.push(FsckIssue::BadRef {
+            name: "HEAD".to_string(),
+            message: err.to_string(),
+        }),
+    }
+    starts.extend(list_refs_at(root)?);
+
+    // Objects left out of a partial clone are promised by its remote.
+    let is_partial = promisor_remote(&GitConfig::read_at(root)?)?.is_some();
+    let mut reachable = HashSet::new();
+    let mut missing = HashSet::new();
+    let mut queue = VecDeque::new();
+
+    for (name, id) in starts {
+        if !objects.contains_key(&id) && !missing.contains(&id) {
+            issues.push(FsckIssue::BadRef {
+                name,
+                message: format!("points to missing object {}", hex::encode(id)),
+            });
+            missing.insert(id);
+        } else {
+            queue.push_back(id);
+        }
+    }
+
+    while let Some(id) = queue.pop_front() {
+        if !reachable.insert(id) {
+            continue;
+        }
+
+        for (target, r#type) in links.get(&id).into_iter().flatten() {
+            if objects.contains_key(target) {
+                queue.push_back(*target);
+            } else if missing.insert(*target) && !is_partial {
+                issues.push(FsckIssue::Missing {
+                    id: *target,
+                    r#type: Some(*r#type),
+                });
+            }
+        }
+    }
+
+    // Objects that are neither reachable nor referenced by another object are dangling.
+    let referenced: HashSet<_> = links
+        .values()
+        .flatten()
+        .map(|(target, _)| *target)
+        .collect();
+
+    let mut dangling: Vec<_> = objects
+        .iter()
+        .filter(|(id, _)| !reachable.contains(*id) && !referenced.contains(*id))
+        .map(|(id, r#type)| FsckIssue::Dangling {
+            id: *id,
+            r#type: *r#type,
+        })
+        .collect();
+    dangling.sort_by_key(|x| match x {
+        FsckIssue::Dangling { id, .. } => *id,
+        _ => unreachable!(),
+    });
+    issues.extend(dangling);
+
+    Ok(issues)
+}
+
+fn check_loose_object(root: &Path, id: HashCode) -> Result<GitObjectHeaderType, FsckIssue> {
+    let bad_object = |message: String| FsckIssue::BadObject {
+        id,
+        r#type: None,
+        message,
+    };
+
+    let mut data = Vec::new();
+    read_compressed_at(id, root)
+        .and_then(|mut reader| reader.read_to_end(&mut data))
+        .map_err(|err| bad_object(format!("cannot inflate: {err}")))?;
+
+    let actual: HashCode = Sha1::digest(&data).into();
+    if actual != id {
+        return Err(FsckIssue::HashMismatch { id, actual });
+    }
+
+    let mut reader = data.as_slice();
+    let header = GitObjectHeader::read(&mut reader).map_err(|err| bad_object(err.to_string()))?;
+    if header.len != reader.len() {
+        return Err(bad_object(format!(
+            "header declares {} bytes but object has {}",
+            header.len,
+            reader.len()
+        )));
+    }
+
+    Ok(header.r#type)
+}
+
+/// Structural checks inspired by `git fsck` ones.
+pub fn check_object(object: &GitObject) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    match object {
+        GitObject::Blob(_) => {}
+        GitObject::Tree(items) => {
+            for item in items {
+                if !matches!(
+                    item.mode,
+                    0o100644 | 0o100755 | 0o120000 | 0o40000 | 0o160000
+                ) {
+                    errors.push(format!("bad mode {:o} for {:?}", item.mode, item.name));
+                }
+                if item.validate_name().is_err() {
+                    errors.push(format!("bad entry name {:?}", item.name));
+                }
+                if item.hash_code == [0; 20] {
+                    errors.push(format!("null object ID for {:?}", item.name));
+                }
+            }
+
+            for pair in items.windows(2) {
+                match tree_sort_key(&pair[0]).cmp(&tree_sort_key(&pair[1])) {
+                    std::cmp::Ordering::Less => {}
+                    std::cmp::Ordering::Equal => {
+                        errors.push(format!("duplicate entry {:?}", pair[1].name))
+                    }
+                    std::cmp::Ordering::Greater => {
+                        errors.push(format!("entry {:?} is not sorted", pair[1].name))
+                    }
+                }
+            }
+        }
+        GitObject::Commit {
+            author, committer, ..
+        } => {
+            for (name, value) in [("author", author), ("committer", committer)] {
+                match value {
+                    Some(value) if !is_valid_signature(value) => {
+                        errors.push(format!("invalid {name} line {value:?}"))
+                    }
+                    Some(_) => {}
+                    None => errors.push(format!("missing {name}")),
+                }
+            }
+        }
+        GitObject::Tag { tag, tagger, .. } => {
+            if tag.is_empty() || tag.contains(char::is_whitespace) {
+                errors.push(format!("invalid tag name {tag:?}"));
+            }
+            match tagger {
+                Some(value) if !is_valid_signature(value) => {
+                    errors.push(format!("invalid tagger line {value:?}"))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    errors
+}
+
+/// Objects pointed to by `object` with the type they are expected to have.
+pub fn object_links(object: &GitObject) -> Vec<(HashCode, GitObjectHeaderType)> {
+    match object {
+        GitObject::Blob(_) => Vec::new(),
+        GitObject::Tree(items) => items
+            .iter()
+            .filter_map(|x| match x.mode {
+                0o40000 => Some((x.hash_code, GitObjectHeaderType::Tree)),
+                // Submodule commits live in another repository.
+                0o160000 => None,
+                _ => Some((x.hash_code, GitObjectHeaderType::Blob)),
+            })
+            .collect(),
+        GitObject::Commit { tree, parents, .. } => {
+            let mut output = vec![(*tree, GitObjectHeaderType::Tree)];
+            output.extend(parents.iter().map(|x| (*x, GitObjectHeaderType::Commit)));
+            output
+        }
+        GitObject::Tag {
+            object,
+            target_type,
+            ..
+        } => vec![(*object, *target_type)],
+    }
+}
+
+/// Git sorts tree entries as if directory names ended with a `/`.
+fn tree_sort_key(item: &GitTreeItem) -> Vec<u8> {
+    let mut key = item.name.as_bytes().to_vec();
+    if item.mode == 0o40000 {
+        key.push(b'/');
+    }
+    key
+}
+
+/// Check identity looks like `Name <email> 1703674545 +0100`.
+fn is_valid_signature(value: &str) -> bool {
+    let Some((identity, date)) = value.rsplit_once('>') else {
+        return false;
+    };
+    let Some((_name, email)) = identity.split_once('<') else {
+        return false;
+    };
+
+    let mut date_iter = date.split_whitespace();
+    let timestamp_ok = date_iter.next().is_some_and(|x| x.parse::<u64>().is_ok());
+    let tz_ok = date_iter.next().is_some_and(|x| {
+        x.len() == 5 && x.starts_with(['+', '-']) && x[1..].chars().all(|c| c.is_ascii_digit())
+    });
+
+    !email.contains(['<', '>']) && timestamp_ok && tz_ok && date_iter.next().is_none()
+}
+
+fn json_type(r#type: Option<GitObjectHeaderType>) -> String {
+    match r#type {
+        Some(r#type) => json_string(r#type.as_str()),
+        None => "null".to_string(),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
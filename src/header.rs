@@ -8,11 +8,33 @@ pub struct GitObjectHeader {
     pub r#type: GitObjectHeaderType,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum GitObjectHeaderType {
     Blob,
     Tree,
     Commit,
+    Tag,
+}
+
+impl GitObjectHeaderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Blob => "blob",
+            Self::Tree => "tree",
+            Self::Commit => "commit",
+            Self::Tag => "tag",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "blob" => Some(Self::Blob),
+            "tree" => Some(Self::Tree),
+            "commit" => Some(Self::Commit),
+            "tag" => Some(Self::Tag),
+            _ => None,
+        }
+    }
 }
 
 impl GitObjectHeader {
@@ -31,26 +53,13 @@ impl GitObjectHeader {
             .and_then(|x| x.parse().ok())
             .ok_or(GitError::InvalidObjectHeader("bad header len"))?;
 
-        let r#type = match header_type {
-            "blob" => GitObjectHeaderType::Blob,
-            "tree" => GitObjectHeaderType::Tree,
-            "commit" => GitObjectHeaderType::Commit,
-            _ => return Err(GitError::InvalidObjectHeader("bad header type")),
-        };
+        let r#type = GitObjectHeaderType::parse(header_type)
+            .ok_or(GitError::InvalidObjectHeader("bad header type"))?;
 
         Ok(Self { len, r#type })
     }
 
     pub fn write<W: io::Write>(&self, output: &mut W) -> io::Result<()> {
-        write!(
-            output,
-            "{} {}\0",
-            match self.r#type {
-                GitObjectHeaderType::Blob => "blob",
-                GitObjectHeaderType::Tree => "tree",
-                GitObjectHeaderType::Commit => "commit",
-            },
-            self.len
-        )
+        write!(output, "{} {}\0", self.r#type.as_str(), self.len)
     }
 }
@@ -0,0 +1,510 @@
+use std::{
+    io::{self, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+use crate::{
+    clone::ProtocolVersion,
+    config::GitConfig,
+    fs_utils::git_dir,
+    packet_line::PacketLine,
+    receive_pack::{receive_pack, ReceivePackOptions},
+    upload_pack::{requested_version, upload_pack, UploadPackOptions},
+    GitError,
+};
+
+/// Services of the smart HTTP protocol.
+const SERVICES: [&str; 2] = ["git-upload-pack", "git-receive-pack"];
+
+/// Largest request body accepted, after decompression.
+const MAX_BODY_LEN: usize = 256 << 20;
+
+/// Longest request or header line accepted.
+const MAX_LINE_LEN: usize = 8 << 10;
+
+/// Most headers accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// Size of chunks a response is streamed in.
+const CHUNK_LEN: usize = 64 << 10;
+
+/// Serve repositories found under `root` over smart HTTP, like `git http-backend`:
+/// `GET <repo>/info/refs?service=<service>` then `POST <repo>/<service>`.
+///
+/// Anyone can fetch, but pushing needs `http.receivepack` set in the repository config.
+pub async fn serve(listener: TcpListener, root: PathBuf) -> Result<(), GitError> {
+    let root = Arc::new(root);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let root = root.clone();
+        tokio::spawn(async move {
+            if let Err(err) = handle_connection(stream, &root).await {
+                eprintln!("error: {err}");
+            }
+        });
+    }
+}
+
+/// Answer requests of a client until it closes the connection.
+async fn handle_connection(stream: TcpStream, root: &Path) -> Result<(), GitError> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    loop {
+        let request = match Request::read(&mut reader).await {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            // What is left of the request can't be skipped reliably.
+            Err(ReadError::Refused(status, message)) => {
+                eprintln!("refused request: {message}");
+                Response::text(status, message)
+                    .write(&mut writer, false)
+                    .await?;
+                break;
+            }
+            Err(ReadError::Git(err)) => return Err(err),
+        };
+        let keep_alive = !request
+            .header("connection")
+            .is_some_and(|x| x.eq_ignore_ascii_case("close"));
+        let response = match respond(root, &request).await {
+            Ok(response) => response,
+            Err(err) => Response::text(500, &err.to_string()),
+        };
+        eprintln!("{} {} {}", request.method, request.target, response.status);
+        response.write(&mut writer, keep_alive).await?;
+        if !keep_alive {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Route `request` to the service of the repository it names.
+async fn respond(root: &Path, request: &Request) -> Result<Response, GitError> {
+    let (path, query) = request
+        .target
+        .split_once('?')
+        .unwrap_or((&request.target, ""));
+
+    let (repo, service, is_advertisement) = if let Some(repo) = path.strip_suffix("/info/refs") {
+        let service = query
+            .split('&')
+            .find_map(|x| x.strip_prefix("service="))
+            .and_then(|x| SERVICES.into_iter().find(|service| *service == x));
+        let Some(service) = service else {
+            return Ok(Response::text(
+                403,
+                "Only the smart HTTP protocol is supported",
+            ));
+        };
+        (repo, service, true)
+    } else {
+        match SERVICES
+            .iter()
+            .find_map(|x| Some((path.strip_suffix(x)?, *x)))
+        {
+            Some((repo, service)) => (repo.trim_end_matches('/'), service, false),
+            None => return Ok(Response::text(404, "Not Found")),
+        }
+    };
+    let method = if is_advertisement { "GET" } else { "POST" };
+    if request.method != method {
+        return Ok(Response::text(405, "Method Not Allowed"));
+    }
+
+    let Some(repo) = repo_path(root, repo) else {
+        return Ok(Response::text(404, "Repository not found"));
+    };
+    let config = GitConfig::read_at(&repo)?;
+    let enabled = match service {
+        "git-upload-pack" => config.get_bool("http.uploadpack").unwrap_or(true),
+        _ => config.get_bool("http.receivepack").unwrap_or(false),
+    };
+    if !enabled {
+        return Ok(Response::text(403, "Service not enabled"));
+    }
+
+    let version = request
+        .header("git-protocol")
+        .map_or(ProtocolVersion::V0, requested_version);
+    if is_advertisement {
+        let mut body = Vec::new();
+        // Protocol v2 starts with its own version line instead.
+        if version == ProtocolVersion::V0 || service == "git-receive-pack" {
+            let line = format!("# service={service}");
+            PacketLine::Command(Bytes::from(line)).write(&mut body)?;
+            PacketLine::End.write(&mut body)?;
+        }
+        let (first, rest) = run_service(repo, service, version, true, Vec::new()).await?;
+        body.extend(first);
+        let content_type = format!("application/x-{service}-advertisement");
+        return Ok(Response::streamed(200, &content_type, body, rest));
+    }
+
+    if request.header("content-type") != Some(&format!("application/x-{service}-request")) {
+        return Ok(Response::text(415, "Unsupported Media Type"));
+    }
+    let (first, rest) = run_service(repo, service, version, false, request.body.clone()).await?;
+    Ok(Response::streamed(
+        200,
+        &format!("application/x-{service}-result"),
+        first,
+        rest,
+    ))
+}
+
+/// Repository at URL path `path` under `root`, `None` if there is none or the path tries
+/// to escape `root`.
+fn repo_path(root: &Path, path: &str) -> Option<PathBuf> {
+    let relative = Path::new(path.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|x| matches!(x, Component::Normal(_)))
+    {
+        return None;
+    }
+    let repo = root.join(relative);
+    git_dir(&repo).join("objects").is_dir().then_some(repo)
+}
+
+/// Run `service` on `repo` for a single request, returning the first chunk of its
+/// output and a channel receiving the rest as it is produced.
+///
+/// Errors the client was told about in the output, like an unknown `want`, still make a
+/// response.
+async fn run_service(
+    repo: PathBuf,
+    service: &'static str,
+    version: ProtocolVersion,
+    advertise_refs: bool,
+    input: Vec<u8>,
+) -> Result<(Vec<u8>, mpsc::Receiver<Vec<u8>>), GitError> {
+    let (sender, mut receiver) = mpsc::channel(16);
+    let task = tokio::task::spawn_blocking(move || {
+        let mut output = BufWriter::with_capacity(
+            CHUNK_LEN,
+            ChannelWriter {
+                sender,
+                written: false,
+            },
+        );
+        let result = match service {
+            "git-upload-pack" => {
+                let options = UploadPackOptions {
+                    version,
+                    stateless_rpc: true,
+                    advertise_refs,
+                };
+                upload_pack(&repo, &mut input.as_slice(), &mut output, &options)
+            }
+            _ => {
+                let options = ReceivePackOptions {
+                    stateless_rpc: true,
+                    advertise_refs,
+                };
+                receive_pack(&repo, &mut input.as_slice(), &mut output, &options)
+            }
+        };
+        output.flush()?;
+        match result {
+            Err(err) if !output.get_ref().written => Err(err),
+            _ => Ok(()),
+        }
+    });
+
+    // Whether the service failed before writing anything decides the status.
+    match receiver.recv().await {
+        Some(first) => Ok((first, receiver)),
+        None => {
+            task.await.map_err(|err| GitError::Io(err.to_string()))??;
+            Ok((Vec::new(), receiver))
+        }
+    }
+}
+
+/// Writer sending what is written through it to a channel, for a blocking task to
+/// stream its output.
+struct ChannelWriter {
+    sender: mpsc::Sender<Vec<u8>>,
+    written: bool,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender
+            .blocking_send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Response closed"))?;
+        self.written = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// HTTP/1.1 request, with its body decoded.
+struct Request {
+    method: String,
+    /// Path and query, like `/repo.git/info/refs?service=git-upload-pack`.
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// Read the next request of a connection, `None` if the client closed it.
+    async fn read<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Self>, ReadError> {
+        let mut line = String::new();
+        if read_line(reader, &mut line, (414, "URI Too Long")).await? == 0 {
+            return Ok(None);
+        }
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(ReadError::Refused(400, "Invalid request line"));
+        };
+        let mut request = Self {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+
+        let too_large = (431, "Request Header Fields Too Large");
+        loop {
+            line.clear();
+            read_line(reader, &mut line, too_large).await?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if request.headers.len() == MAX_HEADERS {
+                return Err(too_large.into());
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                request
+                    .headers
+                    .push((name.to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        if request
+            .header("transfer-encoding")
+            .is_some_and(|x| x.eq_ignore_ascii_case("chunked"))
+        {
+            request.body = read_chunked(reader).await?;
+        } else if let Some(length) = request.header("content-length") {
+            let length: usize = length
+                .parse()
+                .map_err(|_| ReadError::Refused(400, "Invalid Content-Length"))?;
+            if length > MAX_BODY_LEN {
+                return Err(ReadError::TOO_LARGE);
+            }
+            request.body = vec![0; length];
+            reader.read_exact(&mut request.body).await?;
+        }
+        // git compresses large requests.
+        if request.header("content-encoding") == Some("gzip") {
+            let mut body = Vec::new();
+            GzDecoder::new(request.body.as_slice())
+                .take(MAX_BODY_LEN as u64 + 1)
+                .read_to_end(&mut body)?;
+            if body.len() > MAX_BODY_LEN {
+                return Err(ReadError::TOO_LARGE);
+            }
+            request.body = body;
+        }
+        Ok(Some(request))
+    }
+
+    /// Value of header `name`, given in lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Read a body sent with `Transfer-Encoding: chunked`.
+async fn read_chunked<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, ReadError> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    let too_large = (431, "Chunk Line Too Large");
+    loop {
+        line.clear();
+        read_line(reader, &mut line, too_large).await?;
+        // Chunk extensions after `;` are ignored.
+        let size = line.trim_end().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| ReadError::Refused(400, "Invalid chunk size"))?;
+        if size == 0 {
+            break;
+        }
+        let start = body.len();
+        if size > MAX_BODY_LEN - start {
+            return Err(ReadError::TOO_LARGE);
+        }
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).await?;
+        line.clear();
+        read_line(reader, &mut line, too_large).await?;
+    }
+    // Skip trailers up to the empty line.
+    for _ in 0..=MAX_HEADERS {
+        line.clear();
+        if read_line(reader, &mut line, too_large).await? == 0 || line.trim_end().is_empty() {
+            return Ok(body);
+        }
+    }
+    Err(too_large.into())
+}
+
+/// Read a line of at most [`MAX_LINE_LEN`] bytes, refusing the request with `too_long`
+/// otherwise.
+async fn read_line<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    line: &mut String,
+    too_long: (u16, &'static str),
+) -> Result<usize, ReadError> {
+    let len = reader.take(MAX_LINE_LEN as u64 + 1).read_line(line).await?;
+    if len > MAX_LINE_LEN {
+        return Err(too_long.into());
+    }
+    Ok(len)
+}
+
+/// Why a request could not be read.
+enum ReadError {
+    /// The request is invalid or too large, answered with this status and message.
+    Refused(u16, &'static str),
+    Git(GitError),
+}
+
+impl ReadError {
+    const TOO_LARGE: Self = Self::Refused(413, "Payload Too Large");
+}
+
+impl From<(u16, &'static str)> for ReadError {
+    fn from((status, message): (u16, &'static str)) -> Self {
+        Self::Refused(status, message)
+    }
+}
+
+impl From<GitError> for ReadError {
+    fn from(err: GitError) -> Self {
+        Self::Git(err)
+    }
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> Self {
+        Self::Git(err.into())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: String,
+    body: Vec<u8>,
+    /// Rest of the body, sent with `Transfer-Encoding: chunked` as it comes.
+    stream: Option<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type: content_type.to_string(),
+            body,
+            stream: None,
+        }
+    }
+
+    fn streamed(
+        status: u16,
+        content_type: &str,
+        body: Vec<u8>,
+        stream: mpsc::Receiver<Vec<u8>>,
+    ) -> Self {
+        Self {
+            stream: Some(stream),
+            ..Self::new(status, content_type, body)
+        }
+    }
+
+    fn text(status: u16, message: &str) -> Self {
+        Self::new(status, "text/plain", format!("{message}\n").into_bytes())
+    }
+
+    async fn write<W: AsyncWrite + Unpin>(
+        self,
+        writer: &mut W,
+        keep_alive: bool,
+    ) -> Result<(), GitError> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        };
+        let connection = if keep_alive { "keep-alive" } else { "close" };
+        let length = match self.stream {
+            Some(_) => "Transfer-Encoding: chunked".to_string(),
+            None => format!("Content-Length: {}", self.body.len()),
+        };
+        let head = format!(
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\n{length}\r\n\
+             Cache-Control: no-cache\r\nConnection: {connection}\r\n\r\n",
+            self.status, self.content_type,
+        );
+        writer.write_all(head.as_bytes()).await?;
+
+        let Some(mut stream) = self.stream else {
+            writer.write_all(&self.body).await?;
+            writer.flush().await?;
+            return Ok(());
+        };
+        write_chunk(writer, &self.body).await?;
+        while let Some(chunk) = stream.recv().await {
+            write_chunk(writer, &chunk).await?;
+            writer.flush().await?;
+        }
+        writer.write_all(b"0\r\n\r\n").await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}
+
+/// Write `data` as a chunk of a `Transfer-Encoding: chunked` body.
+async fn write_chunk<W: AsyncWrite + Unpin>(writer: &mut W, data: &[u8]) -> Result<(), GitError> {
+    // An empty chunk would end the body.
+    if data.is_empty() {
+        return Ok(());
+    }
+    writer
+        .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+        .await?;
+    writer.write_all(data).await?;
+    writer.write_all(b"\r\n").await?;
+    Ok(())
+}
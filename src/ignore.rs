@@ -0,0 +1,153 @@
+use std::{fs, io, path::Path};
+
+use crate::{fs_utils::git_dir, GitError};
+
+/// Patterns read from `.gitignore` files and `.git/info/exclude`.
+///
+/// See: https://git-scm.com/docs/gitignore#_pattern_format
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    /// Directory of the `.gitignore` file defining this rule, like `src/` (empty at root).
+    base: String,
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    /// Pattern is matched against the whole path instead of the file name.
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    /// Load `.git/info/exclude` and root `.gitignore`.
+    pub fn load_at<P: AsRef<Path>>(root: P) -> Result<Self, GitError> {
+        let root = root.as_ref();
+        let mut rules = Self::default();
+        rules.add_file(git_dir(root).join("info/exclude"), "")?;
+        rules.add_file(root.join(".gitignore"), "")?;
+        Ok(rules)
+    }
+
+    /// Add rules of an ignore file found in `base` directory. Missing files are skipped.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P, base: &str) -> Result<(), GitError> {
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                self.add_patterns(&content, base);
+                Ok(())
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn add_patterns(&mut self, content: &str, base: &str) {
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+
+            self.rules.push(IgnoreRule {
+                base: base.to_string(),
+                pattern: line.trim_start_matches('/').to_string(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Check if `path`, relative to worktree root, is ignored. Last matching rule wins.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        for rule in self.rules.iter().rev() {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let Some(relative) = path.strip_prefix(rule.base.as_str()) else {
+                continue;
+            };
+
+            let text = if rule.anchored {
+                relative
+            } else {
+                relative.rsplit('/').next().unwrap_or(relative)
+            };
+            if glob_match(rule.pattern.as_bytes(), text.as_bytes()) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+/// Match shell glob with `*`, `**`, `?` and `[...]` support. `*` does not match `/`.
+pub fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            // `**/` also matches no directory at all.
+            let rest = &pattern[2..];
+            let rest_no_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            (0..=text.len()).any(|idx| {
+                glob_match(rest, &text[idx..])
+                    || ((idx == 0 || text[idx - 1] == b'/')
+                        && glob_match(rest_no_slash, &text[idx..]))
+            })
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for idx in 0..=text.len() {
+                if glob_match(rest, &text[idx..]) {
+                    return true;
+                }
+                if text.get(idx) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => {
+            matches!(text.first(), Some(c) if *c != b'/') && glob_match(&pattern[1..], &text[1..])
+        }
+        Some(b'[') => {
+            let Some(end) = pattern.iter().skip(2).position(|x| *x == b']') else {
+                return text.first() == Some(&b'[') && glob_match(&pattern[1..], &text[1..]);
+            };
+            let class = &pattern[1..end + 2];
+            let (negated, class) = match class.first() {
+                Some(b'!' | b'^') => (true, &class[1..]),
+                _ => (false, class),
+            };
+            let Some(c) = text.first() else {
+                return false;
+            };
+
+            let mut is_match = false;
+            let mut idx = 0;
+            while idx < class.len() {
+                if idx + 2 < class.len() && class[idx + 1] == b'-' {
+                    is_match |= (class[idx]..=class[idx + 2]).contains(c);
+                    idx += 3;
+                } else {
+                    is_match |= class[idx] == *c;
+                    idx += 1;
+                }
+            }
+            is_match != negated && glob_match(&pattern[end + 3..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::MetadataExt,
+    path::Path,
+};
+
+use sha1::{Digest, Sha1};
+
+use crate::{
+    fs_utils::git_dir,
+    object::{GitObject, GitTreeItem},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+const INDEX_SIGNATURE: &[u8; 4] = b"DIRC";
+
+/// Flag telling an extended flags field follows (index version 3).
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+
+/// Staging area stored in `.git/index`.
+///
+/// See: https://git-scm.com/docs/index-format
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Index {
+    /// Entries sorted by path then stage.
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub hash_code: HashCode,
+    /// Merge stage: 0 for a regular entry, 1 to 3 for conflicts.
+    pub stage: u8,
+    pub path: String,
+}
+
+impl IndexEntry {
+    /// Build entry for a file freshly hashed from the worktree.
+    pub fn from_metadata(path: &str, metadata: &fs::Metadata, hash_code: HashCode) -> Self {
+        Self {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode: worktree_mode(metadata),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            hash_code,
+            stage: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Build entry without any stat information, like entries read from a tree.
+    pub fn new(path: &str, mode: u32, hash_code: HashCode) -> Self {
+        Self {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash_code,
+            stage: 0,
+            path: path.to_string(),
+        }
+    }
+
+    /// Check if cached stat information still matches file on disk.
+    ///
+    /// When it does, the file is assumed unchanged and does not need to be hashed again.
+    pub fn is_stat_clean(&self, metadata: &fs::Metadata) -> bool {
+        self.mtime == (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            && self.ctime == (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            && self.ino == metadata.ino() as u32
+            && self.size == metadata.size() as u32
+            && self.mode == worktree_mode(metadata)
+    }
+}
+
+impl Index {
+    /// Read index of repository, an empty one is returned if there is no index yet.
+    pub fn read_at<P: AsRef<Path>>(root: P) -> Result<Self, GitError> {
+        match fs::read(git_dir(root.as_ref()).join("index")) {
+            Ok(data) => Self::parse(&data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, GitError> {
+        if data.len() < 12 + 20 {
+            return Err(GitError::invalid_content("Index file too small"));
+        }
+        let (content, checksum) = data.split_at(data.len() - 20);
+        if Sha1::digest(content).as_slice() != checksum {
+            return Err(GitError::invalid_content("Index checksum mismatch"));
+        }
+
+        if &content[..4] != INDEX_SIGNATURE {
+            return Err(GitError::invalid_content("Invalid index signature"));
+        }
+        let version = read_u32(content, 4)?;
+        if version != 2 && version != 3 {
+            return Err(GitError::InvalidContent(format!(
+                "Unsupported index version: {version}"
+            )));
+        }
+        let count = read_u32(content, 8)?;
+
+        let mut entries = Vec::with_capacity(count.min(65_536) as usize);
+        let mut offset = 12;
+        for _ in 0..count {
+            let start = offset;
+            let mut fields = [0_u32; 10];
+            for field in &mut fields {
+                *field = read_u32(content, offset)?;
+                offset += 4;
+            }
+
+            let hash_code: HashCode = content
+                .get(offset..offset + 20)
+                .and_then(|x| x.try_into().ok())
+                .ok_or_else(|| GitError::invalid_content("Truncated index entry"))?;
+            offset += 20;
+
+            let flags = read_u16(content, offset)?;
+            offset += 2;
+            if flags & FLAG_EXTENDED != 0 {
+                offset += 2;
+            }
+
+            // Name length is only a hint when longer than the mask, rely on the NUL terminator.
+            let name_len = content
+                .get(offset..)
+                .and_then(|x| x.iter().position(|x| *x == 0))
+                .ok_or_else(|| GitError::invalid_content("Truncated index entry name"))?;
+            if usize::from(flags & FLAG_NAME_MASK) != name_len.min(FLAG_NAME_MASK as usize) {
+                return Err(GitError::invalid_content("Invalid index entry name length"));
+            }
+            let path = std::str::from_utf8(&content[offset..offset + name_len])?.to_string();
+            offset += name_len;
+
+            // Entry is padded with 1 to 8 NUL bytes to keep a multiple of 8 length.
+            offset = start + (offset - start + 8) / 8 * 8;
+
+            entries.push(IndexEntry {
+                ctime: (fields[0], fields[1]),
+                mtime: (fields[2], fields[3]),
+                dev: fields[4],
+                ino: fields[5],
+                mode: fields[6],
+                uid: fields[7],
+                gid: fields[8],
+                size: fields[9],
+                hash_code,
+                stage: ((flags & FLAG_STAGE_MASK) >> 12) as u8,
+                path,
+            });
+        }
+
+        // Extensions (cached tree, resolve undo, ...) are skipped: they are only caches
+        // and are not written back.
+        if offset > content.len() {
+            return Err(GitError::invalid_content("Truncated index entry"));
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// Encode index using version 2 format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(12 + self.entries.len() * 80 + 20);
+        output.extend_from_slice(INDEX_SIGNATURE);
+        output.extend_from_slice(&2_u32.to_be_bytes());
+        output.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+
+        for entry in &self.entries {
+            let start = output.len();
+            for field in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                entry.size,
+            ] {
+                output.extend_from_slice(&field.to_be_bytes());
+            }
+            output.extend_from_slice(&entry.hash_code);
+
+            let name_len = entry.path.len().min(FLAG_NAME_MASK as usize) as u16;
+            let flags = (u16::from(entry.stage & 0x3) << 12) | name_len;
+            output.extend_from_slice(&flags.to_be_bytes());
+            output.extend_from_slice(entry.path.as_bytes());
+
+            let padding = 8 - (output.len() - start) % 8;
+            output.resize(output.len() + padding, 0);
+        }
+
+        let checksum = Sha1::digest(&output);
+        output.extend_from_slice(&checksum);
+        output
+    }
+
+    /// Write index, holding `.git/index.lock` while doing it like git does.
+    pub fn write_at<P: AsRef<Path>>(&self, root: P) -> Result<(), GitError> {
+        let git_dir = git_dir(root.as_ref());
+        let lock_path = git_dir.join("index.lock");
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => GitError::Io(format!(
+                    "Unable to create {}: index is locked by another process",
+                    lock_path.display()
+                )),
+                _ => err.into(),
+            })?;
+
+        let result = file
+            .write_all(&self.to_bytes())
+            .and_then(|()| file.sync_all())
+            .and_then(|()| fs::rename(&lock_path, git_dir.join("index")));
+        if result.is_err() {
+            let _ = fs::remove_file(&lock_path);
+        }
+        Ok(result?)
+    }
+
+    pub fn find(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.position(path, stage).ok().map(|x| &self.entries[x])
+    }
+
+    /// Insert or replace entry, keeping entries sorted.
+    pub fn add(&mut self, entry: IndexEntry) {
+        match self.position(&entry.path, entry.stage) {
+            Ok(idx) => self.entries[idx] = entry,
+            Err(idx) => self.entries.insert(idx, entry),
+        }
+    }
+
+    /// Remove every stage of `path`.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|x| x.path != path);
+    }
+
+    /// Check if some entries are in a conflicted (non zero) stage.
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|x| x.stage != 0)
+    }
+
+    /// Write trees for stage 0 entries, like `git write-tree`, returning the root tree ID.
+    ///
+    /// Fails if conflicts are still unresolved.
+    pub fn write_tree(&self, store: &ObjectStore) -> Result<HashCode, GitError> {
+        if self.has_conflicts() {
+            return Err(GitError::invalid_content(
+                "Cannot write tree with unmerged entries",
+            ));
+        }
+        write_tree_level(store, &self.entries, 0)
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|x| (x.path.as_bytes(), x.stage).cmp(&(path.as_bytes(), stage)))
+    }
+}
+
+/// Write tree of `entries`, all of them sharing a `prefix_len` bytes long directory prefix.
+///
+/// Index order puts `a.txt` before `a/x`, which is already tree order, so entries of a
+/// sub-directory are always contiguous.
+fn write_tree_level(
+    store: &ObjectStore,
+    entries: &[IndexEntry],
+    prefix_len: usize,
+) -> Result<HashCode, GitError> {
+    let mut items = Vec::new();
+    let mut idx = 0;
+
+    while idx < entries.len() {
+        let name = &entries[idx].path[prefix_len..];
+        match name.split_once('/') {
+            Some((dir, _)) => {
+                let dir_prefix = format!("{dir}/");
+                let len = entries[idx..]
+                    .iter()
+                    .take_while(|x| x.path[prefix_len..].starts_with(&dir_prefix))
+                    .count();
+                items.push(GitTreeItem {
+                    mode: 0o40000,
+                    name: dir.to_string(),
+                    hash_code: write_tree_level(
+                        store,
+                        &entries[idx..idx + len],
+                        prefix_len + dir_prefix.len(),
+                    )?,
+                });
+                idx += len;
+            }
+            None => {
+                items.push(GitTreeItem {
+                    mode: entries[idx].mode,
+                    name: name.to_string(),
+                    hash_code: entries[idx].hash_code,
+                });
+                idx += 1;
+            }
+        }
+    }
+
+    store.write(&GitObject::Tree(items))
+}
+
+/// Git mode of a file found in the worktree.
+pub fn worktree_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        0o120000
+    } else if metadata.mode() & 0o111 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, GitError> {
+    data.get(offset..offset + 4)
+        .map(|x| u32::from_be_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| GitError::invalid_content("Truncated index"))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, GitError> {
+    data.get(offset..offset + 2)
+        .map(|x| u16::from_be_bytes(x.try_into().unwrap()))
+        .ok_or_else(|| GitError::invalid_content("Truncated index"))
+}
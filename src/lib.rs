@@ -1,18 +1,48 @@
+pub mod branch;
+pub mod checkout;
 pub mod clone;
+pub mod commit;
+pub mod config;
+pub mod diff;
+pub mod download;
 mod error;
+pub mod fetch;
 pub mod fs_utils;
+pub mod fsck;
 pub mod header;
+pub mod http_server;
+pub mod ignore;
+pub mod index;
+pub mod line_diff;
+pub mod log;
+pub mod merge;
 pub mod object;
 pub mod pack_file;
+pub mod pack_index;
+pub mod pack_writer;
 pub mod packet_line;
+pub mod patch;
+pub mod promisor;
+pub mod push;
+pub mod receive_pack;
+pub mod refs;
+pub mod remote;
+pub mod revision;
+pub mod shallow;
+pub mod sideband;
+pub mod signature;
+pub mod store;
+pub mod tag;
+pub mod transport;
+pub mod upload_pack;
 
 pub use error::*;
 
 pub type HashCode = [u8; 20];
 
-pub fn hash_code_text_to_array(input: &str) -> HashCode {
+pub fn hash_code_text_to_array(input: &str) -> Result<HashCode, GitError> {
     let mut array = [0_u8; 20];
-    let data = hex::decode(input).expect("Invalid hash code received");
-    array.copy_from_slice(&data);
-    array
+    hex::decode_to_slice(input, &mut array)
+        .map_err(|_err| GitError::InvalidHashCode(input.to_string()))?;
+    Ok(array)
 }
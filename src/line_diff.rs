@@ -0,0 +1,885 @@
+use std::{collections::HashMap, fmt::Write, ops::Range, str::FromStr};
+
+use crate::GitError;
+
+/// Amount of bytes looked at to decide if a content is binary, like git.
+const BINARY_CHECK_LEN: usize = 8000;
+
+/// Lines occurring more than this in a region are not used as histogram anchors.
+const HISTOGRAM_MAX_CHAIN: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DiffAlgorithm {
+    #[default]
+    Myers,
+    Patience,
+    Histogram,
+}
+
+impl FromStr for DiffAlgorithm {
+    type Err = GitError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "myers" | "default" => Ok(Self::Myers),
+            "patience" => Ok(Self::Patience),
+            "histogram" => Ok(Self::Histogram),
+            _ => Err(GitError::InvalidContent(format!(
+                "Unknown diff algorithm: {input}"
+            ))),
+        }
+    }
+}
+
+/// Line level edit, holding line indexes in old and / or new content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineOp {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Check if content looks binary: it has a NUL byte near its start.
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_CHECK_LEN)].contains(&0)
+}
+
+/// Split content in lines, keeping their `\n` terminator.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut output = Vec::new();
+    let mut start = 0;
+    for (idx, byte) in content.iter().enumerate() {
+        if *byte == b'\n' {
+            output.push(&content[start..=idx]);
+            start = idx + 1;
+        }
+    }
+    if start < content.len() {
+        output.push(&content[start..]);
+    }
+    output
+}
+
+/// Compute edits turning `old` lines into `new` ones.
+pub fn diff_lines(old: &[&[u8]], new: &[&[u8]], algorithm: DiffAlgorithm) -> Vec<LineOp> {
+    // Compare small integers instead of lines.
+    let mut ids = HashMap::new();
+    let mut intern = |lines: &[&[u8]]| -> Vec<usize> {
+        lines
+            .iter()
+            .map(|line| {
+                let next_id = ids.len();
+                *ids.entry(line.to_vec()).or_insert(next_id)
+            })
+            .collect()
+    };
+    let old_ids = intern(old);
+    let new_ids = intern(new);
+
+    let mut output = Vec::with_capacity(old.len().max(new.len()));
+    diff_range(
+        &old_ids,
+        &new_ids,
+        0..old_ids.len(),
+        0..new_ids.len(),
+        algorithm,
+        &mut output,
+    );
+
+    // Normalize ambiguous changes positions like git, so output does not depend on
+    // which equivalent path the algorithm found.
+    let mut old_changed = vec![false; old.len()];
+    let mut new_changed = vec![false; new.len()];
+    for op in output {
+        match op {
+            LineOp::Delete(idx) => old_changed[idx] = true,
+            LineOp::Insert(idx) => new_changed[idx] = true,
+            LineOp::Equal(..) => {}
+        }
+    }
+    compact_changes(&old_ids, &mut old_changed, &new_changed, old);
+    compact_changes(&new_ids, &mut new_changed, &old_changed, new);
+
+    ops_from_changes(&old_changed, &new_changed)
+}
+
+fn ops_from_changes(old_changed: &[bool], new_changed: &[bool]) -> Vec<LineOp> {
+    let mut output = Vec::with_capacity(old_changed.len().max(new_changed.len()));
+    let (mut old_idx, mut new_idx) = (0, 0);
+
+    while old_idx < old_changed.len() || new_idx < new_changed.len() {
+        let mut is_equal = true;
+        while old_changed.get(old_idx) == Some(&true) {
+            output.push(LineOp::Delete(old_idx));
+            old_idx += 1;
+            is_equal = false;
+        }
+        while new_changed.get(new_idx) == Some(&true) {
+            output.push(LineOp::Insert(new_idx));
+            new_idx += 1;
+            is_equal = false;
+        }
+        if is_equal {
+            output.push(LineOp::Equal(old_idx, new_idx));
+            old_idx += 1;
+            new_idx += 1;
+        }
+    }
+
+    output
+}
+
+/// Run of changed lines `start..end`, possibly empty.
+#[derive(Clone, Copy)]
+struct Group {
+    start: usize,
+    end: usize,
+}
+
+/// View of one side lines and of which ones are changed, with group moves from xdiff.
+struct ChangeSide<'a> {
+    ids: &'a [usize],
+    changed: Vec<bool>,
+}
+
+impl ChangeSide<'_> {
+    fn is_changed(&self, idx: isize) -> bool {
+        idx >= 0 && self.changed.get(idx as usize).copied().unwrap_or(false)
+    }
+
+    fn first_group(&self) -> Group {
+        let mut end = 0;
+        while self.is_changed(end as isize) {
+            end += 1;
+        }
+        Group { start: 0, end }
+    }
+
+    fn next_group(&self, group: &mut Group) -> bool {
+        if group.end == self.changed.len() {
+            return false;
+        }
+        group.start = group.end + 1;
+        group.end = group.start;
+        while self.is_changed(group.end as isize) {
+            group.end += 1;
+        }
+        true
+    }
+
+    fn previous_group(&self, group: &mut Group) -> bool {
+        if group.start == 0 {
+            return false;
+        }
+        group.end = group.start - 1;
+        group.start = group.end;
+        while self.is_changed(group.start as isize - 1) {
+            group.start -= 1;
+        }
+        true
+    }
+
+    fn slide_down(&mut self, group: &mut Group) -> bool {
+        if group.end < self.changed.len() && self.ids[group.start] == self.ids[group.end] {
+            self.changed[group.start] = false;
+            self.changed[group.end] = true;
+            group.start += 1;
+            group.end += 1;
+            while self.is_changed(group.end as isize) {
+                group.end += 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn slide_up(&mut self, group: &mut Group) -> bool {
+        if group.start > 0 && self.ids[group.start - 1] == self.ids[group.end - 1] {
+            group.start -= 1;
+            group.end -= 1;
+            self.changed[group.start] = true;
+            self.changed[group.end] = false;
+            while self.is_changed(group.start as isize - 1) {
+                group.start -= 1;
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Slide groups of changes like xdiff `xdl_change_compact` does: merge groups when
+/// possible, align them with changes of the other side, or else use indent heuristic.
+fn compact_changes(
+    ids: &[usize],
+    changed: &mut Vec<bool>,
+    other_changed: &[bool],
+    lines: &[&[u8]],
+) {
+    let mut side = ChangeSide {
+        ids,
+        changed: std::mem::take(changed),
+    };
+    let other = ChangeSide {
+        ids: &[],
+        changed: other_changed.to_vec(),
+    };
+
+    let mut group = side.first_group();
+    let mut other_group = other.first_group();
+
+    loop {
+        if group.end != group.start {
+            let mut group_size;
+            let mut earliest_end;
+            let mut end_matching_other;
+
+            loop {
+                group_size = group.end - group.start;
+                end_matching_other = None;
+
+                while side.slide_up(&mut group) {
+                    other.previous_group(&mut other_group);
+                }
+                earliest_end = group.end;
+                if other_group.end > other_group.start {
+                    end_matching_other = Some(group.end);
+                }
+
+                while side.slide_down(&mut group) {
+                    other.next_group(&mut other_group);
+                    if other_group.end > other_group.start {
+                        end_matching_other = Some(group.end);
+                    }
+                }
+
+                // Sliding may have merged groups together, do it again.
+                if group_size == group.end - group.start {
+                    break;
+                }
+            }
+
+            if group.end == earliest_end {
+                // No shifting possible.
+            } else if end_matching_other.is_some() {
+                while other_group.end == other_group.start {
+                    side.slide_up(&mut group);
+                    other.previous_group(&mut other_group);
+                }
+            } else {
+                let mut shift = earliest_end.max(group.end.saturating_sub(group_size + 1));
+                shift = shift.max(group.end.saturating_sub(INDENT_HEURISTIC_MAX_SLIDING));
+
+                let mut best: Option<(usize, SplitScore)> = None;
+                while shift <= group.end {
+                    let mut score = SplitScore::default();
+                    score.add(&SplitMeasurement::new(lines, shift));
+                    score.add(&SplitMeasurement::new(lines, shift - group_size));
+                    if best.as_ref().map_or(true, |(_, best)| score.cmp(best) <= 0) {
+                        best = Some((shift, score));
+                    }
+                    shift += 1;
+                }
+
+                if let Some((best_shift, _)) = best {
+                    while group.end > best_shift {
+                        side.slide_up(&mut group);
+                        other.previous_group(&mut other_group);
+                    }
+                }
+            }
+        }
+
+        if !side.next_group(&mut group) {
+            break;
+        }
+        other.next_group(&mut other_group);
+    }
+
+    *changed = side.changed;
+}
+
+/// Maximum length of function context in hunk headers, like xdiff.
+const FUNCTION_CONTEXT_MAX_LEN: usize = 80;
+
+const INDENT_HEURISTIC_MAX_SLIDING: usize = 100;
+const MAX_INDENT: isize = 200;
+const MAX_BLANKS: isize = 20;
+
+/// Indent of a line, `-1` for blank lines.
+fn line_indent(line: &[u8]) -> isize {
+    let mut output = 0;
+    for c in line {
+        if !c.is_ascii_whitespace() && *c != 0x0b {
+            return output;
+        }
+        if *c == b' ' {
+            output += 1;
+        } else if *c == b'\t' {
+            output += 8 - output % 8;
+        }
+        if output >= MAX_INDENT {
+            return MAX_INDENT;
+        }
+    }
+    -1
+}
+
+/// Surroundings of a split point between two lines, used by the indent heuristic.
+struct SplitMeasurement {
+    end_of_file: bool,
+    indent: isize,
+    pre_blank: isize,
+    pre_indent: isize,
+    post_blank: isize,
+    post_indent: isize,
+}
+
+impl SplitMeasurement {
+    fn new(lines: &[&[u8]], split: usize) -> Self {
+        let (end_of_file, indent) = match lines.get(split) {
+            Some(line) => (false, line_indent(line)),
+            None => (true, -1),
+        };
+
+        let mut pre_blank = 0;
+        let mut pre_indent = -1;
+        for line in lines[..split.min(lines.len())].iter().rev() {
+            pre_indent = line_indent(line);
+            if pre_indent != -1 {
+                break;
+            }
+            pre_blank += 1;
+            if pre_blank == MAX_BLANKS {
+                pre_indent = 0;
+                break;
+            }
+        }
+
+        let mut post_blank = 0;
+        let mut post_indent = -1;
+        for line in lines.iter().skip(split + 1) {
+            post_indent = line_indent(line);
+            if post_indent != -1 {
+                break;
+            }
+            post_blank += 1;
+            if post_blank == MAX_BLANKS {
+                post_indent = 0;
+                break;
+            }
+        }
+
+        Self {
+            end_of_file,
+            indent,
+            pre_blank,
+            pre_indent,
+            post_blank,
+            post_indent,
+        }
+    }
+}
+
+/// Badness of a split, weights are the ones tuned for git.
+#[derive(Default)]
+struct SplitScore {
+    effective_indent: isize,
+    penalty: isize,
+}
+
+impl SplitScore {
+    fn add(&mut self, m: &SplitMeasurement) {
+        if m.pre_indent == -1 && m.pre_blank == 0 {
+            self.penalty += 1;
+        }
+        if m.end_of_file {
+            self.penalty += 21;
+        }
+
+        let post_blank = if m.indent == -1 { 1 + m.post_blank } else { 0 };
+        let total_blank = m.pre_blank + post_blank;
+        self.penalty += -30 * total_blank;
+        self.penalty += 6 * post_blank;
+
+        let indent = if m.indent != -1 {
+            m.indent
+        } else {
+            m.post_indent
+        };
+        let any_blanks = total_blank != 0;
+        self.effective_indent += indent;
+
+        if indent == -1 || m.pre_indent == -1 || indent == m.pre_indent {
+            // No adjustment.
+        } else if indent > m.pre_indent {
+            self.penalty += if any_blanks { 10 } else { -4 };
+        } else if m.post_indent != -1 && m.post_indent > indent {
+            self.penalty += if any_blanks { 17 } else { 24 };
+        } else {
+            self.penalty += if any_blanks { 17 } else { 23 };
+        }
+    }
+
+    fn cmp(&self, other: &Self) -> isize {
+        let cmp_indents = (self.effective_indent > other.effective_indent) as isize
+            - ((self.effective_indent < other.effective_indent) as isize);
+        60 * cmp_indents + (self.penalty - other.penalty)
+    }
+}
+
+fn diff_range(
+    old: &[usize],
+    new: &[usize],
+    mut old_range: Range<usize>,
+    mut new_range: Range<usize>,
+    algorithm: DiffAlgorithm,
+    output: &mut Vec<LineOp>,
+) {
+    // Common prefix and suffix never need to be diffed.
+    while !old_range.is_empty()
+        && !new_range.is_empty()
+        && old[old_range.start] == new[new_range.start]
+    {
+        output.push(LineOp::Equal(old_range.start, new_range.start));
+        old_range.start += 1;
+        new_range.start += 1;
+    }
+    let mut suffix = Vec::new();
+    while !old_range.is_empty()
+        && !new_range.is_empty()
+        && old[old_range.end - 1] == new[new_range.end - 1]
+    {
+        old_range.end -= 1;
+        new_range.end -= 1;
+        suffix.push(LineOp::Equal(old_range.end, new_range.end));
+    }
+
+    if old_range.is_empty() {
+        output.extend(new_range.map(LineOp::Insert));
+    } else if new_range.is_empty() {
+        output.extend(old_range.map(LineOp::Delete));
+    } else {
+        let anchors = match algorithm {
+            DiffAlgorithm::Myers => None,
+            DiffAlgorithm::Patience => patience_anchors(old, new, &old_range, &new_range),
+            DiffAlgorithm::Histogram => histogram_anchor(old, new, &old_range, &new_range),
+        };
+
+        match anchors {
+            Some(anchors) => {
+                // Diff regions between anchors recursively.
+                let (mut old_pos, mut new_pos) = (old_range.start, new_range.start);
+                for (old_anchor, new_anchor, len) in anchors {
+                    diff_range(
+                        old,
+                        new,
+                        old_pos..old_anchor,
+                        new_pos..new_anchor,
+                        algorithm,
+                        output,
+                    );
+                    for idx in 0..len {
+                        output.push(LineOp::Equal(old_anchor + idx, new_anchor + idx));
+                    }
+                    old_pos = old_anchor + len;
+                    new_pos = new_anchor + len;
+                }
+                diff_range(
+                    old,
+                    new,
+                    old_pos..old_range.end,
+                    new_pos..new_range.end,
+                    algorithm,
+                    output,
+                );
+            }
+            None => myers(old, new, old_range, new_range, output),
+        }
+    }
+
+    output.extend(suffix.into_iter().rev());
+}
+
+/// Myers O(ND) algorithm, in its linear space variant: find a middle point of an
+/// optimal edit path, then diff both halves.
+///
+/// See: http://www.xmailserver.org/diff2.pdf
+fn myers(
+    old: &[usize],
+    new: &[usize],
+    old_range: Range<usize>,
+    new_range: Range<usize>,
+    output: &mut Vec<LineOp>,
+) {
+    match myers_split(&old[old_range.clone()], &new[new_range.clone()]) {
+        Some((x, y)) => {
+            let (x, y) = (old_range.start + x, new_range.start + y);
+            let algorithm = DiffAlgorithm::Myers;
+            diff_range(
+                old,
+                new,
+                old_range.start..x,
+                new_range.start..y,
+                algorithm,
+                output,
+            );
+            diff_range(
+                old,
+                new,
+                x..old_range.end,
+                y..new_range.end,
+                algorithm,
+                output,
+            );
+        }
+        None => {
+            output.extend(old_range.map(LineOp::Delete));
+            output.extend(new_range.map(LineOp::Insert));
+        }
+    }
+}
+
+/// Find where forward and backward searches of shortest edit path overlap.
+fn myers_split(old: &[usize], new: &[usize]) -> Option<(usize, usize)> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let v_len = 2 * max_d + 2;
+
+    // Furthest x reached on each diagonal, from start and from end.
+    let mut forward = vec![-1_isize; v_len as usize];
+    let mut backward = vec![-1_isize; v_len as usize];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+
+    let delta = n - m;
+    let is_odd = delta % 2 != 0;
+    // Diagonals going out of the grid are trimmed from the search.
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let k1_offset = (offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[k1_offset - 1] < forward[k1_offset + 1])
+            {
+                forward[k1_offset + 1]
+            } else {
+                forward[k1_offset - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && old[x1 as usize] == new[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            forward[k1_offset] = x1;
+
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if is_odd {
+                let k2_offset = offset + delta - k1;
+                if (0..v_len).contains(&k2_offset) && backward[k2_offset as usize] != -1 {
+                    let x2 = n - backward[k2_offset as usize];
+                    if x1 >= x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let k2_offset = (offset + k2) as usize;
+            let mut x2 =
+                if k2 == -d || (k2 != d && backward[k2_offset - 1] < backward[k2_offset + 1]) {
+                    backward[k2_offset + 1]
+                } else {
+                    backward[k2_offset - 1] + 1
+                };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && old[(n - x2 - 1) as usize] == new[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            backward[k2_offset] = x2;
+
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !is_odd {
+                let k1_offset = offset + delta - k2;
+                if (0..v_len).contains(&k1_offset) && forward[k1_offset as usize] != -1 {
+                    let x1 = forward[k1_offset as usize];
+                    let y1 = offset + x1 - k1_offset;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+
+    None
+}
+
+/// Lines unique in both sides, in longest increasing order.
+fn patience_anchors(
+    old: &[usize],
+    new: &[usize],
+    old_range: &Range<usize>,
+    new_range: &Range<usize>,
+) -> Option<Vec<(usize, usize, usize)>> {
+    let mut counts: HashMap<usize, (usize, usize, usize)> = HashMap::new();
+    for idx in old_range.clone() {
+        let entry = counts.entry(old[idx]).or_default();
+        entry.0 += 1;
+        entry.2 = idx;
+    }
+    let mut unique = Vec::new();
+    for idx in new_range.clone() {
+        if let Some(entry) = counts.get_mut(&new[idx]) {
+            entry.1 += 1;
+        }
+    }
+    for idx in new_range.clone() {
+        if let Some((1, 1, old_idx)) = counts.get(&new[idx]) {
+            unique.push((*old_idx, idx));
+        }
+    }
+    if unique.is_empty() {
+        return None;
+    }
+
+    // Longest increasing subsequence on old indexes, using patience sorting.
+    let mut piles: Vec<usize> = Vec::new();
+    let mut previous = vec![None; unique.len()];
+    for (idx, (old_idx, _)) in unique.iter().enumerate() {
+        let pile = piles.partition_point(|x| unique[*x].0 < *old_idx);
+        if pile > 0 {
+            previous[idx] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(idx);
+        } else {
+            piles[pile] = idx;
+        }
+    }
+
+    let mut anchors = Vec::new();
+    let mut current = piles.last().copied();
+    while let Some(idx) = current {
+        anchors.push((unique[idx].0, unique[idx].1, 1));
+        current = previous[idx];
+    }
+    anchors.reverse();
+    Some(anchors)
+}
+
+/// Longest common region around the least frequent line of `old` found in `new`.
+fn histogram_anchor(
+    old: &[usize],
+    new: &[usize],
+    old_range: &Range<usize>,
+    new_range: &Range<usize>,
+) -> Option<Vec<(usize, usize, usize)>> {
+    let mut occurrences: HashMap<usize, Vec<usize>> = HashMap::new();
+    for idx in old_range.clone() {
+        occurrences.entry(old[idx]).or_default().push(idx);
+    }
+
+    // (occurrence count, len, old start, new start)
+    let mut best: Option<(usize, usize, usize, usize)> = None;
+    let mut new_idx = new_range.start;
+    while new_idx < new_range.end {
+        let mut next_idx = new_idx + 1;
+        if let Some(positions) = occurrences.get(&new[new_idx]) {
+            if positions.len() <= HISTOGRAM_MAX_CHAIN {
+                for &old_idx in positions {
+                    // Extend match in both directions.
+                    let (mut old_start, mut new_start) = (old_idx, new_idx);
+                    while old_start > old_range.start
+                        && new_start > new_range.start
+                        && old[old_start - 1] == new[new_start - 1]
+                    {
+                        old_start -= 1;
+                        new_start -= 1;
+                    }
+                    let mut len = old_idx - old_start + 1;
+                    while old_start + len < old_range.end
+                        && new_start + len < new_range.end
+                        && old[old_start + len] == new[new_start + len]
+                    {
+                        len += 1;
+                    }
+
+                    let count = (old_start..old_start + len)
+                        .map(|x| occurrences[&old[x]].len())
+                        .min()
+                        .unwrap_or(usize::MAX);
+                    let is_better = match best {
+                        None => true,
+                        Some((best_count, best_len, ..)) => {
+                            count < best_count || (count == best_count && len > best_len)
+                        }
+                    };
+                    if is_better {
+                        best = Some((count, len, old_start, new_start));
+                        next_idx = next_idx.max(new_start + len);
+                    }
+                }
+            }
+        }
+        new_idx = next_idx;
+    }
+
+    best.map(|(_, len, old_start, new_start)| vec![(old_start, new_start, len)])
+}
+
+/// Group of changes surrounded by context lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub ops: Vec<LineOp>,
+}
+
+/// Group edits in hunks keeping `context` unchanged lines around each change.
+pub fn build_hunks(ops: &[LineOp], context: usize) -> Vec<Hunk> {
+    let mut output = Vec::new();
+    let change_indexes: Vec<_> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| !matches!(x, LineOp::Equal(..)))
+        .map(|(idx, _)| idx)
+        .collect();
+
+    let mut idx = 0;
+    while idx < change_indexes.len() {
+        let start = change_indexes[idx].saturating_sub(context);
+        let mut end = change_indexes[idx];
+        // Merge changes separated by less than two contexts.
+        while idx + 1 < change_indexes.len() && change_indexes[idx + 1] - end <= 2 * context + 1 {
+            idx += 1;
+            end = change_indexes[idx];
+        }
+        let end = (end + context + 1).min(ops.len());
+        idx += 1;
+
+        let hunk_ops = ops[start..end].to_vec();
+        let (mut old_start, mut new_start) = position_before(ops, start);
+        let old_len = hunk_ops
+            .iter()
+            .filter(|x| !matches!(x, LineOp::Insert(_)))
+            .count();
+        let new_len = hunk_ops
+            .iter()
+            .filter(|x| !matches!(x, LineOp::Delete(_)))
+            .count();
+
+        // Unified format uses 1-based lines, and the line before for empty ranges.
+        if old_len > 0 {
+            old_start += 1;
+        }
+        if new_len > 0 {
+            new_start += 1;
+        }
+        output.push(Hunk {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+            ops: hunk_ops,
+        });
+    }
+
+    output
+}
+
+/// Count old and new lines before `ops[idx]`.
+fn position_before(ops: &[LineOp], idx: usize) -> (usize, usize) {
+    ops[..idx].iter().fold((0, 0), |(old, new), op| match op {
+        LineOp::Equal(..) => (old + 1, new + 1),
+        LineOp::Delete(_) => (old + 1, new),
+        LineOp::Insert(_) => (old, new + 1),
+    })
+}
+
+fn format_range(start: usize, len: usize) -> String {
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{start},{len}")
+    }
+}
+
+/// Build unified diff hunks (without file headers) between two contents.
+pub fn unified_diff(old: &[u8], new: &[u8], context: usize, algorithm: DiffAlgorithm) -> String {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let ops = diff_lines(&old_lines, &new_lines, algorithm);
+
+    let mut output = String::new();
+    for hunk in build_hunks(&ops, context) {
+        write!(
+            output,
+            "@@ -{} +{} @@",
+            format_range(hunk.old_start, hunk.old_len),
+            format_range(hunk.new_start, hunk.new_len)
+        )
+        .unwrap();
+        let first_line = if hunk.old_len > 0 {
+            hunk.old_start - 1
+        } else {
+            hunk.old_start
+        };
+        match function_context(&old_lines[..first_line]) {
+            Some(name) => writeln!(output, " {name}").unwrap(),
+            None => output.push('\n'),
+        }
+
+        for op in hunk.ops {
+            let (prefix, line) = match op {
+                LineOp::Equal(idx, _) => (' ', old_lines[idx]),
+                LineOp::Delete(idx) => ('-', old_lines[idx]),
+                LineOp::Insert(idx) => ('+', new_lines[idx]),
+            };
+            output.push(prefix);
+            output.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                output.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    output
+}
+
+/// Find the line shown after hunk ranges, using git default rule: last line before the
+/// hunk starting with a letter, `_` or `$`.
+fn function_context(lines: &[&[u8]]) -> Option<String> {
+    let line = lines.iter().rev().find(
+        |x| matches!(x.first(), Some(c) if c.is_ascii_alphabetic() || *c == b'_' || *c == b'$'),
+    )?;
+    let line = &line[..line.len().min(FUNCTION_CONTEXT_MAX_LEN)];
+    Some(String::from_utf8_lossy(line).trim_end().to_string())
+}
+
+/// Count `(added, deleted)` lines.
+pub fn count_changes(old: &[u8], new: &[u8], algorithm: DiffAlgorithm) -> (usize, usize) {
+    let ops = diff_lines(&split_lines(old), &split_lines(new), algorithm);
+    ops.iter().fold((0, 0), |(added, deleted), op| match op {
+        LineOp::Equal(..) => (added, deleted),
+        LineOp::Delete(_) => (added, deleted + 1),
+        LineOp::Insert(_) => (added + 1, deleted),
+    })
+}
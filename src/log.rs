@@ -0,0 +1,403 @@
+use std::{
+    collections::{BinaryHeap, HashSet},
+    fmt::Write,
+};
+
+use crate::{
+    object::GitObject,
+    revision::{peel_to_commit, rev_parse, tree_entry_at},
+    signature::Signature,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// Parsed commit with its signatures decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommitInfo {
+    pub id: HashCode,
+    pub tree: HashCode,
+    pub parents: Vec<HashCode>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+impl CommitInfo {
+    pub fn read(store: &ObjectStore, id: HashCode) -> Result<Self, GitError> {
+        let GitObject::Commit {
+            tree,
+            parents,
+            author,
+            committer,
+            message,
+        } = store.read(id)?
+        else {
+            return Err(GitError::InvalidContent(format!(
+                "{} is not a commit",
+                hex::encode(id)
+            )));
+        };
+
+        let parse_signature = |value: Option<String>| -> Result<Signature, GitError> {
+            Signature::parse(value.as_deref().unwrap_or_default())
+        };
+        // Parents of shallow commits were never fetched: they are grafted away.
+        let parents = if store.is_shallow(id) {
+            Vec::new()
+        } else {
+            parents
+        };
+
+        Ok(Self {
+            id,
+            tree,
+            parents,
+            author: parse_signature(author)?,
+            committer: parse_signature(committer)?,
+            message,
+        })
+    }
+
+    /// First line of commit message.
+    pub fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or_default()
+    }
+
+    /// Commit message without its subject and following blank lines.
+    pub fn body(&self) -> &str {
+        match self.message.split_once('\n') {
+            Some((_, body)) => body.trim_start_matches('\n'),
+            None => "",
+        }
+    }
+}
+
+/// Set of commits to walk, built from command line revisions.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RevisionRange {
+    pub include: Vec<HashCode>,
+    pub exclude: Vec<HashCode>,
+    /// Exclude commits reachable from every included commit (`A...B`).
+    pub symmetric: bool,
+}
+
+impl RevisionRange {
+    /// Parse revisions like `main`, `^main`, `A..B` or `A...B`.
+    pub fn parse(store: &ObjectStore, revisions: &[String]) -> Result<Self, GitError> {
+        let mut range = Self::default();
+        let resolve = |rev: &str| -> Result<HashCode, GitError> {
+            peel_to_commit(store, rev_parse(store, rev)?)
+        };
+
+        for rev in revisions {
+            if let Some((left, right)) = rev.split_once("...") {
+                range.include.push(resolve(left)?);
+                range.include.push(resolve(right)?);
+                range.symmetric = true;
+            } else if let Some((left, right)) = rev.split_once("..") {
+                range.exclude.push(resolve(left)?);
+                range.include.push(resolve(right)?);
+            } else if let Some(rev) = rev.strip_prefix('^') {
+                range.exclude.push(resolve(rev)?);
+            } else {
+                range.include.push(resolve(rev)?);
+            }
+        }
+
+        if range.include.is_empty() {
+            range.include.push(resolve("HEAD")?);
+        }
+
+        Ok(range)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    pub max_count: Option<usize>,
+    pub first_parent: bool,
+    /// Only keep commits whose author contains this text.
+    pub author: Option<String>,
+    /// Only keep commits committed after this timestamp.
+    pub since: Option<i64>,
+    /// Only keep commits committed before this timestamp.
+    pub until: Option<i64>,
+    /// Only keep commits changing one of these paths.
+    pub paths: Vec<String>,
+}
+
+/// Commit waiting in the walk queue, newest first.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+struct QueuedCommit {
+    time: i64,
+    /// Insertion order breaks ties so older insertions come first.
+    seq: std::cmp::Reverse<u64>,
+    id: HashCode,
+}
+
+/// Walk commit graph from `range` in reverse chronological order.
+pub fn log(
+    store: &ObjectStore,
+    range: &RevisionRange,
+    options: &LogOptions,
+) -> Result<Vec<CommitInfo>, GitError> {
+    let mut excluded = ancestors(store, &range.exclude, false)?;
+    if range.symmetric {
+        let mut common: Option<HashSet<HashCode>> = None;
+        for id in &range.include {
+            let reachable = ancestors(store, &[*id], false)?;
+            common = Some(match common {
+                Some(common) => common.intersection(&reachable).copied().collect(),
+                None => reachable,
+            });
+        }
+        excluded.extend(common.unwrap_or_default());
+    }
+
+    let mut output = Vec::new();
+    let mut seen = HashSet::new();
+    let mut queue = BinaryHeap::new();
+    let mut seq = 0;
+
+    let mut push = |queue: &mut BinaryHeap<QueuedCommit>, commit: &CommitInfo| {
+        seq += 1;
+        queue.push(QueuedCommit {
+            time: commit.committer.time,
+            seq: std::cmp::Reverse(seq),
+            id: commit.id,
+        });
+    };
+
+    for id in &range.include {
+        if !excluded.contains(id) && seen.insert(*id) {
+            push(&mut queue, &CommitInfo::read(store, *id)?);
+        }
+    }
+
+    while let Some(QueuedCommit { id, .. }) = queue.pop() {
+        if options.max_count.is_some_and(|x| output.len() >= x) {
+            break;
+        }
+
+        let commit = CommitInfo::read(store, id)?;
+
+        let parents = if options.first_parent {
+            &commit.parents[..commit.parents.len().min(1)]
+        } else {
+            &commit.parents[..]
+        };
+        for parent in parents {
+            if !excluded.contains(parent) && seen.insert(*parent) {
+                push(&mut queue, &CommitInfo::read(store, *parent)?);
+            }
+        }
+
+        if is_selected(store, &commit, options)? {
+            output.push(commit);
+        }
+    }
+
+    Ok(output)
+}
+
+/// Every commit reachable from `starts`, including themselves.
+pub fn ancestors(
+    store: &ObjectStore,
+    starts: &[HashCode],
+    first_parent: bool,
+) -> Result<HashSet<HashCode>, GitError> {
+    let mut output = HashSet::new();
+    let mut stack = starts.to_vec();
+
+    while let Some(id) = stack.pop() {
+        if !output.insert(id) {
+            continue;
+        }
+
+        let commit = CommitInfo::read(store, id)?;
+        let parents = if first_parent {
+            &commit.parents[..commit.parents.len().min(1)]
+        } else {
+            &commit.parents[..]
+        };
+        stack.extend(parents.iter().filter(|x| !output.contains(*x)));
+    }
+
+    Ok(output)
+}
+
+fn is_selected(
+    store: &ObjectStore,
+    commit: &CommitInfo,
+    options: &LogOptions,
+) -> Result<bool, GitError> {
+    if options.since.is_some_and(|x| commit.committer.time < x)
+        || options.until.is_some_and(|x| commit.committer.time > x)
+    {
+        return Ok(false);
+    }
+
+    if let Some(author) = &options.author {
+        let identity = format!("{} <{}>", commit.author.name, commit.author.email);
+        if !identity.contains(author.as_str()) {
+            return Ok(false);
+        }
+    }
+
+    if options.paths.is_empty() {
+        return Ok(true);
+    }
+
+    // Keep commits that differ from every parent on selected paths.
+    let parents = if options.first_parent {
+        &commit.parents[..commit.parents.len().min(1)]
+    } else {
+        &commit.parents[..]
+    };
+
+    let mut current = Vec::with_capacity(options.paths.len());
+    for path in &options.paths {
+        current.push(tree_entry_at(store, commit.tree, path)?);
+    }
+
+    if parents.is_empty() {
+        return Ok(current.iter().any(|x| x.is_some()));
+    }
+
+    for parent in parents {
+        let parent_tree = CommitInfo::read(store, *parent)?.tree;
+        let mut is_same = true;
+        for (path, entry) in options.paths.iter().zip(&current) {
+            if tree_entry_at(store, parent_tree, path)? != *entry {
+                is_same = false;
+                break;
+            }
+        }
+        if is_same {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Format commit for `log` output.
+///
+/// `format` is either a preset (`oneline`, `short`, `medium`, `full`) or a
+/// format string with placeholders like `%h %s`, optionally prefixed by `format:`.
+pub fn format_commit(commit: &CommitInfo, format: &str) -> String {
+    let mut output = String::new();
+    let id = hex::encode(commit.id);
+
+    match format {
+        "oneline" => output.push_str(&format!("{} {}", &id[..7], commit.subject())),
+        "short" | "medium" | "full" => {
+            writeln!(output, "commit {id}").unwrap();
+            if commit.parents.len() > 1 {
+                let parents: Vec<_> = commit
+                    .parents
+                    .iter()
+                    .map(|x| hex::encode(x)[..7].to_string())
+                    .collect();
+                writeln!(output, "Merge: {}", parents.join(" ")).unwrap();
+            }
+            writeln!(
+                output,
+                "Author: {} <{}>",
+                commit.author.name, commit.author.email
+            )
+            .unwrap();
+            match format {
+                "medium" => writeln!(output, "Date:   {}", commit.author.format_date()).unwrap(),
+                "full" => writeln!(
+                    output,
+                    "Commit: {} <{}>",
+                    commit.committer.name, commit.committer.email
+                )
+                .unwrap(),
+                _ => {}
+            }
+            writeln!(output).unwrap();
+
+            let message = if format == "short" {
+                commit.subject()
+            } else {
+                &commit.message
+            };
+            for line in message.lines() {
+                if line.is_empty() {
+                    writeln!(output).unwrap();
+                } else {
+                    writeln!(output, "    {line}").unwrap();
+                }
+            }
+        }
+        format => {
+            let format = format
+                .strip_prefix("format:")
+                .or_else(|| format.strip_prefix("tformat:"))
+                .unwrap_or(format);
+            format_placeholders(&mut output, commit, format);
+        }
+    }
+
+    output
+}
+
+fn format_placeholders(output: &mut String, commit: &CommitInfo, format: &str) {
+    let id = hex::encode(commit.id);
+    let tree = hex::encode(commit.tree);
+    let parents: Vec<_> = commit.parents.iter().map(hex::encode).collect();
+
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let mut placeholder = String::new();
+        if let Some(next) = chars.next() {
+            placeholder.push(next);
+            // Two letters placeholders like `%an`.
+            if matches!(next, 'a' | 'c') {
+                if let Some(next) = chars.next() {
+                    placeholder.push(next);
+                }
+            }
+        }
+
+        match placeholder.as_str() {
+            "H" => output.push_str(&id),
+            "h" => output.push_str(&id[..7]),
+            "T" => output.push_str(&tree),
+            "t" => output.push_str(&tree[..7]),
+            "P" => output.push_str(&parents.join(" ")),
+            "p" => output.push_str(
+                &parents
+                    .iter()
+                    .map(|x| &x[..7])
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "an" => output.push_str(&commit.author.name),
+            "ae" => output.push_str(&commit.author.email),
+            "ad" => output.push_str(&commit.author.format_date()),
+            "at" => output.push_str(&commit.author.time.to_string()),
+            "cn" => output.push_str(&commit.committer.name),
+            "ce" => output.push_str(&commit.committer.email),
+            "cd" => output.push_str(&commit.committer.format_date()),
+            "ct" => output.push_str(&commit.committer.time.to_string()),
+            "s" => output.push_str(commit.subject()),
+            "b" => output.push_str(commit.body()),
+            "B" => output.push_str(&commit.message),
+            "n" => output.push('\n'),
+            "%" => output.push('%'),
+            // Unknown placeholders are printed as is, like git does.
+            other => {
+                output.push('%');
+                output.push_str(other);
+            }
+        }
+    }
+}
@@ -1,18 +1,53 @@
 use std::{
+    collections::BTreeMap,
     env, fs,
     io::{self, stdout, Write},
     path::{Path, PathBuf},
 };
 
 use bytes::Bytes;
-use clap::{Parser, Subcommand};
+use clap::{ArgAction, Parser, Subcommand};
 use git_starter_rust::{
-    clone::clone,
-    fs_utils::{read_compressed, write_compressed},
+    branch::{
+        ahead_behind, create_branch, delete_branch, is_ancestor, list_branches, read_upstream,
+        rename_branch, set_upstream,
+    },
+    checkout::{checkout, CheckoutOptions, CheckoutTarget},
+    clone::{clone, CloneOptions, ProtocolVersion},
+    commit::{add_paths, cleanup_message, commit, read_merge_heads, CommitOptions},
+    config::GitConfig,
+    diff::{
+        diff_file_maps, diff_index_to_worktree, diff_tree_to_index, diff_trees, flatten_tree,
+        parse_similarity, read_worktree_file, untracked_files, worktree_file_map, DiffEntry,
+        DiffOptions, DiffSide,
+    },
+    fetch::{fetch, RefUpdateStatus},
+    fsck::fsck_at,
     hash_code_text_to_array,
+    http_server::serve,
+    ignore::glob_match,
+    index::Index,
+    line_diff::DiffAlgorithm,
+    log::{format_commit, log, CommitInfo, LogOptions, RevisionRange},
+    merge::{abort_merge, merge, merge_bases, ConflictKind, MergeOptions, MergeOutcome},
     object::{GitObject, GitTreeItem},
+    patch::{format_patch, format_stat, FileStat, PatchOptions},
+    promisor::{fetch_missing, fetch_missing_tree, ObjectFilter},
+    push::{push, resolve_push_refspecs, PushStatus},
+    receive_pack::{receive_pack, ReceivePackOptions},
+    refs::{head_branch_at, resolve_ref_at},
+    remote::{short_ref_name, RefSpec, Remote},
+    revision::{peel_to_commit, peel_to_tree, rev_parse},
+    shallow::{read_shallow, ShallowOptions, INFINITE_DEPTH},
+    sideband::print_progress,
+    signature::{parse_date, Signature},
+    store::ObjectStore,
+    tag::{create_tag, delete_tag, list_tags, tag_message, tag_points_at, TagAnnotation},
+    transport::Transport,
+    upload_pack::{requested_version, upload_pack, UploadPackOptions},
     GitError, HashCode,
 };
+use tokio::net::TcpListener;
 
 #[derive(Parser)]
 struct Args {
@@ -73,7 +108,449 @@ enum SubCommand {
 
         /// Repo path
         dst: PathBuf,
+
+        /// Check out this branch, or tag, instead of the remote HEAD.
+        #[arg(short = 'b', long)]
+        branch: Option<String>,
+
+        /// Only fetch history of the checked out branch.
+        #[arg(long)]
+        single_branch: bool,
+
+        /// Do not check out HEAD after cloning.
+        #[arg(short = 'n', long)]
+        no_checkout: bool,
+
+        /// Make a bare repository, without worktree.
+        #[arg(long)]
+        bare: bool,
+
+        /// Make a bare repository mirroring all remote refs.
+        #[arg(long)]
+        mirror: bool,
+
+        /// Use the pack protocol for a repository given by path, like with a file:// URL.
+        #[arg(long)]
+        no_local: bool,
+
+        /// Copy objects of a local repository instead of hard linking them.
+        #[arg(long)]
+        no_hardlinks: bool,
+
+        #[command(flatten)]
+        shallow: ShallowArgs,
+
+        /// Partial clone leaving out objects on the server, like `blob:none`,
+        /// `blob:limit=<n>` or `tree:0`: they are fetched when needed.
+        #[arg(long, value_name = "filter-spec")]
+        filter: Option<String>,
+    },
+    /// Verify integrity and connectivity of the object store.
+    Fsck {
+        /// Print one JSON object per issue.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show commit logs.
+    Log {
+        /// Show each commit on a single line.
+        #[arg(long)]
+        oneline: bool,
+
+        /// Limit the number of commits to output.
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+
+        /// Output format: oneline, short, medium, full or `format:<string>`.
+        #[arg(long, visible_alias = "pretty")]
+        format: Option<String>,
+
+        /// Follow only the first parent of merge commits.
+        #[arg(long)]
+        first_parent: bool,
+
+        /// Only show commits whose author matches this text.
+        #[arg(long)]
+        author: Option<String>,
+
+        /// Only show commits more recent than this date.
+        #[arg(long, visible_alias = "after")]
+        since: Option<String>,
+
+        /// Only show commits older than this date.
+        #[arg(long, visible_alias = "before")]
+        until: Option<String>,
+
+        /// Revisions or ranges (`A..B`, `A...B`, `^A`) to walk from.
+        revisions: Vec<String>,
+
+        /// Show name and status of changed files.
+        #[arg(long)]
+        name_status: bool,
+
+        /// Only show commits modifying these paths.
+        #[arg(last = true)]
+        paths: Vec<String>,
+    },
+    /// Compare the content and mode of blobs found via two tree objects.
+    DiffTree {
+        /// Recurse into sub-trees.
+        #[arg(short)]
+        recursive: bool,
+
+        /// Show the diff of the root commit against an empty tree.
+        #[arg(long)]
+        root: bool,
+
+        /// Detect renames, with an optional similarity threshold (like `-M=90%`).
+        #[arg(short = 'M', long = "find-renames", num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+        find_renames: Option<String>,
+
+        /// Detect copies as well as renames, with an optional similarity threshold.
+        #[arg(short = 'C', long = "find-copies", num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+        find_copies: Option<String>,
+
+        /// Look for copy sources in unmodified files too.
+        #[arg(long)]
+        find_copies_harder: bool,
+
+        /// Show only names of changed files.
+        #[arg(long, conflicts_with = "name_status")]
+        name_only: bool,
+
+        /// Show only names and status of changed files.
+        #[arg(long)]
+        name_status: bool,
+
+        /// Tree, or commit to compare with its first parent.
+        old: String,
+
+        /// Tree to compare `old` with.
+        new: Option<String>,
+    },
+    /// Show the working tree status, in short format.
+    Status,
+    /// Show changes between commits, commit and working tree, etc.
+    Diff {
+        /// Compare the index with a commit (`HEAD` by default) instead of the worktree.
+        #[arg(long, visible_alias = "staged")]
+        cached: bool,
+
+        #[command(flatten)]
+        output: DiffOutputArgs,
+
+        /// Commits to compare.
+        revisions: Vec<String>,
+
+        /// Only show changes of these paths.
+        #[arg(last = true)]
+        paths: Vec<String>,
     },
+    /// Show an object: commit with its patch, tag, tree or blob.
+    Show {
+        #[command(flatten)]
+        output: DiffOutputArgs,
+
+        /// Object to show, `HEAD` by default.
+        object: Option<String>,
+    },
+    /// Switch branches or check out a commit in the working tree.
+    Checkout {
+        /// Create a new branch starting at <target> and switch to it.
+        #[arg(short = 'b', value_name = "NEW_BRANCH")]
+        new_branch: Option<String>,
+
+        /// Throw away local changes.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Check out a commit without moving to a branch, even if <target> is one.
+        #[arg(long)]
+        detach: bool,
+
+        /// Branch or commit to check out.
+        target: Option<String>,
+    },
+    /// Switch branches.
+    Switch {
+        /// Create a new branch starting at <target> and switch to it.
+        #[arg(short = 'c', long = "create", value_name = "NEW_BRANCH")]
+        new_branch: Option<String>,
+
+        /// Throw away local changes.
+        #[arg(short, long, visible_alias = "discard-changes")]
+        force: bool,
+
+        /// Switch to a commit instead of a branch.
+        #[arg(short, long)]
+        detach: bool,
+
+        /// Branch to switch to, or commit with `--detach`.
+        target: Option<String>,
+    },
+    /// List, create, rename or delete branches.
+    Branch {
+        /// List branches, only the ones matching given patterns if any.
+        #[arg(short, long)]
+        list: bool,
+
+        /// Delete branches, which must be merged.
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Delete branches, even if not merged.
+        #[arg(short = 'D')]
+        force_delete: bool,
+
+        /// Rename a branch (current one if a single name is given).
+        #[arg(short = 'm', long = "move")]
+        rename: bool,
+
+        /// Rename a branch, even if the new name already exists.
+        #[arg(short = 'M')]
+        force_rename: bool,
+
+        /// Reset the branch to <start-point> if it already exists.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Show commit of each branch, and its upstream when given twice.
+        #[arg(short, long, action = ArgAction::Count)]
+        verbose: u8,
+
+        /// Track this upstream, like `origin/main`.
+        #[arg(short = 'u', long, value_name = "UPSTREAM")]
+        set_upstream_to: Option<String>,
+
+        /// Stop tracking any upstream.
+        #[arg(long)]
+        unset_upstream: bool,
+
+        /// Only list branches containing this commit, `HEAD` by default.
+        #[arg(long, num_args = 0..=1, default_missing_value = "HEAD", require_equals = false)]
+        contains: Option<String>,
+
+        /// Branch names, `<name> [<start-point>]` to create one, or list patterns.
+        args: Vec<String>,
+    },
+    /// List, create or delete tags.
+    Tag {
+        /// List tags, only the ones matching given patterns if any.
+        #[arg(short, long)]
+        list: bool,
+
+        /// Delete tags.
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Create an annotated tag.
+        #[arg(short, long)]
+        annotate: bool,
+
+        /// Tag message, paragraphs being given by multiple `-m`. Implies `-a`.
+        #[arg(short, long)]
+        message: Vec<String>,
+
+        /// Read tag message from a file, `-` for standard input. Implies `-a`.
+        #[arg(short = 'F', long)]
+        file: Option<PathBuf>,
+
+        /// Replace an existing tag.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Print the first <n> lines of each tag message when listing (`-n=3`).
+        #[arg(short = 'n', num_args = 0..=1, require_equals = true, default_missing_value = "1")]
+        lines: Option<usize>,
+
+        /// Only list tags of this object, `HEAD` by default.
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "HEAD")]
+        points_at: Option<String>,
+
+        /// Tag names, `<name> [<object>]` to create one, or list patterns.
+        args: Vec<String>,
+    },
+    /// Add file contents to the index.
+    Add {
+        /// Files or directories to stage, `.` for the whole worktree.
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Record the index as a new commit on the current branch.
+    Commit {
+        /// Commit message, paragraphs being given by multiple `-m`.
+        #[arg(short, long)]
+        message: Vec<String>,
+
+        /// Read commit message from a file, `-` for standard input.
+        #[arg(short = 'F', long)]
+        file: Option<PathBuf>,
+
+        /// Replace the tip of the current branch.
+        #[arg(long)]
+        amend: bool,
+
+        /// Allow a commit with the same tree as its parent.
+        #[arg(long)]
+        allow_empty: bool,
+    },
+    /// Join another history into the current branch.
+    Merge {
+        /// Create a merge commit even when a fast-forward is possible.
+        #[arg(long)]
+        no_ff: bool,
+
+        /// Merge commit message.
+        #[arg(short, long)]
+        message: Option<String>,
+
+        /// Give up a merge stopped on conflicts.
+        #[arg(long, conflicts_with_all = ["no_ff", "message", "commit"])]
+        abort: bool,
+
+        /// Commit to merge, like a branch name.
+        #[arg(required_unless_present = "abort")]
+        commit: Option<String>,
+    },
+    /// Find best common ancestors of two commits.
+    MergeBase {
+        /// Print every merge base instead of only one.
+        #[arg(short, long)]
+        all: bool,
+
+        /// Commits to compare.
+        #[arg(num_args = 2, required = true)]
+        commits: Vec<String>,
+    },
+    /// Download objects and refs from another repository.
+    Fetch {
+        /// Remote name or URL, upstream remote of current branch or `origin` by default.
+        remote: Option<String>,
+
+        /// Refspecs to fetch instead of configured ones, like `main:refs/remotes/origin/main`.
+        refspecs: Vec<String>,
+
+        #[command(flatten)]
+        shallow: ShallowArgs,
+
+        /// Fetch <n> more commits from the current shallow boundary.
+        #[arg(long, value_name = "n", conflicts_with_all = ["depth", "unshallow"])]
+        deepen: Option<u32>,
+
+        /// Fetch the whole history of a shallow repository.
+        #[arg(long, conflicts_with_all = ["depth", "shallow_since", "shallow_exclude"])]
+        unshallow: bool,
+    },
+    /// Update remote refs along with the objects they need.
+    Push {
+        /// Remote name or URL, upstream remote of current branch or `origin` by default.
+        remote: Option<String>,
+
+        /// Refspecs to push, like `main` or `HEAD:refs/heads/topic`, current branch by default.
+        refspecs: Vec<String>,
+
+        /// Update remote refs even if they are not fast-forwarded.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Delete given remote refs.
+        #[arg(short, long)]
+        delete: bool,
+
+        /// Make pushed branches track their remote branch.
+        #[arg(short = 'u', long)]
+        set_upstream: bool,
+    },
+    /// Send objects to a client fetching from repository <dir>, over stdin and stdout.
+    UploadPack {
+        /// Answer a single request without advertising refs first, like for smart HTTP.
+        #[arg(long)]
+        stateless_rpc: bool,
+
+        /// Only advertise refs, or capabilities with protocol v2, then exit.
+        #[arg(long)]
+        advertise_refs: bool,
+
+        dir: PathBuf,
+    },
+    /// Update refs of repository <dir> pushed by a client, over stdin and stdout.
+    ReceivePack {
+        /// Answer a single request without advertising refs first, like for smart HTTP.
+        #[arg(long)]
+        stateless_rpc: bool,
+
+        /// Only advertise refs, then exit.
+        #[arg(long)]
+        advertise_refs: bool,
+
+        dir: PathBuf,
+    },
+    /// Serve repositories found under <root> to fetch from and push to.
+    ///
+    /// Pushing to a repository needs `http.receivepack` set to true in its config.
+    Serve {
+        /// Listen for smart HTTP requests on <addr>, like `127.0.0.1:8080`.
+        #[arg(long, value_name = "addr")]
+        http: String,
+
+        root: PathBuf,
+    },
+}
+
+/// History limits shared by `clone` and `fetch`.
+#[derive(clap::Args)]
+struct ShallowArgs {
+    /// Limit history to <depth> commits from each tip.
+    #[arg(long)]
+    depth: Option<u32>,
+
+    /// Limit history to commits more recent than <date>.
+    #[arg(long, value_name = "date")]
+    shallow_since: Option<String>,
+
+    /// Exclude history reachable from remote ref <rev>, may be repeated.
+    #[arg(long, value_name = "rev")]
+    shallow_exclude: Vec<String>,
+}
+
+/// Output options shared by `diff` and `show`.
+#[derive(clap::Args)]
+struct DiffOutputArgs {
+    /// Generate diffs with <n> lines of context.
+    #[arg(short = 'U', long = "unified", default_value_t = 3)]
+    context: usize,
+
+    /// Show a diffstat instead of a patch.
+    #[arg(long)]
+    stat: bool,
+
+    /// Show number of added and deleted lines in decimal notation.
+    #[arg(long)]
+    numstat: bool,
+
+    /// Show only names and status of changed files.
+    #[arg(long)]
+    name_status: bool,
+
+    /// Show only names of changed files.
+    #[arg(long)]
+    name_only: bool,
+
+    /// Detect renames, with an optional similarity threshold (like `-M=90%`).
+    #[arg(short = 'M', long = "find-renames", num_args = 0..=1, require_equals = true, default_missing_value = "50%")]
+    find_renames: Option<String>,
+
+    /// Diff algorithm: myers, patience or histogram.
+    #[arg(long, default_value = "myers")]
+    diff_algorithm: DiffAlgorithm,
+
+    /// Use patience diff algorithm.
+    #[arg(long)]
+    patience: bool,
+
+    /// Use histogram diff algorithm.
+    #[arg(long)]
+    histogram: bool,
 }
 
 #[tokio::main]
@@ -88,6 +565,8 @@ async fn main() -> anyhow::Result<()> {
         }
         SubCommand::CatFile { pretty, name } => {
             if pretty {
+                let id = hash_code_text_to_array(&name)?;
+                fetch_missing(".", &[id], &mut print_progress).await?;
                 command_cat_file(&name)?;
             }
             Ok(())
@@ -103,7 +582,9 @@ async fn main() -> anyhow::Result<()> {
             Ok(())
         }
         SubCommand::WriteTree => {
-            let hash_code = command_write_tree(env::current_dir().expect("Missing current dir"))?;
+            let store = ObjectStore::open(".")?;
+            let hash_code =
+                command_write_tree(&store, env::current_dir().expect("Missing current dir"))?;
 
             println!("{}", hex::encode(hash_code));
             Ok(())
@@ -114,21 +595,1268 @@ async fn main() -> anyhow::Result<()> {
             message,
         } => {
             let hash_code = command_commit_tree(
-                hash_code_text_to_array(&tree),
-                hash_code_text_to_array(&parent),
+                hash_code_text_to_array(&tree)?,
+                hash_code_text_to_array(&parent)?,
                 &message,
             )?;
 
             println!("{}", hex::encode(hash_code));
             Ok(())
         }
-        SubCommand::Clone { url, dst } => {
-            clone(&url, dst).await?;
+        SubCommand::Clone {
+            url,
+            dst,
+            branch,
+            single_branch,
+            no_checkout,
+            bare,
+            mirror,
+            no_local,
+            no_hardlinks,
+            shallow,
+            filter,
+        } => {
+            let options = CloneOptions {
+                branch,
+                single_branch,
+                no_checkout,
+                bare,
+                mirror,
+                no_local,
+                no_hardlinks,
+                shallow: shallow.shallow_options()?,
+                filter: filter.as_deref().map(ObjectFilter::parse).transpose()?,
+            };
+            clone(&url, dst, &options).await?;
+            Ok(())
+        }
+        SubCommand::Fsck { json } => {
+            let issues = fsck_at(".")?;
+            for issue in &issues {
+                if json {
+                    println!("{}", issue.to_json());
+                } else {
+                    println!("{issue}");
+                }
+            }
+
+            let error_count = issues.iter().filter(|x| x.is_error()).count();
+            if error_count > 0 {
+                anyhow::bail!("{error_count} error(s) found");
+            }
+            Ok(())
+        }
+        SubCommand::Log {
+            oneline,
+            max_count,
+            format,
+            first_parent,
+            author,
+            since,
+            until,
+            revisions,
+            name_status,
+            paths,
+        } => {
+            let store = ObjectStore::open(".")?;
+            let range = RevisionRange::parse(&store, &revisions)?;
+            let options = LogOptions {
+                max_count,
+                first_parent,
+                author,
+                since: since.as_deref().map(parse_date).transpose()?,
+                until: until.as_deref().map(parse_date).transpose()?,
+                paths,
+            };
+
+            let format = match (format, oneline) {
+                (Some(format), _) => format,
+                (None, true) => "oneline".to_string(),
+                (None, false) => "medium".to_string(),
+            };
+            let mut stdout = stdout().lock();
+            for (idx, commit) in log(&store, &range, &options)?.iter().enumerate() {
+                let output = format_commit(commit, &format);
+                // Multi lines presets and `format:` separate entries with a newline,
+                // other formats terminate each entry with one.
+                if matches!(format.as_str(), "short" | "medium" | "full")
+                    || format.starts_with("format:")
+                {
+                    if idx > 0 {
+                        writeln!(stdout)?;
+                    }
+                    write!(stdout, "{output}")?;
+                } else {
+                    writeln!(stdout, "{output}")?;
+                }
+
+                // Like git, merges are not diffed by default.
+                if name_status && commit.parents.len() <= 1 {
+                    let parent_tree = match commit.parents.first() {
+                        Some(parent) => Some(CommitInfo::read(&store, *parent)?.tree),
+                        None => None,
+                    };
+                    let options = DiffOptions {
+                        detect_renames: true,
+                        ..Default::default()
+                    };
+                    let changes = diff_trees(&store, parent_tree, Some(commit.tree), &options)?;
+                    if !changes.is_empty() && format != "oneline" {
+                        writeln!(stdout)?;
+                    }
+                    for change in changes {
+                        writeln!(stdout, "{}", change.name_status())?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        SubCommand::DiffTree {
+            recursive,
+            root,
+            find_renames,
+            find_copies,
+            find_copies_harder,
+            name_only,
+            name_status,
+            old,
+            new,
+        } => {
+            let store = ObjectStore::open(".")?;
+            let mut options = DiffOptions {
+                recursive,
+                detect_copies: find_copies.is_some() || find_copies_harder,
+                find_copies_harder,
+                ..Default::default()
+            };
+            if let Some(threshold) = find_copies.as_deref().or(find_renames.as_deref()) {
+                options.detect_renames = true;
+                options.rename_threshold = parse_similarity(threshold)?;
+            }
+            options.detect_renames |= options.detect_copies;
+
+            let (old_tree, new_tree) = match new {
+                Some(new) => (
+                    Some(peel_to_tree(&store, rev_parse(&store, &old)?)?),
+                    peel_to_tree(&store, rev_parse(&store, &new)?)?,
+                ),
+                None => {
+                    // Single commit: compare it with its first parent.
+                    let commit = CommitInfo::read(&store, rev_parse(&store, &old)?)?;
+                    let parent_tree = match commit.parents.first() {
+                        Some(parent) => Some(CommitInfo::read(&store, *parent)?.tree),
+                        None if root => None,
+                        None => return Ok(()),
+                    };
+                    println!("{}", hex::encode(commit.id));
+                    (parent_tree, commit.tree)
+                }
+            };
+
+            for change in diff_trees(&store, old_tree, Some(new_tree), &options)? {
+                if name_only {
+                    println!("{}", change.path());
+                } else if name_status {
+                    println!("{}", change.name_status());
+                } else {
+                    println!("{change}");
+                }
+            }
+            Ok(())
+        }
+        SubCommand::Status => {
+            for line in command_status(".")? {
+                println!("{line}");
+            }
+            Ok(())
+        }
+        SubCommand::Diff {
+            cached,
+            output,
+            revisions,
+            paths,
+        } => {
+            let store = ObjectStore::open(".")?;
+            let diff_options = output.diff_options()?;
+
+            let tree_of = |rev: &str| -> Result<HashCode, GitError> {
+                peel_to_tree(&store, rev_parse(&store, rev)?)
+            };
+            let mut changes = match (cached, revisions.as_slice()) {
+                (true, [] | [_]) => {
+                    let rev = revisions.first().map_or("HEAD", |x| x.as_str());
+                    let index = Index::read_at(".")?;
+                    diff_tree_to_index(&store, Some(tree_of(rev)?), &index, &diff_options)?
+                }
+                (false, []) => diff_index_to_worktree(".", &Index::read_at(".")?)?,
+                (false, [rev]) => {
+                    let index = Index::read_at(".")?;
+                    let old = flatten_tree(&store, tree_of(rev)?)?;
+                    diff_file_maps(&old, &worktree_file_map(".", &index)?)
+                }
+                (false, [old, new]) => diff_trees(
+                    &store,
+                    Some(tree_of(old)?),
+                    Some(tree_of(new)?),
+                    &diff_options,
+                )?,
+                _ => anyhow::bail!("Too many revisions"),
+            };
+
+            if !paths.is_empty() {
+                changes.retain(|x| {
+                    paths.iter().any(|path| {
+                        let path = path.trim_end_matches('/');
+                        x.path() == path || x.path().starts_with(&format!("{path}/"))
+                    })
+                });
+            }
+
+            write_changes(&mut stdout().lock(), &store, &changes, &output)?;
+            Ok(())
+        }
+        SubCommand::Show { output, object } => {
+            let store = ObjectStore::open(".")?;
+            let hash_code = rev_parse(&store, object.as_deref().unwrap_or("HEAD"))?;
+            command_show(&mut stdout().lock(), &store, hash_code, &output)?;
+            Ok(())
+        }
+        SubCommand::Checkout {
+            new_branch,
+            force,
+            detach,
+            target,
+        } => {
+            let target = checkout_target(".", target.as_deref(), new_branch, detach, false)?;
+            fetch_checkout_objects(".", &target).await?;
+            command_checkout(".", &target, force)?;
+            Ok(())
+        }
+        SubCommand::Switch {
+            new_branch,
+            force,
+            detach,
+            target,
+        } => {
+            let target = checkout_target(".", target.as_deref(), new_branch, detach, true)?;
+            fetch_checkout_objects(".", &target).await?;
+            command_checkout(".", &target, force)?;
+            Ok(())
+        }
+        SubCommand::Branch {
+            list,
+            delete,
+            force_delete,
+            rename,
+            force_rename,
+            force,
+            verbose,
+            set_upstream_to,
+            unset_upstream,
+            contains,
+            args,
+        } => {
+            let store = ObjectStore::open(".")?;
+            let current_branch = || -> anyhow::Result<String> {
+                head_branch_at(".")?.ok_or_else(|| anyhow::anyhow!("HEAD is detached"))
+            };
+
+            if delete || force_delete {
+                for name in &args {
+                    let target = delete_branch(".", &store, name, force_delete)?;
+                    println!("Deleted branch {name} (was {}).", &hex::encode(target)[..7]);
+                }
+            } else if rename || force_rename {
+                match args.as_slice() {
+                    [new] => rename_branch(".", &current_branch()?, new, force_rename)?,
+                    [old, new] => rename_branch(".", old, new, force_rename)?,
+                    _ => anyhow::bail!("Expected <old> <new> branch names"),
+                }
+            } else if let Some(upstream) = set_upstream_to {
+                let name = match args.as_slice() {
+                    [] => current_branch()?,
+                    [name] => name.clone(),
+                    _ => anyhow::bail!("Too many branches"),
+                };
+                let upstream = set_upstream(".", &name, &upstream)?;
+                println!(
+                    "branch '{name}' set up to track '{}'.",
+                    upstream.short_name()
+                );
+            } else if unset_upstream {
+                let name = match args.as_slice() {
+                    [] => current_branch()?,
+                    [name] => name.clone(),
+                    _ => anyhow::bail!("Too many branches"),
+                };
+                let mut config = GitConfig::read_at(".")?;
+                config.unset(&format!("branch.{name}.remote"));
+                config.unset(&format!("branch.{name}.merge"));
+                config.write_at(".")?;
+            } else if list || verbose > 0 || contains.is_some() || args.is_empty() {
+                let contains = contains
+                    .map(|x| rev_parse(&store, &x).and_then(|x| peel_to_commit(&store, x)))
+                    .transpose()?;
+                let output = command_branch_list(".", &store, &args, verbose, contains)?;
+                for line in output {
+                    println!("{line}");
+                }
+            } else {
+                let (name, start) = match args.as_slice() {
+                    [name] => (name, "HEAD"),
+                    [name, start] => (name, start.as_str()),
+                    _ => anyhow::bail!("Too many arguments"),
+                };
+                let start = peel_to_commit(&store, rev_parse(&store, start)?)?;
+                create_branch(".", name, start, force)?;
+            }
+            Ok(())
+        }
+        SubCommand::Tag {
+            list,
+            delete,
+            annotate,
+            message,
+            file,
+            force,
+            lines,
+            points_at,
+            args,
+        } => {
+            let store = ObjectStore::open(".")?;
+
+            if delete {
+                for name in &args {
+                    let hash_code = delete_tag(".", name)?;
+                    println!(
+                        "Deleted tag '{name}' (was {})",
+                        &hex::encode(hash_code)[..7]
+                    );
+                }
+            } else if list || lines.is_some() || points_at.is_some() || args.is_empty() {
+                let points_at = points_at.map(|x| rev_parse(&store, &x)).transpose()?;
+                for line in command_tag_list(&store, &args, lines, points_at)? {
+                    println!("{line}");
+                }
+            } else {
+                let (name, target) = match args.as_slice() {
+                    [name] => (name, "HEAD"),
+                    [name, target] => (name, target.as_str()),
+                    _ => anyhow::bail!("Too many arguments"),
+                };
+                let target = rev_parse(&store, target)?;
+
+                let message = match file {
+                    Some(path) if path == Path::new("-") => Some(io::read_to_string(io::stdin())?),
+                    Some(path) => Some(fs::read_to_string(path)?),
+                    None if !message.is_empty() => Some(message.join("\n\n")),
+                    None => None,
+                };
+                let annotation = match message {
+                    Some(message) => Some(TagAnnotation {
+                        tagger: Signature::from_env("COMMITTER", &GitConfig::read_at(".")?)?,
+                        message,
+                    }),
+                    None if annotate => anyhow::bail!("Missing tag message, use -m or -F"),
+                    None => None,
+                };
+                create_tag(".", &store, name, target, annotation.as_ref(), force)?;
+            }
+            Ok(())
+        }
+        SubCommand::Add { paths } => {
+            let store = ObjectStore::open(".")?;
+            add_paths(".", &store, &paths)?;
             Ok(())
         }
+        SubCommand::Commit {
+            message,
+            file,
+            amend,
+            allow_empty,
+        } => {
+            let store = ObjectStore::open(".")?;
+            let message = match file {
+                Some(path) if path == Path::new("-") => io::read_to_string(io::stdin())?,
+                Some(path) => fs::read_to_string(path)?,
+                None if !message.is_empty() => message.join("\n\n"),
+                // No editor is launched, like `git commit --no-edit` with no message.
+                None if !read_merge_heads(".")?.is_empty() => {
+                    let message = fs::read_to_string(".git/MERGE_MSG").unwrap_or_default();
+                    let lines: Vec<_> = message.lines().filter(|x| !x.starts_with('#')).collect();
+                    lines.join("\n")
+                }
+                None if amend => match resolve_ref_at(".", "HEAD")?.map(|x| store.read(x)) {
+                    Some(Ok(GitObject::Commit { message, .. })) => message,
+                    _ => String::new(),
+                },
+                None => anyhow::bail!("Missing commit message, use -m or -F"),
+            };
+
+            let options = CommitOptions { amend, allow_empty };
+            let hash_code = commit(".", &store, &message, &options)?;
+
+            let is_root = matches!(
+                store.read(hash_code)?,
+                GitObject::Commit { parents, .. } if parents.is_empty()
+            );
+            let branch = match head_branch_at(".")? {
+                Some(branch) => branch,
+                None => "detached HEAD".to_string(),
+            };
+            println!(
+                "[{branch}{} {}] {}",
+                if is_root { " (root-commit)" } else { "" },
+                &hex::encode(hash_code)[..7],
+                cleanup_message(&message).lines().next().unwrap_or_default()
+            );
+            Ok(())
+        }
+        SubCommand::Merge {
+            no_ff,
+            message,
+            abort,
+            commit,
+        } => {
+            let store = ObjectStore::open(".")?;
+            if abort {
+                abort_merge(".", &store)?;
+                return Ok(());
+            }
+
+            let name = commit.unwrap_or_default();
+            let theirs = peel_to_commit(&store, rev_parse(&store, &name)?)?;
+            let options = MergeOptions { no_ff, message };
+            let outcome = merge(".", &store, theirs, &name, &options).map_err(|err| match err {
+                GitError::WouldOverwrite(paths) => anyhow::anyhow!(
+                    "Your local changes to the following files would be overwritten by merge:\n\t{}\n\
+                     Please commit your changes or stash them before you merge.",
+                    paths.join("\n\t")
+                ),
+                err => err.into(),
+            })?;
+            command_merge_report(&store, &name, &outcome)
+        }
+        SubCommand::MergeBase { all, commits } => {
+            let store = ObjectStore::open(".")?;
+            let one = peel_to_commit(&store, rev_parse(&store, &commits[0])?)?;
+            let two = peel_to_commit(&store, rev_parse(&store, &commits[1])?)?;
+            let bases = merge_bases(&store, one, two)?;
+            if bases.is_empty() {
+                anyhow::bail!("No merge base found");
+            }
+            for base in bases.iter().take(if all { bases.len() } else { 1 }) {
+                println!("{}", hex::encode(base));
+            }
+            Ok(())
+        }
+        SubCommand::Fetch {
+            remote,
+            refspecs,
+            shallow,
+            deepen,
+            unshallow,
+        } => {
+            let mut options = shallow.shallow_options()?;
+            if let Some(depth) = deepen {
+                options.depth = Some(depth);
+                options.relative = true;
+            }
+            if unshallow {
+                if read_shallow(".")?.is_empty() {
+                    anyhow::bail!("--unshallow on a complete repository does not make sense");
+                }
+                options.depth = Some(INFINITE_DEPTH);
+            }
+            command_fetch(remote, &refspecs, &options).await
+        }
+        SubCommand::Push {
+            remote,
+            refspecs,
+            force,
+            delete,
+            set_upstream,
+        } => command_push(remote, refspecs, force, delete, set_upstream).await,
+        SubCommand::UploadPack {
+            stateless_rpc,
+            advertise_refs,
+            dir,
+        } => {
+            // Clients ask for protocol v2 through the environment.
+            let version =
+                env::var("GIT_PROTOCOL").map_or(ProtocolVersion::V0, |x| requested_version(&x));
+            let options = UploadPackOptions {
+                version,
+                stateless_rpc,
+                advertise_refs,
+            };
+            let mut writer = io::BufWriter::new(stdout().lock());
+            upload_pack(&dir, &mut io::stdin().lock(), &mut writer, &options)?;
+            Ok(())
+        }
+        SubCommand::ReceivePack {
+            stateless_rpc,
+            advertise_refs,
+            dir,
+        } => {
+            let options = ReceivePackOptions {
+                stateless_rpc,
+                advertise_refs,
+            };
+            let mut writer = io::BufWriter::new(stdout().lock());
+            receive_pack(&dir, &mut io::stdin().lock(), &mut writer, &options)?;
+            Ok(())
+        }
+        SubCommand::Serve { http, root } => {
+            let listener = TcpListener::bind(&http).await?;
+            eprintln!(
+                "Serving {} on http://{}",
+                root.display(),
+                listener.local_addr()?
+            );
+            serve(listener, root).await?;
+            Ok(())
+        }
+    }
+}
+
+/// Find remote by name or URL, defaulting to upstream remote of current branch or `origin`.
+fn read_remote(config: &GitConfig, name: Option<String>) -> anyhow::Result<Remote> {
+    let name = match name {
+        Some(name) => name,
+        None => head_branch_at(".")?
+            .and_then(|x| read_upstream(config, &x))
+            .map(|x| x.remote)
+            .filter(|x| x != ".")
+            .unwrap_or_else(|| "origin".to_string()),
+    };
+
+    match Remote::read(config, &name)? {
+        Some(remote) => Ok(remote),
+        // Like git, a path to a local repository works as a URL.
+        None if !matches!(Transport::parse(&name), Ok(Transport::Local { .. }))
+            || Path::new(&name).is_dir() =>
+        {
+            Ok(Remote::from_url(&name))
+        }
+        None => anyhow::bail!("'{name}' does not appear to be a git repository"),
     }
 }
 
+/// Fetch from a configured remote or a URL and print updated refs like git.
+async fn command_fetch(
+    remote: Option<String>,
+    refspecs: &[String],
+    shallow: &ShallowOptions,
+) -> anyhow::Result<()> {
+    let config = GitConfig::read_at(".")?;
+    let mut remote = read_remote(&config, remote)?;
+    if !refspecs.is_empty() {
+        remote.fetch = refspecs
+            .iter()
+            .map(|x| RefSpec::parse(x))
+            .collect::<Result<_, _>>()?;
+    } else if remote.fetch.is_empty() {
+        remote.fetch = Remote::from_url(&remote.url).fetch;
+    }
+
+    let updates = fetch(".", &remote, shallow, &mut print_progress).await?;
+
+    let short_id = |x: HashCode| hex::encode(x)[..7].to_string();
+    let mut lines = Vec::new();
+    for update in &updates {
+        let remote_name = short_ref_name(&update.remote_ref);
+        let local_name = update
+            .local_ref
+            .as_deref()
+            .map_or("FETCH_HEAD", short_ref_name);
+        let is_tag = update.remote_ref.starts_with("refs/tags/");
+        let (flag, summary, suffix) = match (update.status, update.local_ref.is_some()) {
+            (_, false) => ('*', "branch".to_string(), ""),
+            (RefUpdateStatus::New, _) if is_tag => ('*', "[new tag]".to_string(), ""),
+            (RefUpdateStatus::New, _) if update.remote_ref.starts_with("refs/heads/") => {
+                ('*', "[new branch]".to_string(), "")
+            }
+            (RefUpdateStatus::New, _) => ('*', "[new ref]".to_string(), ""),
+            (RefUpdateStatus::UpToDate, _) => continue,
+            (RefUpdateStatus::FastForward, _) => (
+                ' ',
+                format!(
+                    "{}..{}",
+                    short_id(update.old.unwrap_or_default()),
+                    short_id(update.new)
+                ),
+                "",
+            ),
+            (RefUpdateStatus::Forced, _) => (
+                '+',
+                format!(
+                    "{}...{}",
+                    short_id(update.old.unwrap_or_default()),
+                    short_id(update.new)
+                ),
+                "  (forced update)",
+            ),
+            (RefUpdateStatus::Rejected, _) if is_tag => (
+                '!',
+                "[rejected]".to_string(),
+                "  (would clobber existing tag)",
+            ),
+            (RefUpdateStatus::Rejected, _) => {
+                ('!', "[rejected]".to_string(), "  (non-fast-forward)")
+            }
+        };
+        lines.push((flag, summary, remote_name, local_name, suffix));
+    }
+
+    if !lines.is_empty() {
+        eprintln!("From {}", remote.display_url());
+        let width = lines.iter().map(|x| x.2.len()).fold(10, usize::max);
+        for (flag, summary, remote_name, local_name, suffix) in lines {
+            eprintln!(" {flag} {summary:<17} {remote_name:<width$} -> {local_name}{suffix}");
+        }
+    }
+
+    if updates
+        .iter()
+        .any(|x| x.status == RefUpdateStatus::Rejected)
+    {
+        anyhow::bail!("some local refs could not be updated");
+    }
+    Ok(())
+}
+
+/// Push refs to a configured remote or a URL and print updated refs like git.
+async fn command_push(
+    remote: Option<String>,
+    refspecs: Vec<String>,
+    force: bool,
+    delete: bool,
+    set_upstream: bool,
+) -> anyhow::Result<()> {
+    let config = GitConfig::read_at(".")?;
+    let remote = read_remote(&config, remote)?;
+
+    let refspecs = match (refspecs.is_empty(), delete) {
+        (true, true) => anyhow::bail!("--delete doesn't make sense without any refs"),
+        (true, false) => match head_branch_at(".")? {
+            Some(branch) => vec![format!("refs/heads/{branch}")],
+            None => anyhow::bail!("You are not currently on a branch."),
+        },
+        (false, true) => refspecs.iter().map(|x| format!(":{x}")).collect(),
+        (false, false) => refspecs,
+    };
+    let mut refspecs = refspecs
+        .iter()
+        .map(|x| RefSpec::parse(x))
+        .collect::<Result<Vec<_>, _>>()?;
+    for refspec in &mut refspecs {
+        refspec.force |= force;
+    }
+
+    let store = ObjectStore::open(".")?;
+    let push_refs = resolve_push_refspecs(".", &store, &refspecs)?;
+    let updates = push(".", &remote, &push_refs).await?;
+
+    let short_id = |x: Option<HashCode>| hex::encode(x.unwrap_or_default())[..7].to_string();
+    let mut lines = Vec::new();
+    for update in &updates {
+        let src = short_ref_name(&update.src);
+        let dst = short_ref_name(&update.remote_ref);
+        let (flag, summary, reason) = match &update.status {
+            PushStatus::UpToDate => continue,
+            PushStatus::Ok if update.new.is_none() => ('-', "[deleted]".to_string(), None),
+            PushStatus::Ok if update.old.is_none() => {
+                let summary = if update.remote_ref.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else if update.remote_ref.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else {
+                    "[new reference]"
+                };
+                ('*', summary.to_string(), None)
+            }
+            PushStatus::Ok if update.forced => (
+                '+',
+                format!("{}...{}", short_id(update.old), short_id(update.new)),
+                Some("forced update"),
+            ),
+            PushStatus::Ok => (
+                ' ',
+                format!("{}..{}", short_id(update.old), short_id(update.new)),
+                None,
+            ),
+            PushStatus::Rejected(reason) => ('!', "[rejected]".to_string(), Some(reason.as_str())),
+            PushStatus::RemoteRejected(reason) => {
+                ('!', "[remote rejected]".to_string(), Some(reason.as_str()))
+            }
+        };
+        let refs = if update.new.is_none() {
+            dst.to_string()
+        } else {
+            format!("{src} -> {dst}")
+        };
+        let reason = reason.map(|x| format!(" ({x})")).unwrap_or_default();
+        lines.push(format!(" {flag} {summary:<17} {refs}{reason}"));
+    }
+
+    if lines.is_empty() {
+        eprintln!("Everything up-to-date");
+    } else {
+        eprintln!("To {}", remote.url);
+        for line in lines {
+            eprintln!("{line}");
+        }
+    }
+
+    if set_upstream {
+        let mut config = GitConfig::read_at(".")?;
+        for update in &updates {
+            let (Some(branch), Some(remote_branch)) = (
+                update.src.strip_prefix("refs/heads/").or_else(|| {
+                    // Short source names are local branches when pushed to a branch.
+                    (!update.src.starts_with("refs/")).then_some(update.src.as_str())
+                }),
+                update.remote_ref.strip_prefix("refs/heads/"),
+            ) else {
+                continue;
+            };
+            if !matches!(update.status, PushStatus::Ok | PushStatus::UpToDate)
+                || resolve_ref_at(".", &format!("refs/heads/{branch}"))?.is_none()
+            {
+                continue;
+            }
+            config.set(&format!("branch.{branch}.remote"), &remote.name);
+            config.set(&format!("branch.{branch}.merge"), &update.remote_ref);
+            println!(
+                "branch '{branch}' set up to track '{}/{remote_branch}'.",
+                remote.name
+            );
+        }
+        config.write_at(".")?;
+    }
+
+    if updates.iter().any(|x| {
+        matches!(
+            x.status,
+            PushStatus::Rejected(_) | PushStatus::RemoteRejected(_)
+        )
+    }) {
+        anyhow::bail!("failed to push some refs to '{}'", remote.url);
+    }
+    Ok(())
+}
+
+/// Print what merging `name` did like git, failing on conflicts.
+fn command_merge_report(
+    store: &ObjectStore,
+    name: &str,
+    outcome: &MergeOutcome,
+) -> anyhow::Result<()> {
+    let print_stat = |old: Option<HashCode>, new: HashCode| -> Result<(), GitError> {
+        let old = old.map(|x| peel_to_tree(store, x)).transpose()?;
+        let new = peel_to_tree(store, new)?;
+        let changes = diff_trees(store, old, Some(new), &DiffOptions::default())?;
+        let load = |side: &DiffSide| Ok(store.read_raw(side.hash_code)?.1);
+        let mut stats = Vec::with_capacity(changes.len());
+        for change in &changes {
+            stats.push(FileStat::compute(change, &load, DiffAlgorithm::default())?);
+        }
+        print!("{}", format_stat(&stats));
+        Ok(())
+    };
+
+    match outcome {
+        MergeOutcome::UpToDate => println!("Already up to date."),
+        MergeOutcome::FastForward { old, new } => {
+            if let Some(old) = old {
+                println!(
+                    "Updating {}..{}",
+                    &hex::encode(old)[..7],
+                    &hex::encode(new)[..7]
+                );
+            }
+            println!("Fast-forward");
+            print_stat(*old, *new)?;
+        }
+        MergeOutcome::Merged { commit, merge } => {
+            for path in &merge.merged_paths {
+                println!("Auto-merging {path}");
+            }
+            println!("Merge made by the 'ort' strategy.");
+            let parent = CommitInfo::read(store, *commit)?.parents.first().copied();
+            print_stat(parent, *commit)?;
+        }
+        MergeOutcome::Conflicts(merge) => {
+            // Messages are sorted by path, files moved aside by their original path.
+            let mut lines: Vec<_> = merge
+                .merged_paths
+                .iter()
+                .map(|path| (path.as_str(), 0, format!("Auto-merging {path}")))
+                .collect();
+            for conflict in &merge.conflicts {
+                let path = &conflict.path;
+                let (sort_path, line) = match &conflict.kind {
+                    ConflictKind::Content => (
+                        path.as_str(),
+                        format!("CONFLICT (content): Merge conflict in {path}"),
+                    ),
+                    ConflictKind::AddAdd => (
+                        path.as_str(),
+                        format!("CONFLICT (add/add): Merge conflict in {path}"),
+                    ),
+                    ConflictKind::ModifyDelete => {
+                        let (deleted, modified) = match conflict.ours {
+                            Some(_) => (name, "HEAD"),
+                            None => ("HEAD", name),
+                        };
+                        (
+                            path.as_str(),
+                            format!(
+                                "CONFLICT (modify/delete): {path} deleted in {deleted} and \
+                                 modified in {modified}.  Version {modified} of {path} left in tree."
+                            ),
+                        )
+                    }
+                    ConflictKind::FileDirectory(original) => {
+                        let side = if conflict.ours.is_some() {
+                            "HEAD"
+                        } else {
+                            name
+                        };
+                        (
+                            original.as_str(),
+                            format!(
+                                "CONFLICT (file/directory): directory in the way of {original} \
+                                 from {side}; moving it to {path} instead."
+                            ),
+                        )
+                    }
+                };
+                lines.push((sort_path, 1, line));
+            }
+            lines.sort();
+            for (_, _, line) in lines {
+                println!("{line}");
+            }
+            anyhow::bail!("Automatic merge failed; fix conflicts and then commit the result.");
+        }
+    }
+    Ok(())
+}
+
+/// Build `git tag` listing lines, with messages when `lines` is set.
+fn command_tag_list(
+    store: &ObjectStore,
+    patterns: &[String],
+    lines: Option<usize>,
+    points_at: Option<HashCode>,
+) -> Result<Vec<String>, GitError> {
+    let mut output = Vec::new();
+    for (name, hash_code) in list_tags(".")? {
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|x| glob_match(x.as_bytes(), name.as_bytes()))
+        {
+            continue;
+        }
+        if let Some(object) = points_at {
+            if !tag_points_at(store, hash_code, object)? {
+                continue;
+            }
+        }
+
+        match lines {
+            Some(count) => {
+                let message = tag_message(store, hash_code)?;
+                let message: Vec<_> = message.lines().take(count).collect();
+                output.push(format!("{name:15} {}", message.join("\n    ")));
+            }
+            None => output.push(name),
+        }
+    }
+    Ok(output)
+}
+
+/// Build `git branch` listing lines, `verbose` adding commit then upstream details.
+fn command_branch_list(
+    root: &str,
+    store: &ObjectStore,
+    patterns: &[String],
+    verbose: u8,
+    contains: Option<HashCode>,
+) -> Result<Vec<String>, GitError> {
+    let mut entries = Vec::new();
+
+    // Detached HEAD is listed first, like git.
+    if let (None, Some(head)) = (head_branch_at(root)?, resolve_ref_at(root, "HEAD")?) {
+        let label = format!("(HEAD detached at {})", &hex::encode(head)[..7]);
+        entries.push((true, label, head, None));
+    }
+    for branch in list_branches(root)? {
+        entries.push((branch.is_head, branch.name, branch.target, branch.upstream));
+    }
+
+    let mut selected = Vec::new();
+    for entry in entries {
+        let (_, name, target, _) = &entry;
+        if !patterns.is_empty()
+            && !patterns
+                .iter()
+                .any(|x| glob_match(x.as_bytes(), name.as_bytes()))
+        {
+            continue;
+        }
+        if let Some(commit) = contains {
+            if !is_ancestor(store, commit, *target)? {
+                continue;
+            }
+        }
+        selected.push(entry);
+    }
+
+    let width = selected
+        .iter()
+        .map(|(_, x, _, _)| x.len())
+        .max()
+        .unwrap_or(0);
+    let mut output = Vec::with_capacity(selected.len());
+    for (is_head, name, target, upstream) in selected {
+        let marker = if is_head { '*' } else { ' ' };
+        if verbose == 0 {
+            output.push(format!("{marker} {name}"));
+            continue;
+        }
+
+        let mut tracking = Vec::new();
+        if let Some(upstream) = upstream {
+            if verbose > 1 {
+                tracking.push(upstream.short_name());
+            }
+            match resolve_ref_at(root, &upstream.tracking_ref())? {
+                Some(upstream_target) => {
+                    let (ahead, behind) = ahead_behind(store, target, upstream_target)?;
+                    let mut counts = Vec::new();
+                    if ahead > 0 {
+                        counts.push(format!("ahead {ahead}"));
+                    }
+                    if behind > 0 {
+                        counts.push(format!("behind {behind}"));
+                    }
+                    if !counts.is_empty() {
+                        tracking.push(counts.join(", "));
+                    }
+                }
+                None => tracking.push("gone".to_string()),
+            }
+        }
+        let tracking = match tracking.is_empty() {
+            true => String::new(),
+            false => format!("[{}] ", tracking.join(": ")),
+        };
+
+        let commit = CommitInfo::read(store, target)?;
+        output.push(format!(
+            "{marker} {name:width$} {} {tracking}{}",
+            &hex::encode(target)[..7],
+            commit.subject()
+        ));
+    }
+    Ok(output)
+}
+
+/// Find what to check out from command line. Branch names win over other revisions,
+/// `switch` only accepting commits with `--detach`.
+fn checkout_target(
+    root: &str,
+    target: Option<&str>,
+    new_branch: Option<String>,
+    detach: bool,
+    branch_only: bool,
+) -> anyhow::Result<CheckoutTarget> {
+    let store = ObjectStore::open(root)?;
+    if let Some(name) = new_branch {
+        let start = rev_parse(&store, target.unwrap_or("HEAD"))?;
+        return Ok(CheckoutTarget::NewBranch {
+            name,
+            start: peel_to_commit(&store, start)?,
+        });
+    }
+
+    let Some(target) = target else {
+        anyhow::bail!("Missing branch or commit to check out");
+    };
+    if !detach && resolve_ref_at(root, &format!("refs/heads/{target}"))?.is_some() {
+        return Ok(CheckoutTarget::Branch(target.to_string()));
+    }
+    if branch_only && !detach {
+        anyhow::bail!("A branch is expected, got '{target}'");
+    }
+    let commit = peel_to_commit(&store, rev_parse(&store, target)?)?;
+    Ok(CheckoutTarget::Detached(commit))
+}
+
+/// Fetch objects of the target tree a partial clone misses before checking it out.
+async fn fetch_checkout_objects(root: &str, target: &CheckoutTarget) -> anyhow::Result<()> {
+    let store = ObjectStore::open(root)?;
+    let tree = peel_to_tree(&store, target.commit(root)?)?;
+    fetch_missing_tree(root, tree, &mut print_progress).await?;
+    Ok(())
+}
+
+/// Check out target and report where HEAD is now, on stderr like git.
+fn command_checkout(root: &str, target: &CheckoutTarget, force: bool) -> anyhow::Result<()> {
+    let store = ObjectStore::open(root)?;
+    let old_branch = head_branch_at(root)?;
+    let old_head = resolve_ref_at(root, "HEAD")?;
+
+    let options = CheckoutOptions { force };
+    checkout(root, &store, target, &options).map_err(|err| match err {
+        GitError::WouldOverwrite(paths) => anyhow::anyhow!(
+            "Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+             Please commit your changes or stash them before you switch branches.",
+            paths.join("\n\t")
+        ),
+        err => err.into(),
+    })?;
+
+    let describe = |commit: HashCode| -> Result<String, GitError> {
+        let commit = CommitInfo::read(&store, commit)?;
+        Ok(format!(
+            "{} {}",
+            &hex::encode(commit.id)[..7],
+            commit.subject()
+        ))
+    };
+    let new_head = resolve_ref_at(root, "HEAD")?;
+    if let (None, Some(old_head)) = (&old_branch, old_head) {
+        if new_head != Some(old_head) {
+            eprintln!("Previous HEAD position was {}", describe(old_head)?);
+        }
+    }
+
+    match target {
+        CheckoutTarget::Branch(name) if old_branch.as_ref() == Some(name) => {
+            eprintln!("Already on '{name}'")
+        }
+        CheckoutTarget::Branch(name) => eprintln!("Switched to branch '{name}'"),
+        CheckoutTarget::NewBranch { name, .. } => eprintln!("Switched to a new branch '{name}'"),
+        CheckoutTarget::Detached(commit) => eprintln!("HEAD is now at {}", describe(*commit)?),
+    }
+    Ok(())
+}
+
+impl ShallowArgs {
+    fn shallow_options(&self) -> Result<ShallowOptions, GitError> {
+        Ok(ShallowOptions {
+            depth: self.depth,
+            relative: false,
+            since: self.shallow_since.as_deref().map(parse_date).transpose()?,
+            exclude: self.shallow_exclude.clone(),
+        })
+    }
+}
+
+impl DiffOutputArgs {
+    fn diff_options(&self) -> Result<DiffOptions, GitError> {
+        let mut options = DiffOptions::default();
+        if let Some(threshold) = &self.find_renames {
+            options.detect_renames = true;
+            options.rename_threshold = parse_similarity(threshold)?;
+        }
+        Ok(options)
+    }
+
+    fn patch_options(&self) -> PatchOptions {
+        PatchOptions {
+            context: self.context,
+            algorithm: if self.patience {
+                DiffAlgorithm::Patience
+            } else if self.histogram {
+                DiffAlgorithm::Histogram
+            } else {
+                self.diff_algorithm
+            },
+        }
+    }
+}
+
+/// Print changes using the format selected on command line.
+fn write_changes<W: Write>(
+    output: &mut W,
+    store: &ObjectStore,
+    changes: &[DiffEntry],
+    args: &DiffOutputArgs,
+) -> Result<(), GitError> {
+    // Worktree files are not in the object store.
+    let load = |side: &DiffSide| -> Result<Vec<u8>, GitError> {
+        match store.read_raw(side.hash_code) {
+            Ok((_, content)) => Ok(content),
+            Err(GitError::MissingObject(_)) => read_worktree_file(Path::new(&side.path)),
+            Err(err) => Err(err),
+        }
+    };
+    let patch_options = args.patch_options();
+
+    if args.stat || args.numstat {
+        let mut stats = Vec::with_capacity(changes.len());
+        for change in changes {
+            stats.push(FileStat::compute(change, &load, patch_options.algorithm)?);
+        }
+        if args.numstat {
+            for stat in &stats {
+                writeln!(output, "{}", stat.numstat())?;
+            }
+        } else {
+            write!(output, "{}", format_stat(&stats))?;
+        }
+        return Ok(());
+    }
+
+    for change in changes {
+        if args.name_only {
+            writeln!(output, "{}", change.path())?;
+        } else if args.name_status {
+            writeln!(output, "{}", change.name_status())?;
+        } else {
+            write!(output, "{}", format_patch(change, &load, &patch_options)?)?;
+        }
+    }
+    Ok(())
+}
+
+fn command_show<W: Write>(
+    output: &mut W,
+    store: &ObjectStore,
+    hash_code: HashCode,
+    args: &DiffOutputArgs,
+) -> Result<(), GitError> {
+    match store.read(hash_code)? {
+        GitObject::Commit { .. } => {
+            let commit = CommitInfo::read(store, hash_code)?;
+            write!(output, "{}", format_commit(&commit, "medium"))?;
+
+            // Like git without `--cc`, merges are shown without a diff.
+            if commit.parents.len() > 1 {
+                return Ok(());
+            }
+            let parent_tree = match commit.parents.first() {
+                Some(parent) => Some(CommitInfo::read(store, *parent)?.tree),
+                None => None,
+            };
+            let mut diff_options = args.diff_options()?;
+            diff_options.detect_renames = true;
+            let changes = diff_trees(store, parent_tree, Some(commit.tree), &diff_options)?;
+            if !changes.is_empty() {
+                writeln!(output)?;
+            }
+            write_changes(output, store, &changes, args)?;
+        }
+        GitObject::Tag {
+            object,
+            tag,
+            tagger,
+            message,
+            ..
+        } => {
+            writeln!(output, "tag {tag}")?;
+            if let Some(tagger) = tagger.as_deref().map(Signature::parse).transpose()? {
+                writeln!(output, "Tagger: {} <{}>", tagger.name, tagger.email)?;
+                writeln!(output, "Date:   {}", tagger.format_date())?;
+            }
+            writeln!(output)?;
+            writeln!(output, "{}", message.trim_end())?;
+            writeln!(output)?;
+            command_show(output, store, object, args)?;
+        }
+        GitObject::Tree(items) => {
+            writeln!(output, "tree {}", hex::encode(hash_code))?;
+            writeln!(output)?;
+            for item in items {
+                let suffix = if item.mode == 0o40000 { "/" } else { "" };
+                writeln!(output, "{}{suffix}", item.name)?;
+            }
+        }
+        GitObject::Blob(content) => output.write_all(&content)?,
+    }
+    Ok(())
+}
+
+/// Build `git status --short` like lines: `XY path`, `X` being the staged status
+/// and `Y` the unstaged one.
+pub fn command_status<P: AsRef<Path>>(root: P) -> Result<Vec<String>, GitError> {
+    let root = root.as_ref();
+    let store = ObjectStore::open(root)?;
+    let index = Index::read_at(root)?;
+
+    let head_tree = match resolve_ref_at(root, "HEAD")? {
+        Some(head) => Some(peel_to_tree(&store, head)?),
+        None => None,
+    };
+    let options = DiffOptions {
+        detect_renames: true,
+        ..Default::default()
+    };
+    let staged = diff_tree_to_index(&store, head_tree, &index, &options)?;
+    let unstaged = diff_index_to_worktree(root, &index)?;
+
+    let status_letter = |entry: Option<&DiffEntry>| {
+        entry.map_or(' ', |x| x.status.as_string().chars().next().unwrap_or(' '))
+    };
+
+    // Stages found for each unmerged path, as a bit mask.
+    let mut unmerged = BTreeMap::new();
+    for entry in index.entries.iter().filter(|x| x.stage != 0) {
+        *unmerged.entry(entry.path.clone()).or_insert(0) |= 1 << (entry.stage - 1);
+    }
+
+    let mut paths: Vec<_> = staged
+        .iter()
+        .chain(&unstaged)
+        .map(|x| x.path().to_string())
+        .chain(unmerged.keys().cloned())
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    let mut output = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(stages) = unmerged.get(&path) {
+            let code = match stages {
+                0b001 => "DD",
+                0b010 => "AU",
+                0b011 => "UD",
+                0b100 => "UA",
+                0b101 => "DU",
+                0b110 => "AA",
+                _ => "UU",
+            };
+            output.push(format!("{code} {path}"));
+            continue;
+        }
+
+        let staged_entry = staged.iter().find(|x| x.path() == path);
+        let unstaged_entry = unstaged.iter().find(|x| x.path() == path);
+
+        let name = match staged_entry.and_then(|x| x.old.as_ref().zip(x.new.as_ref())) {
+            Some((old, new)) if old.path != new.path => format!("{} -> {}", old.path, new.path),
+            _ => path,
+        };
+        output.push(format!(
+            "{}{} {name}",
+            status_letter(staged_entry),
+            status_letter(unstaged_entry)
+        ));
+    }
+
+    for path in untracked_files(root, &index)? {
+        output.push(format!("?? {path}"));
+    }
+
+    Ok(output)
+}
+
 pub fn command_init() -> io::Result<()> {
     fs::create_dir(".git")?;
     fs::create_dir(".git/objects")?;
@@ -138,8 +1866,7 @@ pub fn command_init() -> io::Result<()> {
 }
 
 pub fn command_cat_file(cs: &str) -> Result<(), GitError> {
-    let mut reader = read_compressed(hash_code_text_to_array(cs))?;
-    let object = GitObject::read(&mut reader)?;
+    let object = ObjectStore::open(".")?.read(hash_code_text_to_array(cs)?)?;
 
     if let GitObject::Blob(content) = object {
         stdout().write_all(&content)?;
@@ -152,17 +1879,14 @@ pub fn command_hash_object<P: AsRef<Path>>(path: P, write: bool) -> Result<HashC
     let content = fs::read(path)?;
     let object = GitObject::Blob(Bytes::from(content));
 
-    let (hash_code, bytes) = object.to_bytes_vec()?;
     if write {
-        write_compressed(hash_code, &bytes)?;
+        return ObjectStore::open(".")?.write(&object);
     }
-
-    Ok(hash_code)
+    Ok(object.to_bytes_vec()?.0)
 }
 
 pub fn command_ls_tree(cs: &str) -> Result<(), GitError> {
-    let mut reader = read_compressed(hash_code_text_to_array(cs))?;
-    let object = GitObject::read(&mut reader)?;
+    let object = ObjectStore::open(".")?.read(hash_code_text_to_array(cs)?)?;
 
     if let GitObject::Tree(items) = object {
         for item in items {
@@ -173,7 +1897,10 @@ pub fn command_ls_tree(cs: &str) -> Result<(), GitError> {
     Ok(())
 }
 
-pub fn command_write_tree<P: AsRef<Path>>(path: P) -> Result<HashCode, GitError> {
+pub fn command_write_tree<P: AsRef<Path>>(
+    store: &ObjectStore,
+    path: P,
+) -> Result<HashCode, GitError> {
     let mut items = Vec::new();
 
     // Build tree items
@@ -188,7 +1915,8 @@ pub fn command_write_tree<P: AsRef<Path>>(path: P) -> Result<HashCode, GitError>
         let file_type = dir_entry.file_type()?;
 
         if file_type.is_file() {
-            let hash_code = command_hash_object(dir_entry.path(), true)?;
+            let content = fs::read(dir_entry.path())?;
+            let hash_code = store.write(&GitObject::Blob(Bytes::from(content)))?;
 
             items.push(GitTreeItem {
                 mode: 0o100644,
@@ -198,7 +1926,7 @@ pub fn command_write_tree<P: AsRef<Path>>(path: P) -> Result<HashCode, GitError>
         }
 
         if file_type.is_dir() {
-            let hash_code = command_write_tree(dir_entry.path())?;
+            let hash_code = command_write_tree(store, dir_entry.path())?;
 
             items.push(GitTreeItem {
                 mode: 0o40000,
@@ -212,9 +1940,7 @@ pub fn command_write_tree<P: AsRef<Path>>(path: P) -> Result<HashCode, GitError>
     let object = GitObject::Tree(items);
 
     // Save to disk
-    let (hash_code, bytes) = object.to_bytes_vec()?;
-    write_compressed(hash_code, &bytes)?;
-    Ok(hash_code)
+    store.write(&object)
 }
 
 pub fn command_commit_tree(
@@ -225,14 +1951,12 @@ pub fn command_commit_tree(
     // Build git object
     let object = GitObject::Commit {
         tree,
-        parent: Some(parent),
+        parents: vec![parent],
         author: None,
         committer: None,
         message: message.to_string(),
     };
 
     // Save to disk
-    let (hash_code, bytes) = object.to_bytes_vec()?;
-    write_compressed(hash_code, &bytes)?;
-    Ok(hash_code)
+    ObjectStore::open(".")?.write(&object)
 }
@@ -0,0 +1,664 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs,
+    ops::Range,
+    path::Path,
+};
+
+use crate::{
+    checkout::{checkout_paths, checkout_tree, CheckoutOptions},
+    commit::{cleanup_message, clear_merge_state, read_merge_heads},
+    config::GitConfig,
+    diff::{diff_tree_to_index, flatten_tree, DiffOptions, FileMap},
+    fs_utils::git_dir,
+    header::GitObjectHeaderType,
+    index::{Index, IndexEntry},
+    line_diff::{diff_lines, is_binary, split_lines, DiffAlgorithm, LineOp},
+    log::{ancestors, CommitInfo},
+    object::GitObject,
+    refs::{head_branch_at, read_ref_at, resolve_ref_at, update_head_at, write_ref_at, RefValue},
+    revision::peel_to_tree,
+    signature::Signature,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_REGULAR: u32 = 0o100000;
+
+/// Size of conflict markers, like `<<<<<<<`.
+const MARKER_LEN: usize = 7;
+
+/// Labels of the sides of a merge between merge bases of a criss-cross history.
+const VIRTUAL_LABELS: [&str; 2] = ["Temporary merge branch 1", "Temporary merge branch 2"];
+
+/// Result of a line level merge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMerge {
+    /// Merged content, with conflict markers around each conflict.
+    pub content: Vec<u8>,
+    pub conflicts: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both sides changed a file in different ways.
+    Content,
+    /// Both sides added a file with different contents.
+    AddAdd,
+    /// One side changed a file the other one deleted.
+    ModifyDelete,
+    /// A file of one side is in the way of a directory of the other one. It is moved to
+    /// `path~<side>`, the original path being kept here.
+    FileDirectory(String),
+}
+
+/// Path left unmerged, with the versions stored in index stages 1 to 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub path: String,
+    pub kind: ConflictKind,
+    pub base: Option<(u32, HashCode)>,
+    pub ours: Option<(u32, HashCode)>,
+    pub theirs: Option<(u32, HashCode)>,
+}
+
+/// Result of a three-way tree merge.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeMerge {
+    /// Files of the merged tree, conflicting ones holding conflict markers.
+    pub files: FileMap,
+    /// Paths changed by both sides, merged line by line.
+    pub merged_paths: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    /// Create a merge commit even when a fast-forward is possible.
+    pub no_ff: bool,
+    /// Merge commit message, built from merged name when missing.
+    pub message: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeOutcome {
+    /// Merged commit is already part of HEAD history.
+    UpToDate,
+    /// HEAD was moved forward, `old` being `None` for an unborn branch.
+    FastForward {
+        old: Option<HashCode>,
+        new: HashCode,
+    },
+    /// A merge commit was created.
+    Merged { commit: HashCode, merge: TreeMerge },
+    /// Merge stopped with conflicts left in index and worktree.
+    Conflicts(TreeMerge),
+}
+
+/// Best common ancestors of two commits: common ancestors that are not ancestors of
+/// another common one. Criss-cross histories have several of them.
+///
+/// Most recent ones come first.
+pub fn merge_bases(
+    store: &ObjectStore,
+    one: HashCode,
+    two: HashCode,
+) -> Result<Vec<HashCode>, GitError> {
+    merge_bases_of(store, &[one], &[two])
+}
+
+/// Merge bases of two commit sets, each set standing for a commit having them as parents.
+fn merge_bases_of(
+    store: &ObjectStore,
+    one: &[HashCode],
+    two: &[HashCode],
+) -> Result<Vec<HashCode>, GitError> {
+    let one = ancestors(store, one, false)?;
+    let two = ancestors(store, two, false)?;
+    let common: HashSet<_> = one.intersection(&two).copied().collect();
+
+    let mut parents = Vec::new();
+    for commit in &common {
+        parents.extend(CommitInfo::read(store, *commit)?.parents);
+    }
+    let redundant = ancestors(store, &parents, false)?;
+
+    let mut output = Vec::new();
+    for commit in common.difference(&redundant) {
+        output.push(CommitInfo::read(store, *commit)?);
+    }
+    output.sort_by(|a, b| {
+        b.committer
+            .time
+            .cmp(&a.committer.time)
+            .then(a.id.cmp(&b.id))
+    });
+    Ok(output.into_iter().map(|x| x.id).collect())
+}
+
+/// Lines `base` of the common ancestor replaced by lines `side` of one side.
+#[derive(Debug)]
+struct Change {
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+fn line_changes(base: &[&[u8]], side: &[&[u8]]) -> Vec<Change> {
+    let mut output = Vec::new();
+    let mut current: Option<Change> = None;
+    let (mut base_idx, mut side_idx) = (0, 0);
+
+    for op in diff_lines(base, side, DiffAlgorithm::Myers) {
+        match op {
+            LineOp::Equal(old, new) => {
+                output.extend(current.take());
+                (base_idx, side_idx) = (old + 1, new + 1);
+            }
+            LineOp::Delete(old) => {
+                current
+                    .get_or_insert(Change {
+                        base: base_idx..base_idx,
+                        side: side_idx..side_idx,
+                    })
+                    .base
+                    .end = old + 1;
+                base_idx = old + 1;
+            }
+            LineOp::Insert(new) => {
+                current
+                    .get_or_insert(Change {
+                        base: base_idx..base_idx,
+                        side: side_idx..side_idx,
+                    })
+                    .side
+                    .end = new + 1;
+                side_idx = new + 1;
+            }
+        }
+    }
+
+    output.extend(current);
+    output
+}
+
+/// Merge changes made by both sides to `base`, like `git merge-file`.
+///
+/// Changes of both sides touching the same or adjacent lines conflict, unless they are
+/// identical. Lines common to both sides at the edges of a conflict are kept out of it.
+pub fn merge_content(base: &[u8], ours: &[u8], theirs: &[u8], labels: [&str; 2]) -> ContentMerge {
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+    let ours_changes = line_changes(&base_lines, &ours_lines);
+    let theirs_changes = line_changes(&base_lines, &theirs_lines);
+
+    let mut content = Vec::with_capacity(ours.len().max(theirs.len()));
+    let mut conflicts = 0;
+    let mut base_pos = 0;
+    let (mut ours_idx, mut theirs_idx) = (0, 0);
+    // Offset of side lines compared to base lines, outside of changes.
+    let (mut ours_delta, mut theirs_delta) = (0_isize, 0_isize);
+
+    while ours_idx < ours_changes.len() || theirs_idx < theirs_changes.len() {
+        let ours_first = match (ours_changes.get(ours_idx), theirs_changes.get(theirs_idx)) {
+            (Some(a), Some(b)) => a.base.start <= b.base.start,
+            (a, _) => a.is_some(),
+        };
+        let (ours_start, theirs_start) = (ours_idx, theirs_idx);
+        let start = if ours_first {
+            ours_changes[ours_idx].base.start
+        } else {
+            theirs_changes[theirs_idx].base.start
+        };
+
+        // Group changes overlapping or touching each other.
+        let mut end = start;
+        loop {
+            if let Some(change) = ours_changes.get(ours_idx).filter(|x| x.base.start <= end) {
+                end = end.max(change.base.end);
+                ours_idx += 1;
+            } else if let Some(change) = theirs_changes
+                .get(theirs_idx)
+                .filter(|x| x.base.start <= end)
+            {
+                end = end.max(change.base.end);
+                theirs_idx += 1;
+            } else {
+                break;
+            }
+        }
+
+        let side_range = |changes: &[Change], delta: &mut isize| {
+            let range_start = (start as isize + *delta) as usize;
+            *delta += changes
+                .iter()
+                .map(|x| x.side.len() as isize - x.base.len() as isize)
+                .sum::<isize>();
+            range_start..(end as isize + *delta) as usize
+        };
+        let ours_group = &ours_changes[ours_start..ours_idx];
+        let theirs_group = &theirs_changes[theirs_start..theirs_idx];
+        let ours_part = &ours_lines[side_range(ours_group, &mut ours_delta)];
+        let theirs_part = &theirs_lines[side_range(theirs_group, &mut theirs_delta)];
+
+        content.extend(base_lines[base_pos..start].concat());
+        base_pos = end;
+
+        if theirs_group.is_empty() || ours_part == theirs_part {
+            content.extend(ours_part.concat());
+        } else if ours_group.is_empty() {
+            content.extend(theirs_part.concat());
+        } else {
+            let prefix = ours_part
+                .iter()
+                .zip(theirs_part)
+                .take_while(|(a, b)| a == b)
+                .count();
+            let suffix = ours_part[prefix..]
+                .iter()
+                .rev()
+                .zip(theirs_part[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+
+            content.extend(ours_part[..prefix].concat());
+            write_conflict(
+                &mut content,
+                &ours_part[prefix..ours_part.len() - suffix],
+                &theirs_part[prefix..theirs_part.len() - suffix],
+                labels,
+            );
+            content.extend(ours_part[ours_part.len() - suffix..].concat());
+            conflicts += 1;
+        }
+    }
+
+    content.extend(base_lines[base_pos..].concat());
+    ContentMerge { content, conflicts }
+}
+
+fn write_conflict(output: &mut Vec<u8>, ours: &[&[u8]], theirs: &[&[u8]], labels: [&str; 2]) {
+    let write_lines = |output: &mut Vec<u8>, lines: &[&[u8]]| {
+        for line in lines {
+            output.extend_from_slice(line);
+        }
+        // Last line of a file may lack its line feed.
+        if output.last().is_some_and(|x| *x != b'\n') {
+            output.push(b'\n');
+        }
+    };
+
+    output.extend(format!("{} {}\n", "<".repeat(MARKER_LEN), labels[0]).as_bytes());
+    write_lines(output, ours);
+    output.extend(format!("{}\n", "=".repeat(MARKER_LEN)).as_bytes());
+    write_lines(output, theirs);
+    output.extend(format!("{} {}\n", ">".repeat(MARKER_LEN), labels[1]).as_bytes());
+}
+
+/// Three-way merge of `ours` and `theirs` trees, `base` being their common ancestor.
+///
+/// Renames are not detected: a renamed file is seen as deleted and added again.
+pub fn merge_trees(
+    store: &ObjectStore,
+    base: Option<HashCode>,
+    ours: HashCode,
+    theirs: HashCode,
+    labels: [&str; 2],
+) -> Result<TreeMerge, GitError> {
+    let base_files = match base {
+        Some(tree) => flatten_tree(store, tree)?,
+        None => FileMap::new(),
+    };
+    let ours_files = flatten_tree(store, ours)?;
+    let theirs_files = flatten_tree(store, theirs)?;
+    let paths: BTreeSet<_> = base_files
+        .keys()
+        .chain(ours_files.keys())
+        .chain(theirs_files.keys())
+        .collect();
+
+    let mut output = TreeMerge::default();
+    for path in paths {
+        let base = base_files.get(path).copied();
+        let ours = ours_files.get(path).copied();
+        let theirs = theirs_files.get(path).copied();
+
+        let conflict = |kind| Conflict {
+            path: path.clone(),
+            kind,
+            base,
+            ours,
+            theirs,
+        };
+
+        let (ours, theirs) = match (ours, theirs) {
+            _ if ours == theirs || base == theirs => {
+                output.files.extend(ours.map(|x| (path.clone(), x)));
+                continue;
+            }
+            _ if base == ours => {
+                output.files.extend(theirs.map(|x| (path.clone(), x)));
+                continue;
+            }
+            (Some(x), None) | (None, Some(x)) => {
+                output.files.insert(path.clone(), x);
+                output.conflicts.push(conflict(ConflictKind::ModifyDelete));
+                continue;
+            }
+            (Some(ours), Some(theirs)) => (ours, theirs),
+            (None, None) => unreachable!("Both sides are equal"),
+        };
+
+        let kind = match base {
+            Some(_) => ConflictKind::Content,
+            None => ConflictKind::AddAdd,
+        };
+        let is_regular = |mode: u32| mode & MODE_TYPE_MASK == MODE_REGULAR;
+        if !is_regular(ours.0) || !is_regular(theirs.0) {
+            // Symlinks and submodules cannot be merged, ours is kept.
+            output.files.insert(path.clone(), ours);
+            output.conflicts.push(conflict(kind));
+            continue;
+        }
+
+        output.merged_paths.push(path.clone());
+        let mode = match base {
+            Some((mode, _)) if mode == ours.0 => theirs.0,
+            _ => ours.0,
+        };
+        let base_content = match base {
+            Some((mode, hash_code)) if is_regular(mode) => store.read_raw(hash_code)?.1,
+            _ => Vec::new(),
+        };
+        let ours_content = store.read_raw(ours.1)?.1;
+        let theirs_content = store.read_raw(theirs.1)?.1;
+
+        if [&base_content, &ours_content, &theirs_content]
+            .iter()
+            .any(|x| is_binary(x))
+        {
+            output.files.insert(path.clone(), ours);
+            output.conflicts.push(conflict(kind));
+            continue;
+        }
+
+        let merged = merge_content(&base_content, &ours_content, &theirs_content, labels);
+        let hash_code = store.write_raw(GitObjectHeaderType::Blob, &merged.content)?;
+        output.files.insert(path.clone(), (mode, hash_code));
+        if merged.conflicts > 0 {
+            output.conflicts.push(conflict(kind));
+        }
+    }
+
+    move_files_out_of_the_way(&mut output, &ours_files, labels);
+    output.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(output)
+}
+
+/// Move files found where the other side has a directory to `path~<side>`.
+fn move_files_out_of_the_way(merge: &mut TreeMerge, ours_files: &FileMap, labels: [&str; 2]) {
+    let dirs: BTreeSet<_> = merge
+        .files
+        .keys()
+        .flat_map(|path| path.match_indices('/').map(|(idx, _)| &path[..idx]))
+        .map(|x| x.to_string())
+        .collect();
+
+    for path in dirs {
+        let Some(version) = merge.files.remove(&path) else {
+            continue;
+        };
+        let is_ours = ours_files.get(&path) == Some(&version);
+        let label = labels[if is_ours { 0 } else { 1 }].replace('/', "_");
+        let new_path = format!("{path}~{label}");
+        merge.files.insert(new_path.clone(), version);
+
+        match merge.conflicts.iter_mut().find(|x| x.path == path) {
+            Some(conflict) => conflict.path = new_path,
+            None => merge.conflicts.push(Conflict {
+                path: new_path,
+                kind: ConflictKind::FileDirectory(path),
+                base: None,
+                ours: Some(version).filter(|_| is_ours),
+                theirs: Some(version).filter(|_| !is_ours),
+            }),
+        }
+    }
+}
+
+/// Tree to use as merge base. When there are several merge bases, they are merged
+/// together first, conflicts being kept as conflict markers in the resulting tree.
+fn virtual_base_tree(
+    store: &ObjectStore,
+    bases: &[HashCode],
+) -> Result<Option<HashCode>, GitError> {
+    let Some((first, others)) = bases.split_first() else {
+        return Ok(None);
+    };
+
+    let mut tree = peel_to_tree(store, *first)?;
+    let mut merged = vec![*first];
+    for other in others {
+        let inner_bases = merge_bases_of(store, &merged, &[*other])?;
+        let inner_tree = virtual_base_tree(store, &inner_bases)?;
+        let merge = merge_trees(
+            store,
+            inner_tree,
+            tree,
+            peel_to_tree(store, *other)?,
+            VIRTUAL_LABELS,
+        )?;
+        tree = write_file_map(store, &merge.files)?;
+        merged.push(*other);
+    }
+    Ok(Some(tree))
+}
+
+fn write_file_map(store: &ObjectStore, files: &FileMap) -> Result<HashCode, GitError> {
+    let index = Index {
+        entries: files
+            .iter()
+            .map(|(path, (mode, hash_code))| IndexEntry::new(path, *mode, *hash_code))
+            .collect(),
+    };
+    index.write_tree(store)
+}
+
+/// Default merge commit message, like `Merge branch 'feature'`.
+pub fn merge_message<P: AsRef<Path>>(root: P, name: &str) -> Result<String, GitError> {
+    let root = root.as_ref();
+    let kind = if read_ref_at(root, &format!("refs/heads/{name}"))?.is_some() {
+        "branch"
+    } else if read_ref_at(root, &format!("refs/remotes/{name}"))?.is_some() {
+        "remote-tracking branch"
+    } else if read_ref_at(root, &format!("refs/tags/{name}"))?.is_some() {
+        "tag"
+    } else {
+        "commit"
+    };
+
+    let mut message = format!("Merge {kind} '{name}'");
+    match head_branch_at(root)? {
+        Some(branch) if branch != "main" && branch != "master" => {
+            message.push_str(&format!(" into {branch}"));
+        }
+        _ => {}
+    }
+    Ok(message)
+}
+
+/// Merge commit `theirs`, named `name` by the user, into HEAD.
+///
+/// Index must match HEAD. On conflicts, merge state is saved in `MERGE_HEAD` and
+/// `MERGE_MSG` so a later commit concludes the merge.
+pub fn merge<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    theirs: HashCode,
+    name: &str,
+    options: &MergeOptions,
+) -> Result<MergeOutcome, GitError> {
+    let root = root.as_ref();
+    if !read_merge_heads(root)?.is_empty() {
+        return Err(GitError::invalid_content(
+            "You have not concluded your merge (MERGE_HEAD exists)",
+        ));
+    }
+    let index = Index::read_at(root)?;
+    if index.has_conflicts() {
+        return Err(GitError::invalid_content(
+            "You need to resolve your current index first",
+        ));
+    }
+
+    let config = GitConfig::read_at(root)?;
+    let committer = Signature::from_env("COMMITTER", &config)?;
+    let log_message = |action: &str| format!("merge {name}: {action}");
+    let their_tree = peel_to_tree(store, theirs)?;
+
+    let Some(head) = resolve_ref_at(root, "HEAD")? else {
+        // Unborn branch just takes merged history.
+        checkout_tree(root, store, None, their_tree, &CheckoutOptions::default())?;
+        update_head_at(root, None, theirs, &committer, &log_message("Fast-forward"))?;
+        return Ok(MergeOutcome::FastForward {
+            old: None,
+            new: theirs,
+        });
+    };
+    let head_tree = peel_to_tree(store, head)?;
+
+    let bases = merge_bases(store, head, theirs)?;
+    if bases.contains(&theirs) {
+        return Ok(MergeOutcome::UpToDate);
+    }
+
+    let staged = diff_tree_to_index(store, Some(head_tree), &index, &DiffOptions::default())?;
+    if !staged.is_empty() {
+        return Err(GitError::WouldOverwrite(
+            staged.iter().map(|x| x.path().to_string()).collect(),
+        ));
+    }
+
+    if bases == [head] && !options.no_ff {
+        checkout_tree(
+            root,
+            store,
+            Some(head_tree),
+            their_tree,
+            &CheckoutOptions::default(),
+        )?;
+        write_ref_at(root, "ORIG_HEAD", &RefValue::Direct(head))?;
+        update_head_at(
+            root,
+            Some(head),
+            theirs,
+            &committer,
+            &log_message("Fast-forward"),
+        )?;
+        return Ok(MergeOutcome::FastForward {
+            old: Some(head),
+            new: theirs,
+        });
+    }
+
+    let base_tree = virtual_base_tree(store, &bases)?;
+    let merge = merge_trees(store, base_tree, head_tree, their_tree, ["HEAD", name])?;
+    let tree = write_file_map(store, &merge.files)?;
+    checkout_tree(
+        root,
+        store,
+        Some(head_tree),
+        tree,
+        &CheckoutOptions::default(),
+    )?;
+    write_ref_at(root, "ORIG_HEAD", &RefValue::Direct(head))?;
+
+    let message = match &options.message {
+        Some(message) => message.clone(),
+        None => merge_message(root, name)?,
+    };
+
+    if !merge.conflicts.is_empty() {
+        let mut index = Index::read_at(root)?;
+        for conflict in &merge.conflicts {
+            index.remove(&conflict.path);
+            for (stage, version) in [(1, conflict.base), (2, conflict.ours), (3, conflict.theirs)] {
+                if let Some((mode, hash_code)) = version {
+                    let mut entry = IndexEntry::new(&conflict.path, mode, hash_code);
+                    entry.stage = stage;
+                    index.add(entry);
+                }
+            }
+        }
+        index.write_at(root)?;
+
+        let git_dir = git_dir(root);
+        let mut merge_message = format!("{message}\n\n# Conflicts:\n");
+        for conflict in &merge.conflicts {
+            merge_message.push_str(&format!("#\t{}\n", conflict.path));
+        }
+        fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+        fs::write(
+            git_dir.join("MERGE_MODE"),
+            if options.no_ff { "no-ff" } else { "" },
+        )?;
+        fs::write(
+            git_dir.join("MERGE_HEAD"),
+            format!("{}\n", hex::encode(theirs)),
+        )?;
+        return Ok(MergeOutcome::Conflicts(merge));
+    }
+
+    let author = Signature::from_env("AUTHOR", &config)?;
+    let commit = store.write(&GitObject::Commit {
+        tree,
+        parents: vec![head, theirs],
+        author: Some(author.to_string()),
+        committer: Some(committer.to_string()),
+        message: cleanup_message(&message),
+    })?;
+    update_head_at(
+        root,
+        Some(head),
+        commit,
+        &committer,
+        &log_message("Merge made by the 'ort' strategy."),
+    )?;
+    Ok(MergeOutcome::Merged { commit, merge })
+}
+
+/// Give up a merge stopped on conflicts, resetting paths it touched to HEAD.
+///
+/// Local changes to other paths are kept.
+pub fn abort_merge<P: AsRef<Path>>(root: P, store: &ObjectStore) -> Result<(), GitError> {
+    let root = root.as_ref();
+    if read_merge_heads(root)?.is_empty() {
+        return Err(GitError::invalid_content(
+            "There is no merge to abort (MERGE_HEAD missing)",
+        ));
+    }
+    let head = resolve_ref_at(root, "HEAD")?
+        .ok_or_else(|| GitError::invalid_content("HEAD does not point to a commit"))?;
+    let head_tree = peel_to_tree(store, head)?;
+    let head_files = flatten_tree(store, head_tree)?;
+
+    let index = Index::read_at(root)?;
+    let mut paths: Vec<_> = index
+        .entries
+        .iter()
+        .filter(|x| x.stage != 0 || head_files.get(&x.path) != Some(&(x.mode, x.hash_code)))
+        .map(|x| x.path.clone())
+        .chain(
+            head_files
+                .keys()
+                .filter(|x| index.find(x, 0).is_none())
+                .cloned(),
+        )
+        .collect();
+    paths.sort();
+    paths.dedup();
+
+    checkout_paths(root, store, head_tree, &paths)?;
+    clear_merge_state(root)
+}
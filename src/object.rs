@@ -1,4 +1,8 @@
-use std::{fs::Permissions, io, os::unix::fs::PermissionsExt};
+use std::{
+    fs::Permissions,
+    io::{self, BufRead, Read},
+    os::unix::fs::PermissionsExt,
+};
 
 use bytes::Bytes;
 use sha1::{Digest, Sha1};
@@ -15,11 +19,18 @@ pub enum GitObject {
     Tree(Vec<GitTreeItem>),
     Commit {
         tree: HashCode,
-        parent: Option<HashCode>,
+        parents: Vec<HashCode>,
         author: Option<String>,
         committer: Option<String>,
         message: String,
     },
+    Tag {
+        object: HashCode,
+        target_type: GitObjectHeaderType,
+        tag: String,
+        tagger: Option<String>,
+        message: String,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -39,17 +50,13 @@ impl GitObject {
         input: &mut R,
         header: GitObjectHeader,
     ) -> Result<Self, GitError> {
+        let content = read_payload(input, header.len)?;
+
         match header.r#type {
-            GitObjectHeaderType::Blob => {
-                let mut content = vec![0; header.len];
-                input.read_exact(&mut content)?;
-                Ok(Self::Blob(Bytes::from(content)))
-            }
+            GitObjectHeaderType::Blob => Ok(Self::Blob(Bytes::from(content))),
             GitObjectHeaderType::Tree => {
-                let mut content = vec![0; header.len];
-                input.read_exact(&mut content)?;
+                let mut content = content.as_slice();
 
-                // NOTE: Maybe there is a nicer way to do bellow code 🤔
                 let mut items = Vec::new();
                 while !content.is_empty() {
                     let end_offset = content
@@ -58,53 +65,56 @@ impl GitObject {
                         .ok_or(GitError::InvalidObjectPayload("Missing end byte"))?;
 
                     // Read mode + name
-                    let chunk: Vec<_> = content.drain(..end_offset).collect();
-
-                    let text = std::str::from_utf8(&chunk)?;
-                    let mut text_iter = text.split_whitespace();
-                    let mode_text = text_iter
-                        .next()
-                        .ok_or(GitError::InvalidObjectPayload("Missing tree item mode"))?;
+                    let text = std::str::from_utf8(&content[..end_offset])?;
+                    let (mode_text, name) = text
+                        .split_once(' ')
+                        .ok_or(GitError::InvalidObjectPayload("Missing tree item name"))?;
                     let mode = u32::from_str_radix(mode_text, 8)
                         .map_err(|_err| GitError::InvalidObjectPayload("Invalid tree item mode"))?;
 
-                    let name = text_iter
-                        .next()
-                        .ok_or(GitError::InvalidObjectPayload("Missing tree item name"))?
-                        .to_string();
-
                     // Rend end byte + hash code
-                    content.drain(..1);
+                    let hash_data = content
+                        .get(end_offset + 1..end_offset + 21)
+                        .ok_or(GitError::InvalidObjectPayload("Truncated tree item hash"))?;
 
                     let mut hash_code = [0_u8; 20];
-                    hash_code.copy_from_slice(content.drain(..20).as_slice());
+                    hash_code.copy_from_slice(hash_data);
                     items.push(GitTreeItem {
                         mode,
-                        name,
+                        name: name.to_string(),
                         hash_code,
                     });
+
+                    content = &content[end_offset + 21..];
                 }
 
                 Ok(Self::Tree(items))
             }
             GitObjectHeaderType::Commit => {
+                let mut input = content.as_slice();
                 let mut buf = String::new();
 
                 // Read tree ID.
                 input.read_line(&mut buf)?;
                 let tree = match buf.trim_end().split_once(' ') {
-                    Some(("tree", x)) => hash_code_text_to_array(x),
+                    Some(("tree", x)) => hash_code_text_to_array(x)?,
                     _ => return Err(GitError::invalid_content("Invalid tree line")),
                 };
 
                 // Read optional objects.
-                let mut parent = None;
+                let mut parents = Vec::new();
                 let mut author = None;
                 let mut committer = None;
 
                 loop {
                     buf.clear();
-                    input.read_line(&mut buf)?;
+
+                    // Input should never end before the message separator.
+                    if input.read_line(&mut buf)? == 0 {
+                        return Err(GitError::InvalidObjectPayload(
+                            "Missing commit message separator",
+                        ));
+                    }
 
                     // Check if we have reach end of metadata.
                     if buf == "\n" {
@@ -114,7 +124,7 @@ impl GitObject {
                     // Otherwise check tags
                     match buf.trim_end().split_once(' ') {
                         Some(("parent", x)) => {
-                            parent = Some(hash_code_text_to_array(x));
+                            parents.push(hash_code_text_to_array(x)?);
                         }
                         Some(("author", x)) => {
                             author = Some(x.to_string());
@@ -127,18 +137,58 @@ impl GitObject {
                 }
 
                 // Read remaining data as end of text.
-                let mut message_buf = Vec::new();
-                input.read_to_end(&mut message_buf)?;
-                let message = std::str::from_utf8(&message_buf)?;
+                let message = std::str::from_utf8(input)?;
 
                 Ok(Self::Commit {
                     tree,
-                    parent,
+                    parents,
                     author,
                     committer,
                     message: message.trim_end().to_string(),
                 })
             }
+            GitObjectHeaderType::Tag => {
+                let mut input = content.as_slice();
+                let mut buf = String::new();
+
+                // Read mandatory fields.
+                let mut read_field = |name: &str| -> Result<String, GitError> {
+                    buf.clear();
+                    input.read_line(&mut buf)?;
+                    match buf.trim_end().split_once(' ') {
+                        Some((key, value)) if key == name => Ok(value.to_string()),
+                        _ => Err(GitError::InvalidContent(format!("Invalid tag {name} line"))),
+                    }
+                };
+
+                let object = hash_code_text_to_array(&read_field("object")?)?;
+                let target_type = GitObjectHeaderType::parse(&read_field("type")?)
+                    .ok_or(GitError::InvalidObjectPayload("Invalid tag target type"))?;
+                let tag = read_field("tag")?;
+
+                // Read optional tagger and skip unknown headers.
+                let mut tagger = None;
+                loop {
+                    buf.clear();
+                    if input.read_line(&mut buf)? == 0 || buf == "\n" {
+                        break;
+                    }
+
+                    if let Some(("tagger", x)) = buf.trim_end().split_once(' ') {
+                        tagger = Some(x.to_string());
+                    }
+                }
+
+                let message = std::str::from_utf8(input)?;
+
+                Ok(Self::Tag {
+                    object,
+                    target_type,
+                    tag,
+                    tagger,
+                    message: message.trim_end().to_string(),
+                })
+            }
         }
     }
 
@@ -187,7 +237,7 @@ impl GitObject {
             }
             Self::Commit {
                 tree,
-                parent,
+                parents,
                 author,
                 committer,
                 message,
@@ -198,7 +248,7 @@ impl GitObject {
                 let mut payload = String::with_capacity(512);
                 writeln!(payload, "tree {}", hex::encode(tree))?;
 
-                if let Some(parent) = parent {
+                for parent in parents {
                     writeln!(payload, "parent {}", hex::encode(parent))?;
                 }
 
@@ -231,6 +281,42 @@ impl GitObject {
                 hasher.update(&header_data);
                 output.write_all(&header_data)?;
 
+                hasher.update(&payload);
+                output.write_all(payload.as_bytes())?;
+            }
+            Self::Tag {
+                object,
+                target_type,
+                tag,
+                tagger,
+                message,
+            } => {
+                use std::fmt::Write;
+
+                // Write payload first
+                let mut payload = String::with_capacity(512);
+                writeln!(payload, "object {}", hex::encode(object))?;
+                writeln!(payload, "type {}", target_type.as_str())?;
+                writeln!(payload, "tag {tag}")?;
+
+                if let Some(tagger) = tagger {
+                    writeln!(payload, "tagger {tagger}")?;
+                }
+
+                writeln!(payload)?;
+                writeln!(payload, "{message}")?;
+
+                // Then, write object
+                let header = GitObjectHeader {
+                    len: payload.len(),
+                    r#type: GitObjectHeaderType::Tag,
+                };
+                let mut header_data = Vec::with_capacity(50);
+                header.write(&mut header_data)?;
+
+                hasher.update(&header_data);
+                output.write_all(&header_data)?;
+
                 hasher.update(&payload);
                 output.write_all(payload.as_bytes())?;
             }
@@ -239,6 +325,15 @@ impl GitObject {
         Ok(hasher.finalize().into())
     }
 
+    pub fn header_type(&self) -> GitObjectHeaderType {
+        match self {
+            Self::Blob(_) => GitObjectHeaderType::Blob,
+            Self::Tree(_) => GitObjectHeaderType::Tree,
+            Self::Commit { .. } => GitObjectHeaderType::Commit,
+            Self::Tag { .. } => GitObjectHeaderType::Tag,
+        }
+    }
+
     pub fn to_bytes_vec(&self) -> Result<(HashCode, Vec<u8>), GitError> {
         let mut bytes = Vec::new();
         let hash_code = self.write(&mut bytes)?;
@@ -250,4 +345,56 @@ impl GitTreeItem {
     pub fn permissions(&self) -> Permissions {
         Permissions::from_mode(self.mode % 0o1_000)
     }
+
+    /// Reject names that could escape the directory they are extracted to.
+    pub fn validate_name(&self) -> Result<(), GitError> {
+        validate_entry_name(&self.name)
+    }
+}
+
+/// Reject tree entry names that could escape the directory they are extracted to.
+pub fn validate_entry_name(name: &str) -> Result<(), GitError> {
+    let is_valid = !matches!(name, "" | "." | "..")
+        && !name.contains(['/', '\0'])
+        && !name.eq_ignore_ascii_case(".git");
+
+    if is_valid {
+        Ok(())
+    } else {
+        Err(GitError::InvalidPath(name.to_string()))
+    }
+}
+
+/// Build loose object bytes from an already encoded payload.
+///
+/// Unlike [`GitObject::write`], payload is kept byte for byte so its hash matches the
+/// one computed by whoever created it.
+pub fn encode_raw_object(r#type: GitObjectHeaderType, payload: &[u8]) -> (HashCode, Vec<u8>) {
+    let mut output = Vec::with_capacity(payload.len() + 32);
+    GitObjectHeader {
+        len: payload.len(),
+        r#type,
+    }
+    .write(&mut output)
+    .expect("Writing to a Vec cannot fail");
+    output.extend_from_slice(payload);
+
+    (Sha1::digest(&output).into(), output)
+}
+
+/// Upper bound of memory reserved up front from an untrusted length.
+pub(crate) const MAX_PREALLOC_LEN: usize = 1 << 20;
+
+/// Read exactly `len` bytes without trusting `len` for allocation.
+pub(crate) fn read_payload<R: io::Read>(input: &mut R, len: usize) -> Result<Vec<u8>, GitError> {
+    let mut content = Vec::with_capacity(len.min(MAX_PREALLOC_LEN));
+    input.take(len as u64).read_to_end(&mut content)?;
+
+    if content.len() != len {
+        return Err(
+            io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer").into(),
+        );
+    }
+
+    Ok(content)
 }
@@ -1,30 +1,33 @@
 use std::{
+    collections::HashMap,
     io::{self, Read},
     path::Path,
 };
 
 use bytes::Buf;
 use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
 
 use crate::{
-    fs_utils::{read_compressed_at, write_compressed_at},
     header::{GitObjectHeader, GitObjectHeaderType},
-    object::GitObject,
-    GitError,
+    object::{GitObject, MAX_PREALLOC_LEN},
+    store::ObjectStore,
+    GitError, HashCode,
 };
 
-const OBJ_COMMIT: u8 = 1;
-const OBJ_TREE: u8 = 2;
-const OBJ_BLOB: u8 = 3;
-const OBJ_TAG: u8 = 4;
-const OBJ_OFS_DELTA: u8 = 6;
-const OBJ_REF_DELTA: u8 = 7;
+pub const OBJ_COMMIT: u8 = 1;
+pub const OBJ_TREE: u8 = 2;
+pub const OBJ_BLOB: u8 = 3;
+pub const OBJ_TAG: u8 = 4;
+pub const OBJ_OFS_DELTA: u8 = 6;
+pub const OBJ_REF_DELTA: u8 = 7;
 
-pub fn unpack_into<R, P>(mut reader: R, dst: P) -> Result<(), GitError>
+pub fn unpack_into<R, P>(reader: R, dst: P) -> Result<(), GitError>
 where
     R: io::BufRead,
     P: AsRef<Path>,
 {
+    let mut reader = CountingReader::new(reader);
     let mut buf = [0_u8; 4];
 
     // Check magic.
@@ -44,75 +47,120 @@ where
     reader.read_exact(&mut buf)?;
     let object_count = u32::from_be_bytes(buf);
 
+    // Open store once instead of once per object: it also caches config.
+    let store = ObjectStore::open(dst)?;
+
+    // Keep track of where objects are so offset deltas can find their base.
+    let mut offsets = HashMap::new();
+
     // Read object.
-    for _object_id in 0..object_count {
-        // Read object header.
-        let (obj_type, obj_len) = read_object_pack_header(&mut reader)?;
-
-        // Decode object content based on its type.
-        match obj_type {
-            OBJ_COMMIT => unpack_git_object(
-                &mut reader,
-                GitObjectHeader {
-                    len: obj_len,
-                    r#type: GitObjectHeaderType::Commit,
-                },
-                &dst,
-            )?,
-            OBJ_TREE => unpack_git_object(
-                &mut reader,
-                GitObjectHeader {
-                    len: obj_len,
-                    r#type: GitObjectHeaderType::Tree,
-                },
-                &dst,
-            )?,
-            OBJ_BLOB => unpack_git_object(
-                &mut reader,
-                GitObjectHeader {
-                    len: obj_len,
-                    r#type: GitObjectHeaderType::Blob,
-                },
-                &dst,
-            )?,
-            OBJ_REF_DELTA => {
-                // Read base object hash.
-                let mut base_object_hash = [0; 20];
-                reader.read_exact(&mut base_object_hash)?;
-
-                // Find base object data from git DB.
-                let mut base_reader = read_compressed_at(base_object_hash, &dst)?;
-
-                let mut base_data = Vec::new();
-                base_reader.read_to_end(&mut base_data)?;
-
-                // Read compressed data.
-                let mut patch_data = Vec::with_capacity(obj_len);
-                let mut decompress = ZlibDecoder::new(&mut reader);
-                decompress.read_to_end(&mut patch_data)?;
-
-                // Apply patch to rebuild git object.
-                let object = apply_patch(&base_data, &patch_data)?;
-
-                // Write object to dst.
-                let (hash_code, obj_content) = object.to_bytes_vec()?;
-                write_compressed_at(hash_code, &obj_content, &dst)?;
-            }
-            OBJ_OFS_DELTA | OBJ_TAG => {
-                unimplemented!("Unimplemented object type decoding: {obj_type}")
-            }
-            // If we get another value, then there is a bug 🐞 or data are corrupted.
-            _ => {
-                return Err(GitError::InvalidContent(format!(
-                    "Invalid object type received: {obj_type}"
-                )))
-            }
-        }
+    for object_index in 0..object_count {
+        let offset = reader.count;
+        let hash_code = unpack_entry(&mut reader, &store, offset, &offsets)
+            .map_err(|err| err.in_pack_entry(object_index, offset))?;
+        offsets.insert(offset, hash_code);
+    }
+
+    // Pack ends with the checksum of everything before it.
+    let actual: HashCode = reader.hasher.clone().finalize().into();
+    let mut checksum = [0; 20];
+    reader.inner.read_exact(&mut checksum)?;
+    if checksum != actual {
+        return Err(GitError::invalid_content("Pack checksum mismatch"));
     }
 
     Ok(())
 }
 
+fn unpack_entry<R: io::BufRead>(
+    reader: &mut R,
+    store: &ObjectStore,
+    offset: u64,
+    offsets: &HashMap<u64, HashCode>,
+) -> Result<HashCode, GitError> {
+    // Read object header.
+    let (obj_type, obj_len) = read_object_pack_header(reader)?;
+
+    // Decode object content based on its type.
+    let base_object_hash = match obj_type {
+        OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => {
+            let header_type = pack_object_type(obj_type)?;
+            let data = read_zlib(reader, obj_len)?;
+            return store.write_raw(header_type, &data);
+        }
+        OBJ_OFS_DELTA => {
+            // Base object has been unpacked before: find it from its offset.
+            let relative_offset = read_ofs_delta_offset(reader)?;
+            offset
+                .checked_sub(relative_offset)
+                .and_then(|base_offset| offsets.get(&base_offset))
+                .copied()
+                .ok_or(GitError::InvalidDelta("Offset delta base not found"))?
+        }
+        OBJ_REF_DELTA => {
+            // Read base object hash.
+            let mut base_object_hash = [0; 20];
+            reader.read_exact(&mut base_object_hash)?;
+            base_object_hash
+        }
+        // If we get another value, then there is a bug 🐞 or data are corrupted.
+        _ => return Err(GitError::UnsupportedObjectType(obj_type)),
+    };
+
+    // Find base object data from git DB.
+    let (base_type, base_data) = store.read_raw(base_object_hash)?;
+
+    // Read compressed data.
+    let patch_data = read_zlib(reader, obj_len)?;
+
+    // Apply patch to rebuild git object and write it to dst.
+    let data = apply_delta(&base_data, &patch_data)?;
+    store.write_raw(base_type, &data)
+}
+
+/// Convert non delta pack object type to git object type.
+pub fn pack_object_type(obj_type: u8) -> Result<GitObjectHeaderType, GitError> {
+    match obj_type {
+        OBJ_COMMIT => Ok(GitObjectHeaderType::Commit),
+        OBJ_TREE => Ok(GitObjectHeaderType::Tree),
+        OBJ_BLOB => Ok(GitObjectHeaderType::Blob),
+        OBJ_TAG => Ok(GitObjectHeaderType::Tag),
+        _ => Err(GitError::UnsupportedObjectType(obj_type)),
+    }
+}
+
+/// Convert git object type to its pack object type.
+pub fn pack_type_number(r#type: GitObjectHeaderType) -> u8 {
+    match r#type {
+        GitObjectHeaderType::Commit => OBJ_COMMIT,
+        GitObjectHeaderType::Tree => OBJ_TREE,
+        GitObjectHeaderType::Blob => OBJ_BLOB,
+        GitObjectHeaderType::Tag => OBJ_TAG,
+    }
+}
+
+/// Read negative offset to base object stored after an offset delta header.
+pub fn read_ofs_delta_offset<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut buf = [0_u8; 1];
+    reader.read_exact(&mut buf)?;
+
+    let mut value = (buf[0] & 0b0111_1111) as u64;
+    while buf[0] & 0b1000_0000 != 0 {
+        // Reject offsets that cannot fit in 64 bits instead of overflowing.
+        if value >= 1 << 56 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Offset delta overflow",
+            ));
+        }
+
+        reader.read_exact(&mut buf)?;
+        value = ((value + 1) << 7) | (buf[0] & 0b0111_1111) as u64;
+    }
+
+    Ok(value)
+}
+
 pub fn read_object_pack_header<R: io::Read>(reader: &mut R) -> io::Result<(u8, usize)> {
     // Read first byte.
     let mut buf = [0_u8; 1];
@@ -125,6 +173,14 @@ pub fn read_object_pack_header<R: io::Read>(reader: &mut R) -> io::Result<(u8, u
 
     let mut offset = 4;
     while msb != 0 {
+        // Reject sizes that cannot fit in memory instead of overflowing.
+        if offset >= usize::BITS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Object size overflow in pack header",
+            ));
+        }
+
         // Read next byte.
         let mut buf = [0_u8; 1];
         reader.read_exact(&mut buf)?;
@@ -138,6 +194,23 @@ pub fn read_object_pack_header<R: io::Read>(reader: &mut R) -> io::Result<(u8, u
     Ok((obj_type, value))
 }
 
+/// Write object type and size the way `read_object_pack_header` reads them.
+pub fn write_object_pack_header<W: io::Write>(
+    writer: &mut W,
+    obj_type: u8,
+    size: usize,
+) -> io::Result<()> {
+    let mut byte = (obj_type << 4) | (size & 0b0000_1111) as u8;
+    let mut size = size >> 4;
+
+    while size != 0 {
+        writer.write_all(&[byte | 0b1000_0000])?;
+        byte = (size & 0b0111_1111) as u8;
+        size >>= 7;
+    }
+    writer.write_all(&[byte])
+}
+
 pub fn read_var_int(data: &[u8]) -> Result<(&[u8], usize), GitError> {
     let mut msb = 1;
     let mut index = 0;
@@ -151,6 +224,10 @@ pub fn read_var_int(data: &[u8]) -> Result<(&[u8], usize), GitError> {
             ));
         };
 
+        if index * 7 >= usize::BITS as usize {
+            return Err(GitError::invalid_content("Var int overflow"));
+        }
+
         // Read payload.
         msb = byte >> 7;
         value |= ((byte & 0b0111_1111) as usize) << (index * 7);
@@ -160,27 +237,26 @@ pub fn read_var_int(data: &[u8]) -> Result<(&[u8], usize), GitError> {
     Ok((&data[index..], value))
 }
 
-fn unpack_git_object<R, P>(reader: &mut R, header: GitObjectHeader, dst: P) -> Result<(), GitError>
-where
-    R: io::BufRead,
-    P: AsRef<Path>,
-{
-    // Read compressed data.
-    let mut data = Vec::with_capacity(header.len);
-    let mut decompress = ZlibDecoder::new(reader);
-    decompress.read_to_end(&mut data)?;
-
-    // Parse object
-    let object = GitObject::read_with_header(&mut data.reader(), header)?;
-
-    // Write object to dst.
-    let (hash_code, obj_content) = object.to_bytes_vec()?;
-    write_compressed_at(hash_code, &obj_content, dst)?;
+pub(crate) fn read_zlib<R: io::BufRead>(
+    reader: &mut R,
+    expected_len: usize,
+) -> Result<Vec<u8>, GitError> {
+    let mut data = Vec::with_capacity(expected_len.min(MAX_PREALLOC_LEN));
+    // A stream inflating past the expected size is refused without inflating it all.
+    ZlibDecoder::new(reader)
+        .take(expected_len as u64 + 1)
+        .read_to_end(&mut data)?;
+
+    if data.len() != expected_len {
+        return Err(GitError::invalid_content(
+            "Inflated object size does not match pack header",
+        ));
+    }
 
-    Ok(())
+    Ok(data)
 }
 
-fn apply_patch(base: &[u8], patch: &[u8]) -> Result<GitObject, GitError> {
+pub fn apply_patch(base: &[u8], patch: &[u8]) -> Result<GitObject, GitError> {
     // Split header and payload from base data.
     let Some(base_header_offset) = base.iter().position(|x| *x == 0) else {
         return Err(GitError::invalid_content("base miss header"));
@@ -189,51 +265,69 @@ fn apply_patch(base: &[u8], patch: &[u8]) -> Result<GitObject, GitError> {
     let (base_header, base_payload) = base.split_at(base_header_offset + 1);
     let base_header = GitObjectHeader::read(&mut base_header.reader())?;
 
+    let output = apply_delta(base_payload, patch)?;
+
+    // Build git object from rebuild content + header
+    let object = GitObject::read_with_header(
+        &mut output.reader(),
+        GitObjectHeader {
+            len: output.len(),
+            r#type: base_header.r#type,
+        },
+    )?;
+
+    Ok(object)
+}
+
+/// Rebuild object payload from its base payload and delta instructions.
+pub fn apply_delta(base_payload: &[u8], patch: &[u8]) -> Result<Vec<u8>, GitError> {
     // Read header and init output object.
     let (patch, source_len) = read_var_int(patch)?;
-    assert_eq!(
-        source_len,
-        base_payload.len(),
-        "Base len is different from information stored in patch {base:?}"
-    );
+    if source_len != base_payload.len() {
+        return Err(GitError::InvalidDelta(
+            "Base len is different from information stored in patch",
+        ));
+    }
 
     let (mut patch, output_len) = read_var_int(patch)?;
-    let mut output = Vec::with_capacity(output_len);
+    let mut output = Vec::with_capacity(output_len.min(MAX_PREALLOC_LEN));
 
     // Loop over instruction and rebuild output object.
     while !patch.is_empty() {
-        let (next_patch, instr) = DeltaInstructionType::from_bytes(patch);
+        let (next_patch, instr) = DeltaInstructionType::from_bytes(patch)?;
         patch = next_patch;
 
         match instr {
             DeltaInstructionType::Copy { offset, size } => {
-                output.extend_from_slice(&base_payload[offset..offset + size]);
+                let chunk = offset
+                    .checked_add(size)
+                    .and_then(|end| base_payload.get(offset..end))
+                    .ok_or(GitError::InvalidDelta("Copy out of base bounds"))?;
+                output.extend_from_slice(chunk);
             }
             DeltaInstructionType::Insert { size } => {
+                if size > patch.len() {
+                    return Err(GitError::InvalidDelta("Insert out of patch bounds"));
+                }
                 let (next_payload, next_patch) = patch.split_at(size);
                 patch = next_patch;
                 output.extend_from_slice(next_payload);
             }
             DeltaInstructionType::Reserved => {}
         }
-    }
 
-    // Build git object from rebuild content + header
-    assert_eq!(
-        output.len(),
-        output_len,
-        "Final output buffer is not the same as expected"
-    );
+        if output.len() > output_len {
+            return Err(GitError::InvalidDelta("Output exceeds expected size"));
+        }
+    }
 
-    let object = GitObject::read_with_header(
-        &mut output.reader(),
-        GitObjectHeader {
-            len: output_len,
-            r#type: base_header.r#type,
-        },
-    )?;
+    if output.len() != output_len {
+        return Err(GitError::InvalidDelta(
+            "Final output buffer is not the same as expected",
+        ));
+    }
 
-    Ok(object)
+    Ok(output)
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -244,45 +338,33 @@ pub enum DeltaInstructionType {
 }
 
 impl DeltaInstructionType {
-    pub fn from_bytes(input: &[u8]) -> (&[u8], Self) {
-        let instr = input[0];
+    pub fn from_bytes(input: &[u8]) -> Result<(&[u8], Self), GitError> {
+        let mut bytes = input.iter();
+        let mut next_byte = || {
+            bytes
+                .next()
+                .map(|x| *x as usize)
+                .ok_or(GitError::InvalidDelta("Truncated delta instruction"))
+        };
+
+        let instr = next_byte()?;
 
         // If MSB is set: then it is a copy.
         if instr >> 7 == 1 {
-            let mut bytes_read = 1;
-
             // Read next 4 optional bytes to get offset to copy from.
             let mut offset = 0;
-            if instr & 0x01 != 0 {
-                offset |= input[bytes_read] as usize;
-                bytes_read += 1;
-            }
-            if instr & 0x02 != 0 {
-                offset |= (input[bytes_read] as usize) << 8;
-                bytes_read += 1;
-            }
-            if instr & 0x04 != 0 {
-                offset |= (input[bytes_read] as usize) << 16;
-                bytes_read += 1;
-            }
-            if instr & 0x08 != 0 {
-                offset |= (input[bytes_read] as usize) << 24;
-                bytes_read += 1;
+            for (bit, shift) in [(0x01, 0), (0x02, 8), (0x04, 16), (0x08, 24)] {
+                if instr & bit != 0 {
+                    offset |= next_byte()? << shift;
+                }
             }
 
             // Read next 3 optional bytes to get object size.
             let mut size = 0;
-            if instr & 0x10 != 0 {
-                size |= input[bytes_read] as usize;
-                bytes_read += 1;
-            }
-            if instr & 0x20 != 0 {
-                size |= (input[bytes_read] as usize) << 8;
-                bytes_read += 1;
-            }
-            if instr & 0x40 != 0 {
-                size |= (input[bytes_read] as usize) << 16;
-                bytes_read += 1;
+            for (bit, shift) in [(0x10, 0), (0x20, 8), (0x40, 16)] {
+                if instr & bit != 0 {
+                    size |= next_byte()? << shift;
+                }
             }
 
             // If size is 0: then set size to a special value.
@@ -291,18 +373,61 @@ impl DeltaInstructionType {
                 size = 0x10000;
             }
 
-            (&input[bytes_read..], Self::Copy { offset, size })
+            let bytes_read = input.len() - bytes.len();
+            Ok((&input[bytes_read..], Self::Copy { offset, size }))
         } else {
             // Otherwise it is an insert instruction
-            let size = (instr & 0b0111_1111) as usize;
+            let size = instr & 0b0111_1111;
 
             // Handle case of 0 size instruction.
             // See: https://github.com/git/git/blob/795ea8776befc95ea2becd8020c7a284677b4161/Documentation/gitformat-pack.txt#L169
             if size == 0 {
-                (&input[1..], Self::Reserved)
+                Ok((&input[1..], Self::Reserved))
             } else {
-                (&input[1..], Self::Insert { size })
+                Ok((&input[1..], Self::Insert { size }))
             }
         }
     }
 }
+
+/// Track how many bytes have been consumed so errors can report pack offsets, and hash
+/// them to check the pack checksum.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+    hasher: Sha1,
+}
+
+impl<R> CountingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            count: 0,
+            hasher: Sha1::new(),
+        }
+    }
+}
+
+impl<R: io::BufRead> io::Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.count += len as u64;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+}
+
+impl<R: io::BufRead> io::BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // Consumed bytes are still buffered: getting them back does not read.
+        if let Ok(buf) = self.inner.fill_buf() {
+            self.hasher.update(&buf[..amt.min(buf.len())]);
+        }
+        self.count += amt as u64;
+        self.inner.consume(amt)
+    }
+}
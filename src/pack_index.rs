@@ -0,0 +1,642 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    os::unix::fs::FileExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use flate2::bufread::ZlibDecoder;
+use sha1::{Digest, Sha1};
+
+use crate::{
+    header::GitObjectHeaderType,
+    object::encode_raw_object,
+    pack_file::{
+        apply_delta, pack_object_type, read_object_pack_header, read_ofs_delta_offset, read_zlib,
+        OBJ_OFS_DELTA, OBJ_REF_DELTA,
+    },
+    GitError, HashCode,
+};
+
+const IDX_MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const IDX_VERSION: u32 = 2;
+const PACK_HEADER_LEN: usize = 12;
+
+/// Longest delta chain we accept before considering pack as corrupted.
+const MAX_DELTA_DEPTH: usize = 4096;
+
+/// Most bytes of decoded delta bases kept per pack, like git `core.deltaBaseCacheLimit`.
+const DELTA_BASE_CACHE_LIMIT: usize = 96 << 20;
+
+/// Callback used to find delta bases that are not in the pack itself.
+pub type ResolveBase<'a> = &'a dyn Fn(HashCode) -> Result<(GitObjectHeaderType, Vec<u8>), GitError>;
+
+/// Content of a version 2 `.idx` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndex {
+    /// Entries sorted by hash code.
+    pub entries: Vec<PackIndexEntry>,
+    pub pack_checksum: HashCode,
+    pub checksum: HashCode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackIndexEntry {
+    pub hash_code: HashCode,
+    pub crc32: u32,
+    pub offset: u64,
+}
+
+impl PackIndex {
+    pub fn parse(data: &[u8]) -> Result<Self, GitError> {
+        let invalid = |msg: &'static str| GitError::InvalidObjectPayload(msg);
+
+        if data.len() < 8 + 256 * 4 + 40 || data[..4] != IDX_MAGIC {
+            return Err(invalid("Invalid pack index magic"));
+        }
+        if read_u32(data, 4) != IDX_VERSION {
+            return Err(invalid("Unsupported pack index version"));
+        }
+
+        // Last fanout entry is the object count.
+        let count = read_u32(data, 8 + 255 * 4) as usize;
+        let hashes_start = 8 + 256 * 4;
+        let crc_start = hashes_start + count * 20;
+        let offsets_start = crc_start + count * 4;
+        let large_offsets_start = offsets_start + count * 4;
+
+        if data.len() < large_offsets_start + 40 {
+            return Err(invalid("Truncated pack index"));
+        }
+
+        let large_offsets_len = data.len() - 40 - large_offsets_start;
+        let mut entries = Vec::with_capacity(count);
+
+        for idx in 0..count {
+            let mut hash_code = [0; 20];
+            hash_code.copy_from_slice(&data[hashes_start + idx * 20..hashes_start + idx * 20 + 20]);
+
+            let offset = read_u32(data, offsets_start + idx * 4);
+            let offset = if offset & 0x8000_0000 == 0 {
+                offset as u64
+            } else {
+                let large_idx = (offset & 0x7fff_ffff) as usize;
+                if (large_idx + 1) * 8 > large_offsets_len {
+                    return Err(invalid("Invalid pack index large offset"));
+                }
+                read_u64(data, large_offsets_start + large_idx * 8)
+            };
+
+            entries.push(PackIndexEntry {
+                hash_code,
+                crc32: read_u32(data, crc_start + idx * 4),
+                offset,
+            });
+        }
+
+        if entries.windows(2).any(|x| x[0].hash_code >= x[1].hash_code) {
+            return Err(invalid("Pack index entries are not sorted"));
+        }
+
+        let mut pack_checksum = [0; 20];
+        pack_checksum.copy_from_slice(&data[data.len() - 40..data.len() - 20]);
+        let mut checksum = [0; 20];
+        checksum.copy_from_slice(&data[data.len() - 20..]);
+
+        Ok(Self {
+            entries,
+            pack_checksum,
+            checksum,
+        })
+    }
+
+    /// Build index by decoding every object of a pack read from `reader`.
+    pub fn build<R: BufRead + Seek>(
+        reader: &mut R,
+        resolve: ResolveBase,
+    ) -> Result<Self, GitError> {
+        let (object_count, data_end) = read_pack_header(reader)?;
+        let cache = Mutex::default();
+        let no_base = |_| None;
+        let mut decoder = EntryDecoder {
+            reader: &mut *reader,
+            data_end,
+            find: &no_base,
+            resolve,
+            cache: &cache,
+        };
+
+        // Find where each entry starts and ends.
+        let mut extents = Vec::with_capacity(object_count.min(1 << 16) as usize);
+        let mut offset = PACK_HEADER_LEN as u64;
+        for object_index in 0..object_count {
+            let end = decoder
+                .entry_end(offset)
+                .map_err(|err| err.in_pack_entry(object_index, offset))?;
+            extents.push((offset, end));
+            offset = end;
+        }
+
+        if offset != data_end {
+            return Err(GitError::invalid_content(
+                "Trailing data after pack entries",
+            ));
+        }
+
+        // Resolve objects: bases stored after their deltas need several passes.
+        let mut known: HashMap<HashCode, u64> = HashMap::new();
+        let mut hashes: HashMap<usize, HashCode> = HashMap::new();
+        let mut pending: Vec<usize> = (0..extents.len()).collect();
+
+        while !pending.is_empty() {
+            let mut next_pending = Vec::new();
+            let mut last_error = None;
+
+            for idx in pending.iter().copied() {
+                let (start, _end) = extents[idx];
+                let find = |hash_code: HashCode| known.get(&hash_code).copied();
+                let mut decoder = EntryDecoder {
+                    reader: &mut *reader,
+                    data_end,
+                    find: &find,
+                    resolve,
+                    cache: &cache,
+                };
+
+                match decoder.decode(start) {
+                    Ok((r#type, payload)) => {
+                        let (hash_code, _) = encode_raw_object(r#type, &payload);
+                        hashes.insert(idx, hash_code);
+                        known.insert(hash_code, start);
+                    }
+                    Err(err @ GitError::MissingObject(_)) => {
+                        last_error = Some(err.in_pack_entry(idx as u32, start));
+                        next_pending.push(idx);
+                    }
+                    Err(err) => return Err(err.in_pack_entry(idx as u32, start)),
+                }
+            }
+
+            // No progress: base is really missing.
+            if next_pending.len() == pending.len() {
+                return Err(last_error.expect("Pending entries always have an error"));
+            }
+            pending = next_pending;
+        }
+
+        let mut entries = Vec::with_capacity(extents.len());
+        for (idx, (start, end)) in extents.into_iter().enumerate() {
+            entries.push(PackIndexEntry {
+                hash_code: hashes[&idx],
+                crc32: crc32_range(reader, start, end)?,
+                offset: start,
+            });
+        }
+        entries.sort_by_key(|x| x.hash_code);
+
+        let mut pack_checksum = [0; 20];
+        reader.seek(SeekFrom::Start(data_end))?;
+        reader.read_exact(&mut pack_checksum)?;
+
+        let mut index = Self {
+            entries,
+            pack_checksum,
+            checksum: [0; 20],
+        };
+        let bytes = index.to_bytes();
+        index.checksum.copy_from_slice(&bytes[bytes.len() - 20..]);
+        Ok(index)
+    }
+
+    /// Encode index to `.idx` format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::with_capacity(8 + 256 * 4 + self.entries.len() * 28 + 40);
+        output.extend_from_slice(&IDX_MAGIC);
+        output.extend_from_slice(&IDX_VERSION.to_be_bytes());
+
+        // Fanout table.
+        let mut count = 0;
+        for first_byte in 0..=255_u8 {
+            count += self
+                .entries
+                .iter()
+                .skip(count)
+                .take_while(|x| x.hash_code[0] == first_byte)
+                .count();
+            output.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+
+        for entry in &self.entries {
+            output.extend_from_slice(&entry.hash_code);
+        }
+        for entry in &self.entries {
+            output.extend_from_slice(&entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for entry in &self.entries {
+            if entry.offset < 0x8000_0000 {
+                output.extend_from_slice(&(entry.offset as u32).to_be_bytes());
+            } else {
+                let large_idx = (large_offsets.len() as u32) | 0x8000_0000;
+                output.extend_from_slice(&large_idx.to_be_bytes());
+                large_offsets.push(entry.offset);
+            }
+        }
+        for offset in large_offsets {
+            output.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        output.extend_from_slice(&self.pack_checksum);
+        let checksum: HashCode = Sha1::digest(&output).into();
+        output.extend_from_slice(&checksum);
+        output
+    }
+
+    pub fn find(&self, hash_code: HashCode) -> Option<u64> {
+        self.entries
+            .binary_search_by_key(&hash_code, |x| x.hash_code)
+            .ok()
+            .map(|idx| self.entries[idx].offset)
+    }
+}
+
+/// A `.pack` file with its index, read entry by entry.
+pub struct PackFile {
+    pub path: PathBuf,
+    pub index: PackIndex,
+    index_data: Vec<u8>,
+    file: File,
+    object_count: u32,
+    /// Offset of the pack checksum, where entries stop.
+    data_end: u64,
+    cache: Mutex<DeltaBaseCache>,
+}
+
+impl PackFile {
+    pub fn open<P: AsRef<Path>>(idx_path: P) -> Result<Self, GitError> {
+        let idx_path = idx_path.as_ref();
+        let index_data = fs::read(idx_path)?;
+        let index = PackIndex::parse(&index_data)?;
+
+        let path = idx_path.with_extension("pack");
+        let file = File::open(&path)?;
+        let (object_count, data_end) = read_pack_header(&mut FileAt::new(&file))?;
+
+        Ok(Self {
+            path,
+            index,
+            index_data,
+            file,
+            object_count,
+            data_end,
+            cache: Mutex::default(),
+        })
+    }
+
+    /// Decode object stored at `offset`, following delta chains.
+    pub fn read_at(
+        &self,
+        offset: u64,
+        resolve: ResolveBase,
+    ) -> Result<(GitObjectHeaderType, Vec<u8>), GitError> {
+        let find = |hash_code: HashCode| self.index.find(hash_code);
+        EntryDecoder {
+            reader: BufReader::new(FileAt::new(&self.file)),
+            data_end: self.data_end,
+            find: &find,
+            resolve,
+            cache: &self.cache,
+        }
+        .decode(offset)
+    }
+
+    /// Check checksums of pack and index files and CRC of every entry.
+    pub fn verify(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut reader = BufReader::new(FileAt::new(&self.file));
+
+        let mut hasher = Sha1::new();
+        let mut trailer = [0; 20];
+        let checksums = io::copy(&mut (&mut reader).take(self.data_end), &mut hasher)
+            .and_then(|_| reader.read_exact(&mut trailer));
+        match checksums {
+            Ok(()) => {
+                let pack_checksum: HashCode = hasher.finalize().into();
+                if pack_checksum != trailer {
+                    errors.push("pack checksum mismatch".to_string());
+                }
+                if pack_checksum != self.index.pack_checksum {
+                    errors.push("pack checksum does not match index".to_string());
+                }
+            }
+            Err(err) => errors.push(format!("cannot read pack: {err}")),
+        }
+
+        let index_end = self.index_data.len() - 20;
+        let index_checksum: HashCode = Sha1::digest(&self.index_data[..index_end]).into();
+        if index_checksum != self.index.checksum {
+            errors.push("index checksum mismatch".to_string());
+        }
+
+        if self.object_count as usize != self.index.entries.len() {
+            errors.push(format!(
+                "pack has {} objects but index has {}",
+                self.object_count,
+                self.index.entries.len()
+            ));
+        }
+
+        // Entries end where next one starts.
+        let mut offsets: Vec<_> = self.index.entries.iter().map(|x| x.offset).collect();
+        offsets.sort_unstable();
+        offsets.push(self.data_end);
+
+        for entry in &self.index.entries {
+            let end = offsets.get(offsets.partition_point(|x| *x <= entry.offset));
+            let crc = end.and_then(|end| crc32_range(&mut reader, entry.offset, *end).ok());
+
+            if crc != Some(entry.crc32) {
+                errors.push(format!(
+                    "CRC mismatch for object {} at offset {}",
+                    hex::encode(entry.hash_code),
+                    entry.offset
+                ));
+            }
+        }
+
+        errors
+    }
+}
+
+/// Build `.idx` file next to a `.pack` file and return its path.
+pub fn index_pack<P: AsRef<Path>>(pack_path: P, resolve: ResolveBase) -> Result<PathBuf, GitError> {
+    let pack_path = pack_path.as_ref();
+    let mut reader = BufReader::new(File::open(pack_path)?);
+    let index = PackIndex::build(&mut reader, resolve)?;
+
+    let idx_path = pack_path.with_extension("idx");
+    fs::write(&idx_path, index.to_bytes())?;
+    Ok(idx_path)
+}
+
+/// Check pack header, returning the object count and the offset of the pack checksum.
+fn read_pack_header<R: Read + Seek>(reader: &mut R) -> Result<(u32, u64), GitError> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    if len < (PACK_HEADER_LEN + 20) as u64 {
+        return Err(GitError::invalid_content("Invalid magic PACK"));
+    }
+
+    let mut header = [0; PACK_HEADER_LEN];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut header)?;
+    if &header[..4] != b"PACK" {
+        return Err(GitError::invalid_content("Invalid magic PACK"));
+    }
+    if read_u32(&header, 4) != 2 {
+        return Err(GitError::invalid_content("Invalid PACK version"));
+    }
+    Ok((read_u32(&header, 8), len - 20))
+}
+
+/// Reader of a file at its own position, so readers can share the file.
+struct FileAt<'a> {
+    file: &'a File,
+    position: u64,
+}
+
+impl<'a> FileAt<'a> {
+    fn new(file: &'a File) -> Self {
+        Self { file, position: 0 }
+    }
+}
+
+impl Read for FileAt<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.file.read_at(buf, self.position)?;
+        self.position += len as u64;
+        Ok(len)
+    }
+}
+
+impl Seek for FileAt<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => self.file.metadata()?.len().checked_add_signed(x),
+            SeekFrom::Current(x) => self.position.checked_add_signed(x),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Decoded objects used as delta bases, by pack offset.
+#[derive(Default)]
+struct DeltaBaseCache {
+    entries: HashMap<u64, (GitObjectHeaderType, Arc<Vec<u8>>)>,
+    size: usize,
+}
+
+impl DeltaBaseCache {
+    fn get(&self, offset: u64) -> Option<(GitObjectHeaderType, Arc<Vec<u8>>)> {
+        self.entries.get(&offset).cloned()
+    }
+
+    fn insert(&mut self, offset: u64, r#type: GitObjectHeaderType, payload: Arc<Vec<u8>>) {
+        if payload.len() > DELTA_BASE_CACHE_LIMIT || self.entries.contains_key(&offset) {
+            return;
+        }
+        // Evict arbitrary bases until the new one fits.
+        while self.size + payload.len() > DELTA_BASE_CACHE_LIMIT {
+            let Some(key) = self.entries.keys().next().copied() else {
+                break;
+            };
+            if let Some((_, evicted)) = self.entries.remove(&key) {
+                self.size -= evicted.len();
+            }
+        }
+        self.size += payload.len();
+        self.entries.insert(offset, (r#type, payload));
+    }
+}
+
+/// Decoder of pack entries read by offset.
+struct EntryDecoder<'a, R> {
+    reader: R,
+    /// Offset of the pack checksum, where entries stop.
+    data_end: u64,
+    /// Offset of an object of the pack, used for ref deltas.
+    find: &'a dyn Fn(HashCode) -> Option<u64>,
+    resolve: ResolveBase<'a>,
+    cache: &'a Mutex<DeltaBaseCache>,
+}
+
+impl<R: BufRead + Seek> EntryDecoder<'_, R> {
+    /// Decode entry at `offset`, following delta chains.
+    ///
+    /// Chains are walked down to their base first, then deltas are applied back up, so
+    /// deep chains don't grow the stack.
+    fn decode(&mut self, offset: u64) -> Result<(GitObjectHeaderType, Vec<u8>), GitError> {
+        // Offset and patch of each delta, from the entry asked for down to its base.
+        let mut deltas = Vec::new();
+        let mut offset = offset;
+        let (r#type, mut data) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(GitError::InvalidDelta("Delta chain is too long"));
+            }
+            if !deltas.is_empty() {
+                let cached = self.lock_cache().get(offset);
+                if let Some(base) = cached {
+                    break base;
+                }
+            }
+
+            self.seek(offset)?;
+            let (obj_type, obj_len) = read_object_pack_header(&mut self.reader)?;
+            match obj_type {
+                OBJ_OFS_DELTA => {
+                    let relative_offset = read_ofs_delta_offset(&mut self.reader)?;
+                    let base_offset = offset
+                        .checked_sub(relative_offset)
+                        .filter(|x| *x > 0)
+                        .ok_or(GitError::InvalidDelta("Offset delta base out of pack"))?;
+                    deltas.push((offset, read_zlib(&mut self.reader, obj_len)?));
+                    offset = base_offset;
+                }
+                OBJ_REF_DELTA => {
+                    let mut base_object_hash = [0; 20];
+                    self.reader.read_exact(&mut base_object_hash)?;
+                    deltas.push((offset, read_zlib(&mut self.reader, obj_len)?));
+                    match (self.find)(base_object_hash) {
+                        Some(base_offset) => offset = base_offset,
+                        None => {
+                            let (base_type, base_data) = (self.resolve)(base_object_hash)?;
+                            break (base_type, Arc::new(base_data));
+                        }
+                    }
+                }
+                _ => {
+                    let r#type = pack_object_type(obj_type)?;
+                    let payload = read_zlib(&mut self.reader, obj_len)?;
+                    if deltas.is_empty() {
+                        return Ok((r#type, payload));
+                    }
+                    let payload = Arc::new(payload);
+                    self.lock_cache().insert(offset, r#type, payload.clone());
+                    break (r#type, payload);
+                }
+            }
+        };
+
+        // Objects in the middle of the chain are kept for other deltas of the same base.
+        let (_, patch) = deltas.remove(0);
+        for (offset, patch) in deltas.into_iter().rev() {
+            let payload = Arc::new(apply_delta(&data, &patch)?);
+            self.lock_cache().insert(offset, r#type, payload.clone());
+            data = payload;
+        }
+        Ok((r#type, apply_delta(&data, &patch)?))
+    }
+
+    fn lock_cache(&self) -> std::sync::MutexGuard<'_, DeltaBaseCache> {
+        // Cache stays consistent even if another reader panicked.
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn seek(&mut self, offset: u64) -> Result<(), GitError> {
+        if offset >= self.data_end {
+            return Err(GitError::invalid_content("Pack offset out of bounds"));
+        }
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Find offset right after compressed data of entry starting at `offset`.
+    fn entry_end(&mut self, offset: u64) -> Result<u64, GitError> {
+        self.seek(offset)?;
+        let (obj_type, _obj_len) = read_object_pack_header(&mut self.reader)?;
+
+        match obj_type {
+            OBJ_OFS_DELTA => {
+                read_ofs_delta_offset(&mut self.reader)?;
+            }
+            OBJ_REF_DELTA => {
+                let mut base_object_hash = [0; 20];
+                self.reader.read_exact(&mut base_object_hash)?;
+            }
+            _ => {
+                pack_object_type(obj_type)?;
+            }
+        }
+
+        // Decompressor only consumes bytes of its own stream.
+        io::copy(&mut ZlibDecoder::new(&mut self.reader), &mut io::sink())?;
+        let end = self.reader.stream_position()?;
+        if end > self.data_end {
+            return Err(GitError::invalid_content("Pack entry overlaps checksum"));
+        }
+        Ok(end)
+    }
+}
+
+/// CRC-32 of bytes `start..end` of `reader`.
+fn crc32_range<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> io::Result<u32> {
+    reader.seek(SeekFrom::Start(start))?;
+    let mut crc = !0;
+    let mut buf = [0; 8192];
+    let mut left = end.saturating_sub(start);
+    while left > 0 {
+        let chunk = &mut buf[..left.min(8192) as usize];
+        reader.read_exact(chunk)?;
+        crc = crc32_update(crc, chunk);
+        left -= chunk.len() as u64;
+    }
+    Ok(!crc)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut buf = [0; 4];
+    buf.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_be_bytes(buf)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_be_bytes(buf)
+}
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut idx = 0;
+    while idx < 256 {
+        let mut value = idx as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xedb8_8320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[idx] = value;
+        idx += 1;
+    }
+    table
+};
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, byte| {
+        CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
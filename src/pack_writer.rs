@@ -0,0 +1,216 @@
+use std::{
+    collections::HashSet,
+    io::{self, Write},
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use sha1::{Digest, Sha1};
+
+use crate::{
+    header::GitObjectHeaderType,
+    log::ancestors,
+    object::GitObject,
+    pack_file::{pack_type_number, write_object_pack_header},
+    revision::peel_tags_with,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+const MODE_TREE: u32 = 0o40000;
+const MODE_GITLINK: u32 = 0o160000;
+
+/// Objects reachable from `include` but not from `exclude`, like
+/// `git rev-list --objects <include> --not <exclude>`.
+///
+/// Objects of `exclude` missing from the store are skipped: the other side may have
+/// history we never fetched.
+pub fn list_objects(
+    store: &ObjectStore,
+    include: &[HashCode],
+    exclude: &[HashCode],
+) -> Result<Vec<HashCode>, GitError> {
+    // Trees and blobs the other side already has.
+    let mut excluded = HashSet::new();
+    let mut excluded_commits = Vec::new();
+    for id in exclude.iter().filter(|x| store.contains(**x)) {
+        let (r#type, target) = peel_tags_with(store, *id, |x| {
+            excluded.insert(x);
+        })?;
+        match r#type {
+            GitObjectHeaderType::Commit => excluded_commits.push(target),
+            GitObjectHeaderType::Tree => mark_tree(store, target, &mut excluded)?,
+            _ => {
+                excluded.insert(target);
+            }
+        }
+    }
+    let uninteresting = ancestors(store, &excluded_commits, false)?;
+
+    let mut output = Vec::new();
+    let mut seen = HashSet::new();
+    let mut commits = Vec::new();
+    let mut trees = Vec::new();
+    for id in include {
+        let (r#type, target) = peel_tags_with(store, *id, |x| {
+            if !excluded.contains(&x) && seen.insert(x) {
+                output.push(x);
+            }
+        })?;
+        match r#type {
+            GitObjectHeaderType::Commit => commits.push(target),
+            GitObjectHeaderType::Tree => trees.push(target),
+            _ => {
+                if !excluded.contains(&target) && seen.insert(target) {
+                    output.push(target);
+                }
+            }
+        }
+    }
+
+    // Commits first, then their content, like git orders packs.
+    let mut edges = HashSet::new();
+    while let Some(id) = commits.pop() {
+        if uninteresting.contains(&id) || !seen.insert(id) {
+            continue;
+        }
+        output.push(id);
+
+        let GitObject::Commit { tree, parents, .. } = store.read(id)? else {
+            return Err(GitError::InvalidContent(format!(
+                "{} is not a commit",
+                hex::encode(id)
+            )));
+        };
+        trees.push(tree);
+        // Parents of shallow commits are missing.
+        let parents = if store.is_shallow(id) {
+            Vec::new()
+        } else {
+            parents
+        };
+        for parent in parents {
+            if !uninteresting.contains(&parent) {
+                commits.push(parent);
+            } else if edges.insert(parent) {
+                // Content of boundary commits is what the other side is likely to share.
+                let GitObject::Commit { tree, .. } = store.read(parent)? else {
+                    continue;
+                };
+                mark_tree(store, tree, &mut excluded)?;
+            }
+        }
+    }
+
+    for id in trees {
+        add_tree(store, id, &excluded, &mut seen, &mut output)?;
+    }
+
+    Ok(output)
+}
+
+/// Add `id` and every object it contains to `excluded`.
+fn mark_tree(
+    store: &ObjectStore,
+    id: HashCode,
+    excluded: &mut HashSet<HashCode>,
+) -> Result<(), GitError> {
+    if !excluded.insert(id) {
+        return Ok(());
+    }
+    let GitObject::Tree(items) = store.read(id)? else {
+        return Ok(());
+    };
+    for item in items {
+        match item.mode {
+            MODE_TREE => mark_tree(store, item.hash_code, excluded)?,
+            MODE_GITLINK => {}
+            _ => {
+                excluded.insert(item.hash_code);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add tree `id` and its content to `output`, skipping objects excluded or already added.
+fn add_tree(
+    store: &ObjectStore,
+    id: HashCode,
+    excluded: &HashSet<HashCode>,
+    seen: &mut HashSet<HashCode>,
+    output: &mut Vec<HashCode>,
+) -> Result<(), GitError> {
+    if excluded.contains(&id) || !seen.insert(id) {
+        return Ok(());
+    }
+    output.push(id);
+
+    let GitObject::Tree(items) = store.read(id)? else {
+        return Err(GitError::InvalidContent(format!(
+            "{} is not a tree",
+            hex::encode(id)
+        )));
+    };
+    for item in items {
+        match item.mode {
+            MODE_TREE => add_tree(store, item.hash_code, excluded, seen, output)?,
+            MODE_GITLINK => {}
+            _ => {
+                if !excluded.contains(&item.hash_code) && seen.insert(item.hash_code) {
+                    output.push(item.hash_code);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write objects `ids` as a version 2 pack, storing each object whole.
+///
+/// Returns the pack checksum, which is also written at its end.
+pub fn write_pack<W: Write>(
+    store: &ObjectStore,
+    ids: &[HashCode],
+    writer: W,
+) -> Result<HashCode, GitError> {
+    let mut writer = HashingWriter {
+        inner: writer,
+        hasher: Sha1::new(),
+    };
+
+    writer.write_all(b"PACK")?;
+    writer.write_all(&2_u32.to_be_bytes())?;
+    let count = u32::try_from(ids.len())
+        .map_err(|_| GitError::invalid_content("Too many objects for a pack"))?;
+    writer.write_all(&count.to_be_bytes())?;
+
+    for id in ids {
+        let (r#type, payload) = store.read_raw(*id)?;
+        write_object_pack_header(&mut writer, pack_type_number(r#type), payload.len())?;
+        let mut encoder = ZlibEncoder::new(&mut writer, Compression::default());
+        encoder.write_all(&payload)?;
+        encoder.finish()?;
+    }
+
+    let checksum: HashCode = writer.hasher.finalize().into();
+    writer.inner.write_all(&checksum)?;
+    Ok(checksum)
+}
+
+/// Writer computing SHA-1 of everything written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha1,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.hasher.update(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -8,6 +8,8 @@ use crate::GitError;
 pub enum PacketLine {
     Command(Bytes),
     End,
+    /// Section separator of protocol v2, `0001`.
+    Delimiter,
 }
 
 impl PacketLine {
@@ -20,6 +22,11 @@ impl PacketLine {
         Self::Command(Bytes::from(data))
     }
 
+    pub fn have(object_id: &str) -> Self {
+        let data = format!("have {}", object_id);
+        Self::Command(Bytes::from(data))
+    }
+
     pub fn done() -> Self {
         Self::Command(Bytes::from_static(b"done"))
     }
@@ -33,6 +40,7 @@ impl PacketLine {
                 Ok(())
             }
             PacketLine::End => write!(writer, "0000"),
+            PacketLine::Delimiter => write!(writer, "0001"),
         }
     }
 
@@ -41,7 +49,9 @@ impl PacketLine {
         reader.read_exact(&mut buf_size)?;
 
         let data_len = usize::from_str_radix(std::str::from_utf8(&buf_size)?, 16)?;
-        if data_len < 4 {
+        if data_len == 1 {
+            Ok(Self::Delimiter)
+        } else if data_len < 4 {
             Ok(Self::End)
         } else {
             let mut data = vec![0; data_len - 4];
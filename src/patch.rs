@@ -0,0 +1,260 @@
+use std::fmt::Write;
+
+use crate::{
+    diff::{DiffEntry, DiffSide, DiffStatus},
+    line_diff::{count_changes, is_binary, unified_diff, DiffAlgorithm},
+    GitError,
+};
+
+/// Abbreviated object names length used in `index` lines.
+const ABBREV_LEN: usize = 7;
+
+/// Total width of `--stat` lines, like git on a 80 columns terminal.
+const STAT_WIDTH: usize = 80;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchOptions {
+    /// Number of unchanged lines around each change.
+    pub context: usize,
+    pub algorithm: DiffAlgorithm,
+}
+
+impl Default for PatchOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            algorithm: DiffAlgorithm::Myers,
+        }
+    }
+}
+
+/// Loads content of one side of a change, from the object store or from the worktree.
+pub type LoadContent<'a> = &'a dyn Fn(&DiffSide) -> Result<Vec<u8>, GitError>;
+
+/// Format a change like `git diff` does, with its `diff --git` header.
+pub fn format_patch(
+    entry: &DiffEntry,
+    load: LoadContent,
+    options: &PatchOptions,
+) -> Result<String, GitError> {
+    let mut output = String::new();
+    let old_path = entry.old.as_ref().map_or(entry.path(), |x| &x.path);
+    let new_path = entry.new.as_ref().map_or(entry.path(), |x| &x.path);
+    writeln!(output, "diff --git a/{old_path} b/{new_path}")?;
+
+    match (&entry.old, &entry.new) {
+        (None, Some(new)) => writeln!(output, "new file mode {:06o}", new.mode)?,
+        (Some(old), None) => writeln!(output, "deleted file mode {:06o}", old.mode)?,
+        (Some(old), Some(new)) => {
+            if old.mode != new.mode {
+                writeln!(output, "old mode {:06o}", old.mode)?;
+                writeln!(output, "new mode {:06o}", new.mode)?;
+            }
+            match entry.status {
+                DiffStatus::Renamed(score) => {
+                    writeln!(output, "similarity index {score}%")?;
+                    writeln!(output, "rename from {old_path}")?;
+                    writeln!(output, "rename to {new_path}")?;
+                }
+                DiffStatus::Copied(score) => {
+                    writeln!(output, "similarity index {score}%")?;
+                    writeln!(output, "copy from {old_path}")?;
+                    writeln!(output, "copy to {new_path}")?;
+                }
+                _ => {}
+            }
+        }
+        (None, None) => return Ok(output),
+    }
+
+    let old_hash = entry.old.as_ref().map_or([0; 20], |x| x.hash_code);
+    let new_hash = entry.new.as_ref().map_or([0; 20], |x| x.hash_code);
+    if old_hash == new_hash {
+        // Pure rename or mode change.
+        return Ok(output);
+    }
+
+    write!(
+        output,
+        "index {}..{}",
+        &hex::encode(old_hash)[..ABBREV_LEN],
+        &hex::encode(new_hash)[..ABBREV_LEN]
+    )?;
+    match (&entry.old, &entry.new) {
+        (Some(old), Some(new)) if old.mode == new.mode => writeln!(output, " {:06o}", old.mode)?,
+        _ => writeln!(output)?,
+    }
+
+    let old_content = match &entry.old {
+        Some(side) => load(side)?,
+        None => Vec::new(),
+    };
+    let new_content = match &entry.new {
+        Some(side) => load(side)?,
+        None => Vec::new(),
+    };
+
+    let old_label = entry
+        .old
+        .as_ref()
+        .map_or("/dev/null".to_string(), |x| format!("a/{}", x.path));
+    let new_label = entry
+        .new
+        .as_ref()
+        .map_or("/dev/null".to_string(), |x| format!("b/{}", x.path));
+
+    if is_binary(&old_content) || is_binary(&new_content) {
+        writeln!(output, "Binary files {old_label} and {new_label} differ")?;
+        return Ok(output);
+    }
+
+    let hunks = unified_diff(
+        &old_content,
+        &new_content,
+        options.context,
+        options.algorithm,
+    );
+    // Like git, no file headers when there is nothing to show (like for an empty new file).
+    if !hunks.is_empty() {
+        writeln!(output, "--- {old_label}")?;
+        writeln!(output, "+++ {new_label}")?;
+        output.push_str(&hunks);
+    }
+    Ok(output)
+}
+
+/// Lines added and removed in a file, `None` counts being used for binary files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    pub path: String,
+    pub changes: Option<(usize, usize)>,
+}
+
+impl FileStat {
+    pub fn compute(
+        entry: &DiffEntry,
+        load: LoadContent,
+        algorithm: DiffAlgorithm,
+    ) -> Result<Self, GitError> {
+        let path = match (&entry.status, &entry.old, &entry.new) {
+            (DiffStatus::Renamed(_) | DiffStatus::Copied(_), Some(old), Some(new)) => {
+                format!("{} => {}", old.path, new.path)
+            }
+            _ => entry.path().to_string(),
+        };
+
+        let old_content = match &entry.old {
+            Some(side) => load(side)?,
+            None => Vec::new(),
+        };
+        let new_content = match &entry.new {
+            Some(side) => load(side)?,
+            None => Vec::new(),
+        };
+
+        let changes = if is_binary(&old_content) || is_binary(&new_content) {
+            None
+        } else {
+            Some(count_changes(&old_content, &new_content, algorithm))
+        };
+        Ok(Self { path, changes })
+    }
+
+    /// Line used by `--numstat`: `added\tdeleted\tpath`.
+    pub fn numstat(&self) -> String {
+        match self.changes {
+            Some((added, deleted)) => format!("{added}\t{deleted}\t{}", self.path),
+            None => format!("-\t-\t{}", self.path),
+        }
+    }
+}
+
+/// Build `--stat` output: one line per file and a summary line.
+pub fn format_stat(stats: &[FileStat]) -> String {
+    let mut output = String::new();
+    if stats.is_empty() {
+        return output;
+    }
+
+    let path_width = stats.iter().map(|x| x.path.len()).max().unwrap_or(0);
+    let max_changes = stats
+        .iter()
+        .filter_map(|x| x.changes.map(|(a, d)| a + d))
+        .max()
+        .unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    // Like git: the graph uses what is left on the line after " path | count ".
+    let graph_width = STAT_WIDTH
+        .saturating_sub(path_width + count_width + 6)
+        .max(6);
+
+    let (mut total_added, mut total_deleted) = (0, 0);
+    for stat in stats {
+        match stat.changes {
+            Some((added, deleted)) => {
+                total_added += added;
+                total_deleted += deleted;
+
+                // Scale graph down when it does not fit, keeping at least one char per side.
+                let (added_width, deleted_width) = if max_changes > graph_width {
+                    let scale = |x: usize| {
+                        if x == 0 {
+                            0
+                        } else {
+                            1 + x * (graph_width - 1) / max_changes
+                        }
+                    };
+                    let total = scale(added + deleted);
+                    let added_width = scale(added);
+                    (added_width, total - added_width)
+                } else {
+                    (added, deleted)
+                };
+
+                writeln!(
+                    output,
+                    " {:path_width$} | {:>count_width$} {}{}",
+                    stat.path,
+                    added + deleted,
+                    "+".repeat(added_width),
+                    "-".repeat(deleted_width)
+                )
+                .unwrap();
+            }
+            None => writeln!(output, " {:path_width$} | Bin", stat.path).unwrap(),
+        }
+    }
+
+    let plural = |count: usize, singular: &str, plural: &str| {
+        if count == 1 {
+            singular.to_string()
+        } else {
+            plural.to_string()
+        }
+    };
+    write!(
+        output,
+        " {} {} changed",
+        stats.len(),
+        plural(stats.len(), "file", "files")
+    )
+    .unwrap();
+    if total_added > 0 || total_deleted == 0 {
+        write!(
+            output,
+            ", {total_added} {}(+)",
+            plural(total_added, "insertion", "insertions")
+        )
+        .unwrap();
+    }
+    if total_deleted > 0 || total_added == 0 {
+        write!(
+            output,
+            ", {total_deleted} {}(-)",
+            plural(total_deleted, "deletion", "deletions")
+        )
+        .unwrap();
+    }
+    output.push('\n');
+    output
+}
@@ -0,0 +1,152 @@
+use std::{collections::HashSet, fmt, path::Path};
+
+use crate::{
+    config::GitConfig, fetch::fetch_objects, fsck::object_links, header::GitObjectHeaderType,
+    remote::Remote, sideband::Progress, store::ObjectStore, GitError, HashCode,
+};
+
+/// Objects a partial clone leaves on the server, sent in a `filter` request line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectFilter {
+    /// `blob:none`: no blob at all.
+    BlobNone,
+    /// `blob:limit=<n>`: only blobs smaller than `n` bytes.
+    BlobLimit(u64),
+    /// `tree:<depth>`: only trees less than `depth` levels below a commit, and no blob.
+    TreeDepth(u64),
+}
+
+impl ObjectFilter {
+    /// Parse a filter spec like `blob:none`, `blob:limit=1m` or `tree:0`.
+    pub fn parse(spec: &str) -> Result<Self, GitError> {
+        let invalid = || GitError::InvalidContent(format!("Invalid filter-spec '{spec}'"));
+
+        if spec == "blob:none" {
+            return Ok(Self::BlobNone);
+        }
+        if let Some(limit) = spec.strip_prefix("blob:limit=") {
+            let (digits, unit) = match limit.char_indices().last() {
+                Some((idx, x)) if x.is_ascii_alphabetic() => (&limit[..idx], x),
+                _ => (limit, 'b'),
+            };
+            let scale = match unit.to_ascii_lowercase() {
+                'b' => 1,
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                _ => return Err(invalid()),
+            };
+            let limit: u64 = digits.parse().map_err(|_| invalid())?;
+            return Ok(Self::BlobLimit(limit * scale));
+        }
+        if let Some(depth) = spec.strip_prefix("tree:") {
+            return Ok(Self::TreeDepth(depth.parse().map_err(|_| invalid())?));
+        }
+        Err(invalid())
+    }
+}
+
+impl fmt::Display for ObjectFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BlobNone => write!(f, "blob:none"),
+            Self::BlobLimit(limit) => write!(f, "blob:limit={limit}"),
+            Self::TreeDepth(depth) => write!(f, "tree:{depth}"),
+        }
+    }
+}
+
+/// Remote promising objects missing from a partial clone, named by `extensions.partialclone`.
+pub fn promisor_remote(config: &GitConfig) -> Result<Option<Remote>, GitError> {
+    match config.get("extensions.partialclone") {
+        Some(name) => Remote::read(config, name),
+        None => Ok(None),
+    }
+}
+
+/// Record `remote` as the promisor of a partial clone made with `filter`, like git.
+pub fn write_promisor_config<P: AsRef<Path>>(
+    root: P,
+    remote: &str,
+    filter: ObjectFilter,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let mut config = GitConfig::read_at(root)?;
+    // Extensions are only honored by repository format version 1.
+    config.set("core.repositoryformatversion", "1");
+    config.set(&format!("remote.{remote}.promisor"), "true");
+    config.set(
+        &format!("remote.{remote}.partialclonefilter"),
+        &filter.to_string(),
+    );
+    config.set("extensions.partialclone", remote);
+    config.write_at(root)
+}
+
+/// Fetch objects among `ids` which are missing locally from the promisor remote.
+///
+/// Nothing is done outside a partial clone: reading a missing object fails as usual.
+pub async fn fetch_missing<P: AsRef<Path>>(
+    root: P,
+    ids: &[HashCode],
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let store = ObjectStore::open(root)?;
+    let missing: Vec<_> = ids
+        .iter()
+        .copied()
+        .filter(|x| !store.contains(*x))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    match promisor_remote(&GitConfig::read_at(root)?)? {
+        Some(remote) => fetch_objects(root, &remote, &missing, progress).await,
+        None => Ok(()),
+    }
+}
+
+/// Fetch every object of `tree` missing from a partial clone, so it can be checked out.
+///
+/// Missing trees come first, then blobs they list, one batch at each level.
+pub async fn fetch_missing_tree<P: AsRef<Path>>(
+    root: P,
+    tree: HashCode,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let Some(remote) = promisor_remote(&GitConfig::read_at(root)?)? else {
+        return Ok(());
+    };
+
+    loop {
+        let store = ObjectStore::open(root)?;
+        let mut missing = Vec::new();
+        let mut seen = HashSet::from([tree]);
+        let mut trees = vec![tree];
+        while let Some(id) = trees.pop() {
+            if !store.contains(id) {
+                missing.push(id);
+                continue;
+            }
+            // Submodule commits are not linked, hence never fetched.
+            for (target, r#type) in object_links(&store.read(id)?) {
+                if !seen.insert(target) {
+                    continue;
+                }
+                if r#type == GitObjectHeaderType::Tree {
+                    trees.push(target);
+                } else if !store.contains(target) {
+                    missing.push(target);
+                }
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+        fetch_objects(root, &remote, &missing, progress).await?;
+    }
+}
@@ -0,0 +1,359 @@
+use std::{io, path::Path};
+
+use bytes::Bytes;
+
+use crate::{
+    branch::is_ancestor,
+    clone::ProtocolVersion,
+    config::GitConfig,
+    hash_code_text_to_array,
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    refs::{
+        append_reflog_at, delete_ref_at, head_branch_at, list_refs_at, resolve_ref_at,
+        write_ref_at, RefValue,
+    },
+    remote::{expand_short_name, RefSpec, Remote},
+    revision::{peel_to_commit, rev_parse},
+    signature::Signature,
+    store::ObjectStore,
+    transport::Connection,
+    GitError, HashCode,
+};
+
+/// Remote ref to update, resolved from a push refspec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushRef {
+    /// Local name of pushed value as given by the user, empty when deleting.
+    pub src: String,
+    pub remote_ref: String,
+    /// New value of remote ref, `None` to delete it.
+    pub new: Option<HashCode>,
+    pub force: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PushStatus {
+    Ok,
+    UpToDate,
+    /// Refused before sending anything, like a non-fast-forward.
+    Rejected(String),
+    /// Refused by the server, like a declined hook.
+    RemoteRejected(String),
+}
+
+/// What happened to a remote ref after a push.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushUpdate {
+    pub src: String,
+    pub remote_ref: String,
+    pub old: Option<HashCode>,
+    pub new: Option<HashCode>,
+    /// Update was not a fast-forward and only accepted because it was forced.
+    pub forced: bool,
+    pub status: PushStatus,
+}
+
+/// Server answer to a push, from the `report-status` capability.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportStatus {
+    /// `None` if the pack was unpacked, the error message otherwise.
+    pub unpack_error: Option<String>,
+    /// Remote refs with `None` if updated, the reason they were not otherwise.
+    pub refs: Vec<(String, Option<String>)>,
+}
+
+/// Resolve push refspecs against local refs.
+///
+/// Source of an exact refspec is either a ref, short names being expanded like git does,
+/// or any revision when a full destination is given. Destination defaults to source name.
+pub fn resolve_push_refspecs<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    refspecs: &[RefSpec],
+) -> Result<Vec<PushRef>, GitError> {
+    let root = root.as_ref();
+    let local_refs = list_refs_at(root)?;
+    let mut output = Vec::new();
+
+    for refspec in refspecs {
+        if refspec.src.is_empty() {
+            let dst = refspec.dst.as_deref().unwrap_or_default();
+            output.push(PushRef {
+                src: String::new(),
+                remote_ref: full_destination(dst, None),
+                new: None,
+                force: refspec.force,
+            });
+            continue;
+        }
+
+        if refspec.is_glob() {
+            for (name, id) in &local_refs {
+                if let Some(remote_ref) = refspec.map(name) {
+                    output.push(PushRef {
+                        src: name.clone(),
+                        remote_ref,
+                        new: Some(*id),
+                        force: refspec.force,
+                    });
+                }
+            }
+            continue;
+        }
+
+        let local_ref = match refspec.src.as_str() {
+            "HEAD" => head_branch_at(root)?.map(|x| format!("refs/heads/{x}")),
+            src => expand_short_name(src)
+                .into_iter()
+                .find(|x| local_refs.iter().any(|(name, _)| name == x)),
+        };
+        let new = match &local_ref {
+            Some(local_ref) => resolve_ref_at(root, local_ref)?,
+            None => None,
+        };
+        let new = match new {
+            Some(new) => new,
+            None => rev_parse(store, &refspec.src).map_err(|_| {
+                GitError::InvalidContent(format!("src refspec {} does not match any", refspec.src))
+            })?,
+        };
+
+        let remote_ref = match (refspec.dst.as_deref(), &local_ref) {
+            (Some(dst), _) => full_destination(dst, local_ref.as_deref()),
+            (None, Some(local_ref)) => local_ref.clone(),
+            (None, None) => {
+                return Err(GitError::InvalidContent(format!(
+                    "The destination you provided is not a full refname for '{}'",
+                    refspec.src
+                )))
+            }
+        };
+
+        output.push(PushRef {
+            src: refspec.src.clone(),
+            remote_ref,
+            new: Some(new),
+            force: refspec.force,
+        });
+    }
+
+    Ok(output)
+}
+
+/// Full remote ref name for destination `dst`, in the same namespace as `src` when short.
+fn full_destination(dst: &str, src: Option<&str>) -> String {
+    if dst.starts_with("refs/") {
+        return dst.to_string();
+    }
+    match src {
+        Some(src) if src.starts_with("refs/tags/") => format!("refs/tags/{dst}"),
+        _ => format!("refs/heads/{dst}"),
+    }
+}
+
+/// Update remote refs, sending the objects the remote lacks in a pack.
+///
+/// Non-fast-forward updates are rejected unless forced. Remote tracking refs mapped by
+/// `remote` fetch refspecs follow successful updates.
+pub async fn push<P: AsRef<Path>>(
+    root: P,
+    remote: &Remote,
+    push_refs: &[PushRef],
+) -> Result<Vec<PushUpdate>, GitError> {
+    let root = root.as_ref();
+    let (mut connection, advertisement) =
+        Connection::open(&remote.url, "git-receive-pack", ProtocolVersion::V0).await?;
+    let has_capability = |name: &str| advertisement.has_capability(name);
+    let remote_refs = advertisement
+        .refs
+        .iter()
+        .filter(|x| !x.name.ends_with("^{}"))
+        .map(|x| Ok((x.name.clone(), hash_code_text_to_array(&x.object_id)?)))
+        .collect::<Result<Vec<_>, GitError>>()?;
+
+    let store = ObjectStore::open(root)?;
+    let mut updates = Vec::with_capacity(push_refs.len());
+    for push_ref in push_refs {
+        let old = remote_refs
+            .iter()
+            .find(|(name, _)| *name == push_ref.remote_ref)
+            .map(|(_, id)| *id);
+        let (status, forced) = check_update(&store, push_ref, old, has_capability("delete-refs"))?;
+        updates.push(PushUpdate {
+            src: push_ref.src.clone(),
+            remote_ref: push_ref.remote_ref.clone(),
+            old,
+            new: push_ref.new,
+            forced,
+            status,
+        });
+    }
+
+    let pending: Vec<_> = updates
+        .iter_mut()
+        .filter(|x| x.status == PushStatus::Ok)
+        .collect();
+    if pending.is_empty() {
+        connection.close().await?;
+        return Ok(updates);
+    }
+
+    let report_status = has_capability("report-status");
+    let mut body = Vec::new();
+    for (idx, update) in pending.iter().enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            hex::encode(update.old.unwrap_or_default()),
+            hex::encode(update.new.unwrap_or_default()),
+            update.remote_ref
+        );
+        if idx == 0 && report_status {
+            line.push_str("\0report-status");
+        }
+        PacketLine::Command(Bytes::from(line)).write(&mut body)?;
+    }
+    PacketLine::End.write(&mut body)?;
+
+    // Deleting refs needs no object.
+    let news: Vec<_> = pending.iter().filter_map(|x| x.new).collect();
+    if !news.is_empty() {
+        let remote_ids: Vec<_> = remote_refs.iter().map(|(_, id)| *id).collect();
+        let objects = list_objects(&store, &news, &remote_ids)?;
+        write_pack(&store, &objects, &mut body)?;
+    }
+
+    let reader = connection.request(body).await?;
+    if report_status {
+        let head = reader.read_head(|x| *x == PacketLine::End).await?;
+        let report = read_report_status(&mut head.as_slice())?;
+        for update in pending {
+            let reason = match &report.unpack_error {
+                Some(_) => Some("unpacker error".to_string()),
+                None => report
+                    .refs
+                    .iter()
+                    .find(|(name, _)| *name == update.remote_ref)
+                    .map_or(Some("no report".to_string()), |(_, reason)| reason.clone()),
+            };
+            if let Some(reason) = reason {
+                update.status = PushStatus::RemoteRejected(reason);
+            }
+        }
+    }
+
+    connection.close().await?;
+
+    update_tracking_refs(root, remote, &updates)?;
+    Ok(updates)
+}
+
+/// Check a ref update locally: `(status, forced)`.
+fn check_update(
+    store: &ObjectStore,
+    push_ref: &PushRef,
+    old: Option<HashCode>,
+    can_delete: bool,
+) -> Result<(PushStatus, bool), GitError> {
+    let rejected = |reason: &str| Ok((PushStatus::Rejected(reason.to_string()), false));
+
+    let (old, new) = match (old, push_ref.new) {
+        (old, new) if old == new => return Ok((PushStatus::UpToDate, false)),
+        (None, None) => return rejected("remote ref does not exist"),
+        (Some(_), None) if !can_delete => return rejected("remote does not support deleting refs"),
+        (None, Some(_)) | (Some(_), None) => return Ok((PushStatus::Ok, false)),
+        (Some(old), Some(new)) => (old, new),
+    };
+
+    if !store.contains(old) {
+        return if push_ref.force {
+            Ok((PushStatus::Ok, true))
+        } else {
+            rejected("fetch first")
+        };
+    }
+
+    let is_fast_forward = !push_ref.remote_ref.starts_with("refs/tags/")
+        && match (peel_to_commit(store, old), peel_to_commit(store, new)) {
+            (Ok(old), Ok(new)) => is_ancestor(store, old, new)?,
+            _ => false,
+        };
+    match (is_fast_forward, push_ref.force) {
+        (true, _) => Ok((PushStatus::Ok, false)),
+        (false, true) => Ok((PushStatus::Ok, true)),
+        (false, false) if push_ref.remote_ref.starts_with("refs/tags/") => {
+            rejected("already exists")
+        }
+        (false, false) => rejected("non-fast-forward"),
+    }
+}
+
+/// Read `report-status` lines up to the final flush.
+pub fn read_report_status<R: io::Read>(reader: &mut R) -> Result<ReportStatus, GitError> {
+    let mut read_line = || -> Result<Option<String>, GitError> {
+        match PacketLine::read(reader)? {
+            PacketLine::Command(data) => Ok(Some(
+                std::str::from_utf8(&data)?
+                    .trim_end_matches('\n')
+                    .to_string(),
+            )),
+            PacketLine::End | PacketLine::Delimiter => Ok(None),
+        }
+    };
+
+    let line = read_line()?.unwrap_or_default();
+    let unpack_error = match line.strip_prefix("unpack ") {
+        Some("ok") => None,
+        Some(error) => Some(error.to_string()),
+        None => {
+            return Err(GitError::Http(format!(
+                "Invalid report-status line '{line}'"
+            )))
+        }
+    };
+
+    let mut refs = Vec::new();
+    while let Some(line) = read_line()? {
+        if let Some(name) = line.strip_prefix("ok ") {
+            refs.push((name.to_string(), None));
+        } else if let Some(rem) = line.strip_prefix("ng ") {
+            let (name, reason) = rem.split_once(' ').unwrap_or((rem, "failed"));
+            refs.push((name.to_string(), Some(reason.to_string())));
+        } else {
+            return Err(GitError::Http(format!(
+                "Invalid report-status line '{line}'"
+            )));
+        }
+    }
+
+    Ok(ReportStatus { unpack_error, refs })
+}
+
+/// Move remote tracking refs of successfully pushed refs, like a fetch would.
+fn update_tracking_refs(
+    root: &Path,
+    remote: &Remote,
+    updates: &[PushUpdate],
+) -> Result<(), GitError> {
+    let config = GitConfig::read_at(root)?;
+    let committer = Signature::from_env("COMMITTER", &config).ok();
+
+    for update in updates.iter().filter(|x| x.status == PushStatus::Ok) {
+        let Some(tracking_ref) = remote.fetch.iter().find_map(|x| x.map(&update.remote_ref)) else {
+            continue;
+        };
+        match update.new {
+            Some(new) => {
+                let old = resolve_ref_at(root, &tracking_ref)?;
+                write_ref_at(root, &tracking_ref, &RefValue::Direct(new))?;
+                if let Some(committer) = &committer {
+                    append_reflog_at(root, &tracking_ref, old, new, committer, "update by push")?;
+                }
+            }
+            None => {
+                delete_ref_at(root, &tracking_ref)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,224 @@
+use std::{
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use crate::{
+    clone::InfoRef,
+    fs_utils::git_dir,
+    hash_code_text_to_array,
+    pack_file::unpack_into,
+    pack_writer::list_objects,
+    packet_line::PacketLine,
+    refs::{
+        delete_ref_at, head_branch_at, is_valid_ref_name, list_refs_at, resolve_ref_at,
+        write_ref_at, RefValue,
+    },
+    store::ObjectStore,
+    upload_pack::{hung_up, line_text, read_line, refuse, write_advertisement, write_line, AGENT},
+    GitError, HashCode,
+};
+
+/// Capabilities advertised by `receive_pack`, besides `agent`.
+///
+/// Thin packs are refused: their bases would have to be added to the pack.
+const CAPABILITIES: [&str; 4] = ["report-status", "delete-refs", "ofs-delta", "no-thin"];
+
+/// How `receive_pack` talks with the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceivePackOptions {
+    /// Answer a single request without advertising refs first, like for smart HTTP.
+    pub stateless_rpc: bool,
+    /// Only advertise refs, then stop.
+    pub advertise_refs: bool,
+}
+
+/// Ref update asked for by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RefUpdate {
+    /// Value the client expects the ref to have, `None` when creating it.
+    old: Option<HashCode>,
+    /// `None` to delete the ref.
+    new: Option<HashCode>,
+    name: String,
+}
+
+/// First part of a push: ref updates then, unless they all delete refs, a pack.
+struct PushRequest {
+    updates: Vec<RefUpdate>,
+    /// Capabilities the client picked, given on the first update line.
+    capabilities: Vec<String>,
+}
+
+/// Update refs of repository `root` like `git-receive-pack`, reading ref updates and
+/// objects of the client from `reader` and answering on `writer`.
+///
+/// Like git, updates are not required to be fast-forwards, but the branch checked out
+/// in a working tree is left alone.
+pub fn receive_pack<P: AsRef<Path>, R: Read, W: Write>(
+    root: P,
+    reader: &mut R,
+    writer: &mut W,
+    options: &ReceivePackOptions,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    if !git_dir(root).join("objects").is_dir() {
+        return Err(GitError::Transport(format!(
+            "'{}' does not appear to be a git repository",
+            root.display()
+        )));
+    }
+
+    if !options.stateless_rpc || options.advertise_refs {
+        let refs: Vec<_> = list_refs_at(root)?
+            .into_iter()
+            .map(|(name, id)| InfoRef {
+                name,
+                object_id: hex::encode(id),
+                capabilities: Vec::new(),
+                symref_target: None,
+            })
+            .collect();
+        let capabilities = format!("{} agent={AGENT}", CAPABILITIES.join(" "));
+        write_advertisement(writer, &refs, &capabilities)?;
+        writer.flush()?;
+    }
+    if options.advertise_refs {
+        return Ok(());
+    }
+
+    // A client with nothing to push hangs up.
+    let Some(request) = read_updates(reader, writer)? else {
+        return Ok(());
+    };
+    let unpacked = match request.updates.iter().any(|x| x.new.is_some()) {
+        true => receive_objects(root, reader),
+        false => Ok(()),
+    };
+
+    let mut report = vec![match &unpacked {
+        Ok(()) => "unpack ok".to_string(),
+        Err(err) => format!("unpack {err}"),
+    }];
+    let store = ObjectStore::open(root)?;
+    // Objects reachable from refs are complete: connectivity checks stop there.
+    let known: Vec<_> = list_refs_at(root)?.into_iter().map(|(_, id)| id).collect();
+    for update in &request.updates {
+        let result = match &unpacked {
+            Ok(()) => update_ref(root, &store, &known, update),
+            Err(_) => Err("unpacker error"),
+        };
+        report.push(match result {
+            Ok(()) => format!("ok {}", update.name),
+            Err(reason) => format!("ng {} {reason}", update.name),
+        });
+    }
+
+    if request.capabilities.iter().any(|x| x == "report-status") {
+        for line in report {
+            write_line(writer, line)?;
+        }
+        PacketLine::End.write(writer)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Read ref update lines like `<old> <new> <name>` up to a flush, `None` if there is none.
+fn read_updates<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<Option<PushRequest>, GitError> {
+    let mut request = PushRequest {
+        updates: Vec::new(),
+        capabilities: Vec::new(),
+    };
+
+    loop {
+        let data = match read_line(reader)? {
+            None | Some(PacketLine::End) if request.updates.is_empty() => return Ok(None),
+            Some(PacketLine::End) => return Ok(Some(request)),
+            Some(PacketLine::Command(data)) => data,
+            Some(PacketLine::Delimiter) => {
+                return Err(refuse(writer, "protocol error: unexpected delimiter"))
+            }
+            None => return Err(hung_up()),
+        };
+
+        let line = line_text(&data)?;
+        let (line, capabilities) = line.split_once('\0').unwrap_or((line, ""));
+        if request.updates.is_empty() {
+            request.capabilities = capabilities
+                .split_whitespace()
+                .map(|x| x.to_string())
+                .collect();
+        }
+
+        let mut parts = line.splitn(3, ' ');
+        let (Some(old), Some(new), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(refuse(
+                writer,
+                &format!("protocol error: expected ref update, got '{line}'"),
+            ));
+        };
+        // A zero ID stands for a missing ref.
+        let id = |x| -> Result<_, GitError> {
+            let id = hash_code_text_to_array(x)?;
+            Ok(Some(id).filter(|x| *x != [0; 20]))
+        };
+        request.updates.push(RefUpdate {
+            old: id(old)?,
+            new: id(new)?,
+            name: name.to_string(),
+        });
+    }
+}
+
+/// Unpack the pack following ref updates into the object store of `root`, checking its
+/// checksum.
+fn receive_objects<R: Read>(root: &Path, reader: &mut R) -> Result<(), GitError> {
+    unpack_into(BufReader::new(reader), root)
+}
+
+/// Apply `update` to refs of `root`, returning why it was refused if it was.
+fn update_ref(
+    root: &Path,
+    store: &ObjectStore,
+    known: &[HashCode],
+    update: &RefUpdate,
+) -> Result<(), &'static str> {
+    if !update.name.starts_with("refs/") || !is_valid_ref_name(&update.name) {
+        return Err("funny refname");
+    }
+    // The client decided on the update from the value we advertised.
+    let current = resolve_ref_at(root, &update.name).map_err(|_| "failed to lock")?;
+    if current != update.old {
+        return Err("stale info");
+    }
+
+    let head = head_branch_at(root).ok().flatten();
+    if head.is_some_and(|x| format!("refs/heads/{x}") == update.name) {
+        if update.new.is_none() {
+            return Err("deletion of the current branch prohibited");
+        }
+        if git_dir(root) != root {
+            return Err("branch is currently checked out");
+        }
+    }
+
+    match update.new {
+        Some(new) if !is_connected(store, new, known) => Err("missing necessary objects"),
+        Some(new) => write_ref_at(root, &update.name, &RefValue::Direct(new))
+            .map_err(|_| "failed to update ref"),
+        None => delete_ref_at(root, &update.name)
+            .map(|_| ())
+            .map_err(|_| "failed to delete"),
+    }
+}
+
+/// Whether every object reachable from `new` is in `store`, stopping at objects reachable
+/// from `known`.
+fn is_connected(store: &ObjectStore, new: HashCode, known: &[HashCode]) -> bool {
+    // Listing reads every commit and tree, blobs are only named.
+    list_objects(store, &[new], known).is_ok_and(|ids| ids.iter().all(|x| store.contains(*x)))
+}
@@ -0,0 +1,333 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{fs_utils::git_dir, hash_code_text_to_array, signature::Signature, GitError, HashCode};
+
+/// Longest chain of symbolic refs we follow, like git does.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// Raw content of a ref: either an object ID or a pointer to another ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefValue {
+    Direct(HashCode),
+    Symbolic(String),
+}
+
+/// Read ref without following symbolic refs, from loose files then `packed-refs`.
+pub fn read_ref_at<P: AsRef<Path>>(root: P, name: &str) -> Result<Option<RefValue>, GitError> {
+    let git_dir = git_dir(root.as_ref());
+
+    match fs::read_to_string(git_dir.join(name)) {
+        Ok(content) => {
+            let content = content.trim_end();
+            return match content.strip_prefix("ref: ") {
+                Some(target) => Ok(Some(RefValue::Symbolic(target.to_string()))),
+                None => Ok(Some(RefValue::Direct(hash_code_text_to_array(content)?))),
+            };
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+        // Reading a directory like `refs/heads` is not an error: there is just no ref.
+        Err(_) if git_dir.join(name).is_dir() => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    Ok(read_packed_refs_at(&root)?
+        .into_iter()
+        .find(|(x, _)| x == name)
+        .map(|(_, hash_code)| RefValue::Direct(hash_code)))
+}
+
+/// Follow symbolic refs until an object ID is found.
+pub fn resolve_ref_at<P: AsRef<Path>>(root: P, name: &str) -> Result<Option<HashCode>, GitError> {
+    let mut name = name.to_string();
+
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref_at(&root, &name)? {
+            Some(RefValue::Direct(hash_code)) => return Ok(Some(hash_code)),
+            Some(RefValue::Symbolic(target)) => name = target,
+            None => return Ok(None),
+        }
+    }
+
+    Err(GitError::InvalidContent(format!(
+        "Symbolic ref loop detected from {name}"
+    )))
+}
+
+/// Write a loose ref, through a `.lock` file so readers never see a partial value.
+pub fn write_ref_at<P: AsRef<Path>>(root: P, name: &str, value: &RefValue) -> Result<(), GitError> {
+    let content = match value {
+        RefValue::Direct(hash_code) => format!("{}\n", hex::encode(hash_code)),
+        RefValue::Symbolic(target) => format!("ref: {target}\n"),
+    };
+    write_locked(&git_dir(root.as_ref()).join(name), content.as_bytes())
+}
+
+/// Append a line to the reflog of `name`, recording its move from `old` to `new`.
+pub fn append_reflog_at<P: AsRef<Path>>(
+    root: P,
+    name: &str,
+    old: Option<HashCode>,
+    new: HashCode,
+    committer: &Signature,
+    message: &str,
+) -> Result<(), GitError> {
+    let log_path = git_dir(root.as_ref()).join("logs").join(name);
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    // Reflog messages are a single line.
+    let message = message.replace('\n', " ");
+    writeln!(
+        file,
+        "{} {} {committer}\t{message}",
+        hex::encode(old.unwrap_or_default()),
+        hex::encode(new)
+    )?;
+    Ok(())
+}
+
+/// Move the branch HEAD points to, or HEAD itself when detached, from `old` to `new`,
+/// recording the move in both reflogs.
+pub fn update_head_at<P: AsRef<Path>>(
+    root: P,
+    old: Option<HashCode>,
+    new: HashCode,
+    committer: &Signature,
+    message: &str,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let ref_name = match read_ref_at(root, "HEAD")? {
+        Some(RefValue::Symbolic(target)) => target,
+        _ => "HEAD".to_string(),
+    };
+
+    write_ref_at(root, &ref_name, &RefValue::Direct(new))?;
+    append_reflog_at(root, &ref_name, old, new, committer, message)?;
+    if ref_name != "HEAD" {
+        append_reflog_at(root, "HEAD", old, new, committer, message)?;
+    }
+    Ok(())
+}
+
+/// Delete a ref, both its loose file and its `packed-refs` line, with its reflog.
+///
+/// Returns `false` if the ref did not exist.
+pub fn delete_ref_at<P: AsRef<Path>>(root: P, name: &str) -> Result<bool, GitError> {
+    let git_dir = git_dir(root.as_ref());
+
+    let mut found = match fs::remove_file(git_dir.join(name)) {
+        Ok(()) => true,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => false,
+        Err(err) => return Err(err.into()),
+    };
+    remove_empty_parents(&git_dir, &git_dir.join(name));
+
+    let packed_path = git_dir.join("packed-refs");
+    let content = match fs::read_to_string(&packed_path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let mut output = String::with_capacity(content.len());
+    let mut skip_peeled = false;
+    for line in content.lines() {
+        // Peeled value lines belong to the ref above them.
+        if line.starts_with('^') && skip_peeled {
+            continue;
+        }
+        skip_peeled = line.split_once(' ').is_some_and(|(_, x)| x == name);
+        if skip_peeled {
+            found = true;
+        } else {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+    if output.len() != content.len() {
+        write_locked(&packed_path, output.as_bytes())?;
+    }
+
+    let log_path = git_dir.join("logs").join(name);
+    if fs::remove_file(&log_path).is_ok() {
+        remove_empty_parents(&git_dir.join("logs"), &log_path);
+    }
+    Ok(found)
+}
+
+/// Move a ref and its reflog to a new name.
+pub fn rename_ref_at<P: AsRef<Path>>(root: P, old: &str, new: &str) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let value = read_ref_at(root, old)?
+        .ok_or_else(|| GitError::InvalidContent(format!("No such ref: {old}")))?;
+
+    let logs_dir = git_dir(root).join("logs");
+    let log = fs::read(logs_dir.join(old)).ok();
+    delete_ref_at(root, old)?;
+    write_ref_at(root, new, &value)?;
+
+    if let Some(log) = log {
+        let log_path = logs_dir.join(new);
+        if let Some(parent) = log_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(log_path, log)?;
+    }
+    Ok(())
+}
+
+/// Replace a file content through a `.lock` file created next to it.
+fn write_locked(path: &Path, content: &[u8]) -> Result<(), GitError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let lock_path = path.with_file_name(format!(
+        "{}.lock",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&lock_path)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => GitError::Io(format!(
+                "Unable to create {}: file is locked by another process",
+                lock_path.display()
+            )),
+            _ => err.into(),
+        })?;
+
+    let result = file
+        .write_all(content)
+        .and_then(|()| fs::rename(&lock_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&lock_path);
+    }
+    Ok(result?)
+}
+
+/// Remove directories left empty below `base`, like `refs/heads/feature/` after its
+/// last branch is deleted. `refs/heads` and `refs/tags` themselves are kept.
+fn remove_empty_parents(base: &Path, path: &Path) {
+    let mut parent = path.parent();
+    while let Some(dir) = parent.filter(|x| {
+        x.starts_with(base.join("refs")) && x.components().count() > base.components().count() + 2
+    }) {
+        if fs::remove_dir(dir).is_err() {
+            break;
+        }
+        parent = dir.parent();
+    }
+}
+
+/// Check ref name follows `git check-ref-format` rules, like `refs/heads/main`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "@"
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && !name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+        && name
+            .split('/')
+            .all(|x| !x.starts_with('.') && !x.ends_with(".lock"))
+}
+
+/// Branch name HEAD points to, `None` when HEAD is detached.
+pub fn head_branch_at<P: AsRef<Path>>(root: P) -> Result<Option<String>, GitError> {
+    match read_ref_at(root, "HEAD")? {
+        Some(RefValue::Symbolic(target)) => {
+            Ok(target.strip_prefix("refs/heads/").map(|x| x.to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// List every ref under `refs/` sorted by name, loose refs shadowing packed ones.
+pub fn list_refs_at<P: AsRef<Path>>(root: P) -> Result<Vec<(String, HashCode)>, GitError> {
+    let mut output = Vec::new();
+    list_loose_refs(root.as_ref(), PathBuf::from("refs"), &mut output)?;
+
+    for (name, hash_code) in read_packed_refs_at(&root)? {
+        if !output.iter().any(|(x, _)| *x == name) {
+            output.push((name, hash_code));
+        }
+    }
+
+    output.sort();
+    Ok(output)
+}
+
+/// Parse `.git/packed-refs`, skipping peeled tag lines.
+pub fn read_packed_refs_at<P: AsRef<Path>>(root: P) -> Result<Vec<(String, HashCode)>, GitError> {
+    let content = match fs::read_to_string(git_dir(root.as_ref()).join("packed-refs")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut output = Vec::new();
+    for line in content.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+
+        let (hash_code, name) = line
+            .split_once(' ')
+            .ok_or_else(|| GitError::invalid_content("Invalid packed-refs line"))?;
+        output.push((name.to_string(), hash_code_text_to_array(hash_code)?));
+    }
+
+    Ok(output)
+}
+
+fn list_loose_refs(
+    root: &Path,
+    name: PathBuf,
+    output: &mut Vec<(String, HashCode)>,
+) -> Result<(), GitError> {
+    let entries = match fs::read_dir(git_dir(root).join(&name)) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let sub_name = name.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            list_loose_refs(root, sub_name, output)?;
+        } else {
+            let content = fs::read_to_string(entry.path())?;
+            let content = content.trim_end();
+
+            // Symbolic refs like `refs/remotes/origin/HEAD` are listed by their target.
+            let hash_code = match content.strip_prefix("ref: ") {
+                Some(target) => match resolve_ref_at(root, target)? {
+                    Some(hash_code) => hash_code,
+                    None => continue,
+                },
+                None => hash_code_text_to_array(content)?,
+            };
+
+            output.push((sub_name.to_string_lossy().to_string(), hash_code));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,161 @@
+use crate::{config::GitConfig, promisor::ObjectFilter, GitError};
+
+/// Mapping between remote refs and local refs, like `+refs/heads/*:refs/remotes/origin/*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefSpec {
+    /// Update destination even if it is not a fast-forward.
+    pub force: bool,
+    pub src: String,
+    /// Local ref to update, `None` to only record fetched commit in `FETCH_HEAD`.
+    pub dst: Option<String>,
+}
+
+impl RefSpec {
+    pub fn parse(input: &str) -> Result<Self, GitError> {
+        let (force, spec) = match input.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, input),
+        };
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (src, Some(dst).filter(|x| !x.is_empty())),
+            None => (spec, None),
+        };
+
+        let is_glob = |x: &str| x.contains('*');
+        // An empty source deletes the destination when pushing.
+        let is_valid = (!src.is_empty() || dst.is_some())
+            && src.matches('*').count() <= 1
+            && dst.map_or(true, |dst| {
+                dst.matches('*').count() <= 1 && is_glob(src) == is_glob(dst)
+            });
+        if !is_valid {
+            return Err(GitError::InvalidContent(format!(
+                "Invalid refspec '{input}'"
+            )));
+        }
+
+        Ok(Self {
+            force,
+            src: src.to_string(),
+            dst: dst.map(|x| x.to_string()),
+        })
+    }
+
+    /// Default refspec of a remote: every branch into `refs/remotes/<remote>/`.
+    pub fn default_fetch(remote: &str) -> Self {
+        Self {
+            force: true,
+            src: "refs/heads/*".to_string(),
+            dst: Some(format!("refs/remotes/{remote}/*")),
+        }
+    }
+
+    /// Part of `name` matched by `*`, or the empty string for an exact match.
+    fn match_source<'a>(&self, name: &'a str) -> Option<&'a str> {
+        match self.src.split_once('*') {
+            Some((prefix, suffix)) => name
+                .strip_prefix(prefix)
+                .and_then(|x| x.strip_suffix(suffix))
+                .filter(|x| !x.is_empty()),
+            None => expand_short_name(&self.src)
+                .into_iter()
+                .any(|x| x == name)
+                .then_some(""),
+        }
+    }
+
+    /// Check if remote ref `name` is selected by this refspec.
+    pub fn matches(&self, name: &str) -> bool {
+        self.match_source(name).is_some()
+    }
+
+    /// Local ref where remote ref `name` is stored, if selected by this refspec.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let matched = self.match_source(name)?;
+        let dst = self.dst.as_deref()?;
+        Some(match dst.split_once('*') {
+            Some((prefix, suffix)) => format!("{prefix}{matched}{suffix}"),
+            None if dst.starts_with("refs/") => dst.to_string(),
+            None => format!("refs/heads/{dst}"),
+        })
+    }
+
+    pub fn is_glob(&self) -> bool {
+        self.src.contains('*')
+    }
+}
+
+/// Full ref names a short name like `main` or `v1.0` may stand for, in git lookup order.
+pub fn expand_short_name(name: &str) -> Vec<String> {
+    if name == "HEAD" || name.starts_with("refs/") {
+        return vec![name.to_string()];
+    }
+    ["refs/", "refs/tags/", "refs/heads/", "refs/remotes/"]
+        .iter()
+        .map(|x| format!("{x}{name}"))
+        .collect()
+}
+
+/// Repository configured in `remote.<name>.*`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+    pub fetch: Vec<RefSpec>,
+    /// Filter of a partial clone, applied to every fetch from this remote.
+    pub partial_clone_filter: Option<ObjectFilter>,
+}
+
+impl Remote {
+    /// Read remote `name` from config, `None` if it has no URL.
+    pub fn read(config: &GitConfig, name: &str) -> Result<Option<Self>, GitError> {
+        let Some(url) = config.get(&format!("remote.{name}.url")) else {
+            return Ok(None);
+        };
+        let fetch = config
+            .get_all(&format!("remote.{name}.fetch"))
+            .into_iter()
+            .map(RefSpec::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        let partial_clone_filter = config
+            .get(&format!("remote.{name}.partialclonefilter"))
+            .map(ObjectFilter::parse)
+            .transpose()?;
+
+        Ok(Some(Self {
+            name: name.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+            fetch,
+            partial_clone_filter,
+        }))
+    }
+
+    /// Anonymous remote for a URL given on command line: only `HEAD` is fetched by default.
+    pub fn from_url(url: &str) -> Self {
+        Self {
+            name: url.to_string(),
+            url: url.trim_end_matches('/').to_string(),
+            fetch: vec![RefSpec {
+                force: false,
+                src: "HEAD".to_string(),
+                dst: None,
+            }],
+            partial_clone_filter: None,
+        }
+    }
+
+    /// URL as git shows it in messages, without trailing `/` or `.git`.
+    pub fn display_url(&self) -> &str {
+        let url = self.url.trim_end_matches('/');
+        url.strip_suffix(".git").unwrap_or(url)
+    }
+}
+
+/// Shorten a ref name for display: `refs/heads/main` is `main`, `refs/remotes/origin/main`
+/// is `origin/main`.
+pub fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|x| name.strip_prefix(x))
+        .unwrap_or(name)
+}
@@ -0,0 +1,201 @@
+use crate::{
+    header::GitObjectHeaderType,
+    object::{GitObject, GitTreeItem},
+    refs::resolve_ref_at,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// Shortest abbreviated object name we accept.
+const MIN_ABBREV_LEN: usize = 4;
+
+/// Resolve revision like `HEAD~2`, `main^2`, `v1.0^{tree}` or `e547aac` to an object ID.
+pub fn rev_parse(store: &ObjectStore, rev: &str) -> Result<HashCode, GitError> {
+    let unknown = || GitError::UnknownRevision(rev.to_string());
+
+    // Split base name from `^` / `~` modifiers.
+    let base_end = rev.find(['^', '~']).unwrap_or(rev.len());
+    let (base, mut modifiers) = rev.split_at(base_end);
+
+    let mut hash_code = resolve_name(store, base)?.ok_or_else(unknown)?;
+
+    while !modifiers.is_empty() {
+        let kind = modifiers.as_bytes()[0];
+        modifiers = &modifiers[1..];
+
+        // Peel operator: `^{}`, `^{commit}`, `^{tree}`.
+        if kind == b'^' && modifiers.starts_with('{') {
+            let end = modifiers.find('}').ok_or_else(unknown)?;
+            hash_code = match &modifiers[1..end] {
+                "" => peel_tags(store, hash_code)?,
+                "commit" => peel_to_commit(store, hash_code)?,
+                "tree" => peel_to_tree(store, hash_code)?,
+                _ => return Err(unknown()),
+            };
+            modifiers = &modifiers[end + 1..];
+            continue;
+        }
+
+        let digits_end = modifiers
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(modifiers.len());
+        let count = match &modifiers[..digits_end] {
+            "" => 1,
+            x => x.parse().map_err(|_err| unknown())?,
+        };
+        modifiers = &modifiers[digits_end..];
+
+        hash_code = match kind {
+            b'^' if count == 0 => peel_to_commit(store, hash_code)?,
+            b'^' => {
+                let parents = commit_parents(store, hash_code)?;
+                *parents.get(count - 1).ok_or_else(unknown)?
+            }
+            _ => {
+                for _ in 0..count {
+                    let parents = commit_parents(store, hash_code)?;
+                    hash_code = *parents.first().ok_or_else(unknown)?;
+                }
+                hash_code
+            }
+        };
+    }
+
+    Ok(hash_code)
+}
+
+/// Resolve a ref name or a (possibly abbreviated) object name.
+fn resolve_name(store: &ObjectStore, name: &str) -> Result<Option<HashCode>, GitError> {
+    let name = if name.is_empty() || name == "@" {
+        "HEAD"
+    } else {
+        name
+    };
+
+    // Same lookup order as git.
+    // See: https://git-scm.com/docs/gitrevisions#Documentation/gitrevisions.txt-emltrefnamegtemegemmasterememheadsmasterememrefsheadsmasterem
+    for candidate in [
+        name.to_string(),
+        format!("refs/{name}"),
+        format!("refs/tags/{name}"),
+        format!("refs/heads/{name}"),
+        format!("refs/remotes/{name}"),
+        format!("refs/remotes/{name}/HEAD"),
+    ] {
+        // Only check upper case names like `HEAD` or `FETCH_HEAD` at repository root.
+        if !candidate.starts_with("refs/") && candidate.chars().any(|c| c.is_ascii_lowercase()) {
+            continue;
+        }
+        // Never look outside of `.git`.
+        if candidate
+            .split('/')
+            .any(|x| x.is_empty() || x == "." || x == "..")
+        {
+            continue;
+        }
+        if let Some(hash_code) = resolve_ref_at(store.root(), &candidate)? {
+            return Ok(Some(hash_code));
+        }
+    }
+
+    if name.len() >= MIN_ABBREV_LEN && name.len() <= 40 {
+        let matches = store.find_by_prefix(name)?;
+        match matches.len() {
+            0 => {}
+            1 => return Ok(Some(matches[0])),
+            _ => {
+                return Err(GitError::InvalidContent(format!(
+                    "Short object ID {name} is ambiguous"
+                )))
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Follow annotated tags until a non tag object is found.
+pub fn peel_tags(store: &ObjectStore, hash_code: HashCode) -> Result<HashCode, GitError> {
+    peel_tags_with(store, hash_code, |_| {}).map(|(_, hash_code)| hash_code)
+}
+
+/// Like [`peel_tags`], calling `visit` on every tag of the chain, and returning the type of
+/// the object at its end too.
+pub fn peel_tags_with(
+    store: &ObjectStore,
+    mut hash_code: HashCode,
+    mut visit: impl FnMut(HashCode),
+) -> Result<(GitObjectHeaderType, HashCode), GitError> {
+    loop {
+        match store.read(hash_code)? {
+            GitObject::Tag { object, .. } => {
+                visit(hash_code);
+                hash_code = object;
+            }
+            object => return Ok((object.header_type(), hash_code)),
+        }
+    }
+}
+
+pub fn peel_to_commit(store: &ObjectStore, hash_code: HashCode) -> Result<HashCode, GitError> {
+    let hash_code = peel_tags(store, hash_code)?;
+    match store.read_raw(hash_code)?.0 {
+        GitObjectHeaderType::Commit => Ok(hash_code),
+        r#type => Err(GitError::InvalidContent(format!(
+            "{} is a {}, not a commit",
+            hex::encode(hash_code),
+            r#type.as_str()
+        ))),
+    }
+}
+
+pub fn peel_to_tree(store: &ObjectStore, hash_code: HashCode) -> Result<HashCode, GitError> {
+    let peeled = peel_tags(store, hash_code)?;
+    match store.read(peeled)? {
+        GitObject::Commit { tree, .. } => Ok(tree),
+        GitObject::Tree(_) => Ok(peeled),
+        _ => Err(GitError::InvalidContent(format!(
+            "{} cannot be resolved to a tree",
+            hex::encode(hash_code)
+        ))),
+    }
+}
+
+fn commit_parents(store: &ObjectStore, hash_code: HashCode) -> Result<Vec<HashCode>, GitError> {
+    let commit = peel_to_commit(store, hash_code)?;
+    if store.is_shallow(commit) {
+        return Ok(Vec::new());
+    }
+    match store.read(commit)? {
+        GitObject::Commit { parents, .. } => Ok(parents),
+        _ => unreachable!("Object has been peeled to a commit"),
+    }
+}
+
+/// Find entry at `path` (like `src/main.rs`) starting from `tree`.
+pub fn tree_entry_at(
+    store: &ObjectStore,
+    tree: HashCode,
+    path: &str,
+) -> Result<Option<GitTreeItem>, GitError> {
+    let mut current = tree;
+    let mut components = path.split('/').filter(|x| !x.is_empty()).peekable();
+    let mut entry = None;
+
+    while let Some(name) = components.next() {
+        let GitObject::Tree(items) = store.read(current)? else {
+            return Ok(None);
+        };
+        let Some(item) = items.into_iter().find(|x| x.name == name) else {
+            return Ok(None);
+        };
+
+        if components.peek().is_some() && item.mode != 0o40000 {
+            return Ok(None);
+        }
+        current = item.hash_code;
+        entry = Some(item);
+    }
+
+    Ok(entry)
+}
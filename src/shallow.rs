@@ -0,0 +1,159 @@
+use std::{collections::HashSet, fs, io, path::Path};
+
+use bytes::Bytes;
+
+use crate::{
+    clone::ProtocolVersion, fs_utils::git_dir, hash_code_text_to_array, packet_line::PacketLine,
+    GitError, HashCode,
+};
+
+/// Depth asking for the whole history, used by `--unshallow`.
+pub const INFINITE_DEPTH: u32 = 0x7fff_ffff;
+
+/// History limits of a shallow fetch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShallowOptions {
+    /// Number of commits to fetch from each tip (`--depth`).
+    pub depth: Option<u32>,
+    /// Count `depth` from the current shallow boundary instead of the tips (`--deepen`).
+    pub relative: bool,
+    /// Skip commits older than this Unix time (`--shallow-since`).
+    pub since: Option<i64>,
+    /// Skip history reachable from these remote refs (`--shallow-exclude`).
+    pub exclude: Vec<String>,
+}
+
+impl ShallowOptions {
+    /// Whether these options move the shallow boundary.
+    pub fn is_deepening(&self) -> bool {
+        self.depth.is_some() || self.since.is_some() || !self.exclude.is_empty()
+    }
+
+    /// Protocol v0 capabilities these options need, beside `shallow`.
+    pub fn capabilities(&self) -> Vec<&'static str> {
+        let mut output = Vec::new();
+        if self.relative {
+            output.push("deepen-relative");
+        }
+        if self.since.is_some() {
+            output.push("deepen-since");
+        }
+        if !self.exclude.is_empty() {
+            output.push("deepen-not");
+        }
+        output
+    }
+
+    /// Request lines telling the server our `shallow` boundary and how to move it.
+    ///
+    /// Protocol v0 asks for relative depth with a capability, v2 with a line.
+    pub fn request_lines(
+        &self,
+        shallow: &HashSet<HashCode>,
+        version: ProtocolVersion,
+    ) -> Vec<String> {
+        let mut shallow: Vec<_> = shallow.iter().collect();
+        shallow.sort();
+        let mut output: Vec<_> = shallow
+            .into_iter()
+            .map(|x| format!("shallow {}", hex::encode(x)))
+            .collect();
+
+        if let Some(depth) = self.depth {
+            output.push(format!("deepen {depth}"));
+            if self.relative && version == ProtocolVersion::V2 {
+                output.push("deepen-relative".to_string());
+            }
+        }
+        if let Some(since) = self.since {
+            output.push(format!("deepen-since {since}"));
+        }
+        for name in &self.exclude {
+            output.push(format!("deepen-not {name}"));
+        }
+        output
+    }
+}
+
+/// Changes of the shallow boundary sent by the server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShallowUpdate {
+    /// Commits we received without their parents.
+    pub shallow: Vec<HashCode>,
+    /// Former boundary commits whose parents were sent.
+    pub unshallow: Vec<HashCode>,
+}
+
+impl ShallowUpdate {
+    /// Parse a `shallow <id>` or `unshallow <id>` line, returning `false` for any other.
+    pub fn parse_line(&mut self, line: &str) -> Result<bool, GitError> {
+        match line.split_once(' ') {
+            Some(("shallow", id)) => self.shallow.push(hash_code_text_to_array(id)?),
+            Some(("unshallow", id)) => self.unshallow.push(hash_code_text_to_array(id)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Read protocol v0 shallow lines up to a flush.
+    pub fn read<R: io::Read>(reader: &mut R) -> Result<Self, GitError> {
+        let mut output = Self::default();
+        while let PacketLine::Command(data) = PacketLine::read(reader)? {
+            let line = std::str::from_utf8(&data)?.trim_end();
+            if let Some(message) = line.strip_prefix("ERR ") {
+                return Err(GitError::Http(format!("remote error: {message}")));
+            }
+            if !output.parse_line(line)? {
+                return Err(GitError::Http(format!(
+                    "Expected shallow list, got '{line}'"
+                )));
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Write request `lines` built by `ShallowOptions::request_lines`.
+pub fn write_request_lines<W: io::Write>(writer: &mut W, lines: &[String]) -> io::Result<()> {
+    for line in lines {
+        PacketLine::Command(Bytes::from(line.clone())).write(writer)?;
+    }
+    Ok(())
+}
+
+/// Commits listed in `.git/shallow`, whose parents are missing from the repository.
+pub fn read_shallow<P: AsRef<Path>>(root: P) -> Result<HashSet<HashCode>, GitError> {
+    let content = match fs::read_to_string(git_dir(root.as_ref()).join("shallow")) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err.into()),
+    };
+    content.lines().map(hash_code_text_to_array).collect()
+}
+
+/// Write `.git/shallow`, removing it when the repository is complete.
+pub fn write_shallow<P: AsRef<Path>>(root: P, shallow: &HashSet<HashCode>) -> Result<(), GitError> {
+    let path = git_dir(root.as_ref()).join("shallow");
+    if shallow.is_empty() {
+        return match fs::remove_file(path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        };
+    }
+
+    let mut lines: Vec<_> = shallow.iter().map(hex::encode).collect();
+    lines.sort();
+    fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+/// Apply a boundary change sent by the server to `.git/shallow`.
+pub fn update_shallow<P: AsRef<Path>>(root: P, update: &ShallowUpdate) -> Result<(), GitError> {
+    let root = root.as_ref();
+    let mut shallow = read_shallow(root)?;
+    shallow.extend(&update.shallow);
+    for id in &update.unshallow {
+        shallow.remove(id);
+    }
+    write_shallow(root, &shallow)
+}
@@ -0,0 +1,185 @@
+use std::io::{self, Write};
+
+use crate::{packet_line::PacketLine, GitError};
+
+/// Band carrying pack data.
+pub const BAND_DATA: u8 = 1;
+/// Band carrying progress messages meant for the user.
+pub const BAND_PROGRESS: u8 = 2;
+/// Band carrying a fatal error, after which the server stops.
+pub const BAND_ERROR: u8 = 3;
+
+/// Most data a `side-band-64k` packet carries after its band.
+pub const MAX_BAND_DATA: usize = 65515;
+/// Most data a `side-band` packet carries after its band.
+pub const MAX_SMALL_BAND_DATA: usize = 995;
+
+/// Event reported while receiving a pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress<'a> {
+    /// Progress line of the server, with its `\r` or `\n` terminator unless it is the
+    /// last one.
+    Remote(&'a str),
+    /// Bytes of pack received so far, `done` once the whole pack is there.
+    Received { bytes: u64, done: bool },
+}
+
+/// Dispatch side-band packets: pack data goes to a writer, progress to a callback.
+///
+/// Progress messages are passed one line at a time, since servers split them over
+/// packets. An error message aborts with `GitError::Remote`.
+pub struct Demuxer<'a, W> {
+    sink: W,
+    received: u64,
+    pending: String,
+    progress: &'a mut dyn FnMut(Progress),
+}
+
+impl<'a, W: Write> Demuxer<'a, W> {
+    pub fn new(sink: W, progress: &'a mut dyn FnMut(Progress)) -> Self {
+        Self {
+            sink,
+            received: 0,
+            pending: String::new(),
+            progress,
+        }
+    }
+
+    /// Handle the content of a side-band packet line.
+    pub fn packet(&mut self, data: &[u8]) -> Result<(), GitError> {
+        let Some((band, payload)) = data.split_first() else {
+            return Err(GitError::Http("Empty side-band packet".to_string()));
+        };
+
+        match *band {
+            BAND_DATA => {
+                self.sink.write_all(payload)?;
+                self.received += payload.len() as u64;
+                (self.progress)(Progress::Received {
+                    bytes: self.received,
+                    done: false,
+                });
+            }
+            BAND_PROGRESS => {
+                self.pending.push_str(&String::from_utf8_lossy(payload));
+                while let Some(idx) = self.pending.find(['\r', '\n']) {
+                    let line: String = self.pending.drain(..=idx).collect();
+                    (self.progress)(Progress::Remote(&line));
+                }
+            }
+            BAND_ERROR => {
+                let message = String::from_utf8_lossy(payload);
+                return Err(GitError::Remote(message.trim_end().to_string()));
+            }
+            band => return Err(GitError::Http(format!("Invalid side-band channel {band}"))),
+        }
+        Ok(())
+    }
+
+    /// Report what is left once the flush ending the pack is read, and return the writer.
+    pub fn finish(mut self) -> Result<W, GitError> {
+        if !self.pending.is_empty() {
+            (self.progress)(Progress::Remote(&self.pending));
+        }
+        (self.progress)(Progress::Received {
+            bytes: self.received,
+            done: true,
+        });
+        self.sink.flush()?;
+        Ok(self.sink)
+    }
+}
+
+/// Read side-band packet lines up to a flush and return pack data.
+pub fn demux<R: io::Read>(
+    reader: &mut R,
+    progress: &mut dyn FnMut(Progress),
+) -> Result<Vec<u8>, GitError> {
+    let mut demuxer = Demuxer::new(Vec::new(), progress);
+    while let PacketLine::Command(data) = PacketLine::read(reader)? {
+        demuxer.packet(&data)?;
+    }
+    demuxer.finish()
+}
+
+/// Write `data` on `band`, split in packets carrying at most `max_data` bytes each.
+pub fn write_band<W: Write>(
+    writer: &mut W,
+    band: u8,
+    data: &[u8],
+    max_data: usize,
+) -> io::Result<()> {
+    for chunk in data.chunks(max_data) {
+        write!(writer, "{:04x}", chunk.len() + 5)?;
+        writer.write_all(&[band])?;
+        writer.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+/// Writer sending everything written through it on `band`, in packets carrying at most
+/// `max_data` bytes each.
+///
+/// Every write makes at least one packet: wrap it in a [`io::BufWriter`] of `max_data`
+/// bytes to send full packets.
+pub struct BandWriter<W> {
+    writer: W,
+    band: u8,
+    max_data: usize,
+}
+
+impl<W: Write> BandWriter<W> {
+    pub fn new(writer: W, band: u8, max_data: usize) -> Self {
+        Self {
+            writer,
+            band,
+            max_data,
+        }
+    }
+}
+
+impl<W: Write> Write for BandWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.max_data);
+        write_band(&mut self.writer, self.band, &buf[..len], self.max_data)?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Print progress on stderr like git: server lines prefixed by `remote: `, then the
+/// size of the pack received so far.
+///
+/// Lines ending with a carriage return are rewritten in place by the next one.
+pub fn print_progress(event: Progress) {
+    let mut stderr = io::stderr().lock();
+    let _ = match event {
+        Progress::Remote(line) => write!(stderr, "remote: {line}"),
+        Progress::Received { bytes, done: false } => {
+            write!(stderr, "Receiving pack: {}\r", format_size(bytes))
+        }
+        Progress::Received { bytes, done: true } => {
+            writeln!(stderr, "Receiving pack: {}, done.", format_size(bytes))
+        }
+    };
+    let _ = stderr.flush();
+}
+
+/// Human readable size, like `3.21 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} bytes");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.2} {}", UNITS[unit])
+}
@@ -0,0 +1,248 @@
+use std::{
+    env, fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config::GitConfig, GitError};
+
+const DAY_NAMES: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Identity line found in commits and tags, like `Name <email> 1703674545 +0100`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since unix epoch.
+    pub time: i64,
+    /// Offset from UTC in minutes.
+    pub tz_offset: i32,
+}
+
+impl Signature {
+    pub fn parse(input: &str) -> Result<Self, GitError> {
+        let invalid = || GitError::InvalidContent(format!("Invalid signature: {input:?}"));
+
+        let (identity, date) = input.rsplit_once('>').ok_or_else(invalid)?;
+        let (name, email) = identity.split_once('<').ok_or_else(invalid)?;
+
+        let mut date_iter = date.split_whitespace();
+        let time = date_iter
+            .next()
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(invalid)?;
+        let tz_offset = date_iter
+            .next()
+            .and_then(parse_tz_offset)
+            .ok_or_else(invalid)?;
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            time,
+            tz_offset,
+        })
+    }
+
+    /// Build signature for a new object, as `GIT_AUTHOR_*` or `GIT_COMMITTER_*` does.
+    ///
+    /// `role` is either `AUTHOR` or `COMMITTER`.
+    pub fn from_env(role: &str, config: &GitConfig) -> Result<Self, GitError> {
+        let name = env::var(format!("GIT_{role}_NAME"))
+            .ok()
+            .or_else(|| config.get("user.name").map(|x| x.to_string()))
+            .ok_or_else(|| GitError::InvalidContent("Missing user.name config".to_string()))?;
+        let email = env::var(format!("GIT_{role}_EMAIL"))
+            .ok()
+            .or_else(|| config.get("user.email").map(|x| x.to_string()))
+            .ok_or_else(|| GitError::InvalidContent("Missing user.email config".to_string()))?;
+
+        let (time, tz_offset) = match env::var(format!("GIT_{role}_DATE")) {
+            Ok(date) => parse_date_with_tz(&date)?,
+            Err(_) => (now(), 0),
+        };
+
+        Ok(Self {
+            name,
+            email,
+            time,
+            tz_offset,
+        })
+    }
+
+    /// Format date like git default format: `Wed Dec 27 11:55:45 2023 +0100`.
+    pub fn format_date(&self) -> String {
+        format_date(self.time, self.tz_offset)
+    }
+
+    pub fn format_tz(&self) -> String {
+        format_tz(self.tz_offset)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            format_tz(self.tz_offset)
+        )
+    }
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+pub fn format_tz(tz_offset: i32) -> String {
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    let tz_offset = tz_offset.abs();
+    format!("{sign}{:02}{:02}", tz_offset / 60, tz_offset % 60)
+}
+
+pub fn format_date(time: i64, tz_offset: i32) -> String {
+    let local = time + tz_offset as i64 * 60;
+    let days = local.div_euclid(86_400);
+    let seconds = local.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        DAY_NAMES[days.rem_euclid(7) as usize],
+        MONTH_NAMES[month as usize - 1],
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        year,
+        format_tz(tz_offset)
+    )
+}
+
+/// Parse date given on command line to a unix timestamp.
+///
+/// Supported formats are: unix timestamp (optionally prefixed by `@`),
+/// `YYYY-MM-DD`, `YYYY-MM-DD HH:MM:SS` (or with a `T` separator) and
+/// relative dates like `3 days ago`.
+pub fn parse_date(input: &str) -> Result<i64, GitError> {
+    parse_date_with_tz(input).map(|(time, _)| time)
+}
+
+fn parse_date_with_tz(input: &str) -> Result<(i64, i32), GitError> {
+    let invalid = || GitError::InvalidContent(format!("Invalid date: {input:?}"));
+    let input = input.trim();
+
+    // Raw git format: `1703674545 +0100`.
+    let mut iter = input.trim_start_matches('@').split_whitespace();
+    if let (Some(Ok(time)), tz, None) = (
+        iter.next().map(str::parse::<i64>),
+        iter.next().map(parse_tz_offset),
+        iter.next(),
+    ) {
+        match tz {
+            None => return Ok((time, 0)),
+            Some(Some(tz_offset)) => return Ok((time, tz_offset)),
+            Some(None) => {}
+        }
+    }
+
+    // Relative date: `2 weeks ago`.
+    if let Some(rem) = input.strip_suffix(" ago") {
+        let (count, unit) = rem.trim().split_once(' ').ok_or_else(invalid)?;
+        let count: i64 = count.parse().map_err(|_err| invalid())?;
+        let unit_seconds = match unit.trim_end_matches('s') {
+            "second" => 1,
+            "minute" => 60,
+            "hour" => 3600,
+            "day" => 86_400,
+            "week" => 7 * 86_400,
+            "month" => 30 * 86_400,
+            "year" => 365 * 86_400,
+            _ => return Err(invalid()),
+        };
+        return Ok((now() - count * unit_seconds, 0));
+    }
+
+    // ISO like date.
+    let (date, time) = match input.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (input, None),
+    };
+
+    let mut date_iter = date.split('-').map(str::parse::<i64>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day)), None) = (
+        date_iter.next(),
+        date_iter.next(),
+        date_iter.next(),
+        date_iter.next(),
+    ) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0;
+    if let Some(time) = time {
+        for (idx, part) in time.split(':').enumerate() {
+            let value: i64 = part.parse().map_err(|_err| invalid())?;
+            if idx > 2 {
+                return Err(invalid());
+            }
+            seconds += value * [3600, 60, 1][idx];
+        }
+    }
+
+    Ok((days_from_civil(year, month, day) * 86_400 + seconds, 0))
+}
+
+fn parse_tz_offset(input: &str) -> Option<i32> {
+    let (sign, digits) = match input.as_bytes().first()? {
+        b'+' => (1, &input[1..]),
+        b'-' => (-1, &input[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let value: i32 = digits.parse().ok()?;
+    Some(sign * (value / 100 * 60 + value % 100))
+}
+
+/// Convert days since epoch to (year, month, day).
+///
+/// See: http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Convert (year, month, day) to days since epoch.
+///
+/// See: http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
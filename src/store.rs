@@ -0,0 +1,201 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::GitConfig,
+    fs_utils::{fsync_objects_enabled, git_dir, read_compressed_at, write_compressed_at},
+    header::{GitObjectHeader, GitObjectHeaderType},
+    object::{encode_raw_object, read_payload, GitObject},
+    pack_index::PackFile,
+    shallow::read_shallow,
+    GitError, HashCode,
+};
+
+/// Access to every object of a repository, whether it is loose or packed.
+pub struct ObjectStore {
+    root: PathBuf,
+    packs: Vec<PackFile>,
+    fsync: bool,
+    shallow: HashSet<HashCode>,
+}
+
+impl ObjectStore {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self, GitError> {
+        let root = root.as_ref().to_path_buf();
+        let fsync = fsync_objects_enabled(&GitConfig::read_at(&root)?);
+
+        let mut packs = Vec::new();
+        for idx_path in list_pack_indexes(&root)? {
+            packs.push(PackFile::open(idx_path)?);
+        }
+
+        let shallow = read_shallow(&root)?;
+
+        Ok(Self {
+            root,
+            packs,
+            fsync,
+            shallow,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn packs(&self) -> &[PackFile] {
+        &self.packs
+    }
+
+    /// Whether commit `hash_code` is at the boundary of a shallow clone: history walks
+    /// must treat it as having no parents.
+    pub fn is_shallow(&self, hash_code: HashCode) -> bool {
+        self.shallow.contains(&hash_code)
+    }
+
+    pub fn contains(&self, hash_code: HashCode) -> bool {
+        self.loose_path(hash_code).exists()
+            || self.packs.iter().any(|x| x.index.find(hash_code).is_some())
+    }
+
+    /// Read object type and payload without parsing it.
+    pub fn read_raw(
+        &self,
+        hash_code: HashCode,
+    ) -> Result<(GitObjectHeaderType, Vec<u8>), GitError> {
+        match read_compressed_at(hash_code, &self.root) {
+            Ok(mut reader) => {
+                let header = GitObjectHeader::read(&mut reader)?;
+                let payload = read_payload(&mut reader, header.len)?;
+                Ok((header.r#type, payload))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                for pack in &self.packs {
+                    if let Some(offset) = pack.index.find(hash_code) {
+                        return pack.read_at(offset, &|base| self.read_raw(base));
+                    }
+                }
+                Err(GitError::missing_object(hash_code))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn read(&self, hash_code: HashCode) -> Result<GitObject, GitError> {
+        let (r#type, payload) = self.read_raw(hash_code)?;
+        GitObject::read_with_header(
+            &mut payload.as_slice(),
+            GitObjectHeader {
+                len: payload.len(),
+                r#type,
+            },
+        )
+    }
+
+    pub fn write(&self, object: &GitObject) -> Result<HashCode, GitError> {
+        let (hash_code, bytes) = object.to_bytes_vec()?;
+        write_compressed_at(hash_code, &bytes, &self.root, self.fsync)?;
+        Ok(hash_code)
+    }
+
+    /// Write an already encoded payload, keeping it byte for byte.
+    pub fn write_raw(
+        &self,
+        r#type: GitObjectHeaderType,
+        payload: &[u8],
+    ) -> Result<HashCode, GitError> {
+        let (hash_code, bytes) = encode_raw_object(r#type, payload);
+        write_compressed_at(hash_code, &bytes, &self.root, self.fsync)?;
+        Ok(hash_code)
+    }
+
+    /// List names of every loose object file.
+    pub fn loose_objects(&self) -> Result<Vec<HashCode>, GitError> {
+        let mut output = Vec::new();
+
+        let objects_dir = git_dir(&self.root).join("objects");
+        for dir_entry in read_dir_sorted(&objects_dir)? {
+            let dir_name = dir_entry.to_string_lossy();
+            if dir_name.len() != 2 {
+                continue;
+            }
+
+            for file_entry in read_dir_sorted(&objects_dir.join(&dir_entry))? {
+                let name = format!("{dir_name}{}", file_entry.to_string_lossy());
+                if let Ok(hash_code) = crate::hash_code_text_to_array(&name) {
+                    output.push(hash_code);
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Find objects whose hex name starts with `prefix`.
+    pub fn find_by_prefix(&self, prefix: &str) -> Result<Vec<HashCode>, GitError> {
+        let prefix = prefix.to_lowercase();
+        if prefix.len() < 2 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(Vec::new());
+        }
+
+        let mut output = Vec::new();
+
+        let dir = git_dir(&self.root).join("objects").join(&prefix[..2]);
+        for file_entry in read_dir_sorted(&dir)? {
+            let name = format!("{}{}", &prefix[..2], file_entry.to_string_lossy());
+            if name.starts_with(&prefix) {
+                if let Ok(hash_code) = crate::hash_code_text_to_array(&name) {
+                    output.push(hash_code);
+                }
+            }
+        }
+
+        for pack in &self.packs {
+            output.extend(
+                pack.index
+                    .entries
+                    .iter()
+                    .map(|x| x.hash_code)
+                    .filter(|x| hex::encode(x).starts_with(&prefix)),
+            );
+        }
+
+        output.sort();
+        output.dedup();
+        Ok(output)
+    }
+
+    fn loose_path(&self, hash_code: HashCode) -> PathBuf {
+        let cs = hex::encode(hash_code);
+        git_dir(&self.root)
+            .join("objects")
+            .join(&cs[..2])
+            .join(&cs[2..])
+    }
+}
+
+/// List `.idx` files of every pack stored in repository.
+pub fn list_pack_indexes<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>, GitError> {
+    let pack_dir = git_dir(root.as_ref()).join("objects/pack");
+    Ok(read_dir_sorted(&pack_dir)?
+        .into_iter()
+        .filter(|x| x.to_string_lossy().ends_with(".idx"))
+        .map(|x| pack_dir.join(x))
+        .collect())
+}
+
+fn read_dir_sorted(path: &Path) -> io::Result<Vec<std::ffi::OsString>> {
+    let mut entries: Vec<_> = match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(|x| x.ok())
+            .map(|x| x.file_name())
+            .collect(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => return Err(err),
+    };
+    entries.sort();
+    Ok(entries)
+}
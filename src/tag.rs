@@ -0,0 +1,105 @@
+use std::path::Path;
+
+use crate::{
+    object::GitObject,
+    refs::{delete_ref_at, is_valid_ref_name, list_refs_at, read_ref_at, write_ref_at, RefValue},
+    signature::Signature,
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+/// Data stored in a tag object, turning a lightweight tag into an annotated one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagAnnotation {
+    pub tagger: Signature,
+    pub message: String,
+}
+
+/// List tags sorted by name, with the object their ref points to.
+pub fn list_tags<P: AsRef<Path>>(root: P) -> Result<Vec<(String, HashCode)>, GitError> {
+    Ok(list_refs_at(root)?
+        .into_iter()
+        .filter_map(|(name, hash_code)| {
+            Some((name.strip_prefix("refs/tags/")?.to_string(), hash_code))
+        })
+        .collect())
+}
+
+/// Create tag `name` on `target`, writing a tag object first when annotated.
+///
+/// Returns the ID the tag ref points to.
+pub fn create_tag<P: AsRef<Path>>(
+    root: P,
+    store: &ObjectStore,
+    name: &str,
+    target: HashCode,
+    annotation: Option<&TagAnnotation>,
+    force: bool,
+) -> Result<HashCode, GitError> {
+    let root = root.as_ref();
+    let ref_name = format!("refs/tags/{name}");
+    if name.starts_with('-') || !is_valid_ref_name(&ref_name) {
+        return Err(GitError::InvalidContent(format!(
+            "'{name}' is not a valid tag name"
+        )));
+    }
+    if !force && read_ref_at(root, &ref_name)?.is_some() {
+        return Err(GitError::InvalidContent(format!(
+            "Tag '{name}' already exists"
+        )));
+    }
+
+    let hash_code = match annotation {
+        Some(annotation) => {
+            let (target_type, _) = store.read_raw(target)?;
+            store.write(&GitObject::Tag {
+                object: target,
+                target_type,
+                tag: name.to_string(),
+                tagger: Some(annotation.tagger.to_string()),
+                message: annotation.message.trim_end().to_string(),
+            })?
+        }
+        None => target,
+    };
+
+    write_ref_at(root, &ref_name, &RefValue::Direct(hash_code))?;
+    Ok(hash_code)
+}
+
+/// Delete a tag, returning the ID its ref pointed to.
+pub fn delete_tag<P: AsRef<Path>>(root: P, name: &str) -> Result<HashCode, GitError> {
+    let root = root.as_ref();
+    let ref_name = format!("refs/tags/{name}");
+    let Some(RefValue::Direct(hash_code)) = read_ref_at(root, &ref_name)? else {
+        return Err(GitError::InvalidContent(format!("Tag '{name}' not found")));
+    };
+
+    delete_ref_at(root, &ref_name)?;
+    Ok(hash_code)
+}
+
+/// Check if a tag ref pointing to `hash_code` designates `object`, directly or through
+/// the tag object it points to. Like git, only one level of tag is peeled.
+pub fn tag_points_at(
+    store: &ObjectStore,
+    hash_code: HashCode,
+    object: HashCode,
+) -> Result<bool, GitError> {
+    if hash_code == object {
+        return Ok(true);
+    }
+    match store.read(hash_code)? {
+        GitObject::Tag { object: tagged, .. } => Ok(tagged == object),
+        _ => Ok(false),
+    }
+}
+
+/// Message shown by `tag -n`: the tag annotation, or the message of the tagged commit
+/// for lightweight tags.
+pub fn tag_message(store: &ObjectStore, hash_code: HashCode) -> Result<String, GitError> {
+    match store.read(hash_code)? {
+        GitObject::Tag { message, .. } | GitObject::Commit { message, .. } => Ok(message),
+        GitObject::Blob(_) | GitObject::Tree(_) => Ok(String::new()),
+    }
+}
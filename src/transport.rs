@@ -0,0 +1,522 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use bytes::Buf;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    process::{Child, Command},
+};
+
+use crate::{
+    clone::{Advertisement, InfoRef, ProtocolVersion},
+    download::ResponseReader,
+    fs_utils::git_dir,
+    packet_line::PacketLine,
+    refs::{list_refs_at, read_ref_at, resolve_ref_at, RefValue},
+    revision::peel_tags,
+    store::ObjectStore,
+    GitError,
+};
+
+/// Port `git daemon` listens on by default.
+const DAEMON_PORT: u16 = 9418;
+
+/// How a remote repository is reached, told by its URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Smart HTTP server, from an `http://` or `https://` URL.
+    Http(String),
+    /// Repository on this machine, from a `file://` URL or a plain path.
+    Local { path: PathBuf, is_file_url: bool },
+    /// Repository reached by running a command over ssh, from an `ssh://` URL or a
+    /// scp-like `[user@]host:path`.
+    Ssh {
+        user: Option<String>,
+        host: String,
+        port: Option<u16>,
+        /// Path on the host, relative to the home directory unless absolute.
+        path: String,
+    },
+    /// Repository served by `git daemon`, from a `git://host[:port]/path` URL.
+    Git {
+        host: String,
+        port: Option<u16>,
+        path: String,
+    },
+}
+
+impl Transport {
+    pub fn parse(url: &str) -> Result<Self, GitError> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(Self::Http(url.trim_end_matches('/').to_string()));
+        }
+        if let Some(path) = url.strip_prefix("file://") {
+            // Like git, only URLs without host are accepted.
+            if !path.starts_with('/') {
+                return Err(GitError::Transport(format!(
+                    "Invalid file URL '{url}': expected file:///<path>"
+                )));
+            }
+            return Ok(Self::Local {
+                path: PathBuf::from(path),
+                is_file_url: true,
+            });
+        }
+        if let Some((scheme, rest)) = url.split_once("://") {
+            if !matches!(scheme, "ssh" | "git+ssh" | "ssh+git" | "git") {
+                return Err(GitError::Transport(format!(
+                    "Unsupported URL scheme '{scheme}' in '{url}'"
+                )));
+            }
+            return Self::parse_host_url(url, scheme, rest);
+        }
+        // Like git, a colon before any slash makes a scp-like URL.
+        if let Some((login, path)) = url.split_once(':') {
+            if !login.is_empty() && !login.contains('/') && !path.is_empty() {
+                let (user, host) = split_login(login);
+                check_ssh_login(user.as_deref(), host)?;
+                return Ok(Self::Ssh {
+                    user,
+                    host: host.to_string(),
+                    port: None,
+                    path: path.to_string(),
+                });
+            }
+        }
+        Ok(Self::Local {
+            path: PathBuf::from(url),
+            is_file_url: false,
+        })
+    }
+
+    /// Parse `rest` of URL `url` like `<scheme>://[user@]host[:port]/path`.
+    fn parse_host_url(url: &str, scheme: &str, rest: &str) -> Result<Self, GitError> {
+        let invalid = || GitError::Transport(format!("Invalid {scheme} URL '{url}'"));
+
+        let (authority, path) = rest
+            .find('/')
+            .map(|x| rest.split_at(x))
+            .ok_or_else(invalid)?;
+        let (user, address) = split_login(authority);
+        // IPv6 addresses come in brackets, the port after them.
+        let (host, port) = match address.strip_prefix('[') {
+            Some(address) => {
+                let (host, port) = address.split_once(']').ok_or_else(invalid)?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match address.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        let port = port.map(|x| x.parse().map_err(|_| invalid())).transpose()?;
+
+        // The daemon maps paths to repositories itself.
+        if scheme == "git" {
+            if user.is_some() {
+                return Err(invalid());
+            }
+            return Ok(Self::Git {
+                host: host.to_string(),
+                port,
+                path: path.to_string(),
+            });
+        }
+        // `/~user/path` is relative to a home directory.
+        let path = match path.strip_prefix("/~") {
+            Some(path) => format!("~{path}"),
+            None => path.to_string(),
+        };
+        check_ssh_login(user.as_deref(), host)?;
+
+        Ok(Self::Ssh {
+            user,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+}
+
+/// Split `[user@]host` into its parts.
+fn split_login(login: &str) -> (Option<String>, &str) {
+    match login.rsplit_once('@') {
+        Some((user, host)) => (Some(user.to_string()), host),
+        None => (None, login),
+    }
+}
+
+/// Refuse a user or host ssh would take for an option, like git does.
+fn check_ssh_login(user: Option<&str>, host: &str) -> Result<(), GitError> {
+    if host.starts_with('-') {
+        return Err(GitError::Transport(format!(
+            "strange hostname '{host}' blocked"
+        )));
+    }
+    match user {
+        Some(user) if user.starts_with('-') => Err(GitError::Transport(format!(
+            "strange username '{user}' blocked"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Connection to `git-upload-pack` or `git-receive-pack` of a remote repository.
+///
+/// Smart HTTP is stateless: each request is a POST answered on its own. Other transports
+/// pipe requests to a process, which keeps the state of the exchange until the connection
+/// is closed.
+pub struct Connection {
+    channel: Channel,
+    service: String,
+    version: ProtocolVersion,
+    /// Reader of the last response, the same for every response of a process.
+    reader: Option<ResponseReader>,
+}
+
+enum Channel {
+    Http {
+        client: reqwest::Client,
+        url: String,
+    },
+    /// Pipe to a process or socket, the process exiting when the connection is closed.
+    Stream {
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        child: Option<Child>,
+    },
+}
+
+impl Connection {
+    /// Connect to `service` of the repository at `url`, asking for protocol v2 when
+    /// `version` is `V2`, and read its advertisement.
+    ///
+    /// Servers not supporting v2 ignore the request and answer with v0.
+    pub async fn open(
+        url: &str,
+        service: &str,
+        version: ProtocolVersion,
+    ) -> Result<(Self, Advertisement), GitError> {
+        match Transport::parse(url)? {
+            Transport::Http(url) => {
+                let client = reqwest::Client::new();
+                let mut request = client.get(format!("{url}/info/refs?service={service}"));
+                if version == ProtocolVersion::V2 {
+                    request = request.header("Git-Protocol", "version=2");
+                }
+                let content = request.send().await?.error_for_status()?.bytes().await?;
+                let advertisement = Advertisement::parse(&mut content.reader())?;
+
+                let connection = Self {
+                    channel: Channel::Http { client, url },
+                    service: service.to_string(),
+                    version: advertisement.version,
+                    reader: None,
+                };
+                Ok((connection, advertisement))
+            }
+            Transport::Local { path, .. } => {
+                let path = path.to_str().ok_or_else(|| {
+                    GitError::Transport(format!("Invalid repository path {path:?}"))
+                })?;
+                let mut command = Command::new("sh");
+                command
+                    .arg("-c")
+                    .arg(format!("{service} {}", shell_quote(path)));
+                if version == ProtocolVersion::V2 {
+                    command.env("GIT_PROTOCOL", "version=2");
+                }
+                Self::spawn(command, service).await
+            }
+            Transport::Ssh {
+                user,
+                host,
+                port,
+                path,
+            } => {
+                let mut args = Vec::new();
+                // OpenSSH only passes variables it is told to.
+                if version == ProtocolVersion::V2 {
+                    args.extend(["-o".to_string(), "SendEnv=GIT_PROTOCOL".to_string()]);
+                }
+                if let Some(port) = port {
+                    args.extend(["-p".to_string(), port.to_string()]);
+                }
+                args.push("--".to_string());
+                args.push(match user {
+                    Some(user) => format!("{user}@{host}"),
+                    None => host,
+                });
+                args.push(format!("{service} {}", shell_quote(&path)));
+
+                let mut command = ssh_command(&args);
+                if version == ProtocolVersion::V2 {
+                    command.env("GIT_PROTOCOL", "version=2");
+                }
+                Self::spawn(command, service).await
+            }
+            Transport::Git { host, port, path } => {
+                Self::connect_daemon(&host, port, &path, service, version).await
+            }
+        }
+    }
+
+    /// Connect to upload-pack of `url` with protocol v2 when the server supports it, then
+    /// list refs starting with one of `prefixes`, or all refs when empty. Protocol v0
+    /// lists them all.
+    pub async fn upload_pack(
+        url: &str,
+        prefixes: &[String],
+    ) -> Result<(Self, Advertisement), GitError> {
+        let (mut connection, mut advertisement) =
+            Self::open(url, "git-upload-pack", ProtocolVersion::V2).await?;
+        if advertisement.version == ProtocolVersion::V2 {
+            advertisement.refs = InfoRef::ls_refs(&mut connection, prefixes).await?;
+        }
+        Ok((connection, advertisement))
+    }
+
+    /// Run `command` speaking `service` protocol on its standard input and output, errors
+    /// going to our standard error.
+    async fn spawn(mut command: Command, service: &str) -> Result<(Self, Advertisement), GitError> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| GitError::Transport(format!("Cannot run {service}: {err}")))?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            return Err(GitError::Transport(format!("Cannot run {service}")));
+        };
+        let reader = ResponseReader::from_stream(stdout);
+        Self::handshake(reader, Box::new(stdin), Some(child), service).await
+    }
+
+    /// Ask `git daemon` listening on `host` for `service` of repository `path`.
+    ///
+    /// Protocol v2 is asked for in an extra parameter, ignored by older daemons.
+    async fn connect_daemon(
+        host: &str,
+        port: Option<u16>,
+        path: &str,
+        service: &str,
+        version: ProtocolVersion,
+    ) -> Result<(Self, Advertisement), GitError> {
+        let stream = TcpStream::connect((host, port.unwrap_or(DAEMON_PORT)))
+            .await
+            .map_err(|err| GitError::Transport(format!("Cannot connect to {host}: {err}")))?;
+        let (read_half, mut write_half) = stream.into_split();
+
+        let host = match port {
+            Some(port) => format!("{host}:{port}"),
+            None => host.to_string(),
+        };
+        let mut request = format!("{service} {path}\0host={host}\0");
+        if version == ProtocolVersion::V2 {
+            request.push_str("\0version=2\0");
+        }
+        // Unlike other packet lines, the request has no trailing newline.
+        write_half
+            .write_all(format!("{:04x}{request}", request.len() + 4).as_bytes())
+            .await?;
+
+        let reader = ResponseReader::from_stream(read_half);
+        Self::handshake(reader, Box::new(write_half), None, service).await
+    }
+
+    /// Read the advertisement starting the exchange over a stream.
+    async fn handshake(
+        mut reader: ResponseReader,
+        writer: Box<dyn AsyncWrite + Send + Unpin>,
+        mut child: Option<Child>,
+        service: &str,
+    ) -> Result<(Self, Advertisement), GitError> {
+        // Both v0 and v2 advertisements end with a flush, unless the server gives up.
+        let is_last = |x: &PacketLine| *x == PacketLine::End || is_error_line(x);
+        let head = match reader.read_head(is_last).await {
+            Ok(head) => head,
+            Err(err) => {
+                // A process usually explained on standard error why it gave up.
+                if let Some(child) = &mut child {
+                    let status = child.wait().await?;
+                    if !status.success() {
+                        return Err(GitError::Transport(format!(
+                            "Could not read from remote repository: {service} {status}"
+                        )));
+                    }
+                }
+                return Err(err);
+            }
+        };
+        let advertisement = Advertisement::parse(&mut head.as_slice())?;
+
+        let connection = Self {
+            channel: Channel::Stream { writer, child },
+            service: service.to_string(),
+            version: advertisement.version,
+            reader: Some(reader),
+        };
+        Ok((connection, advertisement))
+    }
+
+    /// Whether each request stands on its own, repeating what previous ones told.
+    pub fn is_stateless(&self) -> bool {
+        matches!(self.channel, Channel::Http { .. })
+    }
+
+    /// Send request `body` and return the reader of its response.
+    pub async fn request(&mut self, body: Vec<u8>) -> Result<&mut ResponseReader, GitError> {
+        match &mut self.channel {
+            Channel::Http { client, url } => {
+                let service = &self.service;
+                let mut request = client
+                    .post(format!("{url}/{service}"))
+                    .header("Content-Type", format!("application/x-{service}-request"));
+                if self.version == ProtocolVersion::V2 {
+                    request = request.header("Git-Protocol", "version=2");
+                }
+                let response = request.body(body).send().await?.error_for_status()?;
+                Ok(self.reader.insert(ResponseReader::new(response)))
+            }
+            Channel::Stream { writer, .. } => {
+                writer.write_all(&body).await?;
+                writer.flush().await?;
+                self.reader
+                    .as_mut()
+                    .ok_or_else(|| GitError::Transport("Connection closed".to_string()))
+            }
+        }
+    }
+
+    /// End the exchange, waiting for a process to exit.
+    pub async fn close(self) -> Result<(), GitError> {
+        let Self {
+            channel, reader, ..
+        } = self;
+        // Output left unread must not block the process.
+        drop(reader);
+
+        if let Channel::Stream { mut writer, child } = channel {
+            // A flush instead of a request ends the session. The server may be gone
+            // already, after sending a pack.
+            let _ = writer.write_all(b"0000").await;
+            let _ = writer.shutdown().await;
+            drop(writer);
+            if let Some(mut child) = child {
+                child.wait().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `line` is an `ERR` line, a server giving up with a message.
+fn is_error_line(line: &PacketLine) -> bool {
+    matches!(line, PacketLine::Command(data) if data.starts_with(b"ERR "))
+}
+
+/// Command running ssh with `args`, like git: `GIT_SSH_COMMAND` through the shell, else
+/// program `GIT_SSH`, else `ssh`.
+fn ssh_command(args: &[String]) -> Command {
+    if let Ok(ssh) = env::var("GIT_SSH_COMMAND") {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{ssh} \"$@\""))
+            .arg(&ssh)
+            .args(args);
+        return command;
+    }
+    let mut command = Command::new(env::var_os("GIT_SSH").unwrap_or_else(|| "ssh".into()));
+    command.args(args);
+    command
+}
+
+/// Quote `arg` for a POSIX shell command line.
+pub(crate) fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// List refs of the local repository at `path` like an upload-pack advertisement: `HEAD`,
+/// then refs starting with one of `prefixes`, or all refs when empty, peeled tags as
+/// extra `<name>^{}` refs.
+pub fn list_local_refs<P: AsRef<Path>>(
+    path: P,
+    prefixes: &[String],
+) -> Result<Vec<InfoRef>, GitError> {
+    let path = path.as_ref();
+    let store = ObjectStore::open(path)?;
+    let info_ref = |name: &str, id, symref_target| InfoRef {
+        name: name.to_string(),
+        object_id: hex::encode(id),
+        capabilities: Vec::new(),
+        symref_target,
+    };
+    let is_listed =
+        |name: &str| prefixes.is_empty() || prefixes.iter().any(|x| name.starts_with(x));
+
+    let mut output = Vec::new();
+    if let Some(id) = resolve_ref_at(path, "HEAD")?.filter(|_| is_listed("HEAD")) {
+        let symref_target = match read_ref_at(path, "HEAD")? {
+            Some(RefValue::Symbolic(target)) => Some(target),
+            _ => None,
+        };
+        output.push(info_ref("HEAD", id, symref_target));
+    }
+    for (name, id) in list_refs_at(path)? {
+        if !is_listed(&name) {
+            continue;
+        }
+        output.push(info_ref(&name, id, None));
+        let peeled = peel_tags(&store, id)?;
+        if peeled != id {
+            output.push(info_ref(&format!("{name}^{{}}"), peeled, None));
+        }
+    }
+    Ok(output)
+}
+
+/// Copy every object of the local repository at `src` into repository `dst`, with hard
+/// links when `hardlink` is set and both are on the same file system.
+///
+/// Objects already in `dst` and temporary files are skipped.
+pub fn copy_objects<P: AsRef<Path>, Q: AsRef<Path>>(
+    src: P,
+    dst: Q,
+    hardlink: bool,
+) -> Result<(), GitError> {
+    let src = src.as_ref();
+    let objects = git_dir(src).join("objects");
+    if !objects.is_dir() {
+        return Err(GitError::Transport(format!(
+            "'{}' does not appear to be a git repository",
+            src.display()
+        )));
+    }
+    copy_dir(&objects, &git_dir(dst).join("objects"), hardlink)?;
+    Ok(())
+}
+
+fn copy_dir(src: &Path, dst: &Path, hardlink: bool) -> io::Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let (src, dst) = (entry.path(), dst.join(&name));
+        if entry.file_type()?.is_dir() {
+            copy_dir(&src, &dst, hardlink)?;
+        } else if !name.to_string_lossy().starts_with("tmp_") && !dst.exists() {
+            // Links across file systems fail: copy instead.
+            if !hardlink || fs::hard_link(&src, &dst).is_err() {
+                fs::copy(&src, &dst)?;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,633 @@
+use std::{
+    collections::HashSet,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    clone::{InfoRef, ProtocolVersion},
+    fs_utils::git_dir,
+    hash_code_text_to_array,
+    header::GitObjectHeaderType,
+    log::ancestors,
+    object::GitObject,
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    refs::list_refs_at,
+    revision::peel_tags,
+    sideband::{
+        write_band, BandWriter, BAND_DATA, BAND_ERROR, BAND_PROGRESS, MAX_BAND_DATA,
+        MAX_SMALL_BAND_DATA,
+    },
+    store::ObjectStore,
+    transport::list_local_refs,
+    GitError, HashCode,
+};
+
+/// Name and version of this server, sent along with capabilities.
+pub(crate) const AGENT: &str = concat!("git-starter-rust/", env!("CARGO_PKG_VERSION"));
+/// Capabilities advertised with protocol v0, besides `symref` and `agent`.
+const CAPABILITIES: [&str; 6] = [
+    "multi_ack",
+    "multi_ack_detailed",
+    "side-band",
+    "side-band-64k",
+    "no-progress",
+    "include-tag",
+];
+
+/// How `upload_pack` talks with the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadPackOptions {
+    pub version: ProtocolVersion,
+    /// Answer a single request without advertising refs first, like for smart HTTP.
+    pub stateless_rpc: bool,
+    /// Only advertise refs, or capabilities with protocol v2, then stop.
+    pub advertise_refs: bool,
+}
+
+/// Protocol version asked for by a client in `GIT_PROTOCOL`, like `version=2`.
+pub fn requested_version(git_protocol: &str) -> ProtocolVersion {
+    match git_protocol.split(':').any(|x| x == "version=2") {
+        true => ProtocolVersion::V2,
+        false => ProtocolVersion::V0,
+    }
+}
+
+/// Serve objects of repository `root` like `git-upload-pack`, reading requests of the
+/// client from `reader` and answering on `writer`.
+///
+/// Packs store every object whole: `thin-pack` and `ofs-delta` are never advertised.
+pub fn upload_pack<P: AsRef<Path>, R: Read, W: Write>(
+    root: P,
+    reader: &mut R,
+    writer: &mut W,
+    options: &UploadPackOptions,
+) -> Result<(), GitError> {
+    let root = root.as_ref();
+    if !git_dir(root).join("objects").is_dir() {
+        return Err(GitError::Transport(format!(
+            "'{}' does not appear to be a git repository",
+            root.display()
+        )));
+    }
+    let store = ObjectStore::open(root)?;
+
+    match options.version {
+        ProtocolVersion::V0 => serve_v0(root, &store, reader, writer, options),
+        ProtocolVersion::V2 => serve_v2(root, &store, reader, writer, options),
+    }
+}
+
+/// How acknowledgements are sent with protocol v0, depending on client capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MultiAck {
+    /// Only the first common commit is acknowledged.
+    Single,
+    /// `multi_ack`: every common commit is acknowledged with `continue`.
+    Multi,
+    /// `multi_ack_detailed`: acknowledgements tell `common` from `ready`.
+    Detailed,
+}
+
+/// How the pack is sent, from v0 capabilities or v2 arguments.
+struct PackOptions {
+    /// Most data of a side-band packet, `None` to send the pack as is.
+    side_band: Option<usize>,
+    progress: bool,
+    include_tag: bool,
+}
+
+/// First part of a v0 request.
+struct Wants {
+    ids: Vec<HashCode>,
+    /// Capabilities the client picked, given on the first `want` line.
+    capabilities: Vec<String>,
+}
+
+/// Serve protocol v0: refs are advertised upfront, then `want` lines, then `have`
+/// batches each answered after a flush, until `done`.
+fn serve_v0<R: Read, W: Write>(
+    root: &Path,
+    store: &ObjectStore,
+    reader: &mut R,
+    writer: &mut W,
+    options: &UploadPackOptions,
+) -> Result<(), GitError> {
+    let refs = list_local_refs(root, &[])?;
+    if !options.stateless_rpc || options.advertise_refs {
+        let mut capabilities = CAPABILITIES.join(" ");
+        let head = refs.first().filter(|x| x.name == "HEAD");
+        if let Some(target) = head.and_then(|x| x.symref_target.as_deref()) {
+            capabilities.push_str(&format!(" symref=HEAD:{target}"));
+        }
+        capabilities.push_str(&format!(" agent={AGENT}"));
+        write_advertisement(writer, &refs, &capabilities)?;
+        writer.flush()?;
+    }
+    if options.advertise_refs {
+        return Ok(());
+    }
+
+    // A client only listing refs, or already up to date, wants nothing.
+    let Some(wants) = read_wants(reader, writer, &refs)? else {
+        return Ok(());
+    };
+    let has = |name: &str| wants.capabilities.iter().any(|x| x == name);
+    let multi_ack = if has("multi_ack_detailed") {
+        MultiAck::Detailed
+    } else if has("multi_ack") {
+        MultiAck::Multi
+    } else {
+        MultiAck::Single
+    };
+    let pack_options = PackOptions {
+        side_band: if has("side-band-64k") {
+            Some(MAX_BAND_DATA)
+        } else if has("side-band") {
+            Some(MAX_SMALL_BAND_DATA)
+        } else {
+            None
+        },
+        progress: !has("no-progress"),
+        include_tag: has("include-tag"),
+    };
+
+    let mut negotiation = Negotiation::new(store, wants.ids);
+    // Whether the current batch has commits we know, and commits we do not.
+    let (mut got_common, mut got_other) = (false, false);
+    loop {
+        let data = match read_line(reader)? {
+            Some(PacketLine::Command(data)) => data,
+            Some(PacketLine::End) => {
+                let last = negotiation.common.last().copied();
+                if let Some(last) = last.filter(|_| multi_ack == MultiAck::Detailed) {
+                    if got_common && !got_other && negotiation.is_ready()? {
+                        write_line(writer, format!("ACK {} ready", hex::encode(last)))?;
+                    }
+                }
+                if last.is_none() || multi_ack != MultiAck::Single {
+                    write_line(writer, "NAK".to_string())?;
+                }
+                writer.flush()?;
+                // The next request repeats everything on a new connection.
+                if options.stateless_rpc {
+                    return Ok(());
+                }
+                (got_common, got_other) = (false, false);
+                continue;
+            }
+            Some(PacketLine::Delimiter) => {
+                return Err(refuse(writer, "protocol error: unexpected delimiter"))
+            }
+            None => return Err(hung_up()),
+        };
+
+        let line = line_text(&data)?;
+        if line == "done" {
+            break;
+        }
+        let Some(id) = line.strip_prefix("have ") else {
+            return Err(refuse(
+                writer,
+                &format!("protocol error: expected have or done, got '{line}'"),
+            ));
+        };
+        let id = hash_code_text_to_array(id)?;
+
+        if negotiation.have(id) {
+            got_common = true;
+            match multi_ack {
+                MultiAck::Detailed => {
+                    write_line(writer, format!("ACK {} common", hex::encode(id)))?
+                }
+                MultiAck::Multi => write_line(writer, format!("ACK {} continue", hex::encode(id)))?,
+                MultiAck::Single if negotiation.common.len() == 1 => {
+                    write_line(writer, format!("ACK {}", hex::encode(id)))?
+                }
+                MultiAck::Single => {}
+            }
+        } else {
+            got_other = true;
+            // The client may stop digging into history we cannot use.
+            if multi_ack != MultiAck::Single && negotiation.is_ready()? {
+                let status = match multi_ack {
+                    MultiAck::Detailed => "ready",
+                    _ => "continue",
+                };
+                write_line(writer, format!("ACK {} {status}", hex::encode(id)))?;
+            }
+        }
+    }
+
+    // A final acknowledgement, unless the single one was already sent.
+    match negotiation.common.last() {
+        Some(last) if multi_ack != MultiAck::Single => {
+            write_line(writer, format!("ACK {}", hex::encode(last)))?
+        }
+        Some(_) => {}
+        None => write_line(writer, "NAK".to_string())?,
+    }
+    send_pack(
+        root,
+        store,
+        writer,
+        &negotiation.wants,
+        &negotiation.common,
+        &pack_options,
+    )
+}
+
+/// Advertise `refs` with `capabilities` on the first line, ended by a flush.
+pub(crate) fn write_advertisement<W: Write>(
+    writer: &mut W,
+    refs: &[InfoRef],
+    capabilities: &str,
+) -> Result<(), GitError> {
+    match refs.split_first() {
+        Some((first, rest)) => {
+            let line = format!("{} {}\0{capabilities}", first.object_id, first.name);
+            write_line(writer, line)?;
+            for info_ref in rest {
+                write_line(writer, format!("{} {}", info_ref.object_id, info_ref.name))?;
+            }
+        }
+        // An empty repository still has capabilities to tell.
+        None => write_line(
+            writer,
+            format!("{} capabilities^{{}}\0{capabilities}", "0".repeat(40)),
+        )?,
+    }
+    PacketLine::End.write(writer)?;
+    Ok(())
+}
+
+/// Read `want` lines up to a flush, `None` if the client wants nothing.
+fn read_wants<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    refs: &[InfoRef],
+) -> Result<Option<Wants>, GitError> {
+    let mut wants = Wants {
+        ids: Vec::new(),
+        capabilities: Vec::new(),
+    };
+
+    loop {
+        let data = match read_line(reader)? {
+            None | Some(PacketLine::End) if wants.ids.is_empty() => return Ok(None),
+            Some(PacketLine::End) => return Ok(Some(wants)),
+            Some(PacketLine::Command(data)) => data,
+            Some(PacketLine::Delimiter) => {
+                return Err(refuse(writer, "protocol error: unexpected delimiter"))
+            }
+            None => return Err(hung_up()),
+        };
+
+        let line = line_text(&data)?;
+        let Some(rest) = line.strip_prefix("want ") else {
+            return Err(refuse(
+                writer,
+                &format!("protocol error: expected want, got '{line}'"),
+            ));
+        };
+        let (id, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        if wants.ids.is_empty() {
+            wants.capabilities = rest.split_whitespace().map(|x| x.to_string()).collect();
+        }
+        wants.ids.push(check_want(writer, refs, id)?);
+    }
+}
+
+/// Serve protocol v2: capabilities are advertised upfront, then the client sends
+/// `ls-refs` and `fetch` commands until it hangs up.
+fn serve_v2<R: Read, W: Write>(
+    root: &Path,
+    store: &ObjectStore,
+    reader: &mut R,
+    writer: &mut W,
+    options: &UploadPackOptions,
+) -> Result<(), GitError> {
+    if !options.stateless_rpc || options.advertise_refs {
+        for line in ["version 2", &format!("agent={AGENT}"), "ls-refs", "fetch"] {
+            write_line(writer, line.to_string())?;
+        }
+        PacketLine::End.write(writer)?;
+        writer.flush()?;
+    }
+    if options.advertise_refs {
+        return Ok(());
+    }
+
+    loop {
+        let data = match read_line(reader)? {
+            // A flush instead of a command ends the session.
+            None | Some(PacketLine::End) => return Ok(()),
+            Some(PacketLine::Command(data)) => data,
+            Some(PacketLine::Delimiter) => {
+                return Err(refuse(writer, "protocol error: unexpected delimiter"))
+            }
+        };
+        let line = line_text(&data)?.to_string();
+        let Some(command) = line.strip_prefix("command=") else {
+            return Err(refuse(
+                writer,
+                &format!("protocol error: expected command, got '{line}'"),
+            ));
+        };
+
+        let arguments = read_arguments(reader)?;
+        match command {
+            "ls-refs" => ls_refs(root, writer, &arguments)?,
+            "fetch" => fetch(root, store, writer, &arguments)?,
+            _ => return Err(refuse(writer, &format!("invalid command '{command}'"))),
+        }
+        writer.flush()?;
+        if options.stateless_rpc {
+            return Ok(());
+        }
+    }
+}
+
+/// Read the rest of a v2 command: capabilities, which we have no use for, then
+/// arguments after a delimiter, up to a flush.
+fn read_arguments<R: Read>(reader: &mut R) -> Result<Vec<String>, GitError> {
+    let mut arguments = Vec::new();
+    let mut in_arguments = false;
+
+    loop {
+        match read_line(reader)? {
+            Some(PacketLine::Command(data)) if in_arguments => {
+                arguments.push(line_text(&data)?.to_string())
+            }
+            Some(PacketLine::Command(_)) => {}
+            Some(PacketLine::Delimiter) => in_arguments = true,
+            Some(PacketLine::End) => return Ok(arguments),
+            None => return Err(hung_up()),
+        }
+    }
+}
+
+/// Answer `ls-refs`: refs matching `ref-prefix` arguments, with the target of `HEAD`
+/// and peeled tags when asked for.
+fn ls_refs<W: Write>(root: &Path, writer: &mut W, arguments: &[String]) -> Result<(), GitError> {
+    let prefixes: Vec<_> = arguments
+        .iter()
+        .filter_map(|x| x.strip_prefix("ref-prefix "))
+        .map(|x| x.to_string())
+        .collect();
+    let symrefs = arguments.iter().any(|x| x == "symrefs");
+    let peel = arguments.iter().any(|x| x == "peel");
+
+    let mut lines: Vec<String> = Vec::new();
+    for info_ref in list_local_refs(root, &prefixes)? {
+        // Peeled values follow their tag.
+        if info_ref.name.ends_with("^{}") {
+            if let Some(line) = lines.last_mut().filter(|_| peel) {
+                line.push_str(&format!(" peeled:{}", info_ref.object_id));
+            }
+            continue;
+        }
+
+        let mut line = format!("{} {}", info_ref.object_id, info_ref.name);
+        if let Some(target) = info_ref.symref_target.filter(|_| symrefs) {
+            line.push_str(&format!(" symref-target:{target}"));
+        }
+        lines.push(line);
+    }
+
+    for line in lines {
+        write_line(writer, line)?;
+    }
+    PacketLine::End.write(writer)?;
+    Ok(())
+}
+
+/// Answer `fetch`: acknowledge common commits, then send the pack once the client is
+/// done or we are ready.
+fn fetch<W: Write>(
+    root: &Path,
+    store: &ObjectStore,
+    writer: &mut W,
+    arguments: &[String],
+) -> Result<(), GitError> {
+    let refs = list_local_refs(root, &[])?;
+    let mut wants = Vec::new();
+    let mut haves = Vec::new();
+    let mut done = false;
+    let mut pack_options = PackOptions {
+        side_band: Some(MAX_BAND_DATA),
+        progress: true,
+        include_tag: false,
+    };
+
+    for argument in arguments {
+        match argument.split_once(' ') {
+            Some(("want", id)) => wants.push(check_want(writer, &refs, id)?),
+            Some(("have", id)) => haves.push(hash_code_text_to_array(id)?),
+            _ => match argument.as_str() {
+                "done" => done = true,
+                "no-progress" => pack_options.progress = false,
+                "include-tag" => pack_options.include_tag = true,
+                // Objects are never sent as deltas.
+                "thin-pack" | "ofs-delta" => {}
+                _ => {
+                    return Err(refuse(
+                        writer,
+                        &format!("unexpected fetch argument '{argument}'"),
+                    ))
+                }
+            },
+        }
+    }
+
+    let mut negotiation = Negotiation::new(store, wants);
+    for have in haves {
+        negotiation.have(have);
+    }
+    if !done {
+        write_line(writer, "acknowledgments".to_string())?;
+        if negotiation.common.is_empty() {
+            write_line(writer, "NAK".to_string())?;
+        }
+        for id in &negotiation.common {
+            write_line(writer, format!("ACK {}", hex::encode(id)))?;
+        }
+        if !negotiation.is_ready()? {
+            PacketLine::End.write(writer)?;
+            return Ok(());
+        }
+        write_line(writer, "ready".to_string())?;
+        PacketLine::Delimiter.write(writer)?;
+    }
+
+    write_line(writer, "packfile".to_string())?;
+    send_pack(
+        root,
+        store,
+        writer,
+        &negotiation.wants,
+        &negotiation.common,
+        &pack_options,
+    )
+}
+
+/// Objects the client has in common with us, and whether they are enough to send a pack.
+struct Negotiation<'a> {
+    store: &'a ObjectStore,
+    wants: Vec<HashCode>,
+    common: Vec<HashCode>,
+    /// Commits reachable from each wanted commit, walked when first needed.
+    reachable: Option<Vec<HashSet<HashCode>>>,
+}
+
+impl<'a> Negotiation<'a> {
+    fn new(store: &'a ObjectStore, wants: Vec<HashCode>) -> Self {
+        Self {
+            store,
+            wants,
+            common: Vec::new(),
+            reachable: None,
+        }
+    }
+
+    /// Record a `have` line for object `id`, returning whether we have it too.
+    fn have(&mut self, id: HashCode) -> bool {
+        if !self.store.contains(id) {
+            return false;
+        }
+        if !self.common.contains(&id) {
+            self.common.push(id);
+        }
+        true
+    }
+
+    /// Whether every wanted commit has a common ancestor, so the client can stop
+    /// sending `have` lines.
+    fn is_ready(&mut self) -> Result<bool, GitError> {
+        if self.common.is_empty() {
+            return Ok(false);
+        }
+
+        if self.reachable.is_none() {
+            let mut reachable = Vec::new();
+            for want in &self.wants {
+                let target = peel_tags(self.store, *want)?;
+                // Other objects have no history to share.
+                if self.store.read_raw(target)?.0 == GitObjectHeaderType::Commit {
+                    reachable.push(ancestors(self.store, &[target], false)?);
+                }
+            }
+            self.reachable = Some(reachable);
+        }
+        Ok(self
+            .reachable
+            .iter()
+            .flatten()
+            .all(|x| self.common.iter().any(|id| x.contains(id))))
+    }
+}
+
+/// Send objects reachable from `wants` but not from `common`, as side-band packets ended
+/// by a flush if asked for.
+fn send_pack<W: Write>(
+    root: &Path,
+    store: &ObjectStore,
+    writer: &mut W,
+    wants: &[HashCode],
+    common: &[HashCode],
+    options: &PackOptions,
+) -> Result<(), GitError> {
+    let mut ids = list_objects(store, wants, common)?;
+    if options.include_tag {
+        include_tags(root, store, &mut ids)?;
+    }
+    match options.side_band {
+        Some(max_data) => {
+            // Pack is sent as it is written, in full packets.
+            let sent = {
+                let band = BandWriter::new(&mut *writer, BAND_DATA, max_data);
+                let mut band = BufWriter::with_capacity(max_data, band);
+                write_pack(store, &ids, &mut band).and_then(|_| Ok(band.flush()?))
+            };
+            if let Err(err) = sent {
+                // Part of the pack may be sent already: abort it on the error band.
+                let message = format!("upload-pack: {err}");
+                write_band(writer, BAND_ERROR, message.as_bytes(), max_data)?;
+                return Err(err);
+            }
+
+            if options.progress {
+                let message = format!(
+                    "Total {} (delta 0), reused 0 (delta 0), pack-reused 0\n",
+                    ids.len()
+                );
+                write_band(writer, BAND_PROGRESS, message.as_bytes(), max_data)?;
+            }
+            PacketLine::End.write(writer)?;
+        }
+        None => {
+            write_pack(store, &ids, &mut *writer)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Add annotated tags whose target is sent in `ids`, like `include-tag` asks.
+fn include_tags(root: &Path, store: &ObjectStore, ids: &mut Vec<HashCode>) -> Result<(), GitError> {
+    let mut sent: HashSet<_> = ids.iter().copied().collect();
+
+    for (name, id) in list_refs_at(root)? {
+        if !name.starts_with("refs/tags/") || sent.contains(&id) {
+            continue;
+        }
+        let mut chain = Vec::new();
+        let mut target = id;
+        while let GitObject::Tag { object, .. } = store.read(target)? {
+            chain.push(target);
+            target = object;
+        }
+        if !chain.is_empty() && sent.contains(&target) {
+            ids.extend(chain.into_iter().filter(|x| sent.insert(*x)));
+        }
+    }
+    Ok(())
+}
+
+/// Parse `want` line object `id`, which must be the value of an advertised ref.
+fn check_want<W: Write>(writer: &mut W, refs: &[InfoRef], id: &str) -> Result<HashCode, GitError> {
+    if !refs.iter().any(|x| x.object_id == id) {
+        return Err(refuse(writer, &format!("upload-pack: not our ref {id}")));
+    }
+    hash_code_text_to_array(id)
+}
+
+/// Read a packet line, `None` if the client hung up instead.
+pub(crate) fn read_line<R: Read>(reader: &mut R) -> Result<Option<PacketLine>, GitError> {
+    let mut first = [0; 1];
+    if reader.read(&mut first)? == 0 {
+        return Ok(None);
+    }
+    PacketLine::read(&mut (&first[..]).chain(reader)).map(Some)
+}
+
+pub(crate) fn line_text(data: &[u8]) -> Result<&str, GitError> {
+    Ok(std::str::from_utf8(data)?.trim_end_matches('\n'))
+}
+
+pub(crate) fn write_line<W: Write>(writer: &mut W, line: String) -> io::Result<()> {
+    PacketLine::Command(Bytes::from(line)).write(writer)
+}
+
+/// Tell the client why we give up with an `ERR` line, and return the matching error.
+pub(crate) fn refuse<W: Write>(writer: &mut W, message: &str) -> GitError {
+    let _ = write_line(writer, format!("ERR {message}")).and_then(|_| writer.flush());
+    GitError::InvalidContent(message.to_string())
+}
+
+pub(crate) fn hung_up() -> GitError {
+    GitError::io("The remote end hung up unexpectedly")
+}
@@ -0,0 +1,174 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    process, thread,
+};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    object::{GitObject, GitTreeItem},
+    packet_line::PacketLine,
+    store::ObjectStore,
+    HashCode,
+};
+
+/// Path in the temporary directory unique to this test, cleared of previous runs.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("git-rust-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&path);
+    path
+}
+
+/// Create an empty repository in a temporary directory unique to this test.
+pub fn temp_repo(name: &str) -> PathBuf {
+    let path = temp_dir(name);
+    fs::create_dir_all(path.join(".git/objects")).unwrap();
+    fs::create_dir_all(path.join(".git/refs/heads")).unwrap();
+    fs::write(path.join(".git/HEAD"), "ref: refs/heads/master\n").unwrap();
+    path
+}
+
+pub fn blob(store: &ObjectStore, content: &str) -> HashCode {
+    store
+        .write(&GitObject::Blob(Bytes::from(content.to_string())))
+        .unwrap()
+}
+
+/// Write nested trees from a flat `(path, mode, content)` list.
+pub fn tree(store: &ObjectStore, files: &[(&str, u32, &str)]) -> HashCode {
+    let mut dirs: BTreeMap<&str, Vec<(&str, u32, &str)>> = BTreeMap::new();
+    let mut items = Vec::new();
+
+    for (path, mode, content) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, *mode, content)),
+            None => items.push(GitTreeItem {
+                mode: *mode,
+                name: path.to_string(),
+                hash_code: blob(store, content),
+            }),
+        }
+    }
+    for (dir, files) in dirs {
+        items.push(GitTreeItem {
+            mode: 0o40000,
+            name: dir.to_string(),
+            hash_code: tree(store, &files),
+        });
+    }
+
+    items.sort_by(|a, b| a.name.cmp(&b.name));
+    store.write(&GitObject::Tree(items)).unwrap()
+}
+
+/// Author and committer of commits written by [`commit`].
+pub const SIGNATURE: &str = "Alice <alice@example.com> 1000 +0000";
+
+/// Write a commit whose tree holds `message` in a single file.
+pub fn commit(store: &ObjectStore, parents: Vec<HashCode>, message: &str) -> HashCode {
+    let tree = tree(store, &[("file", 0o100644, message)]);
+    commit_tree(store, tree, parents, SIGNATURE, message)
+}
+
+/// Like [`commit`], with `time` as author and committer date.
+pub fn commit_at(
+    store: &ObjectStore,
+    parents: Vec<HashCode>,
+    message: &str,
+    time: i64,
+) -> HashCode {
+    let tree = tree(store, &[("file", 0o100644, message)]);
+    let signature = format!("Alice <alice@example.com> {time} +0000");
+    commit_tree(store, tree, parents, &signature, message)
+}
+
+/// Write a commit of `tree` with `signature` as author and committer.
+pub fn commit_tree(
+    store: &ObjectStore,
+    tree: HashCode,
+    parents: Vec<HashCode>,
+    signature: &str,
+    message: &str,
+) -> HashCode {
+    store
+        .write(&GitObject::Commit {
+            tree,
+            parents,
+            author: Some(signature.to_string()),
+            committer: Some(signature.to_string()),
+            message: message.to_string(),
+        })
+        .unwrap()
+}
+
+/// Encode `lines` as pkt-lines, `0000` and `0001` standing for flush and delimiter packets.
+pub fn pkt_lines(lines: &[&str]) -> Vec<u8> {
+    let mut output = Vec::new();
+    for line in lines {
+        match *line {
+            "0000" => PacketLine::End.write(&mut output).unwrap(),
+            "0001" => PacketLine::Delimiter.write(&mut output).unwrap(),
+            line => PacketLine::Command(Bytes::from(line.to_string()))
+                .write(&mut output)
+                .unwrap(),
+        }
+    }
+    output
+}
+
+/// Serve HTTP on a local port from a background thread, answering every request with
+/// `handler(method, path_and_query, body)` which returns content type and body.
+///
+/// Returns the server base URL.
+pub fn serve_http<F>(handler: F) -> String
+where
+    F: Fn(&str, &str, &[u8]) -> (String, Vec<u8>) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut parts = request_line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_string();
+            let path = parts.next().unwrap_or_default().to_string();
+
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((key, value)) = header.split_once(':') {
+                    if key.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let (content_type, response) = handler(&method, &path, &body);
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                response.len()
+            )
+            .unwrap();
+            stream.write_all(&response).unwrap();
+        }
+    });
+
+    url
+}
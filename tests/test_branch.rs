@@ -0,0 +1,163 @@
+mod common;
+
+use std::fs;
+
+use git_starter_rust::{
+    branch::{
+        ahead_behind, create_branch, delete_branch, is_ancestor, is_valid_branch_name,
+        list_branches, rename_branch, set_upstream, Upstream,
+    },
+    config::GitConfig,
+    refs::{
+        delete_ref_at, head_branch_at, list_refs_at, read_ref_at, rename_ref_at, write_ref_at,
+        RefValue,
+    },
+    store::ObjectStore,
+    HashCode,
+};
+
+/// History: `c1 <- c2` on master (checked out), `c1 <- c3` on `side`.
+fn setup(name: &str) -> (std::path::PathBuf, ObjectStore, [HashCode; 3]) {
+    let root = common::temp_repo(name);
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit(&store, vec![], "c1");
+    let c2 = common::commit(&store, vec![c1], "c2");
+    let c3 = common::commit(&store, vec![c1], "c3");
+    write_ref_at(&root, "refs/heads/master", &RefValue::Direct(c2)).unwrap();
+    write_ref_at(&root, "refs/heads/side", &RefValue::Direct(c3)).unwrap();
+    (root, store, [c1, c2, c3])
+}
+
+#[test]
+fn test_refs_store() {
+    let (root, _, [c1, c2, _]) = setup("refs-store");
+    fs::write(
+        root.join(".git/packed-refs"),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/packed\n{} refs/tags/v1\n^{}\n",
+            hex::encode(c1),
+            hex::encode(c2),
+            hex::encode(c1)
+        ),
+    )
+    .unwrap();
+
+    write_ref_at(&root, "refs/heads/feature/x", &RefValue::Direct(c1)).unwrap();
+    assert!(!root.join(".git/refs/heads/feature/x.lock").exists());
+    assert!(delete_ref_at(&root, "refs/heads/feature/x").unwrap());
+    assert!(!root.join(".git/refs/heads/feature").exists());
+    assert!(root.join(".git/refs/heads").exists());
+    assert!(!delete_ref_at(&root, "refs/heads/feature/x").unwrap());
+
+    // Packed ref is removed with its peeled line, other ones are kept.
+    assert!(delete_ref_at(&root, "refs/tags/v1").unwrap());
+    assert_eq!(
+        fs::read_to_string(root.join(".git/packed-refs")).unwrap(),
+        format!(
+            "# pack-refs with: peeled fully-peeled sorted\n{} refs/heads/packed\n",
+            hex::encode(c1)
+        )
+    );
+
+    fs::create_dir_all(root.join(".git/logs/refs/heads")).unwrap();
+    fs::write(root.join(".git/logs/refs/heads/packed"), "log\n").unwrap();
+    rename_ref_at(&root, "refs/heads/packed", "refs/heads/renamed").unwrap();
+    assert_eq!(read_ref_at(&root, "refs/heads/packed").unwrap(), None);
+    assert_eq!(
+        read_ref_at(&root, "refs/heads/renamed").unwrap(),
+        Some(RefValue::Direct(c1))
+    );
+    assert_eq!(
+        fs::read_to_string(root.join(".git/logs/refs/heads/renamed")).unwrap(),
+        "log\n"
+    );
+    let names: Vec<_> = list_refs_at(&root)
+        .unwrap()
+        .into_iter()
+        .map(|(x, _)| x)
+        .collect();
+    assert_eq!(
+        names,
+        ["refs/heads/master", "refs/heads/renamed", "refs/heads/side"]
+    );
+}
+
+#[test]
+fn test_create_delete() {
+    let (root, store, [c1, c2, c3]) = setup("branch-create-delete");
+
+    create_branch(&root, "topic", c1, false).unwrap();
+    assert!(create_branch(&root, "topic", c2, false).is_err());
+    create_branch(&root, "topic", c2, true).unwrap();
+    assert!(create_branch(&root, "master", c1, true).is_err());
+    assert!(create_branch(&root, "bad..name", c1, false).is_err());
+
+    let branches = list_branches(&root).unwrap();
+    let names: Vec<_> = branches.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["master", "side", "topic"]);
+    assert!(branches[0].is_head);
+    assert_eq!(branches[2].target, c2);
+
+    // `side` is not merged in HEAD.
+    assert!(delete_branch(&root, &store, "side", false).is_err());
+    assert_eq!(delete_branch(&root, &store, "topic", false).unwrap(), c2);
+    assert!(delete_branch(&root, &store, "master", true).is_err());
+
+    // Unless it is merged in its upstream.
+    write_ref_at(&root, "refs/remotes/origin/side", &RefValue::Direct(c3)).unwrap();
+    set_upstream(&root, "side", "origin/side").unwrap();
+    assert_eq!(delete_branch(&root, &store, "side", false).unwrap(), c3);
+    assert_eq!(
+        GitConfig::read_at(&root).unwrap().get("branch.side.remote"),
+        None
+    );
+}
+
+#[test]
+fn test_rename_and_upstream() {
+    let (root, store, [c1, c2, c3]) = setup("branch-rename");
+
+    assert_eq!(
+        set_upstream(&root, "master", "side").unwrap(),
+        Upstream {
+            remote: ".".to_string(),
+            merge: "refs/heads/side".to_string(),
+        }
+    );
+    assert!(set_upstream(&root, "master", "origin/missing").is_err());
+
+    rename_branch(&root, "master", "main", false).unwrap();
+    assert_eq!(head_branch_at(&root).unwrap().as_deref(), Some("main"));
+    assert_eq!(read_ref_at(&root, "refs/heads/master").unwrap(), None);
+    let config = GitConfig::read_at(&root).unwrap();
+    assert_eq!(config.get("branch.main.remote"), Some("."));
+    assert_eq!(config.get("branch.main.merge"), Some("refs/heads/side"));
+    assert_eq!(config.get("branch.master.merge"), None);
+    let upstream = list_branches(&root).unwrap()[0].upstream.clone().unwrap();
+    assert_eq!(upstream.short_name(), "side");
+
+    assert!(rename_branch(&root, "main", "side", false).is_err());
+    rename_branch(&root, "side", "main", true).unwrap();
+    assert_eq!(
+        read_ref_at(&root, "refs/heads/main").unwrap(),
+        Some(RefValue::Direct(c3))
+    );
+    // Overwritten branch config is dropped.
+    assert_eq!(list_branches(&root).unwrap()[0].upstream, None);
+
+    let upstream = Upstream {
+        remote: "origin".to_string(),
+        merge: "refs/heads/main".to_string(),
+    };
+    assert_eq!(upstream.tracking_ref(), "refs/remotes/origin/main");
+    assert_eq!(upstream.short_name(), "origin/main");
+
+    assert!(is_ancestor(&store, c1, c3).unwrap());
+    assert!(!is_ancestor(&store, c2, c3).unwrap());
+    assert_eq!(ahead_behind(&store, c2, c3).unwrap(), (1, 1));
+    assert_eq!(ahead_behind(&store, c2, c1).unwrap(), (1, 0));
+
+    assert!(is_valid_branch_name("feature/login"));
+    assert!(!is_valid_branch_name("-b"));
+    assert!(!is_valid_branch_name("HEAD"));
+}
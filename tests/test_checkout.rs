@@ -0,0 +1,180 @@
+mod common;
+
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use git_starter_rust::{
+    checkout::{checkout, CheckoutOptions, CheckoutTarget},
+    index::Index,
+    refs::{head_branch_at, is_valid_ref_name, read_ref_at, write_ref_at, RefValue},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+fn read(root: &Path, path: &str) -> String {
+    fs::read_to_string(root.join(path)).unwrap()
+}
+
+/// Repository with `main` checked out and a `feature` branch.
+fn setup(name: &str) -> (std::path::PathBuf, ObjectStore, HashCode, HashCode) {
+    let root = common::temp_repo(name);
+    let store = ObjectStore::open(&root).unwrap();
+
+    let main = common::commit_tree(
+        &store,
+        common::tree(
+            &store,
+            &[
+                ("a.txt", 0o100644, "a"),
+                ("same.txt", 0o100644, "same"),
+                ("doc/x.md", 0o100644, "x"),
+                ("run.sh", 0o100755, "echo"),
+                ("link", 0o120000, "a.txt"),
+            ],
+        ),
+        vec![],
+        common::SIGNATURE,
+        "main",
+    );
+    let feature = common::commit_tree(
+        &store,
+        common::tree(
+            &store,
+            &[
+                ("a.txt", 0o100644, "a v2"),
+                ("same.txt", 0o100644, "same"),
+                ("doc", 0o100644, "doc file"),
+                ("run.sh", 0o100644, "echo"),
+                ("new/file.rs", 0o100644, "new"),
+            ],
+        ),
+        vec![],
+        common::SIGNATURE,
+        "feature",
+    );
+    write_ref_at(&root, "refs/heads/main", &RefValue::Direct(main)).unwrap();
+    write_ref_at(&root, "refs/heads/feature", &RefValue::Direct(feature)).unwrap();
+
+    // First checkout from an unborn branch fills an empty worktree.
+    let target = CheckoutTarget::Branch("main".to_string());
+    checkout(&root, &store, &target, &CheckoutOptions::default()).unwrap();
+    (root, store, main, feature)
+}
+
+#[test]
+fn test_switch_branches() {
+    let (root, store, _, feature) = setup("checkout-switch");
+    assert_eq!(read(&root, "doc/x.md"), "x");
+    assert_eq!(
+        fs::read_link(root.join("link")).unwrap().to_str(),
+        Some("a.txt")
+    );
+    assert_eq!(
+        fs::metadata(root.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o755
+    );
+    assert_eq!(Index::read_at(&root).unwrap().entries.len(), 5);
+
+    let target = CheckoutTarget::Branch("feature".to_string());
+    checkout(&root, &store, &target, &CheckoutOptions::default()).unwrap();
+    assert_eq!(head_branch_at(&root).unwrap().as_deref(), Some("feature"));
+    assert_eq!(read(&root, "a.txt"), "a v2");
+    assert_eq!(read(&root, "doc"), "doc file");
+    assert_eq!(read(&root, "new/file.rs"), "new");
+    assert!(!root.join("link").exists());
+    assert_eq!(
+        fs::metadata(root.join("run.sh"))
+            .unwrap()
+            .permissions()
+            .mode()
+            & 0o777,
+        0o644
+    );
+
+    let index = Index::read_at(&root).unwrap();
+    let paths: Vec<_> = index.entries.iter().map(|x| x.path.as_str()).collect();
+    assert_eq!(paths, ["a.txt", "doc", "new/file.rs", "run.sh", "same.txt"]);
+    // Stat information is recorded so files are not seen as modified.
+    assert!(index
+        .entries
+        .iter()
+        .all(|x| x.is_stat_clean(&fs::symlink_metadata(root.join(&x.path)).unwrap())));
+
+    // Back to main: directories left empty are removed.
+    let target = CheckoutTarget::Branch("main".to_string());
+    checkout(&root, &store, &target, &CheckoutOptions::default()).unwrap();
+    assert!(!root.join("new").exists());
+    assert_eq!(read(&root, "doc/x.md"), "x");
+
+    let target = CheckoutTarget::Detached(feature);
+    checkout(&root, &store, &target, &CheckoutOptions::default()).unwrap();
+    assert_eq!(head_branch_at(&root).unwrap(), None);
+    assert_eq!(
+        read_ref_at(&root, "HEAD").unwrap(),
+        Some(RefValue::Direct(feature))
+    );
+}
+
+#[test]
+fn test_local_changes() {
+    let (root, store, main, _) = setup("checkout-local-changes");
+    let to_feature = CheckoutTarget::Branch("feature".to_string());
+
+    // Changes to files identical in both branches are carried over.
+    fs::write(root.join("same.txt"), "local").unwrap();
+    checkout(&root, &store, &to_feature, &CheckoutOptions::default()).unwrap();
+    assert_eq!(read(&root, "same.txt"), "local");
+
+    let to_main = CheckoutTarget::Branch("main".to_string());
+    checkout(&root, &store, &to_main, &CheckoutOptions::default()).unwrap();
+
+    // Other ones would be lost, as would be untracked files in the way.
+    fs::write(root.join("a.txt"), "local").unwrap();
+    fs::create_dir_all(root.join("new")).unwrap();
+    fs::write(root.join("new/file.rs"), "untracked").unwrap();
+    assert_eq!(
+        checkout(&root, &store, &to_feature, &CheckoutOptions::default()),
+        Err(GitError::WouldOverwrite(vec![
+            "a.txt".to_string(),
+            "new/file.rs".to_string()
+        ]))
+    );
+    assert_eq!(head_branch_at(&root).unwrap().as_deref(), Some("main"));
+    assert_eq!(read(&root, "doc/x.md"), "x");
+
+    let options = CheckoutOptions { force: true };
+    checkout(&root, &store, &to_feature, &options).unwrap();
+    assert_eq!(read(&root, "a.txt"), "a v2");
+    assert_eq!(read(&root, "same.txt"), "same");
+    assert_eq!(read(&root, "new/file.rs"), "new");
+
+    // New branch.
+    let target = CheckoutTarget::NewBranch {
+        name: "topic".to_string(),
+        start: main,
+    };
+    checkout(&root, &store, &target, &CheckoutOptions::default()).unwrap();
+    assert_eq!(head_branch_at(&root).unwrap().as_deref(), Some("topic"));
+    assert_eq!(
+        read_ref_at(&root, "refs/heads/topic").unwrap(),
+        Some(RefValue::Direct(main))
+    );
+    assert_eq!(read(&root, "a.txt"), "a");
+    assert!(checkout(&root, &store, &target, &CheckoutOptions::default()).is_err());
+}
+
+#[test]
+fn test_ref_names() {
+    assert!(is_valid_ref_name("refs/heads/main"));
+    assert!(is_valid_ref_name("refs/heads/feature/x-1"));
+    assert!(!is_valid_ref_name("refs/heads/a..b"));
+    assert!(!is_valid_ref_name("refs/heads/.hidden"));
+    assert!(!is_valid_ref_name("refs/heads/x.lock"));
+    assert!(!is_valid_ref_name("refs/heads/a b"));
+    assert!(!is_valid_ref_name("refs/heads/a~1"));
+    assert!(!is_valid_ref_name("refs/heads/x/"));
+    assert!(!is_valid_ref_name("refs/heads/x@{1}"));
+}
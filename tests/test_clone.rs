@@ -0,0 +1,288 @@
+mod common;
+
+use std::fs;
+
+use git_starter_rust::{
+    clone::{clone, Advertisement, CloneOptions, InfoRef, ProtocolVersion},
+    config::GitConfig,
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    refs::resolve_ref_at,
+    store::ObjectStore,
+    GitError,
+};
+
+fn info_ref(name: &str, object_id: &str, symref_target: Option<&str>) -> InfoRef {
+    InfoRef {
+        name: name.to_string(),
+        object_id: object_id.to_string(),
+        capabilities: Vec::new(),
+        symref_target: symref_target.map(|x| x.to_string()),
+    }
+}
+
+#[test]
+fn test_parse_advertisement_v0() {
+    let head = "1".repeat(40);
+    let tag = "2".repeat(40);
+    let input = common::pkt_lines(&[
+        "# service=git-upload-pack",
+        "0000",
+        &format!("{head} HEAD\0multi_ack symref=HEAD:refs/heads/main"),
+        &format!("{head} refs/heads/main"),
+        &format!("{tag} refs/tags/v1"),
+        &format!("{head} refs/tags/v1^{{}}"),
+        "0000",
+    ]);
+
+    let advertisement = Advertisement::parse(&mut input.as_slice()).unwrap();
+    assert_eq!(advertisement.version, ProtocolVersion::V0);
+    assert_eq!(
+        advertisement.capabilities,
+        ["multi_ack", "symref=HEAD:refs/heads/main"]
+    );
+    assert!(advertisement.has_capability("multi_ack"));
+    assert!(advertisement.has_capability("symref"));
+    let names: Vec<_> = advertisement.refs.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["HEAD", "refs/heads/main", "refs/tags/v1", "refs/tags/v1^{}"]
+    );
+    assert_eq!(
+        advertisement.refs[0].symref_target.as_deref(),
+        Some("refs/heads/main")
+    );
+    assert_eq!(advertisement.refs[1].object_id, head);
+
+    // Version 1 is v0 with a version line.
+    let input = common::pkt_lines(&["version 1", &format!("{head} HEAD\0agent=git"), "0000"]);
+    let advertisement = Advertisement::parse(&mut input.as_slice()).unwrap();
+    assert_eq!(advertisement.version, ProtocolVersion::V0);
+    assert_eq!(advertisement.refs.len(), 1);
+}
+
+#[test]
+fn test_parse_advertisement_v2() {
+    let input = common::pkt_lines(&[
+        "version 2",
+        "agent=git/2.39.5",
+        "ls-refs=unborn",
+        "fetch=shallow wait-for-done",
+        "object-format=sha1",
+        "0000",
+    ]);
+
+    let advertisement = Advertisement::parse(&mut input.as_slice()).unwrap();
+    assert_eq!(advertisement.version, ProtocolVersion::V2);
+    assert!(advertisement.refs.is_empty());
+    assert!(advertisement.has_capability("ls-refs"));
+    assert!(advertisement.has_capability("fetch"));
+    assert!(!advertisement.has_capability("server-option"));
+}
+
+#[test]
+fn test_ls_refs() {
+    let request = InfoRef::ls_refs_request(&["refs/heads/".to_string()]).unwrap();
+    assert_eq!(
+        String::from_utf8(request).unwrap(),
+        "0014command=ls-refs\n00010009peel\n000csymrefs\n001bref-prefix refs/heads/\n0000"
+    );
+
+    let head = "1".repeat(40);
+    let tag = "2".repeat(40);
+    let input = common::pkt_lines(&[
+        &format!("{head} HEAD symref-target:refs/heads/main"),
+        &format!("{head} refs/heads/main"),
+        &format!("{tag} refs/tags/v1 peeled:{head}"),
+        "0000",
+    ]);
+    assert_eq!(
+        InfoRef::parse_ls_refs(&mut input.as_slice()).unwrap(),
+        [
+            info_ref("HEAD", &head, Some("refs/heads/main")),
+            info_ref("refs/heads/main", &head, None),
+            info_ref("refs/tags/v1", &tag, None),
+            info_ref("refs/tags/v1^{}", &head, None),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_list_for_repo_v2() {
+    let head = "1".repeat(40);
+    let url = common::serve_http(move |method, path, body| {
+        let output = if method == "GET" {
+            assert_eq!(path, "/repo.git/info/refs?service=git-upload-pack");
+            common::pkt_lines(&["version 2", "ls-refs", "fetch", "0000"])
+        } else {
+            assert_eq!(path, "/repo.git/git-upload-pack");
+            assert!(body.starts_with(b"0014command=ls-refs\n"));
+            common::pkt_lines(&[
+                &format!("{head} HEAD symref-target:refs/heads/main"),
+                "0000",
+            ])
+        };
+        (
+            "application/x-git-upload-pack-advertisement".to_string(),
+            output,
+        )
+    });
+
+    let refs = InfoRef::list_for_repo(&format!("{url}/repo.git"))
+        .await
+        .unwrap();
+    assert_eq!(
+        refs,
+        [info_ref("HEAD", &"1".repeat(40), Some("refs/heads/main"))]
+    );
+}
+
+#[tokio::test]
+async fn test_clone_branches() {
+    let src = common::temp_repo("clone-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![c1], "second");
+    let mut pack = Vec::new();
+    write_pack(
+        &store,
+        &list_objects(&store, &[c2], &[]).unwrap(),
+        &mut pack,
+    )
+    .unwrap();
+
+    let url = common::serve_http(move |method, _, body| {
+        let output = if method == "GET" {
+            common::pkt_lines(&["version 2", "ls-refs", "fetch=shallow", "0000"])
+        } else if body.starts_with(b"0014command=ls-refs\n") {
+            common::pkt_lines(&[
+                &format!("{} HEAD symref-target:refs/heads/main", hex::encode(c2)),
+                &format!("{} refs/heads/feature", hex::encode(c1)),
+                &format!("{} refs/heads/main", hex::encode(c2)),
+                &format!("{} refs/tags/v1", hex::encode(c1)),
+                "0000",
+            ])
+        } else {
+            let mut output = common::pkt_lines(&["packfile"]);
+            let packet = [&[1], pack.as_slice()].concat();
+            output.extend_from_slice(format!("{:04x}", packet.len() + 4).as_bytes());
+            output.extend_from_slice(&packet);
+            PacketLine::End.write(&mut output).unwrap();
+            output
+        };
+        ("application/x-git-upload-pack-result".to_string(), output)
+    });
+    let url = format!("{url}/repo.git");
+    let dst = common::temp_dir("clone-dst");
+
+    // The remote HEAD is checked out, with every branch as a remote-tracking one.
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    assert_eq!(
+        fs::read_to_string(dst.join(".git/HEAD")).unwrap(),
+        "ref: refs/heads/main\n"
+    );
+    assert_eq!(resolve_ref_at(&dst, "refs/heads/main").unwrap(), Some(c2));
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/remotes/origin/feature").unwrap(),
+        Some(c1)
+    );
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/remotes/origin/HEAD").unwrap(),
+        Some(c2)
+    );
+    assert_eq!(resolve_ref_at(&dst, "refs/tags/v1").unwrap(), Some(c1));
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "second");
+    let config = GitConfig::read_at(&dst).unwrap();
+    assert_eq!(config.get("remote.origin.url"), Some(url.as_str()));
+    assert_eq!(
+        config.get("remote.origin.fetch"),
+        Some("+refs/heads/*:refs/remotes/origin/*")
+    );
+    assert_eq!(config.get("branch.main.merge"), Some("refs/heads/main"));
+
+    let options = CloneOptions {
+        branch: Some("feature".to_string()),
+        single_branch: true,
+        ..Default::default()
+    };
+    fs::remove_dir_all(&dst).unwrap();
+    clone(&url, &dst, &options).await.unwrap();
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(c1));
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "first");
+    assert_eq!(
+        GitConfig::read_at(&dst).unwrap().get("remote.origin.fetch"),
+        Some("+refs/heads/feature:refs/remotes/origin/feature")
+    );
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/remotes/origin/main").unwrap(),
+        None
+    );
+
+    let options = CloneOptions {
+        branch: Some("missing".to_string()),
+        ..Default::default()
+    };
+    fs::remove_dir_all(&dst).unwrap();
+    assert!(clone(&url, &dst, &options).await.is_err());
+    assert!(!dst.exists());
+
+    // Remote branches are the local ones of a bare repository.
+    let options = CloneOptions {
+        bare: true,
+        ..Default::default()
+    };
+    clone(&url, &dst, &options).await.unwrap();
+    assert!(!dst.join(".git").exists());
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/heads/feature").unwrap(),
+        Some(c1)
+    );
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(c2));
+    let config = GitConfig::read_at(&dst).unwrap();
+    assert_eq!(config.get_bool("core.bare"), Some(true));
+    assert_eq!(config.get("remote.origin.fetch"), None);
+}
+
+#[tokio::test]
+async fn test_clone_invalid_names() {
+    let serve = |refs: Vec<String>| {
+        common::serve_http(move |method, _, _| {
+            let output = if method == "GET" {
+                common::pkt_lines(&["version 2", "ls-refs", "0000"])
+            } else {
+                let mut lines: Vec<_> = refs.iter().map(|x| x.as_str()).collect();
+                lines.push("0000");
+                common::pkt_lines(&lines)
+            };
+            ("application/x-git-upload-pack-result".to_string(), output)
+        })
+    };
+    let id = "1".repeat(40);
+    let dst = common::temp_dir("clone-invalid-dst");
+
+    let url = serve(vec![format!(
+        "{id} HEAD symref-target:refs/heads/../../../x"
+    )]);
+    assert_eq!(
+        clone(&format!("{url}/repo.git"), &dst, &CloneOptions::default()).await,
+        Err(GitError::InvalidContent(
+            "Invalid remote HEAD target 'refs/heads/../../../x'".to_string()
+        ))
+    );
+
+    let url = serve(vec![
+        format!("{id} HEAD symref-target:refs/heads/main"),
+        format!("{id} refs/heads/main"),
+        format!("{id} refs/../../x"),
+    ]);
+    let options = CloneOptions {
+        mirror: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        clone(&format!("{url}/repo.git"), &dst, &options).await,
+        Err(GitError::InvalidContent(
+            "Invalid ref name 'refs/../../x' on remote".to_string()
+        ))
+    );
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use git_starter_rust::{
+    commit::{add_paths, cleanup_message, commit, CommitOptions},
+    config::GitConfig,
+    index::Index,
+    object::{GitObject, GitTreeItem},
+    refs::{read_ref_at, resolve_ref_at, write_ref_at, RefValue},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+fn setup(name: &str) -> (std::path::PathBuf, ObjectStore) {
+    let root = common::temp_repo(name);
+    let mut config = GitConfig::default();
+    config.set("user.name", "Alice");
+    config.set("user.email", "alice@example.com");
+    config.write_at(&root).unwrap();
+    let store = ObjectStore::open(&root).unwrap();
+    (root, store)
+}
+
+fn write(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+fn add(root: &Path, store: &ObjectStore, paths: &[&str]) {
+    let paths: Vec<_> = paths.iter().map(|x| x.to_string()).collect();
+    add_paths(root, store, &paths).unwrap();
+}
+
+fn read_commit(store: &ObjectStore, hash_code: HashCode) -> (HashCode, Vec<HashCode>, String) {
+    match store.read(hash_code).unwrap() {
+        GitObject::Commit {
+            tree,
+            parents,
+            message,
+            ..
+        } => (tree, parents, message),
+        _ => panic!("Not a commit"),
+    }
+}
+
+#[test]
+fn test_write_tree() {
+    let (root, store) = setup("commit-write-tree");
+    write(&root, "a.txt", "a");
+    write(&root, "a/b", "b");
+    write(&root, "a-b", "c");
+    add(&root, &store, &["."]);
+
+    let index = Index::read_at(&root).unwrap();
+    let tree = index.write_tree(&store).unwrap();
+    let GitObject::Tree(items) = store.read(tree).unwrap() else {
+        panic!("Not a tree");
+    };
+    let names: Vec<_> = items.iter().map(|x| x.name.as_str()).collect();
+    // Git tree order: directories sort as if their name ended with `/`.
+    assert_eq!(names, ["a-b", "a.txt", "a"]);
+    assert_eq!(
+        store.read(items[2].hash_code).unwrap(),
+        GitObject::Tree(vec![GitTreeItem {
+            mode: 0o100644,
+            name: "b".to_string(),
+            hash_code: index.find("a/b", 0).unwrap().hash_code,
+        }])
+    );
+}
+
+#[test]
+fn test_commit_history() {
+    let (root, store) = setup("commit-history");
+    let options = CommitOptions::default();
+    assert!(commit(&root, &store, "empty", &options).is_err());
+
+    write(&root, "a.txt", "a");
+    write(&root, "dir/b.txt", "b");
+    add(&root, &store, &["a.txt", "dir"]);
+    let c1 = commit(&root, &store, "\n\nfirst  \n\n\nbody\n\n", &options).unwrap();
+    let (tree1, parents, message) = read_commit(&store, c1);
+    assert!(parents.is_empty());
+    assert_eq!(message, "first\n\nbody");
+    assert_eq!(
+        read_ref_at(&root, "refs/heads/master").unwrap(),
+        Some(RefValue::Direct(c1))
+    );
+
+    // Same tree as parent.
+    assert_eq!(
+        commit(&root, &store, "again", &options),
+        Err(GitError::InvalidContent("Nothing to commit".to_string()))
+    );
+    let allow_empty = CommitOptions {
+        allow_empty: true,
+        ..Default::default()
+    };
+    let c2 = commit(&root, &store, "again", &allow_empty).unwrap();
+    assert_eq!(
+        read_commit(&store, c2),
+        (tree1, vec![c1], "again".to_string())
+    );
+
+    // Removed files are dropped from the index.
+    fs::remove_file(root.join("dir/b.txt")).unwrap();
+    write(&root, "a.txt", "a v2");
+    add(&root, &store, &["."]);
+    let c3 = commit(&root, &store, "third", &options).unwrap();
+    let amend = CommitOptions {
+        amend: true,
+        ..Default::default()
+    };
+    let c4 = commit(&root, &store, "third amended", &amend).unwrap();
+    let (tree4, parents, _) = read_commit(&store, c4);
+    assert_eq!(parents, [c2]);
+    assert_eq!(tree4, read_commit(&store, c3).0);
+    let GitObject::Tree(items) = store.read(tree4).unwrap() else {
+        panic!("Not a tree");
+    };
+    assert_eq!(items.len(), 1);
+
+    let log = fs::read_to_string(root.join(".git/logs/refs/heads/master")).unwrap();
+    let messages: Vec<_> = log.lines().map(|x| x.split_once('\t').unwrap().1).collect();
+    assert_eq!(
+        messages,
+        [
+            "commit (initial): first",
+            "commit: again",
+            "commit: third",
+            "commit (amend): third amended"
+        ]
+    );
+    assert!(log.lines().last().unwrap().starts_with(&format!(
+        "{} {} Alice <alice@example.com> ",
+        hex::encode(c3),
+        hex::encode(c4)
+    )));
+    assert_eq!(
+        fs::read_to_string(root.join(".git/logs/HEAD")).unwrap(),
+        log
+    );
+
+    // Detached HEAD moves by itself.
+    write_ref_at(&root, "HEAD", &RefValue::Direct(c1)).unwrap();
+    let c5 = commit(&root, &store, "detached", &allow_empty).unwrap();
+    assert_eq!(resolve_ref_at(&root, "HEAD").unwrap(), Some(c5));
+    assert_eq!(
+        read_ref_at(&root, "refs/heads/master").unwrap(),
+        Some(RefValue::Direct(c4))
+    );
+}
+
+#[test]
+fn test_add_errors() {
+    let (root, store) = setup("commit-add-errors");
+    write(&root, ".gitignore", "*.log\n");
+    write(&root, "debug.log", "log");
+    assert!(add_paths(&root, &store, &["missing".to_string()]).is_err());
+    assert!(add_paths(&root, &store, &["debug.log".to_string()]).is_err());
+
+    add(&root, &store, &["."]);
+    let index = Index::read_at(&root).unwrap();
+    let paths: Vec<_> = index.entries.iter().map(|x| x.path.as_str()).collect();
+    assert_eq!(paths, [".gitignore"]);
+}
+
+#[test]
+fn test_cleanup_message() {
+    assert_eq!(cleanup_message("subject"), "subject");
+    assert_eq!(
+        cleanup_message("  \n\nsubject \t\n\n\n\nbody\n"),
+        "subject\n\nbody"
+    );
+    assert_eq!(cleanup_message("\n \n"), "");
+}
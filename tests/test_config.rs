@@ -0,0 +1,73 @@
+use git_starter_rust::config::GitConfig;
+
+const SAMPLE: &str = r#"
+# Some comment
+[core]
+	repositoryformatversion = 0
+	bare = false
+	logallrefupdates
+[remote "origin"]
+	url = https://github.com/arthurlm/codecrafters-git-rust ; inline comment
+	fetch = +refs/heads/*:refs/remotes/origin/*
+	fetch = +refs/tags/*:refs/tags/*
+[branch.main]
+	remote = origin
+"#;
+
+#[test]
+fn test_get() {
+    let config = GitConfig::parse(SAMPLE).unwrap();
+
+    assert_eq!(config.get("core.repositoryformatversion"), Some("0"));
+    assert_eq!(config.get("CORE.Bare"), Some("false"));
+    assert_eq!(config.get_bool("core.bare"), Some(false));
+    assert_eq!(config.get_bool("core.logallrefupdates"), Some(true));
+    assert_eq!(
+        config.get("remote.origin.url"),
+        Some("https://github.com/arthurlm/codecrafters-git-rust")
+    );
+    assert_eq!(
+        config.get_all("remote.origin.fetch"),
+        vec![
+            "+refs/heads/*:refs/remotes/origin/*",
+            "+refs/tags/*:refs/tags/*"
+        ]
+    );
+    assert_eq!(config.get("branch.main.remote"), Some("origin"));
+    assert_eq!(config.get("core.missing"), None);
+    assert_eq!(config.get("invalid"), None);
+}
+
+#[test]
+fn test_set_and_write() {
+    let mut config = GitConfig::parse(SAMPLE).unwrap();
+
+    config.set("core.bare", "true");
+    config.set("user.name", "Arthur LE MOIGNE");
+    config.add("remote.origin.fetch", "+refs/notes/*:refs/notes/*");
+    config.unset("core.logallrefupdates");
+    config.remove_section("branch", Some("main"));
+
+    assert_eq!(
+        config.to_string(),
+        "[core]\n\
+         \trepositoryformatversion = 0\n\
+         \tbare = true\n\
+         [remote \"origin\"]\n\
+         \turl = https://github.com/arthurlm/codecrafters-git-rust\n\
+         \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+         \tfetch = +refs/tags/*:refs/tags/*\n\
+         \tfetch = +refs/notes/*:refs/notes/*\n\
+         [user]\n\
+         \tname = Arthur LE MOIGNE\n"
+    );
+
+    // Output must be parsed back to the same config.
+    assert_eq!(GitConfig::parse(&config.to_string()).unwrap(), config);
+}
+
+#[test]
+fn test_invalid() {
+    assert!(GitConfig::parse("key = value").is_err());
+    assert!(GitConfig::parse("[core").is_err());
+}
@@ -0,0 +1,284 @@
+mod common;
+
+use std::fs;
+
+use git_starter_rust::{
+    diff::{
+        diff_index_to_worktree, diff_tree_to_index, diff_trees, hash_worktree_file,
+        parse_similarity, untracked_files, DiffEntry, DiffOptions, DiffStatus,
+    },
+    ignore::{glob_match, IgnoreRules},
+    index::{Index, IndexEntry},
+    store::ObjectStore,
+};
+
+fn name_status(entries: &[DiffEntry]) -> Vec<String> {
+    entries.iter().map(|x| x.name_status()).collect()
+}
+
+/// Content long enough to keep a good similarity score after small edits.
+fn lines(prefix: &str, count: usize) -> String {
+    (0..count).map(|x| format!("{prefix} line {x}\n")).collect()
+}
+
+#[test]
+fn test_diff_trees() {
+    let root = common::temp_repo("diff-trees");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let old = common::tree(
+        &store,
+        &[
+            ("README.md", 0o100644, "readme"),
+            ("run.sh", 0o100644, "echo"),
+            ("link", 0o100644, "target"),
+            ("src/lib.rs", 0o100644, "lib"),
+            ("src/main.rs", 0o100644, "main"),
+            ("doc", 0o100644, "doc file"),
+        ],
+    );
+    let new = common::tree(
+        &store,
+        &[
+            ("README.md", 0o100644, "readme"),
+            ("run.sh", 0o100755, "echo"),
+            ("link", 0o120000, "target"),
+            ("src/lib.rs", 0o100644, "lib v2"),
+            ("src/new.rs", 0o100644, "new"),
+            ("doc/index.md", 0o100644, "doc dir"),
+        ],
+    );
+
+    let options = DiffOptions::default();
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert_eq!(
+        name_status(&changes),
+        [
+            "D\tdoc",
+            "A\tdoc/index.md",
+            "T\tlink",
+            "M\trun.sh",
+            "M\tsrc/lib.rs",
+            "D\tsrc/main.rs",
+            "A\tsrc/new.rs",
+        ]
+    );
+    assert_eq!(
+        changes[3].to_string(),
+        format!(
+            ":100644 100755 {0} {0} M\trun.sh",
+            hex::encode(common::blob(&store, "echo"))
+        )
+    );
+    assert_eq!(
+        changes[5].to_string(),
+        format!(
+            ":100644 000000 {} {} D\tsrc/main.rs",
+            hex::encode(common::blob(&store, "main")),
+            "0".repeat(40)
+        )
+    );
+
+    // Without recursion, changed directories are reported as a single entry.
+    let options = DiffOptions {
+        recursive: false,
+        ..Default::default()
+    };
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert_eq!(
+        name_status(&changes),
+        ["T\tdoc", "T\tlink", "M\trun.sh", "M\tsrc"]
+    );
+
+    // Root commit.
+    let changes = diff_trees(&store, None, Some(old), &DiffOptions::default()).unwrap();
+    assert_eq!(changes.len(), 6);
+    assert!(changes.iter().all(|x| x.status == DiffStatus::Added));
+
+    assert!(
+        diff_trees(&store, Some(new), Some(new), &DiffOptions::default())
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_renames_and_copies() {
+    let root = common::temp_repo("diff-renames");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let big = lines("big", 20);
+    let other = lines("other", 20);
+    let edited = format!("{}changed\n", lines("other", 18));
+    let old = common::tree(
+        &store,
+        &[
+            ("a.txt", 0o100644, &big),
+            ("b.txt", 0o100644, &other),
+            ("c.txt", 0o100644, "unrelated"),
+            ("keep.txt", 0o100644, &lines("keep", 10)),
+        ],
+    );
+    let new = common::tree(
+        &store,
+        &[
+            ("dir/a.txt", 0o100644, &big),
+            ("renamed.txt", 0o100644, &edited),
+            ("d.txt", 0o100644, "something else"),
+            ("keep.txt", 0o100644, &lines("keep", 10)),
+            ("keep_copy.txt", 0o100644, &lines("keep", 10)),
+        ],
+    );
+
+    let changes = diff_trees(&store, Some(old), Some(new), &DiffOptions::default()).unwrap();
+    assert_eq!(changes.len(), 7);
+
+    let options = DiffOptions {
+        detect_renames: true,
+        ..Default::default()
+    };
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert_eq!(
+        name_status(&changes),
+        [
+            "D\tc.txt",
+            "A\td.txt",
+            "R100\ta.txt\tdir/a.txt",
+            "A\tkeep_copy.txt",
+            "R089\tb.txt\trenamed.txt",
+        ]
+    );
+
+    let options = DiffOptions {
+        detect_renames: true,
+        rename_threshold: 90,
+        ..Default::default()
+    };
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert!(name_status(&changes).contains(&"D\tb.txt".to_string()));
+    assert!(name_status(&changes).contains(&"A\trenamed.txt".to_string()));
+
+    // Unmodified files are only used as copy source when looking harder.
+    let options = DiffOptions {
+        detect_renames: true,
+        detect_copies: true,
+        ..Default::default()
+    };
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert!(name_status(&changes).contains(&"A\tkeep_copy.txt".to_string()));
+
+    let options = DiffOptions {
+        detect_renames: true,
+        detect_copies: true,
+        find_copies_harder: true,
+        ..Default::default()
+    };
+    let changes = diff_trees(&store, Some(old), Some(new), &options).unwrap();
+    assert!(name_status(&changes).contains(&"C100\tkeep.txt\tkeep_copy.txt".to_string()));
+}
+
+#[test]
+fn test_parse_similarity() {
+    assert_eq!(parse_similarity("90%").unwrap(), 90);
+    assert_eq!(parse_similarity("100%").unwrap(), 100);
+    assert_eq!(parse_similarity("5").unwrap(), 50);
+    assert_eq!(parse_similarity("05").unwrap(), 5);
+    assert_eq!(parse_similarity("75").unwrap(), 75);
+    assert!(parse_similarity("101%").is_err());
+    assert!(parse_similarity("abc").is_err());
+    assert!(parse_similarity("").is_err());
+}
+
+#[test]
+fn test_index_and_worktree() {
+    let root = common::temp_repo("diff-worktree");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let head = common::tree(
+        &store,
+        &[
+            ("a.txt", 0o100644, "a"),
+            ("b.txt", 0o100644, "b"),
+            ("old.txt", 0o100644, &lines("moved", 10)),
+        ],
+    );
+
+    fs::write(root.join("a.txt"), "a").unwrap();
+    fs::write(root.join("b.txt"), "b").unwrap();
+    fs::write(root.join("new.txt"), lines("moved", 10)).unwrap();
+    fs::write(root.join(".gitignore"), "*.log\n/build/\n").unwrap();
+    fs::write(root.join("debug.log"), "").unwrap();
+    fs::create_dir_all(root.join("build")).unwrap();
+    fs::write(root.join("build/out"), "").unwrap();
+    fs::create_dir_all(root.join("sub")).unwrap();
+    fs::write(root.join("sub/.gitignore"), "*.tmp\n!keep.tmp\n").unwrap();
+    fs::write(root.join("sub/x.tmp"), "").unwrap();
+    fs::write(root.join("sub/keep.tmp"), "").unwrap();
+
+    let mut index = Index::default();
+    for path in ["a.txt", "b.txt", "new.txt"] {
+        let path_on_disk = root.join(path);
+        let metadata = fs::symlink_metadata(&path_on_disk).unwrap();
+        let hash_code = hash_worktree_file(&path_on_disk).unwrap();
+        index.add(IndexEntry::from_metadata(path, &metadata, hash_code));
+    }
+    assert_eq!(
+        index.find("a.txt", 0).unwrap().hash_code,
+        common::blob(&store, "a")
+    );
+
+    // Staged changes.
+    let options = DiffOptions {
+        detect_renames: true,
+        ..Default::default()
+    };
+    let changes = diff_tree_to_index(&store, Some(head), &index, &options).unwrap();
+    assert_eq!(name_status(&changes), ["R100\told.txt\tnew.txt"]);
+
+    // Unstaged changes.
+    assert!(diff_index_to_worktree(&root, &index).unwrap().is_empty());
+    fs::write(root.join("a.txt"), "a v2").unwrap();
+    fs::remove_file(root.join("b.txt")).unwrap();
+    assert_eq!(
+        name_status(&diff_index_to_worktree(&root, &index).unwrap()),
+        ["M\ta.txt", "D\tb.txt"]
+    );
+
+    assert_eq!(
+        untracked_files(&root, &index).unwrap(),
+        [".gitignore", "sub/.gitignore", "sub/keep.tmp"]
+    );
+}
+
+#[test]
+fn test_ignore_rules() {
+    let mut rules = IgnoreRules::default();
+    rules.add_patterns(
+        "# comment\n\n*.o\n/root.txt\ndoc/*.html\nbuild/\n!important.o\n",
+        "",
+    );
+    rules.add_patterns("*.txt\n", "nested/");
+
+    assert!(rules.is_ignored("main.o", false));
+    assert!(rules.is_ignored("src/main.o", false));
+    assert!(!rules.is_ignored("important.o", false));
+    assert!(rules.is_ignored("root.txt", false));
+    assert!(!rules.is_ignored("src/root.txt", false));
+    assert!(rules.is_ignored("doc/index.html", false));
+    assert!(!rules.is_ignored("doc/api/index.html", false));
+    assert!(rules.is_ignored("build", true));
+    assert!(!rules.is_ignored("build", false));
+    assert!(rules.is_ignored("nested/a.txt", false));
+    assert!(!rules.is_ignored("a.txt", false));
+
+    assert!(glob_match(b"**/foo", b"foo"));
+    assert!(glob_match(b"**/foo", b"a/b/foo"));
+    assert!(glob_match(b"a/**/b", b"a/b"));
+    assert!(glob_match(b"a/**/b", b"a/x/y/b"));
+    assert!(glob_match(b"foo/**", b"foo/a/b"));
+    assert!(glob_match(b"f?o", b"foo"));
+    assert!(!glob_match(b"f?o", b"f/o"));
+    assert!(glob_match(b"[a-c]x", b"bx"));
+    assert!(!glob_match(b"[!a-c]x", b"bx"));
+    assert!(!glob_match(b"*.rs", b"src/main.rs"));
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use std::{fs, io::Cursor};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    download::{ResponseReader, TempPack},
+    object::GitObject,
+    pack_file::unpack_into,
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    sideband::Progress,
+    store::ObjectStore,
+};
+
+#[tokio::test]
+async fn test_download() {
+    let src = common::temp_repo("download-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let blob = store
+        .write(&GitObject::Blob(Bytes::from(vec![b'x'; 200_000])))
+        .unwrap();
+    let mut pack = Vec::new();
+    write_pack(
+        &store,
+        &list_objects(&store, &[blob], &[]).unwrap(),
+        &mut pack,
+    )
+    .unwrap();
+
+    // Pack data over several side-band packets, with a progress line split between two.
+    let mut response = Vec::new();
+    PacketLine::command(b"NAK").write(&mut response).unwrap();
+    let mut packets: Vec<Vec<u8>> = vec![b"\x02Counting ".to_vec(), b"\x02done.\n".to_vec()];
+    for chunk in pack.chunks(60_000) {
+        packets.push([&[1], chunk].concat());
+    }
+    for packet in packets {
+        response.extend_from_slice(format!("{:04x}", packet.len() + 4).as_bytes());
+        response.extend_from_slice(&packet);
+    }
+    PacketLine::End.write(&mut response).unwrap();
+
+    let mut reader = ResponseReader::from_stream(Cursor::new(response));
+    let head = reader.read_head(|_| true).await.unwrap();
+    assert_eq!(head, b"0008NAK\n");
+
+    let dst = common::temp_repo("download-dst");
+    let temp_pack = TempPack::create(&dst).unwrap();
+    let mut messages = Vec::new();
+    let mut received = 0;
+    reader
+        .receive_pack(true, temp_pack.writer(), &mut |event| match event {
+            Progress::Remote(line) => messages.push(line.to_string()),
+            Progress::Received { bytes, .. } => received = bytes,
+        })
+        .await
+        .unwrap();
+    assert_eq!(messages, ["Counting done.\n"]);
+    assert_eq!(received, pack.len() as u64);
+
+    unpack_into(temp_pack.reader().unwrap(), &dst).unwrap();
+    assert!(ObjectStore::open(&dst).unwrap().contains(blob));
+
+    // Temporary pack is removed once dropped.
+    drop(temp_pack);
+    let pack_dir = dst.join(".git/objects/pack");
+    assert_eq!(fs::read_dir(pack_dir).unwrap().count(), 0);
+}
@@ -0,0 +1,411 @@
+mod common;
+
+use std::fs;
+
+use bytes::Bytes;
+
+use git_starter_rust::{
+    config::GitConfig,
+    fetch::{
+        fetch_request, read_acks, read_fetch_response, ref_prefixes, select_refs, update_refs,
+        upload_pack_request, Ack, FetchRef, FetchResponse, Negotiator, RefUpdateStatus,
+    },
+    packet_line::PacketLine,
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    remote::{RefSpec, Remote},
+    shallow::ShallowUpdate,
+    sideband::demux,
+    store::ObjectStore,
+    GitError,
+};
+
+fn origin(refspecs: &[&str]) -> Remote {
+    Remote {
+        name: "origin".to_string(),
+        url: "https://example.com/repo.git".to_string(),
+        fetch: refspecs
+            .iter()
+            .map(|x| RefSpec::parse(x).unwrap())
+            .collect(),
+        partial_clone_filter: None,
+    }
+}
+
+#[test]
+fn test_refspec() {
+    let refspec = RefSpec::parse("+refs/heads/*:refs/remotes/origin/*").unwrap();
+    assert_eq!(refspec, RefSpec::default_fetch("origin"));
+    assert_eq!(
+        refspec.map("refs/heads/feature/x").as_deref(),
+        Some("refs/remotes/origin/feature/x")
+    );
+    assert_eq!(refspec.map("refs/tags/v1"), None);
+
+    let refspec = RefSpec::parse("main:tmp").unwrap();
+    assert!(!refspec.force);
+    assert!(refspec.matches("refs/heads/main"));
+    assert_eq!(
+        refspec.map("refs/heads/main").as_deref(),
+        Some("refs/heads/tmp")
+    );
+
+    let refspec = RefSpec::parse("v1.0").unwrap();
+    assert_eq!(refspec.dst, None);
+    assert!(refspec.matches("refs/tags/v1.0"));
+    assert_eq!(refspec.map("refs/tags/v1.0"), None);
+
+    for input in ["", "refs/heads/*:refs/x", "refs/*/a/*:refs/*"] {
+        assert_eq!(
+            RefSpec::parse(input),
+            Err(GitError::InvalidContent(format!(
+                "Invalid refspec '{input}'"
+            )))
+        );
+    }
+}
+
+#[test]
+fn test_remote_read() {
+    let config = GitConfig::parse(
+        "[remote \"origin\"]\n\
+         \turl = https://example.com/repo.git/\n\
+         \tfetch = +refs/heads/*:refs/remotes/origin/*\n\
+         \tfetch = +refs/tags/*:refs/tags/*\n",
+    )
+    .unwrap();
+
+    let remote = Remote::read(&config, "origin").unwrap().unwrap();
+    assert_eq!(remote.url, "https://example.com/repo.git");
+    assert_eq!(remote.display_url(), "https://example.com/repo");
+    assert_eq!(remote.fetch.len(), 2);
+    assert_eq!(Remote::read(&config, "upstream").unwrap(), None);
+}
+
+#[test]
+fn test_select_refs() {
+    let remote_refs = vec![
+        ("HEAD".to_string(), [1; 20]),
+        ("refs/heads/main".to_string(), [1; 20]),
+        ("refs/heads/topic".to_string(), [2; 20]),
+        ("refs/tags/v1".to_string(), [3; 20]),
+        // A malicious server trying to write outside `.git`.
+        ("refs/heads/../../../x".to_string(), [4; 20]),
+        ("refs/heads/a\\..\\x".to_string(), [4; 20]),
+    ];
+
+    let remote = origin(&["+refs/heads/*:refs/remotes/origin/*", "v1"]);
+    let selected = select_refs(&remote, &remote_refs).unwrap();
+    assert_eq!(
+        selected,
+        [
+            FetchRef {
+                remote_ref: "refs/heads/main".to_string(),
+                id: [1; 20],
+                local_ref: Some("refs/remotes/origin/main".to_string()),
+                force: true,
+            },
+            FetchRef {
+                remote_ref: "refs/heads/topic".to_string(),
+                id: [2; 20],
+                local_ref: Some("refs/remotes/origin/topic".to_string()),
+                force: true,
+            },
+            FetchRef {
+                remote_ref: "refs/tags/v1".to_string(),
+                id: [3; 20],
+                local_ref: None,
+                force: false,
+            },
+        ]
+    );
+
+    assert!(select_refs(&origin(&["missing"]), &remote_refs).is_err());
+    assert!(select_refs(&origin(&["refs/heads/../../../x"]), &remote_refs).is_err());
+}
+
+#[test]
+fn test_negotiator() {
+    let root = common::temp_repo("fetch-negotiator");
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit_at(&store, vec![], "c1", 1);
+    let c2 = common::commit_at(&store, vec![c1], "c2", 2);
+    let c3 = common::commit_at(&store, vec![c2], "c3", 5);
+    let c4 = common::commit_at(&store, vec![c1], "c4", 3);
+    let c5 = common::commit_at(&store, vec![c4], "c5", 4);
+
+    // Most recent commits first.
+    let mut negotiator = Negotiator::new(&store, &[c3, c5]).unwrap();
+    let mut haves = Vec::new();
+    while let Some(id) = negotiator.next_have().unwrap() {
+        haves.push(id);
+    }
+    assert_eq!(haves, [c3, c5, c4, c2, c1]);
+
+    // Ancestors of common commits are not sent.
+    let mut negotiator = Negotiator::new(&store, &[c3, c5]).unwrap();
+    assert_eq!(negotiator.next_have().unwrap(), Some(c3));
+    assert_eq!(negotiator.next_have().unwrap(), Some(c5));
+    assert!(negotiator.ack(c4).unwrap());
+    assert!(!negotiator.ack(c1).unwrap());
+    assert_eq!(negotiator.next_have().unwrap(), Some(c2));
+    assert_eq!(negotiator.next_have().unwrap(), None);
+}
+
+#[test]
+fn test_upload_pack_request() {
+    let want = [0xaa; 20];
+    let have = [0xbb; 20];
+
+    let request =
+        upload_pack_request(&[want], &["multi_ack_detailed"], &[], &[have], false).unwrap();
+    let expected = format!(
+        "0045want {} multi_ack_detailed\n00000032have {}\n0000",
+        hex::encode(want),
+        hex::encode(have)
+    );
+    assert_eq!(String::from_utf8(request).unwrap(), expected);
+
+    let request = upload_pack_request(&[want, have], &[], &[], &[], true).unwrap();
+    let expected = format!(
+        "0032want {}\n0032want {}\n00000009done\n",
+        hex::encode(want),
+        hex::encode(have)
+    );
+    assert_eq!(String::from_utf8(request).unwrap(), expected);
+
+    // Shallow lines follow wants.
+    let shallow_lines = ["deepen 1".to_string()];
+    let request = upload_pack_request(&[want], &["shallow"], &shallow_lines, &[], true).unwrap();
+    let expected = format!(
+        "003awant {} shallow\n000ddeepen 1\n00000009done\n",
+        hex::encode(want)
+    );
+    assert_eq!(String::from_utf8(request).unwrap(), expected);
+}
+
+#[test]
+fn test_read_acks() {
+    let id = [0xcc; 20];
+    let response = format!(
+        "0038ACK {0} common\n0037ACK {0} ready\n0008NAK\n0031ACK {0}\nPACK",
+        hex::encode(id)
+    );
+    let mut reader = response.as_bytes();
+    assert_eq!(
+        read_acks(&mut reader).unwrap(),
+        [Ack::Common(id), Ack::Ready(id), Ack::Nak]
+    );
+    assert_eq!(read_acks(&mut reader).unwrap(), [Ack::Final(id)]);
+    assert_eq!(reader, b"PACK");
+
+    let mut reader = &b"0012ERR forbidden\n"[..];
+    assert_eq!(
+        read_acks(&mut reader),
+        Err(GitError::Http("remote error: forbidden".to_string()))
+    );
+}
+
+#[test]
+fn test_ref_prefixes() {
+    let remote = origin(&["+refs/heads/*:refs/remotes/origin/*", "v1"]);
+    assert_eq!(
+        ref_prefixes(&remote),
+        [
+            "refs/heads/",
+            "refs/v1",
+            "refs/tags/v1",
+            "refs/heads/v1",
+            "refs/remotes/v1",
+            "refs/tags/"
+        ]
+    );
+    assert_eq!(ref_prefixes(&origin(&["HEAD"])), ["HEAD"]);
+}
+
+#[test]
+fn test_fetch_request() {
+    let want = [0xaa; 20];
+    let have = [0xbb; 20];
+
+    let shallow_lines = [
+        format!("shallow {}", hex::encode(have)),
+        "deepen 2".to_string(),
+    ];
+    let request = fetch_request(&[want], &shallow_lines, &[have], true).unwrap();
+    let expected = format!(
+        "0012command=fetch\n0001000ethin-pack\n000eofs-delta\n0010include-tag\n\
+         0032want {0}\n0035shallow {1}\n000ddeepen 2\n0032have {1}\n0009done\n0000",
+        hex::encode(want),
+        hex::encode(have)
+    );
+    assert_eq!(String::from_utf8(request).unwrap(), expected);
+}
+
+#[test]
+fn test_read_fetch_response() {
+    let id = [0xcc; 20];
+    let line = |output: &mut Vec<u8>, data: &[u8]| {
+        PacketLine::Command(Bytes::copy_from_slice(data))
+            .write(output)
+            .unwrap()
+    };
+
+    // Negotiation round without pack.
+    let mut input = Vec::new();
+    line(&mut input, b"acknowledgments");
+    line(&mut input, format!("ACK {}", hex::encode(id)).as_bytes());
+    PacketLine::End.write(&mut input).unwrap();
+    assert_eq!(
+        read_fetch_response(&mut input.as_slice()).unwrap(),
+        FetchResponse {
+            common: vec![id],
+            ..Default::default()
+        }
+    );
+
+    // Reading stops at the pack, left for the side-band demultiplexer.
+    let mut input = Vec::new();
+    line(&mut input, b"acknowledgments");
+    line(&mut input, b"ready");
+    PacketLine::Delimiter.write(&mut input).unwrap();
+    line(&mut input, b"shallow-info");
+    line(
+        &mut input,
+        format!("shallow {}", hex::encode(id)).as_bytes(),
+    );
+    PacketLine::Delimiter.write(&mut input).unwrap();
+    line(&mut input, b"packfile");
+    line(&mut input, b"\x01PACK");
+    PacketLine::End.write(&mut input).unwrap();
+    let mut reader = input.as_slice();
+    let response = read_fetch_response(&mut reader).unwrap();
+    assert!(response.ready);
+    assert!(response.has_pack);
+    assert_eq!(
+        response.shallow_update,
+        ShallowUpdate {
+            shallow: vec![id],
+            unshallow: Vec::new(),
+        }
+    );
+    // Packet lines end with a newline when written by `PacketLine`.
+    assert_eq!(demux(&mut reader, &mut |_| {}).unwrap(), b"PACK\n");
+
+    let mut input = Vec::new();
+    line(&mut input, b"ERR access denied");
+    assert_eq!(
+        read_fetch_response(&mut input.as_slice()),
+        Err(GitError::Http("remote error: access denied".to_string()))
+    );
+}
+
+#[test]
+fn test_update_refs() {
+    let root = common::temp_repo("fetch-update-refs");
+    let mut config = GitConfig::default();
+    config.set("user.name", "Alice");
+    config.set("user.email", "alice@example.com");
+    config.set("branch.master.remote", "origin");
+    config.set("branch.master.merge", "refs/heads/b");
+    config.write_at(&root).unwrap();
+
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit_at(&store, vec![], "c1", 1);
+    let c2 = common::commit_at(&store, vec![c1], "c2", 2);
+    let c3 = common::commit_at(&store, vec![c1], "c3", 3);
+    for (name, id) in [("a", c1), ("b", c2), ("c", c2), ("d", c1)] {
+        let name = format!("refs/remotes/origin/{name}");
+        write_ref_at(&root, &name, &RefValue::Direct(id)).unwrap();
+    }
+
+    let remote = origin(&[
+        "+refs/heads/*:refs/remotes/origin/*",
+        "refs/heads/c:refs/remotes/origin/c",
+    ]);
+    let fetched: Vec<_> = [
+        ("a", c2, false),
+        ("b", c3, true),
+        ("c", c3, false),
+        ("d", c1, false),
+        ("e", c3, false),
+    ]
+    .into_iter()
+    .map(|(name, id, force)| FetchRef {
+        remote_ref: format!("refs/heads/{name}"),
+        id,
+        local_ref: Some(format!("refs/remotes/origin/{name}")),
+        force,
+    })
+    .collect();
+
+    let updates = update_refs(&root, &store, &remote, &fetched).unwrap();
+    let statuses: Vec<_> = updates.iter().map(|x| x.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            RefUpdateStatus::FastForward,
+            RefUpdateStatus::Forced,
+            RefUpdateStatus::Rejected,
+            RefUpdateStatus::UpToDate,
+            RefUpdateStatus::New,
+        ]
+    );
+
+    let resolve = |name: &str| {
+        resolve_ref_at(&root, &format!("refs/remotes/origin/{name}"))
+            .unwrap()
+            .unwrap()
+    };
+    assert_eq!(
+        [
+            resolve("a"),
+            resolve("b"),
+            resolve("c"),
+            resolve("d"),
+            resolve("e")
+        ],
+        [c2, c3, c2, c1, c3]
+    );
+
+    let fetch_ref = FetchRef {
+        remote_ref: "refs/heads/x".to_string(),
+        id: c1,
+        local_ref: Some("refs/remotes/origin/../../../../x".to_string()),
+        force: true,
+    };
+    assert!(update_refs(&root, &store, &remote, &[fetch_ref]).is_err());
+    assert!(!root.join("x").exists());
+
+    let log = fs::read_to_string(root.join(".git/logs/refs/remotes/origin/b")).unwrap();
+    assert!(log.starts_with(&format!("{} {} Alice", hex::encode(c2), hex::encode(c3))));
+    assert!(log.ends_with("\tfetch origin: forced-update\n"));
+
+    // Upstream of current branch and refs from exact refspecs are merged.
+    let fetch_head = fs::read_to_string(root.join(".git/FETCH_HEAD")).unwrap();
+    let lines: Vec<_> = fetch_head.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            format!(
+                "{}\t\tbranch 'b' of https://example.com/repo",
+                hex::encode(c3)
+            ),
+            format!(
+                "{}\t\tbranch 'c' of https://example.com/repo",
+                hex::encode(c3)
+            ),
+            format!(
+                "{}\tnot-for-merge\tbranch 'a' of https://example.com/repo",
+                hex::encode(c2)
+            ),
+            format!(
+                "{}\tnot-for-merge\tbranch 'd' of https://example.com/repo",
+                hex::encode(c1)
+            ),
+            format!(
+                "{}\tnot-for-merge\tbranch 'e' of https://example.com/repo",
+                hex::encode(c3)
+            ),
+        ]
+    );
+}
@@ -1,12 +1,14 @@
-use std::io::Read;
+mod common;
 
-use git_starter_rust::{fs_utils::*, hash_code_text_to_array};
+use std::{fs, io::Read, os::unix::fs::PermissionsExt};
+
+use git_starter_rust::{config::GitConfig, fs_utils::*, hash_code_text_to_array};
 
 #[test]
 fn test_rw() {
-    let mut reader = read_compressed(hash_code_text_to_array(
-        "fa6eb0c05a11f886f3d5e15f7c1dc794428b9228",
-    ))
+    let mut reader = read_compressed(
+        hash_code_text_to_array("fa6eb0c05a11f886f3d5e15f7c1dc794428b9228").unwrap(),
+    )
     .unwrap();
     let mut buf = Vec::with_capacity(512);
     reader.read_to_end(&mut buf).unwrap();
@@ -21,3 +23,48 @@ fn test_rw() {
           Add tests on fs utils\n"
     );
 }
+
+#[test]
+fn test_write_atomic() {
+    let repo = common::temp_repo("fs-atomic");
+    let hash_code = hash_code_text_to_array("b172bdb8bda3a22be75a84d9c47f36fd2ead05c4").unwrap();
+
+    write_compressed_at(hash_code, b"blob 7\0world !", &repo, false).unwrap();
+
+    // Object must be readable and marked as read-only.
+    let object_path = repo.join(".git/objects/b1/72bdb8bda3a22be75a84d9c47f36fd2ead05c4");
+    let metadata = fs::metadata(&object_path).unwrap();
+    assert_eq!(metadata.permissions().mode() & 0o777, 0o444);
+
+    // Writing it again must not fail nor change it.
+    write_compressed_at(hash_code, b"garbage", &repo, true).unwrap();
+
+    let mut buf = Vec::new();
+    read_compressed_at(hash_code, &repo)
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, b"blob 7\0world !");
+
+    // No temporary file should be left behind.
+    let entries: Vec<_> = fs::read_dir(object_path.parent().unwrap())
+        .unwrap()
+        .collect();
+    assert_eq!(entries.len(), 1);
+}
+
+#[test]
+fn test_fsync_config() {
+    fn check(content: &str, expected: bool) {
+        let config = GitConfig::parse(content).unwrap();
+        assert_eq!(fsync_objects_enabled(&config), expected, "{content}");
+    }
+
+    check("", false);
+    check("[core]\n\tfsync = loose-object\n", true);
+    check("[core]\n\tfsync = index,reference\n", false);
+    check("[core]\n\tfsync = committed\n", true);
+    check("[core]\n\tfsync = derived-metadata\n", false);
+    check("[core]\n\tfsyncObjectFiles = true\n", true);
+    check("[core]\n\tfsyncObjectFiles = false\n", false);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    fs_utils::write_compressed_at,
+    fsck::{fsck_at, FsckIssue},
+    header::GitObjectHeaderType,
+    object::{GitObject, GitTreeItem},
+    store::ObjectStore,
+    HashCode,
+};
+
+fn blob(store: &ObjectStore, content: &'static [u8]) -> HashCode {
+    store
+        .write(&GitObject::Blob(Bytes::from_static(content)))
+        .unwrap()
+}
+
+fn tree(store: &ObjectStore, items: Vec<(u32, &str, HashCode)>) -> HashCode {
+    let items = items
+        .into_iter()
+        .map(|(mode, name, hash_code)| GitTreeItem {
+            mode,
+            name: name.to_string(),
+            hash_code,
+        })
+        .collect();
+    store.write(&GitObject::Tree(items)).unwrap()
+}
+
+fn set_master(root: &Path, hash_code: HashCode) {
+    fs::write(
+        root.join(".git/refs/heads/master"),
+        format!("{}\n", hex::encode(hash_code)),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_valid_repo() {
+    let root = common::temp_repo("fsck-valid");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let hello = blob(&store, b"hello");
+    let sub_tree = tree(&store, vec![(0o100644, "world.txt", hello)]);
+    let root_tree = tree(
+        &store,
+        vec![(0o100644, "hello.txt", hello), (0o40000, "sub", sub_tree)],
+    );
+    let first = common::commit_tree(&store, root_tree, vec![], common::SIGNATURE, "Test");
+    let second = common::commit_tree(&store, root_tree, vec![first], common::SIGNATURE, "Test");
+    set_master(&root, second);
+
+    assert_eq!(fsck_at(&root).unwrap(), vec![]);
+}
+
+#[test]
+fn test_missing_and_dangling() {
+    let root = common::temp_repo("fsck-missing");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let missing_blob = [0xaa; 20];
+    let root_tree = tree(&store, vec![(0o100644, "gone.txt", missing_blob)]);
+    let head = common::commit_tree(&store, root_tree, vec![], common::SIGNATURE, "Test");
+    set_master(&root, head);
+
+    let dangling = blob(&store, b"nobody points to me");
+
+    assert_eq!(
+        fsck_at(&root).unwrap(),
+        vec![
+            FsckIssue::Missing {
+                id: missing_blob,
+                r#type: Some(GitObjectHeaderType::Blob),
+            },
+            FsckIssue::Dangling {
+                id: dangling,
+                r#type: GitObjectHeaderType::Blob,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_bad_objects() {
+    let root = common::temp_repo("fsck-bad");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let hello = blob(&store, b"hello");
+    let unsorted_tree = tree(&store, vec![(0o100644, "b", hello), (0o100664, "a", hello)]);
+    let head = common::commit_tree(&store, unsorted_tree, vec![], common::SIGNATURE, "Test");
+    set_master(&root, head);
+
+    // Object stored under the wrong name.
+    let wrong_name = [0x11; 20];
+    write_compressed_at(wrong_name, b"blob 3\0foo", &root, false).unwrap();
+
+    let issues = fsck_at(&root).unwrap();
+    assert_eq!(
+        issues.iter().map(|x| x.to_string()).collect::<Vec<_>>(),
+        vec![
+            format!(
+                "hash-mismatch {} (content hashes to 19102815663d23f8b75a47e7a01965dcdc96468c)",
+                hex::encode(wrong_name)
+            ),
+            format!(
+                "bad-object tree {}: bad mode 100664 for \"a\"",
+                hex::encode(unsorted_tree)
+            ),
+            format!(
+                "bad-object tree {}: entry \"a\" is not sorted",
+                hex::encode(unsorted_tree)
+            ),
+        ]
+    );
+    assert!(issues.iter().all(|x| x.is_error()));
+}
+
+#[test]
+fn test_bad_ref() {
+    let root = common::temp_repo("fsck-bad-ref");
+    set_master(&root, [0xbb; 20]);
+
+    assert_eq!(
+        fsck_at(&root).unwrap(),
+        vec![FsckIssue::BadRef {
+            name: "HEAD".to_string(),
+            message: format!("points to missing object {}", "bb".repeat(20)),
+        }]
+    );
+}
+
+#[test]
+fn test_json() {
+    assert_eq!(
+        FsckIssue::Missing {
+            id: [0xaa; 20],
+            r#type: None
+        }
+        .to_json(),
+        format!(
+            "{{\"kind\":\"missing\",\"id\":\"{}\",\"type\":null}}",
+            "aa".repeat(20)
+        )
+    );
+    assert_eq!(
+        FsckIssue::BadRef {
+            name: "refs/heads/\"quoted\"".to_string(),
+            message: "line\nbreak".to_string()
+        }
+        .to_json(),
+        "{\"kind\":\"bad-ref\",\"name\":\"refs/heads/\\\"quoted\\\"\",\"message\":\"line\\nbreak\"}"
+    );
+}
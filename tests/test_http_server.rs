@@ -0,0 +1,188 @@
+mod common;
+
+use std::{env, fs, path::Path};
+
+use git_starter_rust::{
+    clone::{clone, CloneOptions},
+    config::GitConfig,
+    fetch::fetch,
+    http_server::serve,
+    push::{push, PushRef, PushStatus},
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    remote::Remote,
+    shallow::ShallowOptions,
+    store::ObjectStore,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+/// Serve the temporary directory on a local port, returning the URL of repository `repo`
+/// found there.
+async fn start_server(repo: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(serve(listener, env::temp_dir()));
+    let name = repo.file_name().unwrap().to_str().unwrap();
+    format!("http://{addr}/{name}")
+}
+
+fn origin(dst: &Path) -> Remote {
+    Remote::read(&GitConfig::read_at(dst).unwrap(), "origin")
+        .unwrap()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_serve_fetch() {
+    let src = common::temp_repo("serve-fetch-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+    let url = start_server(&src).await;
+
+    let dst = common::temp_dir("serve-fetch-dst");
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "first");
+
+    let c2 = common::commit(&store, vec![c1], "second");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c2)).unwrap();
+    let updates = fetch(&dst, &origin(&dst), &ShallowOptions::default(), &mut |_| {})
+        .await
+        .unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/remotes/origin/master").unwrap(),
+        Some(c2)
+    );
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{url}/info/refs?service=git-upload-pack"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(
+        response.headers()["content-type"],
+        "application/x-git-upload-pack-advertisement"
+    );
+    assert!(response
+        .bytes()
+        .await
+        .unwrap()
+        .starts_with(b"001e# service=git-upload-pack\n0000"));
+
+    let missing = url.replace("serve-fetch-src", "missing");
+    let response = client
+        .get(format!("{missing}/info/refs?service=git-upload-pack"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    let response = client
+        .get(format!("{url}/git-upload-pack"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 405);
+}
+
+#[tokio::test]
+async fn test_serve_push() {
+    let src = common::temp_repo("serve-push-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+    let url = start_server(&src).await;
+
+    let dst = common::temp_dir("serve-push-dst");
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    let c2 = common::commit(&ObjectStore::open(&dst).unwrap(), vec![c1], "second");
+    let push_ref = |remote_ref: &str| PushRef {
+        src: "master".to_string(),
+        remote_ref: remote_ref.to_string(),
+        new: Some(c2),
+        force: false,
+    };
+
+    // Pushing has to be enabled.
+    assert!(push(&dst, &origin(&dst), &[push_ref("refs/heads/topic")])
+        .await
+        .is_err());
+
+    let mut config = GitConfig::read_at(&src).unwrap();
+    config.set("http.receivepack", "true");
+    config.write_at(&src).unwrap();
+    let updates = push(
+        &dst,
+        &origin(&dst),
+        &[push_ref("refs/heads/topic"), push_ref("refs/heads/master")],
+    )
+    .await
+    .unwrap();
+    assert_eq!(updates[0].status, PushStatus::Ok);
+    assert_eq!(
+        updates[1].status,
+        PushStatus::RemoteRejected("branch is currently checked out".to_string())
+    );
+    assert_eq!(resolve_ref_at(&src, "refs/heads/topic").unwrap(), Some(c2));
+    assert_eq!(resolve_ref_at(&src, "refs/heads/master").unwrap(), Some(c1));
+}
+
+/// Send `request` as is, returning the status line of the response.
+async fn raw_request(url: &str, request: &[u8]) -> String {
+    let addr = url.trim_start_matches("http://").split('/').next().unwrap();
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request).await.unwrap();
+    // The server may close before reading everything, so the connection can end in a
+    // reset after the response.
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response).await;
+    let response = String::from_utf8_lossy(&response);
+    response.lines().next().unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn test_serve_limits() {
+    let src = common::temp_repo("serve-limits-src");
+    let url = start_server(&src).await;
+    let path = "/git-rust-serve-limits-src";
+
+    let request =
+        format!("POST {path}/git-upload-pack HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n");
+    assert_eq!(
+        raw_request(&url, request.as_bytes()).await,
+        "HTTP/1.1 413 Payload Too Large"
+    );
+
+    let request = format!(
+        "POST {path}/git-upload-pack HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+         ffffffffff\r\n"
+    );
+    assert_eq!(
+        raw_request(&url, request.as_bytes()).await,
+        "HTTP/1.1 413 Payload Too Large"
+    );
+
+    let request = format!("POST {path}/git-upload-pack HTTP/1.1\r\nContent-Length: -1\r\n\r\n");
+    assert_eq!(
+        raw_request(&url, request.as_bytes()).await,
+        "HTTP/1.1 400 Bad Request"
+    );
+
+    let request = format!(
+        "GET {path}/info/refs HTTP/1.1\r\nX-Long: {}\r\n\r\n",
+        "a".repeat(10000)
+    );
+    assert_eq!(
+        raw_request(&url, request.as_bytes()).await,
+        "HTTP/1.1 431 Request Header Fields Too Large"
+    );
+
+    let request = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10000));
+    assert_eq!(
+        raw_request(&url, request.as_bytes()).await,
+        "HTTP/1.1 414 URI Too Long"
+    );
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::fs;
+
+use git_starter_rust::{
+    index::{Index, IndexEntry},
+    GitError,
+};
+
+fn sample_index() -> Index {
+    let mut index = Index::default();
+    index.add(IndexEntry::new("src/main.rs", 0o100644, [2; 20]));
+    index.add(IndexEntry::new("README.md", 0o100644, [1; 20]));
+    index.add(IndexEntry::new("run.sh", 0o100755, [3; 20]));
+    index
+}
+
+#[test]
+fn test_add_find_remove() {
+    let mut index = sample_index();
+    let paths: Vec<_> = index.entries.iter().map(|x| x.path.as_str()).collect();
+    assert_eq!(paths, ["README.md", "run.sh", "src/main.rs"]);
+
+    index.add(IndexEntry::new("run.sh", 0o100644, [4; 20]));
+    assert_eq!(index.entries.len(), 3);
+    assert_eq!(index.find("run.sh", 0).unwrap().hash_code, [4; 20]);
+    assert!(index.find("run.sh", 2).is_none());
+    assert!(!index.has_conflicts());
+
+    let mut conflict = IndexEntry::new("run.sh", 0o100644, [5; 20]);
+    conflict.stage = 2;
+    index.add(conflict);
+    assert!(index.has_conflicts());
+    assert_eq!(index.find("run.sh", 2).unwrap().hash_code, [5; 20]);
+
+    index.remove("run.sh");
+    assert!(index.find("run.sh", 0).is_none());
+    assert!(index.find("run.sh", 2).is_none());
+    assert_eq!(index.entries.len(), 2);
+}
+
+#[test]
+fn test_roundtrip() {
+    let mut index = sample_index();
+    index.entries[0].stage = 1;
+    let data = index.to_bytes();
+
+    assert_eq!(&data[..12], b"DIRC\x00\x00\x00\x02\x00\x00\x00\x03");
+    // Each entry is padded to a multiple of 8 bytes.
+    assert_eq!((data.len() - 12 - 20) % 8, 0);
+    assert_eq!(Index::parse(&data).unwrap(), index);
+}
+
+#[test]
+fn test_read_write() {
+    let root = common::temp_repo("index-read-write");
+    assert_eq!(Index::read_at(&root).unwrap(), Index::default());
+
+    let index = sample_index();
+    index.write_at(&root).unwrap();
+    assert_eq!(Index::read_at(&root).unwrap(), index);
+    assert!(!root.join(".git/index.lock").exists());
+
+    // An existing lock file means someone else is writing the index.
+    fs::write(root.join(".git/index.lock"), "").unwrap();
+    assert!(matches!(index.write_at(&root), Err(GitError::Io(_))));
+}
+
+#[test]
+fn test_invalid() {
+    let mut data = sample_index().to_bytes();
+    assert_eq!(
+        Index::parse(&data[..40]),
+        Err(GitError::invalid_content("Index checksum mismatch"))
+    );
+
+    data[20] ^= 0xff;
+    assert_eq!(
+        Index::parse(&data),
+        Err(GitError::invalid_content("Index checksum mismatch"))
+    );
+    assert_eq!(
+        Index::parse(b"DIRC"),
+        Err(GitError::invalid_content("Index file too small"))
+    );
+}
@@ -0,0 +1,291 @@
+mod common;
+
+use std::fs;
+
+use git_starter_rust::{
+    log::{format_commit, log, CommitInfo, LogOptions, RevisionRange},
+    revision::rev_parse,
+    signature::{format_date, parse_date, Signature},
+    store::ObjectStore,
+    GitError, HashCode,
+};
+
+struct History {
+    c1: HashCode,
+    c2: HashCode,
+    c3: HashCode,
+    merge: HashCode,
+}
+
+/// Build following history, `side` branch being authored by Bob:
+///
+/// ```text
+/// c1 -- c2 ------ merge (master)
+///   \            /
+///    c3 (side) -
+/// ```
+fn build_history(name: &str) -> (ObjectStore, History) {
+    let root = common::temp_repo(name);
+    let store = ObjectStore::open(&root).unwrap();
+
+    let t1 = common::tree(&store, &[("a.txt", 0o100644, "1")]);
+    let t2 = common::tree(
+        &store,
+        &[("a.txt", 0o100644, "1"), ("b.txt", 0o100644, "1")],
+    );
+    let t3 = common::tree(&store, &[("a.txt", 0o100644, "2")]);
+    let t4 = common::tree(
+        &store,
+        &[("a.txt", 0o100644, "2"), ("b.txt", 0o100644, "1")],
+    );
+
+    let alice = |time| format!("Alice <alice@example.com> {time} +0100");
+    let bob = "Bob <bob@example.com> 1500 +0100";
+    let c1 = common::commit_tree(&store, t1, vec![], &alice(1000), "Initial commit");
+    let c2 = common::commit_tree(&store, t2, vec![c1], &alice(2000), "Add b\n\nWith a body");
+    let c3 = common::commit_tree(&store, t3, vec![c1], bob, "Update a");
+    let merge = common::commit_tree(&store, t4, vec![c2, c3], &alice(3000), "Merge side");
+
+    fs::write(
+        root.join(".git/refs/heads/master"),
+        format!("{}\n", hex::encode(merge)),
+    )
+    .unwrap();
+    fs::write(
+        root.join(".git/refs/heads/side"),
+        format!("{}\n", hex::encode(c3)),
+    )
+    .unwrap();
+
+    (store, History { c1, c2, c3, merge })
+}
+
+fn run_log(store: &ObjectStore, revisions: &[&str], options: &LogOptions) -> Vec<HashCode> {
+    let revisions: Vec<_> = revisions.iter().map(|x| x.to_string()).collect();
+    let range = RevisionRange::parse(store, &revisions).unwrap();
+    log(store, &range, options)
+        .unwrap()
+        .into_iter()
+        .map(|x| x.id)
+        .collect()
+}
+
+#[test]
+fn test_signature() {
+    let signature =
+        Signature::parse("Arthur LE MOIGNE <arthur.lemoigne@gmail.com> 1703674545 +0100").unwrap();
+    assert_eq!(signature.name, "Arthur LE MOIGNE");
+    assert_eq!(signature.email, "arthur.lemoigne@gmail.com");
+    assert_eq!(signature.time, 1703674545);
+    assert_eq!(signature.tz_offset, 60);
+    assert_eq!(signature.format_date(), "Wed Dec 27 11:55:45 2023 +0100");
+    assert_eq!(
+        signature.to_string(),
+        "Arthur LE MOIGNE <arthur.lemoigne@gmail.com> 1703674545 +0100"
+    );
+
+    assert!(Signature::parse("Missing email 1703674545 +0100").is_err());
+    assert!(Signature::parse("Name <email> 1703674545").is_err());
+}
+
+#[test]
+fn test_dates() {
+    assert_eq!(format_date(0, 0), "Thu Jan 1 00:00:00 1970 +0000");
+    assert_eq!(
+        format_date(951782400, -330),
+        "Mon Feb 28 18:30:00 2000 -0530"
+    );
+
+    assert_eq!(parse_date("1703674545").unwrap(), 1703674545);
+    assert_eq!(parse_date("@1703674545 +0100").unwrap(), 1703674545);
+    assert_eq!(parse_date("2000-02-29").unwrap(), 951782400);
+    assert_eq!(
+        parse_date("2000-02-29 06:00:30").unwrap(),
+        951782400 + 21630
+    );
+    assert_eq!(
+        parse_date("2000-02-29T06:00:30").unwrap(),
+        951782400 + 21630
+    );
+    assert!(parse_date("2 days ago").unwrap() < parse_date("1 day ago").unwrap());
+
+    assert!(parse_date("2000-13-01").is_err());
+    assert!(parse_date("yesterday-ish").is_err());
+}
+
+#[test]
+fn test_rev_parse() {
+    let (store, history) = build_history("log-rev-parse");
+
+    assert_eq!(rev_parse(&store, "HEAD").unwrap(), history.merge);
+    assert_eq!(rev_parse(&store, "@").unwrap(), history.merge);
+    assert_eq!(rev_parse(&store, "master").unwrap(), history.merge);
+    assert_eq!(rev_parse(&store, "refs/heads/side").unwrap(), history.c3);
+    assert_eq!(rev_parse(&store, "HEAD^").unwrap(), history.c2);
+    assert_eq!(rev_parse(&store, "HEAD^2").unwrap(), history.c3);
+    assert_eq!(rev_parse(&store, "HEAD~2").unwrap(), history.c1);
+    assert_eq!(rev_parse(&store, "master^2~1").unwrap(), history.c1);
+    assert_eq!(rev_parse(&store, "HEAD^0").unwrap(), history.merge);
+
+    let short = hex::encode(history.c2)[..8].to_string();
+    assert_eq!(rev_parse(&store, &short).unwrap(), history.c2);
+
+    let tree = CommitInfo::read(&store, history.c3).unwrap().tree;
+    assert_eq!(rev_parse(&store, "side^{tree}").unwrap(), tree);
+
+    assert_eq!(
+        rev_parse(&store, "HEAD^3"),
+        Err(GitError::UnknownRevision("HEAD^3".to_string()))
+    );
+    assert_eq!(
+        rev_parse(&store, "unknown"),
+        Err(GitError::UnknownRevision("unknown".to_string()))
+    );
+    assert!(rev_parse(&store, "../../HEAD").is_err());
+}
+
+#[test]
+fn test_log_order() {
+    let (store, history) = build_history("log-order");
+
+    assert_eq!(
+        run_log(&store, &[], &LogOptions::default()),
+        vec![history.merge, history.c2, history.c3, history.c1]
+    );
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                max_count: Some(2),
+                ..Default::default()
+            }
+        ),
+        vec![history.merge, history.c2]
+    );
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                first_parent: true,
+                ..Default::default()
+            }
+        ),
+        vec![history.merge, history.c2, history.c1]
+    );
+}
+
+#[test]
+fn test_log_ranges() {
+    let (store, history) = build_history("log-ranges");
+    let options = LogOptions::default();
+
+    let c2 = hex::encode(history.c2);
+    assert_eq!(
+        run_log(&store, &[&format!("{c2}..master")], &options),
+        vec![history.merge, history.c3]
+    );
+    assert_eq!(
+        run_log(&store, &["master", "^side"], &options),
+        vec![history.merge, history.c2]
+    );
+    assert_eq!(
+        run_log(&store, &["side..HEAD"], &options),
+        vec![history.merge, history.c2]
+    );
+    assert_eq!(
+        run_log(&store, &[&format!("{c2}...side")], &options),
+        vec![history.c2, history.c3]
+    );
+}
+
+#[test]
+fn test_log_filters() {
+    let (store, history) = build_history("log-filters");
+
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                author: Some("Bob".to_string()),
+                ..Default::default()
+            }
+        ),
+        vec![history.c3]
+    );
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                since: Some(1500),
+                until: Some(2000),
+                ..Default::default()
+            }
+        ),
+        vec![history.c2, history.c3]
+    );
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                paths: vec!["a.txt".to_string()],
+                ..Default::default()
+            }
+        ),
+        vec![history.c3, history.c1]
+    );
+    assert_eq!(
+        run_log(
+            &store,
+            &[],
+            &LogOptions {
+                paths: vec!["b.txt".to_string()],
+                ..Default::default()
+            }
+        ),
+        vec![history.c2]
+    );
+}
+
+#[test]
+fn test_format_commit() {
+    let (store, history) = build_history("log-format");
+    let merge = CommitInfo::read(&store, history.merge).unwrap();
+    let c2 = CommitInfo::read(&store, history.c2).unwrap();
+
+    let merge_id = hex::encode(history.merge);
+    let c2_id = hex::encode(history.c2);
+    let c3_id = hex::encode(history.c3);
+
+    assert_eq!(
+        format_commit(&merge, "oneline"),
+        format!("{} Merge side", &merge_id[..7])
+    );
+    assert_eq!(
+        format_commit(&merge, "medium"),
+        format!(
+            "commit {merge_id}\n\
+            Merge: {} {}\n\
+            Author: Alice <alice@example.com>\n\
+            Date:   Thu Jan 1 01:50:00 1970 +0100\n\
+            \n    Merge side\n",
+            &c2_id[..7],
+            &c3_id[..7]
+        )
+    );
+    assert_eq!(
+        format_commit(&c2, "format:%h|%an|%ae|%at|%s|%b|%%|%x"),
+        format!(
+            "{}|Alice|alice@example.com|2000|Add b|With a body|%|%x",
+            &c2_id[..7]
+        )
+    );
+    assert_eq!(
+        format_commit(&merge, "tformat:%P%n%T"),
+        format!("{c2_id} {c3_id}\n{}", hex::encode(merge.tree))
+    );
+}
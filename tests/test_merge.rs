@@ -0,0 +1,347 @@
+mod common;
+
+use std::{fs, path::Path};
+
+use git_starter_rust::{
+    checkout::{checkout, CheckoutOptions, CheckoutTarget},
+    commit::{add_paths, commit, read_merge_heads, CommitOptions},
+    config::GitConfig,
+    index::Index,
+    log::CommitInfo,
+    merge::{
+        abort_merge, merge, merge_bases, merge_content, merge_message, ConflictKind, MergeOptions,
+        MergeOutcome,
+    },
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    store::ObjectStore,
+    HashCode,
+};
+
+fn setup(name: &str) -> (std::path::PathBuf, ObjectStore) {
+    let root = common::temp_repo(name);
+    let mut config = GitConfig::default();
+    config.set("user.name", "Alice");
+    config.set("user.email", "alice@example.com");
+    config.write_at(&root).unwrap();
+    let store = ObjectStore::open(&root).unwrap();
+    (root, store)
+}
+
+/// Write files, `None` content deleting them, then commit everything.
+fn commit_files(
+    root: &Path,
+    store: &ObjectStore,
+    files: &[(&str, Option<&str>)],
+    message: &str,
+) -> HashCode {
+    for (path, content) in files {
+        let full_path = root.join(path);
+        match content {
+            Some(content) => {
+                fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+                fs::write(full_path, content).unwrap();
+            }
+            None => fs::remove_file(full_path).unwrap(),
+        }
+    }
+    add_paths(root, store, &[".".to_string()]).unwrap();
+    commit(root, store, message, &CommitOptions::default()).unwrap()
+}
+
+fn switch(root: &Path, store: &ObjectStore, branch: &str) {
+    let target = CheckoutTarget::Branch(branch.to_string());
+    checkout(root, store, &target, &CheckoutOptions::default()).unwrap();
+}
+
+#[test]
+fn test_merge_content() {
+    let labels = ["HEAD", "feature"];
+    let base = b"1\n2\n3\n4\n5\n6\n7\n";
+
+    // Changes far enough from each other.
+    let merged = merge_content(
+        base,
+        b"one\n2\n3\n4\n5\n6\n7\n",
+        b"1\n2\n3\n4\n5\n6\nseven\n",
+        labels,
+    );
+    assert_eq!(merged.conflicts, 0);
+    assert_eq!(merged.content, b"one\n2\n3\n4\n5\n6\nseven\n");
+
+    // Identical changes on both sides.
+    let same = b"1\n2\nthree\n4\n5\n6\n7\n";
+    let merged = merge_content(base, same, same, labels);
+    assert_eq!(
+        (merged.content.as_slice(), merged.conflicts),
+        (&same[..], 0)
+    );
+
+    // Adjacent changes conflict, common lines are kept out of conflict.
+    let merged = merge_content(
+        base,
+        b"1\nx\nsame\nours\n4\n5\n6\n7\n",
+        b"1\nx\nsame\ntheirs\n4\n5\n6\n7",
+        labels,
+    );
+    assert_eq!(merged.conflicts, 1);
+    assert_eq!(
+        String::from_utf8(merged.content).unwrap(),
+        "1\nx\nsame\n<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n4\n5\n6\n7"
+    );
+
+    // Add / add with an empty base, last lines lacking line feed.
+    let merged = merge_content(b"", b"a", b"b", labels);
+    assert_eq!(
+        String::from_utf8(merged.content).unwrap(),
+        "<<<<<<< HEAD\na\n=======\nb\n>>>>>>> feature\n"
+    );
+}
+
+#[test]
+fn test_merge_bases() {
+    let (root, store) = setup("merge-bases");
+    let a = commit_files(&root, &store, &[("f", Some("a"))], "A");
+    let b1 = commit_files(&root, &store, &[("f", Some("b1"))], "B1");
+    write_ref_at(&root, "HEAD", &RefValue::Direct(a)).unwrap();
+    let c1 = commit_files(&root, &store, &[("f", Some("c1"))], "C1");
+    let allow_empty = CommitOptions {
+        allow_empty: true,
+        ..Default::default()
+    };
+
+    assert_eq!(merge_bases(&store, b1, c1).unwrap(), [a]);
+    assert_eq!(merge_bases(&store, a, c1).unwrap(), [a]);
+    assert_eq!(merge_bases(&store, c1, c1).unwrap(), [c1]);
+
+    // Criss-cross: each side merges the other one's first commit.
+    let merge_commit = |parents: [HashCode; 2]| {
+        write_ref_at(&root, "HEAD", &RefValue::Direct(parents[0])).unwrap();
+        fs::write(
+            root.join(".git/MERGE_HEAD"),
+            format!("{}\n", hex::encode(parents[1])),
+        )
+        .unwrap();
+        commit(&root, &store, "merge", &allow_empty).unwrap()
+    };
+    let b2 = merge_commit([b1, c1]);
+    let c2 = merge_commit([c1, b1]);
+    assert_eq!(CommitInfo::read(&store, b2).unwrap().parents, [b1, c1]);
+
+    let mut bases = merge_bases(&store, b2, c2).unwrap();
+    bases.sort();
+    let mut expected = [b1, c1];
+    expected.sort();
+    assert_eq!(bases, expected);
+}
+
+#[test]
+fn test_fast_forward_and_merge_commit() {
+    let (root, store) = setup("merge-ff");
+    let base = commit_files(&root, &store, &[("f.txt", Some("1\n2\n3\n4\n5\n"))], "base");
+    write_ref_at(&root, "refs/heads/feature", &RefValue::Direct(base)).unwrap();
+    switch(&root, &store, "feature");
+    let feature = commit_files(
+        &root,
+        &store,
+        &[
+            ("f.txt", Some("one\n2\n3\n4\n5\n")),
+            ("new.txt", Some("new")),
+        ],
+        "feature",
+    );
+    switch(&root, &store, "master");
+
+    let options = MergeOptions::default();
+    assert_eq!(
+        merge(&root, &store, feature, "feature", &options).unwrap(),
+        MergeOutcome::FastForward {
+            old: Some(base),
+            new: feature
+        }
+    );
+    assert_eq!(resolve_ref_at(&root, "HEAD").unwrap(), Some(feature));
+    assert_eq!(resolve_ref_at(&root, "ORIG_HEAD").unwrap(), Some(base));
+    assert_eq!(fs::read_to_string(root.join("new.txt")).unwrap(), "new");
+    assert_eq!(
+        merge(&root, &store, base, "base", &options).unwrap(),
+        MergeOutcome::UpToDate
+    );
+
+    // Diverged histories are merged line by line.
+    let main = commit_files(
+        &root,
+        &store,
+        &[("f.txt", Some("one\n2\n3\n4\nfive\n"))],
+        "main",
+    );
+    write_ref_at(&root, "refs/heads/topic", &RefValue::Direct(feature)).unwrap();
+    switch(&root, &store, "topic");
+    let side = commit_files(
+        &root,
+        &store,
+        &[("f.txt", Some("one\nTWO\n3\n4\n5\n")), ("g.txt", Some("g"))],
+        "side",
+    );
+
+    let MergeOutcome::Merged {
+        commit,
+        merge: result,
+    } = merge(&root, &store, main, "master", &options).unwrap()
+    else {
+        panic!("Merge commit expected");
+    };
+    assert!(result.conflicts.is_empty());
+    assert_eq!(result.merged_paths, ["f.txt"]);
+    let info = CommitInfo::read(&store, commit).unwrap();
+    assert_eq!(info.parents, [side, main]);
+    assert_eq!(info.message, "Merge branch 'master' into topic");
+    assert_eq!(
+        fs::read_to_string(root.join("f.txt")).unwrap(),
+        "one\nTWO\n3\n4\nfive\n"
+    );
+    assert_eq!(fs::read_to_string(root.join("g.txt")).unwrap(), "g");
+
+    // `--no-ff` creates a merge commit anyway.
+    let options = MergeOptions {
+        no_ff: true,
+        message: Some("Custom".to_string()),
+    };
+    write_ref_at(&root, "refs/heads/other", &RefValue::Direct(commit)).unwrap();
+    switch(&root, &store, "other");
+    let tip = commit_files(&root, &store, &[("h.txt", Some("h"))], "tip");
+    switch(&root, &store, "topic");
+    let MergeOutcome::Merged { commit: no_ff, .. } =
+        merge(&root, &store, tip, "other", &options).unwrap()
+    else {
+        panic!("Merge commit expected");
+    };
+    let info = CommitInfo::read(&store, no_ff).unwrap();
+    assert_eq!(info.parents, [commit, tip]);
+    assert_eq!(info.message, "Custom");
+    assert_eq!(
+        merge_message(&root, "v1.0").unwrap(),
+        "Merge commit 'v1.0' into topic"
+    );
+}
+
+#[test]
+fn test_conflicts() {
+    let (root, store) = setup("merge-conflicts");
+    let base = commit_files(
+        &root,
+        &store,
+        &[
+            ("f.txt", Some("1\n2\n3\n")),
+            ("del.txt", Some("keep\n")),
+            ("same.txt", Some("same\n")),
+        ],
+        "base",
+    );
+    write_ref_at(&root, "refs/heads/feature", &RefValue::Direct(base)).unwrap();
+    switch(&root, &store, "feature");
+    let feature = commit_files(
+        &root,
+        &store,
+        &[
+            ("f.txt", Some("1\ntwo\n3\n")),
+            ("del.txt", None),
+            ("dd", Some("file")),
+        ],
+        "feature",
+    );
+    switch(&root, &store, "master");
+    let main = commit_files(
+        &root,
+        &store,
+        &[
+            ("f.txt", Some("1\nTWO\n3\n")),
+            ("del.txt", Some("keep\nmore\n")),
+            ("dd/x", Some("x")),
+        ],
+        "main",
+    );
+
+    // Local changes to paths untouched by the merge are fine.
+    fs::write(root.join("same.txt"), "local\n").unwrap();
+    let MergeOutcome::Conflicts(result) =
+        merge(&root, &store, feature, "feature", &MergeOptions::default()).unwrap()
+    else {
+        panic!("Conflicts expected");
+    };
+    let kinds: Vec<_> = result
+        .conflicts
+        .iter()
+        .map(|x| (x.path.as_str(), x.kind.clone()))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            ("dd~feature", ConflictKind::FileDirectory("dd".to_string())),
+            ("del.txt", ConflictKind::ModifyDelete),
+            ("f.txt", ConflictKind::Content),
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(root.join("f.txt")).unwrap(),
+        "1\n<<<<<<< HEAD\nTWO\n=======\ntwo\n>>>>>>> feature\n3\n"
+    );
+    assert_eq!(fs::read_to_string(root.join("dd~feature")).unwrap(), "file");
+    assert_eq!(fs::read_to_string(root.join("dd/x")).unwrap(), "x");
+
+    let index = Index::read_at(&root).unwrap();
+    let stages = |path: &str| -> Vec<u8> {
+        index
+            .entries
+            .iter()
+            .filter(|x| x.path == path)
+            .map(|x| x.stage)
+            .collect()
+    };
+    assert_eq!(stages("f.txt"), [1, 2, 3]);
+    assert_eq!(stages("del.txt"), [1, 2]);
+    assert_eq!(stages("dd~feature"), [3]);
+    assert_eq!(stages("dd/x"), [0]);
+    assert_eq!(read_merge_heads(&root).unwrap(), [feature]);
+    assert!(fs::read_to_string(root.join(".git/MERGE_MSG"))
+        .unwrap()
+        .starts_with("Merge branch 'feature'\n\n# Conflicts:\n#\tdd~feature\n"));
+    assert!(merge(&root, &store, feature, "feature", &MergeOptions::default()).is_err());
+
+    // Abort restores HEAD content but keeps other local changes.
+    abort_merge(&root, &store).unwrap();
+    assert!(read_merge_heads(&root).unwrap().is_empty());
+    assert!(!Index::read_at(&root).unwrap().has_conflicts());
+    assert_eq!(
+        fs::read_to_string(root.join("f.txt")).unwrap(),
+        "1\nTWO\n3\n"
+    );
+    assert!(!root.join("dd~feature").exists());
+    assert_eq!(
+        fs::read_to_string(root.join("same.txt")).unwrap(),
+        "local\n"
+    );
+    assert!(abort_merge(&root, &store).is_err());
+
+    // Resolving conflicts then committing concludes the merge.
+    merge(&root, &store, feature, "feature", &MergeOptions::default()).unwrap();
+    assert!(commit(&root, &store, "merge", &CommitOptions::default()).is_err());
+    fs::write(root.join("f.txt"), "1\n2\n3\n").unwrap();
+    fs::remove_file(root.join("dd~feature")).unwrap();
+    add_paths(
+        &root,
+        &store,
+        &[
+            "f.txt".to_string(),
+            "del.txt".to_string(),
+            "dd~feature".to_string(),
+        ],
+    )
+    .unwrap();
+    let merged = commit(&root, &store, "Resolved", &CommitOptions::default()).unwrap();
+    assert_eq!(
+        CommitInfo::read(&store, merged).unwrap().parents,
+        [main, feature]
+    );
+    assert!(read_merge_heads(&root).unwrap().is_empty());
+    assert!(!root.join(".git/MERGE_MSG").exists());
+}
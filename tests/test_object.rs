@@ -172,10 +172,8 @@ fn build_expected_simple_tree() -> GitObject {
 
 fn build_expected_simple_commit() -> GitObject {
     GitObject::Commit {
-        tree: hash_code_text_to_array("e45ecd9e9fe4fcf69a6b35533afe57913090ce97"),
-        parent: Some(hash_code_text_to_array(
-            "74cc4ab80371ac64c33928d8c632e38de70a184f",
-        )),
+        tree: hash_code_text_to_array("e45ecd9e9fe4fcf69a6b35533afe57913090ce97").unwrap(),
+        parents: vec![hash_code_text_to_array("74cc4ab80371ac64c33928d8c632e38de70a184f").unwrap()],
         author: Some("Arthur LE MOIGNE <arthur.lemoigne@gmail.com> 1703674545 +0100".to_string()),
         committer: Some(
             "Arthur LE MOIGNE <arthur.lemoigne@gmail.com> 1703675206 +0100".to_string(),
@@ -183,3 +181,81 @@ fn build_expected_simple_commit() -> GitObject {
         message: "Add write-tree".to_string(),
     }
 }
+
+#[test]
+fn test_read_invalid_tree() {
+    check_err_eq(
+        b"tree 14\x00100644 file\0ab",
+        GitError::InvalidObjectPayload("Truncated tree item hash"),
+    );
+    check_err_eq(
+        b"tree 8\x00100644 a",
+        GitError::InvalidObjectPayload("Missing end byte"),
+    );
+    check_err_eq(
+        b"tree 10\x00100644\0abc",
+        GitError::InvalidObjectPayload("Missing tree item name"),
+    );
+}
+
+#[test]
+fn test_read_invalid_commit() {
+    check_err_eq(
+        b"commit 9\0tree abc\n",
+        GitError::InvalidHashCode("abc".to_string()),
+    );
+    check_err_eq(
+        b"commit 46\0tree 89ad93569b2e3de0e8b6ce13f4b9bf9bfea055e6\n",
+        GitError::InvalidObjectPayload("Missing commit message separator"),
+    );
+}
+
+#[test]
+fn test_read_tree_name_with_space() {
+    let mut data = b"tree 43\x00100644 hello world.txt\0".to_vec();
+    data.extend_from_slice(&[7; 20]);
+
+    check_read_eq(
+        &data,
+        GitObject::Tree(vec![GitTreeItem {
+            mode: 0o100644,
+            name: "hello world.txt".to_string(),
+            hash_code: [7; 20],
+        }]),
+    );
+}
+
+#[test]
+fn test_tree_item_validate_name() {
+    fn check(name: &str, is_valid: bool) {
+        let item = GitTreeItem {
+            mode: 0o100644,
+            name: name.to_string(),
+            hash_code: [0; 20],
+        };
+        assert_eq!(item.validate_name().is_ok(), is_valid, "{name}");
+    }
+
+    check("README.md", true);
+    check(".gitignore", true);
+    check("", false);
+    check(".", false);
+    check("..", false);
+    check(".git", false);
+    check(".GIT", false);
+    check("../escape", false);
+    check("/etc/passwd", false);
+}
+
+#[test]
+fn test_hash_code_text_to_array() {
+    assert_eq!(hash_code_text_to_array(&"01".repeat(20)).unwrap(), [1; 20]);
+    assert_eq!(
+        hash_code_text_to_array("zz"),
+        Err(GitError::InvalidHashCode("zz".to_string()))
+    );
+    assert_eq!(
+        hash_code_text_to_array("0102"),
+        Err(GitError::InvalidHashCode("0102".to_string()))
+    );
+}
@@ -0,0 +1,100 @@
+//! Property tests: any generated object must be read back identically once written.
+
+use bytes::Bytes;
+use git_starter_rust::object::{GitObject, GitTreeItem};
+
+/// Small deterministic PRNG so failures can be replayed from their seed.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn hash_code(&mut self) -> [u8; 20] {
+        let mut output = [0; 20];
+        output.iter_mut().for_each(|x| *x = self.next() as u8);
+        output
+    }
+
+    /// Printable text without line breaks, never starting nor ending with a space.
+    fn text(&mut self, min_len: usize, max_len: usize) -> String {
+        const CHARS: &[u8] =
+            b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789 .-_<>@+";
+        let len = min_len + self.below(max_len - min_len + 1);
+        let text: String = (0..len)
+            .map(|_| CHARS[self.below(CHARS.len())] as char)
+            .collect();
+        text.trim().to_string()
+    }
+
+    fn object(&mut self) -> GitObject {
+        match self.below(3) {
+            0 => GitObject::Blob(Bytes::from(self.bytes(512))),
+            1 => {
+                const MODES: [u32; 5] = [0o100644, 0o100755, 0o120000, 0o40000, 0o160000];
+                let count = self.below(8);
+                let items = (0..count)
+                    .map(|_| GitTreeItem {
+                        mode: MODES[self.below(MODES.len())],
+                        name: format!("{}.{}", self.text(1, 16), self.below(100)),
+                        hash_code: self.hash_code(),
+                    })
+                    .collect();
+                GitObject::Tree(items)
+            }
+            _ => {
+                let message_lines = self.below(4) + 1;
+                let message = (0..message_lines)
+                    .map(|_| self.text(0, 40))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+
+                GitObject::Commit {
+                    tree: self.hash_code(),
+                    parents: (0..self.below(3)).map(|_| self.hash_code()).collect(),
+                    author: Some(format!(
+                        "{} <a@b.c> {} +0100",
+                        self.text(1, 20),
+                        self.next() % 2_000_000_000
+                    )),
+                    committer: Some(format!(
+                        "{} <c@d.e> {} -0500",
+                        self.text(1, 20),
+                        self.next() % 2_000_000_000
+                    )),
+                    message: message.trim_end().to_string(),
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_read_write_roundtrip() {
+    for seed in 1..2_000_u64 {
+        let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let object = rng.object();
+
+        let (hash_code, bytes) = object.to_bytes_vec().unwrap();
+        let object_read = GitObject::read(&mut bytes.as_slice())
+            .unwrap_or_else(|err| panic!("seed {seed}: cannot read {object:?}: {err}"));
+        assert_eq!(object_read, object, "seed {seed}");
+
+        // Hash must only depend on content.
+        let (hash_code_again, _) = object_read.to_bytes_vec().unwrap();
+        assert_eq!(hash_code_again, hash_code, "seed {seed}");
+    }
+}
@@ -1,8 +1,12 @@
-use std::env;
+use std::{env, io::Write};
 
 use bytes::{Buf, Bytes};
+use flate2::{write::ZlibEncoder, Compression};
 use git_starter_rust::{
-    pack_file::{read_object_pack_header, unpack_into, DeltaInstructionType},
+    object::GitObject,
+    pack_file::{
+        apply_patch, read_object_pack_header, read_var_int, unpack_into, DeltaInstructionType,
+    },
     GitError,
 };
 
@@ -53,7 +57,7 @@ fn test_read_object_size() {
 #[test]
 fn test_decode_delta_instr() {
     fn check(input: &[u8], expected: DeltaInstructionType) {
-        let (rem, out) = DeltaInstructionType::from_bytes(input);
+        let (rem, out) = DeltaInstructionType::from_bytes(input).unwrap();
         assert_eq!(out, expected);
         assert_eq!(rem.len(), 0);
     }
@@ -83,3 +87,97 @@ fn test_decode_delta_instr() {
         DeltaInstructionType::Insert { size: 0b0111_0010 },
     );
 }
+
+#[test]
+fn test_invalid_entry() {
+    // Type 5 is reserved: error must point to first entry.
+    assert_eq!(
+        unpack_static_err(b"PACK\0\0\0\x02\0\0\0\x01\x50"),
+        GitError::UnsupportedObjectType(5).in_pack_entry(0, 12)
+    );
+
+    // Truncated entry.
+    assert_eq!(
+        unpack_static_err(b"PACK\0\0\0\x02\0\0\0\x01"),
+        GitError::io("failed to fill whole buffer").in_pack_entry(0, 12)
+    );
+
+    // Ref delta against unknown object.
+    let mut data = b"PACK\0\0\0\x02\0\0\0\x01\x70".to_vec();
+    data.extend_from_slice(&[0xab; 20]);
+    assert_eq!(
+        unpack_into(&mut Bytes::from(data).reader(), env::temp_dir()).unwrap_err(),
+        GitError::MissingObject("ab".repeat(20)).in_pack_entry(0, 12)
+    );
+
+    // Blob of one byte inflating to much more.
+    let mut data = b"PACK\0\0\0\x02\0\0\0\x01\x31".to_vec();
+    let mut encoder = ZlibEncoder::new(&mut data, Compression::best());
+    encoder.write_all(&vec![0; 1 << 20]).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(
+        unpack_into(&mut Bytes::from(data).reader(), env::temp_dir()).unwrap_err(),
+        GitError::invalid_content("Inflated object size does not match pack header")
+            .in_pack_entry(0, 12)
+    );
+}
+
+#[test]
+fn test_read_object_size_overflow() {
+    let data = [0xff_u8; 16];
+    assert!(read_object_pack_header(&mut Bytes::copy_from_slice(&data).reader()).is_err());
+    assert!(read_var_int(&data).is_err());
+}
+
+#[test]
+fn test_decode_delta_instr_truncated() {
+    fn check_err(input: &[u8]) {
+        assert_eq!(
+            DeltaInstructionType::from_bytes(input).unwrap_err(),
+            GitError::InvalidDelta("Truncated delta instruction")
+        );
+    }
+
+    check_err(&[]);
+    check_err(&[0b1000_0001]);
+    check_err(&[0b1011_0000, 0b1101_0001]);
+}
+
+#[test]
+fn test_apply_patch_invalid() {
+    const BASE: &[u8] = b"blob 5\0hello";
+
+    fn check_err(patch: &[u8], expected: GitError) {
+        assert_eq!(apply_patch(BASE, patch).unwrap_err(), expected);
+    }
+
+    // Source size does not match.
+    check_err(
+        &[4, 5],
+        GitError::InvalidDelta("Base len is different from information stored in patch"),
+    );
+
+    // Copy past end of base.
+    check_err(
+        &[5, 5, 0b1001_0001, 3, 5],
+        GitError::InvalidDelta("Copy out of base bounds"),
+    );
+
+    // Insert more than available.
+    check_err(
+        &[5, 5, 4, b'a'],
+        GitError::InvalidDelta("Insert out of patch bounds"),
+    );
+
+    // Output too short.
+    check_err(
+        &[5, 5, 1, b'a'],
+        GitError::InvalidDelta("Final output buffer is not the same as expected"),
+    );
+
+    // Valid patch.
+    assert_eq!(
+        apply_patch(BASE, &[5, 6, 0b1001_0000, 4, 2, b'!', b'!']).unwrap(),
+        GitObject::Blob(Bytes::from_static(b"hell!!"))
+    );
+}
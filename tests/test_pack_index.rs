@@ -0,0 +1,141 @@
+mod common;
+
+use std::{
+    fs,
+    io::{Cursor, Write},
+    thread,
+};
+
+use bytes::{Buf, Bytes};
+use flate2::{write::ZlibEncoder, Compression};
+use git_starter_rust::{
+    pack_file::unpack_into,
+    pack_index::{crc32, index_pack, PackIndex},
+    store::ObjectStore,
+    GitError,
+};
+use sha1::{Digest, Sha1};
+
+const PACK: &[u8] = include_bytes!("./data/sqlite-rust.pack");
+
+fn no_external_base(
+    hash_code: git_starter_rust::HashCode,
+) -> Result<(git_starter_rust::header::GitObjectHeaderType, Vec<u8>), GitError> {
+    Err(GitError::missing_object(hash_code))
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+}
+
+#[test]
+fn test_build_and_parse() {
+    let index = PackIndex::build(&mut Cursor::new(PACK), &no_external_base).unwrap();
+    let object_count = u32::from_be_bytes(PACK[8..12].try_into().unwrap());
+    assert_eq!(index.entries.len(), object_count as usize);
+    assert_eq!(index.pack_checksum[..], PACK[PACK.len() - 20..]);
+
+    // Encoded index must be parsed back to the same value.
+    let parsed = PackIndex::parse(&index.to_bytes()).unwrap();
+    assert_eq!(parsed, index);
+
+    for entry in &index.entries {
+        assert_eq!(index.find(entry.hash_code), Some(entry.offset));
+    }
+    assert_eq!(index.find([0; 20]), None);
+}
+
+#[test]
+fn test_packed_objects_match_unpacked_ones() {
+    let packed_repo = common::temp_repo("pack-index-packed");
+    let loose_repo = common::temp_repo("pack-index-loose");
+
+    // Store pack as is in a first repository.
+    let pack_dir = packed_repo.join(".git/objects/pack");
+    fs::create_dir_all(&pack_dir).unwrap();
+    let pack_path = pack_dir.join("pack-test.pack");
+    fs::write(&pack_path, PACK).unwrap();
+    index_pack(&pack_path, &no_external_base).unwrap();
+
+    // Explode it in a second one.
+    unpack_into(&mut Bytes::from_static(PACK).reader(), &loose_repo).unwrap();
+
+    let packed_store = ObjectStore::open(&packed_repo).unwrap();
+    let loose_store = ObjectStore::open(&loose_repo).unwrap();
+
+    let loose_objects = loose_store.loose_objects().unwrap();
+    assert_eq!(
+        loose_objects.len(),
+        packed_store.packs()[0].index.entries.len()
+    );
+
+    for hash_code in loose_objects {
+        assert!(packed_store.contains(hash_code));
+        assert_eq!(
+            packed_store.read_raw(hash_code).unwrap(),
+            loose_store.read_raw(hash_code).unwrap()
+        );
+    }
+
+    assert!(packed_store.packs()[0].verify().is_empty());
+}
+
+#[test]
+fn test_parse_invalid() {
+    assert!(PackIndex::parse(b"").is_err());
+    assert!(PackIndex::parse(&[0; 2000]).is_err());
+}
+
+#[test]
+fn test_deep_delta_chain() {
+    // Blob "0" then offset deltas each making the next number from the previous one.
+    let depth = 3000;
+    let mut pack = b"PACK\0\0\0\x02".to_vec();
+    pack.extend_from_slice(&(depth as u32 + 1).to_be_bytes());
+    let mut previous = pack.len();
+    pack.push(0x31);
+    pack.extend(zlib(b"0"));
+    for number in 1..=depth {
+        let (base, result) = ((number - 1).to_string(), number.to_string());
+        let mut delta = vec![base.len() as u8, result.len() as u8, result.len() as u8];
+        delta.extend_from_slice(result.as_bytes());
+
+        let offset = pack.len();
+        pack.push(0x60 | delta.len() as u8);
+        pack.push((offset - previous) as u8);
+        pack.extend(zlib(&delta));
+        previous = offset;
+    }
+    let checksum = Sha1::digest(&pack);
+    pack.extend_from_slice(&checksum);
+
+    let root = common::temp_repo("pack-index-deep");
+    let pack_dir = root.join(".git/objects/pack");
+    fs::create_dir_all(&pack_dir).unwrap();
+    let pack_path = pack_dir.join("pack-deep.pack");
+    fs::write(&pack_path, &pack).unwrap();
+    index_pack(&pack_path, &no_external_base).unwrap();
+
+    // Decoding the end of the chain with nothing cached must not need a deep stack.
+    let store = ObjectStore::open(&root).unwrap();
+    let pack = &store.packs()[0];
+    let offset = pack.index.entries.iter().map(|x| x.offset).max().unwrap();
+    let (_, payload) = thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(256 << 10)
+            .spawn_scoped(scope, || pack.read_at(offset, &no_external_base))
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+    .unwrap();
+    assert_eq!(payload, depth.to_string().as_bytes());
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use bytes::Bytes;
+use git_starter_rust::{
+    object::{GitObject, GitTreeItem},
+    pack_file::unpack_into,
+    pack_writer::{list_objects, write_pack},
+    store::ObjectStore,
+    HashCode,
+};
+
+fn tree_item(mode: u32, name: &str, hash_code: HashCode) -> GitTreeItem {
+    GitTreeItem {
+        mode,
+        name: name.to_string(),
+        hash_code,
+    }
+}
+
+#[test]
+fn test_list_objects() {
+    let root = common::temp_repo("pack-writer-list");
+    let store = ObjectStore::open(&root).unwrap();
+
+    let a1 = store
+        .write(&GitObject::Blob(Bytes::from_static(b"a1")))
+        .unwrap();
+    let a2 = store
+        .write(&GitObject::Blob(Bytes::from_static(b"a2")))
+        .unwrap();
+    let b = store
+        .write(&GitObject::Blob(Bytes::from_static(b"b")))
+        .unwrap();
+    let dir = store
+        .write(&GitObject::Tree(vec![tree_item(0o100644, "b", b)]))
+        .unwrap();
+    let tree1 = store
+        .write(&GitObject::Tree(vec![
+            tree_item(0o100644, "a", a1),
+            tree_item(0o40000, "dir", dir),
+        ]))
+        .unwrap();
+    let tree2 = store
+        .write(&GitObject::Tree(vec![
+            tree_item(0o100644, "a", a2),
+            tree_item(0o40000, "dir", dir),
+        ]))
+        .unwrap();
+    let c1 = common::commit_tree(&store, tree1, vec![], common::SIGNATURE, "message");
+    let c2 = common::commit_tree(&store, tree2, vec![c1], common::SIGNATURE, "message");
+
+    assert_eq!(
+        list_objects(&store, &[c2], &[]).unwrap(),
+        [c2, c1, tree2, a2, dir, b, tree1, a1]
+    );
+    // Unchanged directory is shared with the excluded commit.
+    assert_eq!(list_objects(&store, &[c2], &[c1]).unwrap(), [c2, tree2, a2]);
+    assert!(list_objects(&store, &[c2], &[c2]).unwrap().is_empty());
+    // Unknown excluded objects are ignored.
+    assert_eq!(list_objects(&store, &[c1], &[[7; 20]]).unwrap().len(), 5);
+}
+
+#[test]
+fn test_write_pack() {
+    let root = common::temp_repo("pack-writer-src");
+    let store = ObjectStore::open(&root).unwrap();
+    let blob = store
+        .write(&GitObject::Blob(Bytes::from(vec![b'x'; 1000])))
+        .unwrap();
+    let tree = store
+        .write(&GitObject::Tree(vec![tree_item(0o100644, "x", blob)]))
+        .unwrap();
+    let commit = common::commit_tree(&store, tree, vec![], common::SIGNATURE, "message");
+
+    let mut pack = Vec::new();
+    let ids = list_objects(&store, &[commit], &[]).unwrap();
+    let checksum = write_pack(&store, &ids, &mut pack).unwrap();
+    assert_eq!(&pack[..12], b"PACK\0\0\0\x02\0\0\0\x03");
+    assert_eq!(pack[pack.len() - 20..], checksum);
+
+    let dst = common::temp_repo("pack-writer-dst");
+    unpack_into(pack.as_slice(), &dst).unwrap();
+    let dst_store = ObjectStore::open(&dst).unwrap();
+    for id in ids {
+        assert_eq!(dst_store.read(id).unwrap(), store.read(id).unwrap());
+    }
+}
@@ -12,8 +12,10 @@ fn test_write() {
     }
 
     check(PacketLine::End, b"0000");
+    check(PacketLine::Delimiter, b"0001");
     check(PacketLine::want("hello"), b"000fwant hello\n");
     check(PacketLine::want("world !"), b"0011want world !\n");
+    check(PacketLine::have("hello"), b"000fhave hello\n");
     check(PacketLine::done(), b"0009done\n");
 }
 
@@ -26,6 +28,7 @@ fn test_read() {
     }
 
     check(b"0000", PacketLine::End);
+    check(b"0001", PacketLine::Delimiter);
     check(b"0004", PacketLine::Command(Bytes::new()));
     check(b"0005\n", PacketLine::Command(Bytes::from_static(b"\n")));
     check(b"0010hello world\n", PacketLine::command(b"hello world\n"));
@@ -0,0 +1,212 @@
+use git_starter_rust::{
+    diff::{DiffEntry, DiffSide, DiffStatus},
+    line_diff::{
+        count_changes, diff_lines, is_binary, split_lines, unified_diff, DiffAlgorithm, LineOp,
+    },
+    patch::{format_patch, format_stat, FileStat, PatchOptions},
+    GitError,
+};
+
+const ALGORITHMS: [DiffAlgorithm; 3] = [
+    DiffAlgorithm::Myers,
+    DiffAlgorithm::Patience,
+    DiffAlgorithm::Histogram,
+];
+
+fn side(path: &str, mode: u32, fill: u8) -> DiffSide {
+    DiffSide {
+        path: path.to_string(),
+        mode,
+        hash_code: [fill; 20],
+    }
+}
+
+/// Content of sides is picked from their hash first byte.
+fn load(side: &DiffSide) -> Result<Vec<u8>, GitError> {
+    Ok(match side.hash_code[0] {
+        1 => b"fn main() {\n    one();\n    two();\n}\n".to_vec(),
+        2 => b"fn main() {\n    one();\n    three();\n}\n".to_vec(),
+        3 => b"bin\0ary".to_vec(),
+        _ => Vec::new(),
+    })
+}
+
+#[test]
+fn test_split_and_binary() {
+    assert_eq!(split_lines(b"a\nb\nc"), [&b"a\n"[..], b"b\n", b"c"]);
+    assert!(split_lines(b"").is_empty());
+    assert!(is_binary(b"a\0b"));
+    assert!(!is_binary(b"text\n"));
+    assert_eq!("histogram".parse(), Ok(DiffAlgorithm::Histogram));
+    assert!("unknown".parse::<DiffAlgorithm>().is_err());
+}
+
+#[test]
+fn test_diff_lines() {
+    let old = split_lines(b"a\nb\nc\nd\n");
+    let new = split_lines(b"a\nc\nd\ne\n");
+    for algorithm in ALGORITHMS {
+        assert_eq!(
+            diff_lines(&old, &new, algorithm),
+            [
+                LineOp::Equal(0, 0),
+                LineOp::Delete(1),
+                LineOp::Equal(2, 1),
+                LineOp::Equal(3, 2),
+                LineOp::Insert(3),
+            ]
+        );
+    }
+
+    // Ambiguous insertions are slid down to the end of the repeated block.
+    let old = split_lines(b"x\n}\n");
+    let new = split_lines(b"x\n}\ny\n}\n");
+    assert_eq!(
+        diff_lines(&old, &new, DiffAlgorithm::Myers),
+        [
+            LineOp::Equal(0, 0),
+            LineOp::Equal(1, 1),
+            LineOp::Insert(2),
+            LineOp::Insert(3),
+        ]
+    );
+
+    // Every algorithm gives a minimal enough edit script on a bigger input.
+    let old: String = (0..100).map(|x| format!("line {}\n", x % 7)).collect();
+    let new: String = (0..100).map(|x| format!("line {}\n", x % 5)).collect();
+    for algorithm in ALGORITHMS {
+        let (added, deleted) = count_changes(old.as_bytes(), new.as_bytes(), algorithm);
+        assert_eq!(100 - deleted, 100 - added);
+        assert!(added < 100);
+    }
+}
+
+#[test]
+fn test_unified_diff() {
+    let old: String = (1..=20).map(|x| format!("{x}\n")).collect();
+    let new = old
+        .replacen("3\n", "three\n", 1)
+        .replace("18\n", "eighteen\n");
+    assert_eq!(
+        unified_diff(old.as_bytes(), new.as_bytes(), 3, DiffAlgorithm::Myers),
+        "@@ -1,6 +1,6 @@\n 1\n 2\n-3\n+three\n 4\n 5\n 6\n\
+         @@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+eighteen\n 19\n 20\n"
+    );
+    // Close changes share the same hunk.
+    assert_eq!(
+        unified_diff(old.as_bytes(), new.as_bytes(), 8, DiffAlgorithm::Myers)
+            .matches("@@ -")
+            .count(),
+        1
+    );
+
+    assert_eq!(
+        unified_diff(b"a\nb", b"a\nc\n", 3, DiffAlgorithm::Myers),
+        "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n"
+    );
+    assert_eq!(
+        unified_diff(b"", b"new\n", 3, DiffAlgorithm::Myers),
+        "@@ -0,0 +1 @@\n+new\n"
+    );
+
+    // Function context is the last line starting like a definition.
+    let old = b"fn main() {\n    a();\n    b();\n    c();\n    d();\n    e();\n}\n";
+    let new = b"fn main() {\n    a();\n    b();\n    c();\n    d();\n    f();\n}\n";
+    assert_eq!(
+        unified_diff(old, new, 1, DiffAlgorithm::Myers),
+        "@@ -5,3 +5,3 @@ fn main() {\n     d();\n-    e();\n+    f();\n }\n"
+    );
+}
+
+#[test]
+fn test_format_patch() {
+    let options = PatchOptions::default();
+    let modified = DiffEntry {
+        status: DiffStatus::Modified,
+        old: Some(side("src/main.rs", 0o100644, 1)),
+        new: Some(side("src/main.rs", 0o100755, 2)),
+    };
+    assert_eq!(
+        format_patch(&modified, &load, &options).unwrap(),
+        "diff --git a/src/main.rs b/src/main.rs\n\
+         old mode 100644\n\
+         new mode 100755\n\
+         index 0101010..0202020\n\
+         --- a/src/main.rs\n\
+         +++ b/src/main.rs\n\
+         @@ -1,4 +1,4 @@\n fn main() {\n     one();\n-    two();\n+    three();\n }\n"
+    );
+
+    let added = DiffEntry {
+        status: DiffStatus::Added,
+        old: None,
+        new: Some(side("empty", 0o100644, 4)),
+    };
+    assert_eq!(
+        format_patch(&added, &load, &options).unwrap(),
+        "diff --git a/empty b/empty\n\
+         new file mode 100644\n\
+         index 0000000..0404040\n"
+    );
+
+    let binary = DiffEntry {
+        status: DiffStatus::Deleted,
+        old: Some(side("image.png", 0o100644, 3)),
+        new: None,
+    };
+    assert_eq!(
+        format_patch(&binary, &load, &options).unwrap(),
+        "diff --git a/image.png b/image.png\n\
+         deleted file mode 100644\n\
+         index 0303030..0000000\n\
+         Binary files a/image.png and /dev/null differ\n"
+    );
+
+    let renamed = DiffEntry {
+        status: DiffStatus::Renamed(100),
+        old: Some(side("old.rs", 0o100644, 1)),
+        new: Some(side("new.rs", 0o100644, 1)),
+    };
+    assert_eq!(
+        format_patch(&renamed, &load, &options).unwrap(),
+        "diff --git a/old.rs b/new.rs\n\
+         similarity index 100%\n\
+         rename from old.rs\n\
+         rename to new.rs\n"
+    );
+}
+
+#[test]
+fn test_stat() {
+    let modified = DiffEntry {
+        status: DiffStatus::Modified,
+        old: Some(side("src/main.rs", 0o100644, 1)),
+        new: Some(side("src/main.rs", 0o100644, 2)),
+    };
+    let stat = FileStat::compute(&modified, &load, DiffAlgorithm::Myers).unwrap();
+    assert_eq!(stat.changes, Some((1, 1)));
+    assert_eq!(stat.numstat(), "1\t1\tsrc/main.rs");
+
+    let binary = FileStat {
+        path: "image.png".to_string(),
+        changes: None,
+    };
+    assert_eq!(binary.numstat(), "-\t-\timage.png");
+
+    assert_eq!(
+        format_stat(&[stat, binary]),
+        " src/main.rs | 2 +-\n image.png   | Bin\n 2 files changed, 1 insertion(+), 1 deletion(-)\n"
+    );
+
+    // Big changes are scaled down to fit on 80 columns, keeping their ratio.
+    let big = FileStat {
+        path: "big.txt".to_string(),
+        changes: Some((300, 100)),
+    };
+    let output = format_stat(&[big]);
+    let first_line = output.lines().next().unwrap();
+    assert_eq!(
+        first_line,
+        format!(" big.txt | 400 {}{}", "+".repeat(48), "-".repeat(16))
+    );
+}
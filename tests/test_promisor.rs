@@ -0,0 +1,138 @@
+mod common;
+
+use bytes::Bytes;
+use git_starter_rust::{
+    config::GitConfig,
+    fsck::fsck_at,
+    object::{GitObject, GitTreeItem},
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    promisor::{fetch_missing, promisor_remote, write_promisor_config, ObjectFilter},
+    store::ObjectStore,
+    GitError,
+};
+
+/// Make `root` a partial clone of `url`.
+fn setup_partial_clone(root: &std::path::Path, url: &str) {
+    let mut config = GitConfig::read_at(root).unwrap();
+    config.set("remote.origin.url", url);
+    config.write_at(root).unwrap();
+    write_promisor_config(root, "origin", ObjectFilter::BlobNone).unwrap();
+}
+
+#[test]
+fn test_object_filter() {
+    for (spec, filter) in [
+        ("blob:none", ObjectFilter::BlobNone),
+        ("blob:limit=100", ObjectFilter::BlobLimit(100)),
+        ("tree:0", ObjectFilter::TreeDepth(0)),
+    ] {
+        assert_eq!(ObjectFilter::parse(spec).unwrap(), filter);
+        assert_eq!(filter.to_string(), spec);
+    }
+    assert_eq!(
+        ObjectFilter::parse("blob:limit=2k").unwrap(),
+        ObjectFilter::BlobLimit(2048)
+    );
+    assert_eq!(
+        ObjectFilter::parse("blob:limit=1M").unwrap(),
+        ObjectFilter::BlobLimit(1 << 20)
+    );
+
+    for spec in [
+        "blob:some",
+        "blob:limit=",
+        "blob:limit=1x",
+        "tree:a",
+        "sparse:oid",
+    ] {
+        assert_eq!(
+            ObjectFilter::parse(spec),
+            Err(GitError::InvalidContent(format!(
+                "Invalid filter-spec '{spec}'"
+            )))
+        );
+    }
+}
+
+#[test]
+fn test_promisor_config() {
+    let root = common::temp_repo("promisor-config");
+    assert_eq!(
+        promisor_remote(&GitConfig::read_at(&root).unwrap()).unwrap(),
+        None
+    );
+
+    setup_partial_clone(&root, "https://example.com/repo.git");
+    let config = GitConfig::read_at(&root).unwrap();
+    assert_eq!(config.get("core.repositoryformatversion"), Some("1"));
+    assert_eq!(config.get_bool("remote.origin.promisor"), Some(true));
+    let remote = promisor_remote(&config).unwrap().unwrap();
+    assert_eq!(remote.url, "https://example.com/repo.git");
+    assert_eq!(remote.partial_clone_filter, Some(ObjectFilter::BlobNone));
+}
+
+#[test]
+fn test_fsck_partial_clone() {
+    let root = common::temp_repo("promisor-fsck");
+    let store = ObjectStore::open(&root).unwrap();
+    let tree = store
+        .write(&GitObject::Tree(vec![GitTreeItem {
+            mode: 0o100644,
+            name: "big.bin".to_string(),
+            hash_code: [0xaa; 20],
+        }]))
+        .unwrap();
+    std::fs::write(root.join(".git/refs/heads/master"), hex::encode(tree)).unwrap();
+    assert_eq!(fsck_at(&root).unwrap().len(), 1);
+
+    // Blobs left on the server are not reported.
+    setup_partial_clone(&root, "https://example.com/repo.git");
+    assert_eq!(fsck_at(&root).unwrap(), vec![]);
+}
+
+#[tokio::test]
+async fn test_fetch_missing() {
+    let src = common::temp_repo("promisor-src");
+    let src_store = ObjectStore::open(&src).unwrap();
+    let blob = src_store
+        .write(&GitObject::Blob(Bytes::from_static(b"large content\n")))
+        .unwrap();
+    let mut pack = Vec::new();
+    write_pack(
+        &src_store,
+        &list_objects(&src_store, &[blob], &[]).unwrap(),
+        &mut pack,
+    )
+    .unwrap();
+
+    let want = format!("want {}", hex::encode(blob));
+    let url = common::serve_http(move |method, path, body| {
+        let output = if method == "GET" {
+            assert_eq!(path, "/repo.git/info/refs?service=git-upload-pack");
+            common::pkt_lines(&["version 2", "ls-refs", "fetch=shallow filter", "0000"])
+        } else {
+            // The blob is asked for without any negotiation.
+            let body = String::from_utf8_lossy(body);
+            assert!(body.contains(&want));
+            assert!(body.contains("filter blob:none"));
+            assert!(!body.contains("have "));
+            let mut output = common::pkt_lines(&["packfile"]);
+            let packet = [&[1], pack.as_slice()].concat();
+            output.extend_from_slice(format!("{:04x}", packet.len() + 4).as_bytes());
+            output.extend_from_slice(&packet);
+            PacketLine::End.write(&mut output).unwrap();
+            output
+        };
+        ("application/x-git-upload-pack-result".to_string(), output)
+    });
+
+    let root = common::temp_repo("promisor-dst");
+    // Outside a partial clone, nothing is fetched.
+    fetch_missing(&root, &[blob], &mut |_| {}).await.unwrap();
+    assert!(!ObjectStore::open(&root).unwrap().contains(blob));
+
+    setup_partial_clone(&root, &format!("{url}/repo.git"));
+    fetch_missing(&root, &[blob], &mut |_| {}).await.unwrap();
+    assert!(ObjectStore::open(&root).unwrap().contains(blob));
+}
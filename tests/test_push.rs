@@ -0,0 +1,302 @@
+mod common;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    commit::{add_paths, commit, CommitOptions},
+    config::GitConfig,
+    pack_file::unpack_into,
+    packet_line::PacketLine,
+    push::{push, read_report_status, resolve_push_refspecs, PushRef, PushStatus, ReportStatus},
+    refs::{delete_ref_at, list_refs_at, resolve_ref_at, write_ref_at, RefValue},
+    remote::{RefSpec, Remote},
+    store::ObjectStore,
+    HashCode,
+};
+
+fn setup(name: &str) -> (PathBuf, ObjectStore) {
+    let root = common::temp_repo(name);
+    let mut config = GitConfig::default();
+    config.set("user.name", "Alice");
+    config.set("user.email", "alice@example.com");
+    config.write_at(&root).unwrap();
+    let store = ObjectStore::open(&root).unwrap();
+    (root, store)
+}
+
+fn commit_file(root: &Path, store: &ObjectStore, path: &str, content: &str) -> HashCode {
+    fs::write(root.join(path), content).unwrap();
+    add_paths(root, store, &[path.to_string()]).unwrap();
+    commit(root, store, content, &CommitOptions::default()).unwrap()
+}
+
+fn pkt_line(output: &mut Vec<u8>, line: String) {
+    PacketLine::Command(Bytes::from(line))
+        .write(output)
+        .unwrap();
+}
+
+/// Minimal `git-receive-pack` over HTTP storing pushed refs in `server`, whose hook
+/// declines any update of `refs/heads/protected`.
+fn serve_receive_pack(server: PathBuf) -> String {
+    common::serve_http(move |method, path, body| {
+        let mut output = Vec::new();
+
+        if method == "GET" {
+            assert_eq!(path, "/repo.git/info/refs?service=git-receive-pack");
+            pkt_line(&mut output, "# service=git-receive-pack".to_string());
+            PacketLine::End.write(&mut output).unwrap();
+
+            let mut refs = list_refs_at(&server).unwrap();
+            if refs.is_empty() {
+                refs.push(("capabilities^{}".to_string(), [0; 20]));
+            }
+            for (idx, (name, id)) in refs.iter().enumerate() {
+                let capabilities = if idx == 0 {
+                    "\0report-status delete-refs"
+                } else {
+                    ""
+                };
+                pkt_line(
+                    &mut output,
+                    format!("{} {name}{capabilities}", hex::encode(id)),
+                );
+            }
+            PacketLine::End.write(&mut output).unwrap();
+            return (
+                "application/x-git-receive-pack-advertisement".to_string(),
+                output,
+            );
+        }
+
+        assert_eq!(path, "/repo.git/git-receive-pack");
+        let mut reader = body;
+        let mut commands = Vec::new();
+        while let PacketLine::Command(data) = PacketLine::read(&mut reader).unwrap() {
+            let line = String::from_utf8(data.to_vec()).unwrap();
+            let line = line.trim_end().split('\0').next().unwrap().to_string();
+            let mut parts = line.split(' ');
+            let (_old, new, name) = (parts.next(), parts.next().unwrap(), parts.next().unwrap());
+            commands.push((new.to_string(), name.to_string()));
+        }
+        if !reader.is_empty() {
+            unpack_into(reader, &server).unwrap();
+        }
+
+        pkt_line(&mut output, "unpack ok".to_string());
+        for (new, name) in commands {
+            if name == "refs/heads/protected" {
+                pkt_line(&mut output, format!("ng {name} hook declined"));
+                continue;
+            }
+            if new == "0".repeat(40) {
+                delete_ref_at(&server, &name).unwrap();
+            } else {
+                let id = git_starter_rust::hash_code_text_to_array(&new).unwrap();
+                write_ref_at(&server, &name, &RefValue::Direct(id)).unwrap();
+            }
+            pkt_line(&mut output, format!("ok {name}"));
+        }
+        PacketLine::End.write(&mut output).unwrap();
+        ("application/x-git-receive-pack-result".to_string(), output)
+    })
+}
+
+fn push_ref(src: &str, remote_ref: &str, new: Option<HashCode>, force: bool) -> PushRef {
+    PushRef {
+        src: src.to_string(),
+        remote_ref: remote_ref.to_string(),
+        new,
+        force,
+    }
+}
+
+#[tokio::test]
+async fn test_push() {
+    let (root, store) = setup("push-client");
+    let server = common::temp_repo("push-server");
+    let remote = Remote {
+        name: "origin".to_string(),
+        url: format!("{}/repo.git", serve_receive_pack(server.clone())),
+        fetch: vec![RefSpec::default_fetch("origin")],
+        partial_clone_filter: None,
+    };
+
+    let c1 = commit_file(&root, &store, "a.txt", "one");
+    let updates = push(
+        &root,
+        &remote,
+        &[push_ref("master", "refs/heads/master", Some(c1), false)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(updates[0].status, PushStatus::Ok);
+    assert_eq!(updates[0].old, None);
+    assert_eq!(
+        resolve_ref_at(&server, "refs/heads/master").unwrap(),
+        Some(c1)
+    );
+    assert_eq!(
+        resolve_ref_at(&root, "refs/remotes/origin/master").unwrap(),
+        Some(c1)
+    );
+    let server_store = ObjectStore::open(&server).unwrap();
+    assert_eq!(server_store.loose_objects().unwrap().len(), 3);
+
+    // Only new objects are sent.
+    let c2 = commit_file(&root, &store, "b.txt", "two");
+    let updates = push(
+        &root,
+        &remote,
+        &[push_ref("master", "refs/heads/master", Some(c2), false)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(updates[0].status, PushStatus::Ok);
+    assert_eq!(updates[0].old, Some(c1));
+    assert!(!updates[0].forced);
+    assert_eq!(server_store.loose_objects().unwrap().len(), 6);
+
+    let updates = push(
+        &root,
+        &remote,
+        &[push_ref("master", "refs/heads/master", Some(c2), false)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(updates[0].status, PushStatus::UpToDate);
+
+    // Rewinding needs force.
+    let updates = push(
+        &root,
+        &remote,
+        &[push_ref("master", "refs/heads/master", Some(c1), false)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        updates[0].status,
+        PushStatus::Rejected("non-fast-forward".to_string())
+    );
+    assert_eq!(
+        resolve_ref_at(&server, "refs/heads/master").unwrap(),
+        Some(c2)
+    );
+
+    let updates = push(
+        &root,
+        &remote,
+        &[
+            push_ref("master", "refs/heads/master", Some(c1), true),
+            push_ref("master", "refs/heads/protected", Some(c1), false),
+            push_ref("master", "refs/heads/topic", Some(c2), false),
+        ],
+    )
+    .await
+    .unwrap();
+    let statuses: Vec<_> = updates
+        .iter()
+        .map(|x| (x.status.clone(), x.forced))
+        .collect();
+    assert_eq!(
+        statuses,
+        [
+            (PushStatus::Ok, true),
+            (
+                PushStatus::RemoteRejected("hook declined".to_string()),
+                false
+            ),
+            (PushStatus::Ok, false),
+        ]
+    );
+    assert_eq!(
+        resolve_ref_at(&server, "refs/heads/master").unwrap(),
+        Some(c1)
+    );
+    assert_eq!(
+        resolve_ref_at(&root, "refs/remotes/origin/protected").unwrap(),
+        None
+    );
+
+    let updates = push(
+        &root,
+        &remote,
+        &[push_ref("", "refs/heads/topic", None, false)],
+    )
+    .await
+    .unwrap();
+    assert_eq!(updates[0].status, PushStatus::Ok);
+    assert_eq!(resolve_ref_at(&server, "refs/heads/topic").unwrap(), None);
+    assert_eq!(
+        resolve_ref_at(&root, "refs/remotes/origin/topic").unwrap(),
+        None
+    );
+}
+
+#[test]
+fn test_resolve_push_refspecs() {
+    let (root, store) = setup("push-refspecs");
+    let c1 = commit_file(&root, &store, "a.txt", "one");
+    let c2 = commit_file(&root, &store, "a.txt", "two");
+    write_ref_at(&root, "refs/tags/v1", &RefValue::Direct(c1)).unwrap();
+
+    let refspecs: Vec<_> = [
+        "master",
+        "+HEAD~1:topic",
+        "v1:v2",
+        ":old",
+        "refs/heads/*:refs/heads/x/*",
+    ]
+    .iter()
+    .map(|x| RefSpec::parse(x).unwrap())
+    .collect();
+    assert_eq!(
+        resolve_push_refspecs(&root, &store, &refspecs).unwrap(),
+        [
+            push_ref("master", "refs/heads/master", Some(c2), false),
+            push_ref("HEAD~1", "refs/heads/topic", Some(c1), true),
+            push_ref("v1", "refs/tags/v2", Some(c1), false),
+            push_ref("", "refs/heads/old", None, false),
+            push_ref("refs/heads/master", "refs/heads/x/master", Some(c2), false),
+        ]
+    );
+
+    let refspecs = [RefSpec::parse("missing").unwrap()];
+    assert!(resolve_push_refspecs(&root, &store, &refspecs).is_err());
+}
+
+#[test]
+fn test_read_report_status() {
+    let mut input = Vec::new();
+    pkt_line(&mut input, "unpack ok".to_string());
+    pkt_line(&mut input, "ok refs/heads/main".to_string());
+    pkt_line(
+        &mut input,
+        "ng refs/heads/topic non-fast-forward".to_string(),
+    );
+    PacketLine::End.write(&mut input).unwrap();
+
+    assert_eq!(
+        read_report_status(&mut input.as_slice()).unwrap(),
+        ReportStatus {
+            unpack_error: None,
+            refs: vec![
+                ("refs/heads/main".to_string(), None),
+                (
+                    "refs/heads/topic".to_string(),
+                    Some("non-fast-forward".to_string())
+                ),
+            ],
+        }
+    );
+
+    let mut input = Vec::new();
+    pkt_line(&mut input, "unpack index-pack failed".to_string());
+    PacketLine::End.write(&mut input).unwrap();
+    let report = read_report_status(&mut input.as_slice()).unwrap();
+    assert_eq!(report.unpack_error.as_deref(), Some("index-pack failed"));
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use bytes::Bytes;
+use git_starter_rust::{
+    clone::Advertisement,
+    pack_writer::{list_objects, write_pack},
+    packet_line::PacketLine,
+    push::{read_report_status, ReportStatus},
+    receive_pack::{receive_pack, ReceivePackOptions},
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    store::ObjectStore,
+    HashCode,
+};
+
+/// Write ref update line `<old> <new> <name>`, with `capabilities` if there are any.
+fn update_line(
+    output: &mut Vec<u8>,
+    old: Option<HashCode>,
+    new: Option<HashCode>,
+    name: &str,
+    capabilities: &str,
+) {
+    let mut line = format!(
+        "{} {} {name}",
+        hex::encode(old.unwrap_or_default()),
+        hex::encode(new.unwrap_or_default())
+    );
+    if !capabilities.is_empty() {
+        line = format!("{line}\0{capabilities}");
+    }
+    PacketLine::Command(Bytes::from(line))
+        .write(output)
+        .unwrap();
+}
+
+fn serve(root: &std::path::Path, input: &[u8]) -> ReportStatus {
+    let options = ReceivePackOptions {
+        stateless_rpc: true,
+        advertise_refs: false,
+    };
+    let mut output = Vec::new();
+    receive_pack(root, &mut &input[..], &mut output, &options).unwrap();
+    read_report_status(&mut output.as_slice()).unwrap()
+}
+
+#[test]
+fn test_receive_pack() {
+    let server = common::temp_repo("receive-pack-server");
+    let c1 = common::commit(&ObjectStore::open(&server).unwrap(), vec![], "first");
+    write_ref_at(&server, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+
+    let options = ReceivePackOptions {
+        stateless_rpc: false,
+        advertise_refs: true,
+    };
+    let mut output = Vec::new();
+    receive_pack(&server, &mut &b""[..], &mut output, &options).unwrap();
+    let advertisement = Advertisement::parse(&mut output.as_slice()).unwrap();
+    assert_eq!(advertisement.refs.len(), 1);
+    assert_eq!(advertisement.refs[0].name, "refs/heads/master");
+    assert!(advertisement.has_capability("report-status"));
+    assert!(advertisement.has_capability("delete-refs"));
+
+    let client = common::temp_repo("receive-pack-client");
+    let store = ObjectStore::open(&client).unwrap();
+    common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![c1], "second");
+
+    let mut input = Vec::new();
+    update_line(
+        &mut input,
+        None,
+        Some(c2),
+        "refs/heads/topic",
+        "report-status",
+    );
+    update_line(&mut input, Some(c2), Some(c2), "refs/heads/stale", "");
+    update_line(&mut input, None, Some(c2), "refs/heads/bad..name", "");
+    update_line(&mut input, Some(c1), Some(c2), "refs/heads/master", "");
+    PacketLine::End.write(&mut input).unwrap();
+    let objects = list_objects(&store, &[c2], &[c1]).unwrap();
+    write_pack(&store, &objects, &mut input).unwrap();
+
+    let report = serve(&server, &input);
+    assert_eq!(report.unpack_error, None);
+    let reasons: Vec<_> = report.refs.iter().map(|(_, x)| x.as_deref()).collect();
+    assert_eq!(
+        reasons,
+        [
+            None,
+            Some("stale info"),
+            Some("funny refname"),
+            Some("branch is currently checked out")
+        ]
+    );
+    assert_eq!(
+        resolve_ref_at(&server, "refs/heads/topic").unwrap(),
+        Some(c2)
+    );
+    assert_eq!(
+        resolve_ref_at(&server, "refs/heads/master").unwrap(),
+        Some(c1)
+    );
+
+    // Deleting refs needs no pack.
+    let mut input = Vec::new();
+    update_line(
+        &mut input,
+        Some(c2),
+        None,
+        "refs/heads/topic",
+        "report-status",
+    );
+    PacketLine::End.write(&mut input).unwrap();
+    let report = serve(&server, &input);
+    assert_eq!(report.refs, [("refs/heads/topic".to_string(), None)]);
+    assert_eq!(resolve_ref_at(&server, "refs/heads/topic").unwrap(), None);
+}
+
+#[test]
+fn test_receive_pack_incomplete() {
+    let server = common::temp_repo("receive-pack-incomplete-server");
+    let c1 = common::commit(&ObjectStore::open(&server).unwrap(), vec![], "first");
+    write_ref_at(&server, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+
+    let client = common::temp_repo("receive-pack-incomplete-client");
+    let store = ObjectStore::open(&client).unwrap();
+    common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![c1], "second");
+    let c3 = common::commit(&store, vec![c2], "third");
+
+    // History of a ref must be complete down to what refs already reach.
+    let mut input = Vec::new();
+    update_line(
+        &mut input,
+        None,
+        Some(c3),
+        "refs/heads/topic",
+        "report-status",
+    );
+    PacketLine::End.write(&mut input).unwrap();
+    let objects = list_objects(&store, &[c3], &[c2]).unwrap();
+    write_pack(&store, &objects, &mut input).unwrap();
+
+    let report = serve(&server, &input);
+    assert_eq!(report.unpack_error, None);
+    assert_eq!(
+        report.refs,
+        [(
+            "refs/heads/topic".to_string(),
+            Some("missing necessary objects".to_string())
+        )]
+    );
+    assert_eq!(resolve_ref_at(&server, "refs/heads/topic").unwrap(), None);
+
+    // A pack with a wrong checksum is refused as a whole.
+    let mut input = Vec::new();
+    update_line(
+        &mut input,
+        None,
+        Some(c2),
+        "refs/heads/topic",
+        "report-status",
+    );
+    PacketLine::End.write(&mut input).unwrap();
+    let objects = list_objects(&store, &[c2], &[c1]).unwrap();
+    write_pack(&store, &objects, &mut input).unwrap();
+    *input.last_mut().unwrap() ^= 0xff;
+
+    let report = serve(&server, &input);
+    assert!(report.unpack_error.is_some());
+    assert_eq!(
+        report.refs,
+        [(
+            "refs/heads/topic".to_string(),
+            Some("unpacker error".to_string())
+        )]
+    );
+    assert_eq!(resolve_ref_at(&server, "refs/heads/topic").unwrap(), None);
+}
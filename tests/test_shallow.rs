@@ -0,0 +1,166 @@
+mod common;
+
+use std::{collections::HashSet, fs};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    clone::ProtocolVersion,
+    fsck::fsck_at,
+    log::{log, CommitInfo, LogOptions, RevisionRange},
+    packet_line::PacketLine,
+    revision::rev_parse,
+    shallow::{read_shallow, update_shallow, write_shallow, ShallowOptions, ShallowUpdate},
+    store::ObjectStore,
+    GitError,
+};
+
+#[test]
+fn test_request_lines() {
+    let shallow = HashSet::from([[0xbb; 20], [0xaa; 20]]);
+
+    let options = ShallowOptions {
+        depth: Some(2),
+        relative: true,
+        ..Default::default()
+    };
+    assert!(options.is_deepening());
+    assert_eq!(options.capabilities(), ["deepen-relative"]);
+    assert_eq!(
+        options.request_lines(&shallow, ProtocolVersion::V2),
+        [
+            format!("shallow {}", hex::encode([0xaa; 20])),
+            format!("shallow {}", hex::encode([0xbb; 20])),
+            "deepen 2".to_string(),
+            "deepen-relative".to_string(),
+        ]
+    );
+    // Protocol v0 only has the capability.
+    assert_eq!(
+        options.request_lines(&shallow, ProtocolVersion::V0).len(),
+        3
+    );
+
+    let options = ShallowOptions {
+        since: Some(1700000000),
+        exclude: vec!["refs/heads/old".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(options.capabilities(), ["deepen-since", "deepen-not"]);
+    assert_eq!(
+        options.request_lines(&HashSet::new(), ProtocolVersion::V0),
+        ["deepen-since 1700000000", "deepen-not refs/heads/old"]
+    );
+
+    // A shallow repository still tells its boundary when not moving it.
+    let options = ShallowOptions::default();
+    assert!(!options.is_deepening());
+    assert_eq!(
+        options.request_lines(&HashSet::new(), ProtocolVersion::V2),
+        Vec::<String>::new()
+    );
+    assert_eq!(
+        options.request_lines(&shallow, ProtocolVersion::V2).len(),
+        2
+    );
+}
+
+#[test]
+fn test_read_update() {
+    let line = |output: &mut Vec<u8>, data: String| {
+        PacketLine::Command(Bytes::from(data))
+            .write(output)
+            .unwrap()
+    };
+
+    let mut input = Vec::new();
+    line(&mut input, format!("shallow {}", hex::encode([0xaa; 20])));
+    line(&mut input, format!("unshallow {}", hex::encode([0xbb; 20])));
+    PacketLine::End.write(&mut input).unwrap();
+    input.extend_from_slice(b"0008NAK\n");
+    let mut reader = input.as_slice();
+    assert_eq!(
+        ShallowUpdate::read(&mut reader).unwrap(),
+        ShallowUpdate {
+            shallow: vec![[0xaa; 20]],
+            unshallow: vec![[0xbb; 20]],
+        }
+    );
+    assert_eq!(reader, b"0008NAK\n");
+
+    let mut input = Vec::new();
+    line(&mut input, "ERR unknown ref".to_string());
+    assert_eq!(
+        ShallowUpdate::read(&mut input.as_slice()),
+        Err(GitError::Http("remote error: unknown ref".to_string()))
+    );
+}
+
+#[test]
+fn test_shallow_file() {
+    let root = common::temp_repo("shallow-file");
+    let path = root.join(".git/shallow");
+    assert!(read_shallow(&root).unwrap().is_empty());
+
+    write_shallow(&root, &HashSet::from([[0xbb; 20], [0xaa; 20]])).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        format!("{}\n{}\n", hex::encode([0xaa; 20]), hex::encode([0xbb; 20]))
+    );
+
+    // Deepening replaces the boundary.
+    let update = ShallowUpdate {
+        shallow: vec![[0xcc; 20]],
+        unshallow: vec![[0xaa; 20]],
+    };
+    update_shallow(&root, &update).unwrap();
+    assert_eq!(
+        read_shallow(&root).unwrap(),
+        HashSet::from([[0xbb; 20], [0xcc; 20]])
+    );
+
+    // The file goes away once history is complete.
+    let update = ShallowUpdate {
+        shallow: Vec::new(),
+        unshallow: vec![[0xbb; 20], [0xcc; 20]],
+    };
+    update_shallow(&root, &update).unwrap();
+    assert!(!path.exists());
+}
+
+#[test]
+fn test_grafted_history() {
+    let root = common::temp_repo("shallow-graft");
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![c1], "second");
+    let c3 = common::commit(&store, vec![c2], "third");
+    fs::write(root.join(".git/refs/heads/master"), hex::encode(c3) + "\n").unwrap();
+
+    // Drop the first commit as if it was never fetched.
+    let c1_hex = hex::encode(c1);
+    fs::remove_file(
+        root.join(".git/objects")
+            .join(&c1_hex[..2])
+            .join(&c1_hex[2..]),
+    )
+    .unwrap();
+    write_shallow(&root, &HashSet::from([c2])).unwrap();
+
+    let store = ObjectStore::open(&root).unwrap();
+    assert!(store.is_shallow(c2));
+    assert!(CommitInfo::read(&store, c2).unwrap().parents.is_empty());
+
+    let range = RevisionRange::parse(&store, &["HEAD".to_string()]).unwrap();
+    let ids: Vec<_> = log(&store, &range, &LogOptions::default())
+        .unwrap()
+        .into_iter()
+        .map(|x| x.id)
+        .collect();
+    assert_eq!(ids, [c3, c2]);
+
+    assert_eq!(rev_parse(&store, "HEAD~1").unwrap(), c2);
+    assert!(rev_parse(&store, "HEAD~2").is_err());
+
+    // Missing parents of the boundary are not reported.
+    assert!(fsck_at(&root).unwrap().iter().all(|x| !x.is_error()));
+}
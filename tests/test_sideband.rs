@@ -0,0 +1,69 @@
+use bytes::Bytes;
+use git_starter_rust::{
+    packet_line::PacketLine,
+    sideband::{demux, Progress},
+    GitError,
+};
+
+fn band(output: &mut Vec<u8>, band: u8, payload: &[u8]) {
+    let mut data = vec![band];
+    data.extend_from_slice(payload);
+    // `PacketLine::write` adds a newline, so write the packet by hand.
+    output.extend_from_slice(format!("{:04x}", data.len() + 4).as_bytes());
+    output.extend_from_slice(&data);
+}
+
+#[test]
+fn test_demux() {
+    let mut input = Vec::new();
+    band(&mut input, 2, b"Counting objects: 50% (1/2)\r");
+    band(&mut input, 1, b"PACK\0\0");
+    band(
+        &mut input,
+        2,
+        b"Counting objects: 100% (2/2), done.\nCompressing obj",
+    );
+    band(&mut input, 2, b"ects: done.\nTotal 2");
+    band(&mut input, 1, b"\0\x02");
+    PacketLine::End.write(&mut input).unwrap();
+    input.extend_from_slice(b"rest");
+
+    let mut messages = Vec::new();
+    let mut received = Vec::new();
+    let mut reader = input.as_slice();
+    let pack = demux(&mut reader, &mut |event| match event {
+        Progress::Remote(line) => messages.push(line.to_string()),
+        Progress::Received { bytes, done } => received.push((bytes, done)),
+    })
+    .unwrap();
+    assert_eq!(pack, b"PACK\0\0\0\x02");
+    assert_eq!(
+        messages,
+        [
+            "Counting objects: 50% (1/2)\r",
+            "Counting objects: 100% (2/2), done.\n",
+            "Compressing objects: done.\n",
+            "Total 2",
+        ]
+    );
+    assert_eq!(received, [(6, false), (8, false), (8, true)]);
+    assert_eq!(reader, b"rest");
+}
+
+#[test]
+fn test_demux_error() {
+    let mut input = Vec::new();
+    band(&mut input, 1, b"PACK");
+    band(&mut input, 3, b"upload-pack: not our ref\n");
+    PacketLine::End.write(&mut input).unwrap();
+    assert_eq!(
+        demux(&mut input.as_slice(), &mut |_| {}),
+        Err(GitError::Remote("upload-pack: not our ref".to_string()))
+    );
+
+    let mut input = Vec::new();
+    PacketLine::Command(Bytes::from_static(b"\x04?"))
+        .write(&mut input)
+        .unwrap();
+    assert!(demux(&mut input.as_slice(), &mut |_| {}).is_err());
+}
@@ -0,0 +1,81 @@
+mod common;
+
+use std::{env, fs, os::unix::fs::PermissionsExt, process};
+
+use bytes::Bytes;
+use git_starter_rust::{
+    clone::{clone, CloneOptions},
+    object::{GitObject, GitTreeItem},
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    store::ObjectStore,
+};
+
+#[tokio::test]
+async fn test_ssh_clone() {
+    let src = common::temp_repo("ssh-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let blob = store
+        .write(&GitObject::Blob(Bytes::from_static(b"over ssh")))
+        .unwrap();
+    let tree = store
+        .write(&GitObject::Tree(vec![GitTreeItem {
+            mode: 0o100644,
+            name: "file".to_string(),
+            hash_code: blob,
+        }]))
+        .unwrap();
+    let signature = "Alice <alice@example.com> 1000 +0000".to_string();
+    let commit = store
+        .write(&GitObject::Commit {
+            tree,
+            parents: Vec::new(),
+            author: Some(signature.clone()),
+            committer: Some(signature),
+            message: "first".to_string(),
+        })
+        .unwrap();
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(commit)).unwrap();
+
+    // Stand-in for ssh running the remote command locally, recording its arguments.
+    let dir = env::temp_dir().join(format!("git-rust-ssh-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let log = dir.join("args");
+    let script = dir.join("ssh");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\necho \"$@\" >> '{}'\nfor last; do :; done\nexec sh -c \"$last\"\n",
+            log.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+    env::set_var(
+        "GIT_SSH_COMMAND",
+        format!("{} -o BatchMode=yes", script.display()),
+    );
+
+    let dst = dir.join("dst");
+    let url = format!("ssh://git@example.com:2222{}", src.display());
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(commit));
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "over ssh");
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        format!(
+            "-o BatchMode=yes -o SendEnv=GIT_PROTOCOL -p 2222 -- git@example.com git-upload-pack '{}'\n",
+            src.display()
+        )
+    );
+
+    // Scp-like URL.
+    fs::remove_dir_all(&dst).unwrap();
+    clone(
+        &format!("example.com:{}", src.display()),
+        &dst,
+        &CloneOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(commit));
+}
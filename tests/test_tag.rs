@@ -0,0 +1,123 @@
+mod common;
+
+use bytes::Bytes;
+use git_starter_rust::{
+    header::GitObjectHeaderType,
+    object::GitObject,
+    refs::{read_ref_at, RefValue},
+    revision::{peel_tags, peel_to_tree},
+    signature::Signature,
+    store::ObjectStore,
+    tag::{create_tag, delete_tag, list_tags, tag_message, tag_points_at, TagAnnotation},
+};
+
+fn annotation(message: &str) -> TagAnnotation {
+    TagAnnotation {
+        tagger: Signature::parse("Bob <bob@example.com> 1700000000 +0100").unwrap(),
+        message: message.to_string(),
+    }
+}
+
+#[test]
+fn test_create_list_delete() {
+    let root = common::temp_repo("tag-create");
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit(&store, vec![], "first\n\nbody");
+    let c2 = common::commit(&store, vec![], "second");
+
+    assert_eq!(
+        create_tag(&root, &store, "v1", c1, None, false).unwrap(),
+        c1
+    );
+    assert!(create_tag(&root, &store, "v1", c2, None, false).is_err());
+    assert!(create_tag(&root, &store, "bad name", c2, None, false).is_err());
+
+    let tag = create_tag(
+        &root,
+        &store,
+        "v2",
+        c2,
+        Some(&annotation("Release 2\n\nNotes\n")),
+        false,
+    )
+    .unwrap();
+    assert_eq!(
+        store.read(tag).unwrap(),
+        GitObject::Tag {
+            object: c2,
+            target_type: GitObjectHeaderType::Commit,
+            tag: "v2".to_string(),
+            tagger: Some("Bob <bob@example.com> 1700000000 +0100".to_string()),
+            message: "Release 2\n\nNotes".to_string(),
+        }
+    );
+    assert_eq!(peel_tags(&store, tag).unwrap(), c2);
+
+    // Tag of a tree peels to the tree itself.
+    let tree = store.write(&GitObject::Tree(vec![])).unwrap();
+    let tree_tag = store
+        .write(&GitObject::Tag {
+            object: tree,
+            target_type: GitObjectHeaderType::Tree,
+            tag: "tree".to_string(),
+            tagger: None,
+            message: "Tree".to_string(),
+        })
+        .unwrap();
+    assert_eq!(peel_to_tree(&store, tree_tag).unwrap(), tree);
+
+    // Tag of a blob.
+    let blob = store
+        .write(&GitObject::Blob(Bytes::from_static(b"data")))
+        .unwrap();
+    create_tag(&root, &store, "data", blob, None, false).unwrap();
+
+    assert_eq!(
+        list_tags(&root).unwrap(),
+        [
+            ("data".to_string(), blob),
+            ("v1".to_string(), c1),
+            ("v2".to_string(), tag)
+        ]
+    );
+    assert_eq!(tag_message(&store, c1).unwrap(), "first\n\nbody");
+    assert_eq!(tag_message(&store, tag).unwrap(), "Release 2\n\nNotes");
+    assert_eq!(tag_message(&store, blob).unwrap(), "");
+
+    create_tag(&root, &store, "v1", c2, None, true).unwrap();
+    assert_eq!(
+        read_ref_at(&root, "refs/tags/v1").unwrap(),
+        Some(RefValue::Direct(c2))
+    );
+
+    assert_eq!(delete_tag(&root, "v2").unwrap(), tag);
+    assert!(delete_tag(&root, "v2").is_err());
+    assert_eq!(list_tags(&root).unwrap().len(), 2);
+}
+
+#[test]
+fn test_points_at() {
+    let root = common::temp_repo("tag-points-at");
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![], "second");
+
+    let tag = create_tag(&root, &store, "v1", c1, Some(&annotation("v1")), false).unwrap();
+    let tag_of_tag = create_tag(
+        &root,
+        &store,
+        "v1-signed",
+        tag,
+        Some(&annotation("v1")),
+        false,
+    )
+    .unwrap();
+
+    assert!(tag_points_at(&store, c1, c1).unwrap());
+    assert!(!tag_points_at(&store, c1, c2).unwrap());
+    assert!(tag_points_at(&store, tag, c1).unwrap());
+    assert!(tag_points_at(&store, tag, tag).unwrap());
+    // Only one level of tag is peeled.
+    assert!(tag_points_at(&store, tag_of_tag, tag).unwrap());
+    assert!(!tag_points_at(&store, tag_of_tag, c1).unwrap());
+}
@@ -0,0 +1,282 @@
+mod common;
+
+use std::{
+    fs,
+    io::{Read, Write},
+    net::TcpListener,
+    os::{fd::OwnedFd, unix::fs::MetadataExt},
+    path::PathBuf,
+    process::{self, Stdio},
+    thread,
+};
+
+use git_starter_rust::{
+    clone::{clone, CloneOptions},
+    config::GitConfig,
+    fetch::fetch,
+    refs::{resolve_ref_at, write_ref_at, RefValue},
+    remote::Remote,
+    shallow::ShallowOptions,
+    store::ObjectStore,
+    transport::{list_local_refs, Transport},
+    GitError,
+};
+
+#[test]
+fn test_parse_url() {
+    assert_eq!(
+        Transport::parse("https://example.com/repo.git/").unwrap(),
+        Transport::Http("https://example.com/repo.git".to_string())
+    );
+    assert_eq!(
+        Transport::parse("file:///srv/repo.git").unwrap(),
+        Transport::Local {
+            path: PathBuf::from("/srv/repo.git"),
+            is_file_url: true,
+        }
+    );
+    assert_eq!(
+        Transport::parse("../repo").unwrap(),
+        Transport::Local {
+            path: PathBuf::from("../repo"),
+            is_file_url: false,
+        }
+    );
+    assert_eq!(
+        Transport::parse("file://host/repo.git"),
+        Err(GitError::Transport(
+            "Invalid file URL 'file://host/repo.git': expected file:///<path>".to_string()
+        ))
+    );
+    assert!(Transport::parse("ftp://example.com/repo.git").is_err());
+}
+
+#[test]
+fn test_parse_git_url() {
+    assert_eq!(
+        Transport::parse("git://example.com:9419/srv/repo.git").unwrap(),
+        Transport::Git {
+            host: "example.com".to_string(),
+            port: Some(9419),
+            path: "/srv/repo.git".to_string(),
+        }
+    );
+    assert_eq!(
+        Transport::parse("git://example.com/~alice/repo").unwrap(),
+        Transport::Git {
+            host: "example.com".to_string(),
+            port: None,
+            path: "/~alice/repo".to_string(),
+        }
+    );
+    let url = "git://alice@example.com/repo";
+    assert_eq!(
+        Transport::parse(url),
+        Err(GitError::Transport(format!("Invalid git URL '{url}'")))
+    );
+}
+
+#[test]
+fn test_parse_ssh_url() {
+    let ssh = |user: Option<&str>, host: &str, port, path: &str| Transport::Ssh {
+        user: user.map(|x| x.to_string()),
+        host: host.to_string(),
+        port,
+        path: path.to_string(),
+    };
+
+    assert_eq!(
+        Transport::parse("git@github.com:org/repo.git").unwrap(),
+        ssh(Some("git"), "github.com", None, "org/repo.git")
+    );
+    assert_eq!(
+        Transport::parse("host:/srv/repo.git").unwrap(),
+        ssh(None, "host", None, "/srv/repo.git")
+    );
+    assert_eq!(
+        Transport::parse("ssh://git@example.com:2222/srv/repo.git").unwrap(),
+        ssh(Some("git"), "example.com", Some(2222), "/srv/repo.git")
+    );
+    assert_eq!(
+        Transport::parse("ssh://[::1]:22/~alice/repo").unwrap(),
+        ssh(None, "::1", Some(22), "~alice/repo")
+    );
+
+    // A slash before the colon makes a path.
+    assert!(matches!(
+        Transport::parse("./dir:name").unwrap(),
+        Transport::Local { .. }
+    ));
+    for url in ["ssh://host", "ssh://host:port/repo", "ssh:///repo"] {
+        assert_eq!(
+            Transport::parse(url),
+            Err(GitError::Transport(format!("Invalid ssh URL '{url}'")))
+        );
+    }
+
+    // ssh would take them for options.
+    for url in [
+        "-oProxyCommand=touch${IFS}pwned:repo",
+        "ssh://-oProxyCommand=touch${IFS}pwned/repo",
+    ] {
+        assert_eq!(
+            Transport::parse(url),
+            Err(GitError::Transport(
+                "strange hostname '-oProxyCommand=touch${IFS}pwned' blocked".to_string()
+            ))
+        );
+    }
+    assert_eq!(
+        Transport::parse("ssh://-oProxyCommand=x@host/repo"),
+        Err(GitError::Transport(
+            "strange username '-oProxyCommand=x' blocked".to_string()
+        ))
+    );
+}
+
+#[tokio::test]
+async fn test_local_clone() {
+    let src = common::temp_repo("local-clone-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+
+    let prefixes = ["HEAD".to_string(), "refs/heads/".to_string()];
+    let refs = list_local_refs(&src, &prefixes).unwrap();
+    assert_eq!(refs.len(), 2);
+    assert_eq!(refs[0].name, "HEAD");
+    assert_eq!(refs[0].symref_target.as_deref(), Some("refs/heads/master"));
+
+    let dst = common::temp_dir("local-clone-dst");
+    clone(src.to_str().unwrap(), &dst, &CloneOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(c1));
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "first");
+    assert_eq!(
+        GitConfig::read_at(&dst).unwrap().get("remote.origin.url"),
+        Some(fs::canonicalize(&src).unwrap().to_str().unwrap())
+    );
+
+    // Objects are shared with the source.
+    let hex = hex::encode(c1);
+    let object = format!(".git/objects/{}/{}", &hex[..2], &hex[2..]);
+    assert!(fs::metadata(dst.join(&object)).unwrap().nlink() >= 2);
+
+    let options = CloneOptions {
+        no_hardlinks: true,
+        ..Default::default()
+    };
+    // An existing repository is never cloned over.
+    assert_eq!(
+        clone(src.to_str().unwrap(), &dst, &options).await,
+        Err(GitError::Io(format!(
+            "destination path '{}' already exists and is not an empty directory",
+            dst.display()
+        )))
+    );
+    assert_eq!(resolve_ref_at(&dst, "HEAD").unwrap(), Some(c1));
+
+    fs::remove_dir_all(&dst).unwrap();
+    clone(src.to_str().unwrap(), &dst, &options).await.unwrap();
+    assert_eq!(fs::metadata(dst.join(&object)).unwrap().nlink(), 1);
+}
+
+#[tokio::test]
+async fn test_file_url() {
+    let src = common::temp_repo("file-url-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+
+    // The pack protocol runs against `git-upload-pack` of the source.
+    let url = format!("file://{}", src.display());
+    let dst = common::temp_dir("file-url-dst");
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "first");
+
+    let c2 = common::commit(&store, vec![c1], "second");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c2)).unwrap();
+    let remote = Remote::read(&GitConfig::read_at(&dst).unwrap(), "origin")
+        .unwrap()
+        .unwrap();
+    let updates = fetch(&dst, &remote, &ShallowOptions::default(), &mut |_| {})
+        .await
+        .unwrap();
+    assert_eq!(updates.len(), 1);
+    assert_eq!(
+        resolve_ref_at(&dst, "refs/remotes/origin/master").unwrap(),
+        Some(c2)
+    );
+}
+
+/// Accept one connection like `git daemon`, returning the request and answering
+/// with `git-upload-pack` of `src`, or with an error line if there is none.
+fn serve_daemon(listener: TcpListener, src: Option<PathBuf>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut size = [0; 4];
+        stream.read_exact(&mut size).unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&size).unwrap(), 16).unwrap();
+        let mut request = vec![0; size - 4];
+        stream.read_exact(&mut request).unwrap();
+
+        match src {
+            Some(src) => {
+                let input = OwnedFd::from(stream.try_clone().unwrap());
+                let status = process::Command::new("git-upload-pack")
+                    .args(["--strict".as_ref(), src.as_os_str()])
+                    .env("GIT_PROTOCOL", "version=2")
+                    .stdin(Stdio::from(input))
+                    .stdout(Stdio::from(OwnedFd::from(stream)))
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+            }
+            None => {
+                stream
+                    .write_all(b"0024ERR access denied or repository\n")
+                    .unwrap();
+            }
+        }
+        String::from_utf8(request).unwrap()
+    })
+}
+
+#[tokio::test]
+async fn test_daemon_clone() {
+    let src = common::temp_repo("daemon-src");
+    let store = ObjectStore::open(&src).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    write_ref_at(&src, "refs/heads/master", &RefValue::Direct(c1)).unwrap();
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = serve_daemon(listener, Some(src.join(".git")));
+
+    let url = format!("git://127.0.0.1:{port}/repo.git");
+    let dst = common::temp_dir("daemon-dst");
+    clone(&url, &dst, &CloneOptions::default()).await.unwrap();
+    assert_eq!(fs::read_to_string(dst.join("file")).unwrap(), "first");
+    assert_eq!(
+        server.join().unwrap(),
+        format!("git-upload-pack /repo.git\0host=127.0.0.1:{port}\0\0version=2\0")
+    );
+
+    // The daemon refusing a repository is reported with its message.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = serve_daemon(listener, None);
+    let url = format!("git://127.0.0.1:{port}/missing.git");
+    let dst = common::temp_dir("daemon-missing");
+    let result = clone(&url, &dst, &CloneOptions::default()).await;
+    server.join().unwrap();
+    assert_eq!(
+        result.err(),
+        Some(GitError::Http(
+            "remote error: access denied or repository".to_string()
+        ))
+    );
+    // A failed clone leaves nothing behind.
+    assert!(!dst.exists());
+}
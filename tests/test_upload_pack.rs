@@ -0,0 +1,238 @@
+mod common;
+
+use std::path::Path;
+
+use bytes::Bytes;
+use git_starter_rust::{
+    clone::{Advertisement, ProtocolVersion},
+    fetch::{fetch_request, read_acks, read_fetch_response, upload_pack_request, Ack},
+    header::GitObjectHeaderType,
+    object::GitObject,
+    pack_file::unpack_into,
+    packet_line::PacketLine,
+    refs::{write_ref_at, RefValue},
+    sideband::demux,
+    store::ObjectStore,
+    upload_pack::{requested_version, upload_pack, UploadPackOptions},
+    GitError, HashCode,
+};
+
+/// Repository with commits `c1` then `c2` on `master`, and tag `v2` annotating `c2`.
+fn setup(name: &str) -> (std::path::PathBuf, [HashCode; 3]) {
+    let root = common::temp_repo(name);
+    let store = ObjectStore::open(&root).unwrap();
+    let c1 = common::commit(&store, vec![], "first");
+    let c2 = common::commit(&store, vec![c1], "second");
+    let tag = store
+        .write(&GitObject::Tag {
+            object: c2,
+            target_type: GitObjectHeaderType::Commit,
+            tag: "v2".to_string(),
+            tagger: Some("Alice <alice@example.com> 1000 +0000".to_string()),
+            message: "second\n".to_string(),
+        })
+        .unwrap();
+    write_ref_at(&root, "refs/heads/master", &RefValue::Direct(c2)).unwrap();
+    write_ref_at(&root, "refs/tags/v2", &RefValue::Direct(tag)).unwrap();
+    (root, [c1, c2, tag])
+}
+
+fn serve(root: &Path, input: &[u8], version: ProtocolVersion, stateless_rpc: bool) -> Vec<u8> {
+    let options = UploadPackOptions {
+        version,
+        stateless_rpc,
+        advertise_refs: false,
+    };
+    let mut output = Vec::new();
+    upload_pack(root, &mut &input[..], &mut output, &options).unwrap();
+    output
+}
+
+fn text_lines<R: std::io::Read>(reader: &mut R) -> Vec<String> {
+    let mut output = Vec::new();
+    while let PacketLine::Command(data) = PacketLine::read(reader).unwrap() {
+        output.push(
+            String::from_utf8(data.to_vec())
+                .unwrap()
+                .trim_end()
+                .to_string(),
+        );
+    }
+    output
+}
+
+/// Unpack `pack` into a new repository and return its object store.
+fn unpack(name: &str, pack: &[u8]) -> ObjectStore {
+    let root = common::temp_repo(name);
+    unpack_into(pack, &root).unwrap();
+    ObjectStore::open(&root).unwrap()
+}
+
+#[test]
+fn test_requested_version() {
+    assert_eq!(requested_version("version=2"), ProtocolVersion::V2);
+    assert_eq!(
+        requested_version("object-format=sha1:version=2"),
+        ProtocolVersion::V2
+    );
+    assert_eq!(requested_version("version=1"), ProtocolVersion::V0);
+}
+
+#[test]
+fn test_upload_pack_v0() {
+    let (root, [c1, c2, tag]) = setup("upload-pack-v0");
+    let capabilities = ["multi_ack_detailed", "side-band-64k", "include-tag"];
+
+    let options = UploadPackOptions {
+        version: ProtocolVersion::V0,
+        stateless_rpc: true,
+        advertise_refs: true,
+    };
+    let mut output = Vec::new();
+    upload_pack(&root, &mut &b""[..], &mut output, &options).unwrap();
+    let advertisement = Advertisement::parse(&mut output.as_slice()).unwrap();
+    let names: Vec<_> = advertisement.refs.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "HEAD",
+            "refs/heads/master",
+            "refs/tags/v2",
+            "refs/tags/v2^{}"
+        ]
+    );
+    assert_eq!(
+        advertisement.refs[0].symref_target.as_deref(),
+        Some("refs/heads/master")
+    );
+    for capability in capabilities {
+        assert!(advertisement.has_capability(capability));
+    }
+
+    // A stateless round stops after acknowledging a batch.
+    let request = upload_pack_request(&[c2], &capabilities, &[], &[c1], false).unwrap();
+    let output = serve(&root, &request, ProtocolVersion::V0, true);
+    assert_eq!(
+        read_acks(&mut output.as_slice()).unwrap(),
+        [Ack::Common(c1), Ack::Ready(c1), Ack::Nak]
+    );
+
+    let request = upload_pack_request(&[c2], &capabilities, &[], &[c1], true).unwrap();
+    let output = serve(&root, &request, ProtocolVersion::V0, true);
+    let mut reader = output.as_slice();
+    assert_eq!(
+        read_acks(&mut reader).unwrap(),
+        [Ack::Common(c1), Ack::Final(c1)]
+    );
+    let pack = demux(&mut reader, &mut |_| {}).unwrap();
+    let store = unpack("upload-pack-v0-dst", &pack);
+    assert!(store.contains(c2) && store.contains(tag));
+    assert!(!store.contains(c1));
+
+    // A stateful session reads everything on one connection.
+    let mut request = upload_pack_request(&[c2], &["multi_ack"], &[], &[c1], false).unwrap();
+    PacketLine::done().write(&mut request).unwrap();
+    let output = serve(&root, &request, ProtocolVersion::V0, false);
+    let mut reader = output.as_slice();
+    Advertisement::parse(&mut reader).unwrap();
+    assert_eq!(
+        read_acks(&mut reader).unwrap(),
+        [Ack::Continue(c1), Ack::Nak]
+    );
+    assert_eq!(read_acks(&mut reader).unwrap(), [Ack::Final(c1)]);
+    // Without side-band, the pack follows as is.
+    let store = unpack("upload-pack-v0-stateful-dst", reader);
+    assert!(store.contains(c2) && !store.contains(tag));
+}
+
+#[test]
+fn test_upload_pack_v2() {
+    let (root, [c1, c2, tag]) = setup("upload-pack-v2");
+
+    let mut request = Vec::new();
+    for line in [
+        "command=ls-refs",
+        "0001",
+        "symrefs",
+        "peel",
+        "ref-prefix refs/tags/",
+    ] {
+        match line {
+            "0001" => PacketLine::Delimiter.write(&mut request).unwrap(),
+            line => PacketLine::Command(Bytes::from(line))
+                .write(&mut request)
+                .unwrap(),
+        }
+    }
+    PacketLine::End.write(&mut request).unwrap();
+    // Only `have` lines we do not know: not ready yet.
+    request.extend(fetch_request(&[c2], &[], &[[7; 20]], false).unwrap());
+    request.extend(fetch_request(&[c2], &[], &[c1], false).unwrap());
+    PacketLine::End.write(&mut request).unwrap();
+
+    let output = serve(&root, &request, ProtocolVersion::V2, false);
+    let mut reader = output.as_slice();
+    let advertisement = Advertisement::parse(&mut reader).unwrap();
+    assert_eq!(advertisement.version, ProtocolVersion::V2);
+    assert!(advertisement.capabilities.contains(&"ls-refs".to_string()));
+
+    assert_eq!(
+        text_lines(&mut reader),
+        [format!(
+            "{} refs/tags/v2 peeled:{}",
+            hex::encode(tag),
+            hex::encode(c2)
+        )]
+    );
+
+    let response = read_fetch_response(&mut reader).unwrap();
+    assert!(!response.ready && !response.has_pack);
+
+    let response = read_fetch_response(&mut reader).unwrap();
+    assert_eq!(response.common, [c1]);
+    assert!(response.ready && response.has_pack);
+    let pack = demux(&mut reader, &mut |_| {}).unwrap();
+    let store = unpack("upload-pack-v2-dst", &pack);
+    // Fetch commands always ask for `include-tag`.
+    assert!(store.contains(c2) && store.contains(tag));
+    assert!(!store.contains(c1));
+    assert!(reader.is_empty());
+}
+
+#[test]
+fn test_upload_pack_empty() {
+    let root = common::temp_repo("upload-pack-empty");
+    let options = UploadPackOptions {
+        version: ProtocolVersion::V0,
+        stateless_rpc: false,
+        advertise_refs: true,
+    };
+    let mut output = Vec::new();
+    upload_pack(&root, &mut &b""[..], &mut output, &options).unwrap();
+    let advertisement = Advertisement::parse(&mut output.as_slice()).unwrap();
+    assert!(advertisement.refs.is_empty());
+    assert!(advertisement.has_capability("side-band-64k"));
+}
+
+#[test]
+fn test_upload_pack_not_our_ref() {
+    let (root, _) = setup("upload-pack-not-our-ref");
+    let id = [9; 20];
+
+    let request = upload_pack_request(&[id], &[], &[], &[], true).unwrap();
+    let options = UploadPackOptions {
+        version: ProtocolVersion::V0,
+        stateless_rpc: true,
+        advertise_refs: false,
+    };
+    let mut output = Vec::new();
+    let message = format!("upload-pack: not our ref {}", hex::encode(id));
+    assert_eq!(
+        upload_pack(&root, &mut request.as_slice(), &mut output, &options),
+        Err(GitError::InvalidContent(message.clone()))
+    );
+    assert_eq!(
+        read_acks(&mut output.as_slice()),
+        Err(GitError::Http(format!("remote error: {message}")))
+    );
+}